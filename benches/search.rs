@@ -0,0 +1,79 @@
+//! Benchmarks for searching in huge listings. Each iteration types one character and erases it
+//! again, which is what the user does on every keystroke.
+//!
+//! Run with `cargo bench --bench search`.
+
+use std::path::PathBuf;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+
+use tere::{GapSearchMode, TereAppState, TereSettings};
+
+const SIZES: [usize; 2] = [100_000, 1_000_000];
+
+// GapSearchMode is not Clone, so each benchmark creates its own
+type ModeConstructor = fn() -> GapSearchMode;
+
+/// Names that look a bit like the contents of a real folder, with mixed case and non-ASCII
+/// characters. None of them exist, so that reading their metadata is fast.
+fn synthetic_names(n: usize) -> Vec<String> {
+    const STEMS: [&str; 8] = [
+        "Documents", "report", "IMG", "Übersicht", "build-output", "notes", "Makefile", "data",
+    ];
+    const EXTENSIONS: [&str; 5] = ["txt", "rs", "JPG", "tar.gz", "md"];
+    (0..n)
+        .map(|i| {
+            format!(
+                "tere-bench-nonexistent-{}_{:07}.{}",
+                STEMS[i % STEMS.len()],
+                i,
+                EXTENSIONS[i % EXTENSIONS.len()],
+            )
+        })
+        .collect()
+}
+
+fn create_state(names: &[String], gap_search_mode: GapSearchMode) -> TereAppState {
+    let settings = TereSettings {
+        gap_search_mode,
+        ..Default::default()
+    };
+    TereAppState::from_items(settings, PathBuf::from("/"), names.to_vec(), 80, 40).unwrap()
+}
+
+fn bench_search(c: &mut Criterion) {
+    let modes: [(&str, ModeConstructor); 3] = [
+        ("no-gap", || GapSearchMode::NoGapSearch),
+        ("gap-from-start", || GapSearchMode::GapSearchFromStart),
+        ("gap-anywhere", || GapSearchMode::GapSearchAnywere),
+    ];
+    // a prefix that matches everything, a query that matches most items with gap search, and
+    // one that matches nothing
+    let queries = ["tere-bench-", "tbdo", "xyzzy"];
+
+    let mut group = c.benchmark_group("search");
+    group.sample_size(10);
+    for n in SIZES {
+        let names = synthetic_names(n);
+        for (mode_name, mode) in &modes {
+            let mut state = create_state(&names, mode());
+            for query in queries {
+                // type all but the last character, and then benchmark the last one
+                let (prefix, last) = query.split_at(query.len() - 1);
+                state.clear_search();
+                state.advance_search(prefix);
+                let id = BenchmarkId::new(format!("{}/{}", mode_name, query), n);
+                group.bench_function(id, |b| {
+                    b.iter(|| {
+                        state.advance_search(last);
+                        state.erase_search_char();
+                    })
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_search);
+criterion_main!(benches);
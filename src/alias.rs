@@ -0,0 +1,124 @@
+/// Resolving the alias files that the macOS Finder creates, so that they can be entered like
+/// symlinks. An alias is a regular file that contains a bookmark, which records the location of
+/// its target in a way that survives the target being moved. The bookmarks can only be resolved
+/// with the Core Foundation API, so on other platforms aliases are left as they are.
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// The beginning of the contents of every alias file
+const BOOKMARK_MAGIC: &[u8] = b"book\0\0\0\0mark\0\0\0\0";
+
+/// Whether the file at `path` looks like an alias file. This only reads the first few bytes of
+/// the file, which is much faster than actually trying to resolve it.
+pub fn is_alias_file(path: &Path) -> bool {
+    let mut header = [0; BOOKMARK_MAGIC.len()];
+    std::fs::File::open(path)
+        .and_then(|mut f| f.read_exact(&mut header))
+        .is_ok_and(|_| header == BOOKMARK_MAGIC)
+}
+
+/// The target of the alias file at `path`, or `None` if it's not an alias file or if the target
+/// doesn't exist anymore. Aliases that point to unmounted network volumes are not resolved,
+/// because mounting them can take a long time and show dialogs.
+pub fn resolve_alias(path: &Path) -> Option<PathBuf> {
+    if !is_alias_file(path) {
+        return None;
+    }
+    resolve_bookmark(path)
+}
+
+#[cfg(target_os = "macos")]
+fn resolve_bookmark(path: &Path) -> Option<PathBuf> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    use std::ptr;
+
+    use core_foundation_sys::base::{kCFAllocatorDefault, Boolean, CFIndex, CFRelease, CFTypeRef};
+    use core_foundation_sys::data::CFDataRef;
+    use core_foundation_sys::url::{
+        kCFBookmarkResolutionWithoutMountingMask, kCFBookmarkResolutionWithoutUIMask,
+        CFURLCreateBookmarkDataFromFile, CFURLCreateByResolvingBookmarkData,
+        CFURLCreateFromFileSystemRepresentation, CFURLGetFileSystemRepresentation, CFURLRef,
+    };
+
+    // PATH_MAX on macOS
+    const MAX_PATH_LEN: usize = 1024;
+
+    // A Core Foundation object that is released when it's dropped
+    struct Owned(CFTypeRef);
+
+    impl Drop for Owned {
+        fn drop(&mut self) {
+            unsafe { CFRelease(self.0) }
+        }
+    }
+
+    fn owned<T>(obj: *const T) -> Option<Owned> {
+        (!obj.is_null()).then(|| Owned(obj as CFTypeRef))
+    }
+
+    let path = path.as_os_str().as_bytes();
+    let mut buf = vec![0u8; MAX_PATH_LEN];
+    unsafe {
+        let url = owned(CFURLCreateFromFileSystemRepresentation(
+            kCFAllocatorDefault,
+            path.as_ptr(),
+            path.len() as CFIndex,
+            false as Boolean,
+        ))?;
+        let bookmark = owned(CFURLCreateBookmarkDataFromFile(
+            kCFAllocatorDefault,
+            url.0 as CFURLRef,
+            ptr::null_mut(),
+        ))?;
+        let target = owned(CFURLCreateByResolvingBookmarkData(
+            kCFAllocatorDefault,
+            bookmark.0 as CFDataRef,
+            kCFBookmarkResolutionWithoutUIMask | kCFBookmarkResolutionWithoutMountingMask,
+            ptr::null(),
+            ptr::null(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        ))?;
+        if CFURLGetFileSystemRepresentation(
+            target.0 as CFURLRef,
+            true as Boolean,
+            buf.as_mut_ptr(),
+            buf.len() as CFIndex,
+        ) == 0
+        {
+            return None;
+        }
+    }
+
+    let len = buf.iter().position(|&b| b == 0)?;
+    Some(PathBuf::from(OsStr::from_bytes(&buf[..len])))
+}
+
+#[cfg(not(target_os = "macos"))]
+fn resolve_bookmark(_path: &Path) -> Option<PathBuf> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_is_alias_file() {
+        let tmp = TempDir::new("alias-file");
+
+        let mut contents = BOOKMARK_MAGIC.to_vec();
+        contents.extend_from_slice(&[0x30, 0x02, 0, 0]);
+        std::fs::write(tmp.join("alias"), contents).unwrap();
+        std::fs::write(tmp.join("text"), "bookmark").unwrap();
+        std::fs::write(tmp.join("short"), "book").unwrap();
+
+        assert!(is_alias_file(&tmp.join("alias")));
+        assert!(!is_alias_file(&tmp.join("text")));
+        assert!(!is_alias_file(&tmp.join("short")));
+        assert!(!is_alias_file(&tmp.join("missing")));
+        assert!(!is_alias_file(&tmp));
+    }
+}
@@ -2,85 +2,446 @@
 /// independent of a "graphical" front-end, such as crossterm.
 use clap::ArgMatches;
 
-use std::collections::BTreeMap;
+use std::collections::{HashMap, HashSet};
 use std::convert::TryFrom;
-use std::ffi::OsStr;
+use std::ffi::{OsStr, OsString};
 use std::io::{Error as IOError, ErrorKind, Result as IOResult};
-use std::path::{Component, Path, PathBuf};
+use std::path::{Component, Path, PathBuf, Prefix, PrefixComponent};
+use std::sync::{Arc, OnceLock};
+use std::time::{Duration, Instant, SystemTime};
 
+use rayon::prelude::*;
 use regex::Regex;
+use unicode_segmentation::UnicodeSegmentation;
 
 #[path = "settings.rs"]
 mod settings;
-use settings::TereSettings;
-pub use settings::{CaseSensitiveMode, GapSearchMode};
+pub use settings::{
+    TereSettings, ArchiveMode, AutoCdMode, CaseSensitiveMode, GapSearchMode, Hooks,
+    NoMatchBehavior, UnboundKeyBehavior,
+};
 
 #[path = "history.rs"]
 mod history;
 use history::HistoryTree;
 
+#[path = "dir_reader.rs"]
+mod dir_reader;
+use dir_reader::{merge_sorted, read_dir_sorted, DirCache, DirReader, ReadUpdate};
+
+#[path = "dir_source.rs"]
+mod dir_source;
+pub use dir_source::{DirBatches, DirSource, FsDirSource, MemoryDirSource};
+
+#[path = "view_prefs.rs"]
+mod view_prefs;
+pub use view_prefs::{SortMode, ViewPrefs};
+use view_prefs::ViewPrefsStore;
+
+#[path = "filter_preset.rs"]
+mod filter_preset;
+pub use filter_preset::FilterPreset;
+
+#[path = "dir_watcher.rs"]
+mod dir_watcher;
+use dir_watcher::DirWatcher;
+
+#[path = "alias.rs"]
+mod alias;
+
+#[path = "remote_fs.rs"]
+mod remote_fs;
+pub use remote_fs::DiskSpace;
+
+#[path = "sftp.rs"]
+mod sftp;
+pub use sftp::{SftpDirSource, SshUrl};
+
+#[path = "archive.rs"]
+mod archive;
+pub use archive::ArchiveDirSource;
+
 use crate::error::TereError;
+use crate::profile;
+use crate::ui::external::{expand_command_template, shell_command};
 
 pub const NO_MATCHES_MSG: &str = "No matches";
 
+/// Whether file systems ignore case in names, if it can't be found out from the names in a
+/// folder. The usual file systems of macOS and Windows do.
+const FS_IGNORES_CASE_BY_DEFAULT: bool = cfg!(any(target_os = "macos", windows));
+
 /// The match locations of a given item. A list of *byte offsets* into the item's name that match
 /// the current search pattern.
 pub type MatchesLocType = Vec<(usize, usize)>;
 
+/// The parts of an item's name that match the current search, as sorted and non-overlapping
+/// ranges of *grapheme indices*, for drawing them. The end of each range is exclusive.
+pub type MatchSpansType = Vec<(usize, usize)>;
+
+/// How an item matches the current search, see `MatchesVec::match_locations` and
+/// `MatchesVec::match_spans`.
+struct ItemMatch {
+    locations: MatchesLocType,
+    spans: MatchSpansType,
+}
+
+/// The matcher of a search, and whether it was tested against the original or the lowercased
+/// item names.
+#[derive(Clone)]
+struct Search {
+    matcher: Arc<dyn NameMatcher + Send>,
+    case_sensitive: bool,
+}
+
+impl Search {
+    fn target<'a>(&self, item: &'a CustomDirEntry) -> &'a str {
+        if self.case_sensitive {
+            item.file_name()
+        } else {
+            item.lowercase_file_name()
+        }
+    }
+}
+
+const MATCH_CHUNK_SIZE: usize = 256;
+
 /// A vector that keeps track of items that are 'filtered'. It offers indexing/viewing
 /// both the vector of filtered items and the whole unfiltered vector.
-struct MatchesVec {
+pub struct MatchesVec {
     all_items: Vec<CustomDirEntry>,
-    // Each key-value pair in this map corresponds to an item in `all_items` that matches the
-    // current search. The key is the item's index in `all_items`, while the value contains the
-    // regex match locations. We use a BTreeMap to always keep the matches sorted, so that they are
-    // in the same order relative to each other as they are in `all_items`.
-    matches: BTreeMap<usize, MatchesLocType>,
+    // The indices into `all_items` of the items that match the current search, in increasing
+    // order, so that the matches are in the same order relative to each other as they are in
+    // `all_items`. Keeping them in a vector means that the n:th match can be found without
+    // going through the others.
+    kept: Vec<usize>,
+    // How each item in `kept` matches, in chunks of `MATCH_CHUNK_SIZE` items at the same
+    // positions. This is computed when the item is first drawn, since with huge listings most
+    // matches are never shown, and the chunks avoid allocating for all of them on every search
+    // update.
+    matches: Vec<OnceLock<Box<[OnceLock<ItemMatch>]>>>,
+    // The current search, or `None` if the items have just been set and `kept` is empty
+    search: Option<Search>,
+    // The matching items of the searches that were narrowed down to the current one, most recent
+    // last, for going back to them when the search is erased
+    previous: Vec<(Vec<usize>, Search)>,
+    // If true, only folders can match, see `set_dirs_only`
+    dirs_only: bool,
 }
 
 impl MatchesVec {
     /// Return a vector of the indices of the matches
-    fn kept_indices(&self) -> Vec<usize> {
-        self.matches.keys().copied().collect()
+    pub fn kept_indices(&self) -> Vec<usize> {
+        self.kept.clone()
+    }
+
+    /// The position of the item at index `idx` of all items among the matches, if it matches
+    fn position_in_kept(&self, idx: usize) -> Option<usize> {
+        self.kept.binary_search(&idx).ok()
+    }
+
+    /// Return all items, including the ones that don't match
+    pub fn all_items(&self) -> &[CustomDirEntry] {
+        &self.all_items
+    }
+
+    /// Return the match locations of the item at index `idx` of all items, or `None` if the item
+    /// doesn't match
+    pub fn match_locations(&self, idx: usize) -> Option<&MatchesLocType> {
+        self.position_in_kept(idx)
+            .and_then(|pos| self.nth_match(pos))
+            .map(|m| &m.locations)
+    }
+
+    /// Return the match spans of the item at index `idx` of all items, or `None` if the item
+    /// doesn't match
+    pub fn match_spans(&self, idx: usize) -> Option<&MatchSpansType> {
+        self.position_in_kept(idx)
+            .and_then(|pos| self.nth_match(pos))
+            .map(|m| &m.spans)
+    }
+
+    /// How the n:th item that has not been filtered out matches
+    fn nth_match(&self, n: usize) -> Option<&ItemMatch> {
+        let (idx, search) = (self.kept.get(n)?, self.search.as_ref()?);
+        let chunk = self.matches[n / MATCH_CHUNK_SIZE].get_or_init(|| {
+            let chunk_len = MATCH_CHUNK_SIZE.min(self.kept.len() - n / MATCH_CHUNK_SIZE * MATCH_CHUNK_SIZE);
+            std::iter::repeat_with(OnceLock::new).take(chunk_len).collect()
+        });
+        Some(chunk[n % MATCH_CHUNK_SIZE].get_or_init(|| {
+            let item = &self.all_items[*idx];
+            let locations = search.matcher.match_locations(search.target(item)).unwrap_or_default();
+            // an empty search matches everything, avoid segmenting the name for nothing
+            let spans = if locations.is_empty() {
+                vec![]
+            } else {
+                grapheme_spans(item.grapheme_offsets(), &locations)
+            };
+            ItemMatch { locations, spans }
+        }))
     }
 
     /// Return a vector of all items that have not been filtered out
     pub fn kept_items(&self) -> Vec<&CustomDirEntry> {
-        self.matches
-            .keys()
+        self.kept
+            .iter()
             .filter_map(|idx| self.all_items.get(*idx))
             .collect()
     }
 
+    /// The n:th item that has not been filtered out
+    fn nth_kept_item(&self, n: usize) -> Option<&CustomDirEntry> {
+        self.kept.get(n).and_then(|idx| self.all_items.get(*idx))
+    }
+
     /// Update the collection of matching items by going through all items in the full collection
-    /// and testing a regex pattern against the filenames
-    pub fn update_matches(&mut self, search_ptn: &Regex, case_sensitive: bool) {
-        self.matches.clear();
-        self.matches = self
-            .all_items
-            .iter()
-            .enumerate()
-            .filter_map(|(i, item)| {
-                let target = if case_sensitive {
-                    item.file_name_checked()
-                } else {
-                    item.file_name_checked().to_lowercase()
-                };
-                let mut capture_locations = search_ptn.capture_locations();
-                if search_ptn
-                    .captures_read(&mut capture_locations, &target)
-                    .is_some()
-                {
-                    // have to do it this way using range because capture_locations has no iter() method
-                    let locs = (1..capture_locations.len())
-                        .filter_map(|i| capture_locations.get(i))
-                        .collect();
-                    Some((i, locs))
-                } else {
-                    None
-                }
+    /// and testing a pattern against the filenames. The pattern can be a regex from
+    /// `search_pattern`, or a faster `SearchQuery`.
+    pub fn update_matches<M>(&mut self, matcher: &M, case_sensitive: bool)
+    where
+        M: NameMatcher + Clone + Send + 'static,
+    {
+        let search = Search {
+            matcher: Arc::new(matcher.clone()),
+            case_sensitive,
+        };
+        let kept = self.find_matches((0..self.all_items.len()).into_par_iter(), &search);
+        self.previous.clear();
+        self.set_matches(kept, search);
+    }
+
+    /// Like `update_matches`, but only test the items that matched the previous search. This
+    /// gives the same result if everything that matches `matcher` also matched the previous
+    /// search, which is the case when a character is added to the search query. The previous
+    /// matches are kept, see `restore_previous_matches`.
+    pub fn narrow_matches<M>(&mut self, matcher: &M, case_sensitive: bool)
+    where
+        M: NameMatcher + Clone + Send + 'static,
+    {
+        let previous_search = match self.search.take() {
+            Some(search) => search,
+            None => return self.update_matches(matcher, case_sensitive),
+        };
+        let search = Search {
+            matcher: Arc::new(matcher.clone()),
+            case_sensitive,
+        };
+        let previous_kept = std::mem::take(&mut self.kept);
+        let kept = self.find_matches(previous_kept.par_iter().copied(), &search);
+        self.previous.push((previous_kept, previous_search));
+        self.set_matches(kept, search);
+    }
+
+    /// Go back to the matches from before the latest call to `narrow_matches`. Returns false if
+    /// there are none, because the matches have been updated with `update_matches` since.
+    pub fn restore_previous_matches(&mut self) -> bool {
+        match self.previous.pop() {
+            Some((kept, search)) => {
+                self.set_matches(kept, search);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// If `dirs_only` is true, only folders can match the searches from now on, while the files
+    /// are still kept in `all_items`. This takes effect on the next call to `update_matches` or
+    /// `narrow_matches`.
+    pub fn set_dirs_only(&mut self, dirs_only: bool) {
+        self.dirs_only = dirs_only;
+    }
+
+    /// The indices of the `candidates` that match `search`, in the same order
+    fn find_matches<I>(&self, candidates: I, search: &Search) -> Vec<usize>
+    where
+        I: ParallelIterator<Item = usize>,
+    {
+        // collecting a parallel iterator keeps the order of the items
+        candidates
+            .filter(|i| {
+                let item = &self.all_items[*i];
+                (!self.dirs_only || item.is_dir()) && search.matcher.is_match(search.target(item))
             })
+            .collect()
+    }
+
+    fn set_matches(&mut self, kept: Vec<usize>, search: Search) {
+        self.matches = std::iter::repeat_with(OnceLock::new)
+            .take(kept.len().div_ceil(MATCH_CHUNK_SIZE))
             .collect();
+        self.kept = kept;
+        self.search = Some(search);
+    }
+}
+
+/// Convert match locations (byte ranges) into the ranges of the graphemes that start inside them,
+/// given the byte offsets at which the graphemes start. Adjacent ranges are merged.
+fn grapheme_spans(offsets: &[usize], locs: &MatchesLocType) -> MatchSpansType {
+    let mut spans: MatchSpansType = Vec::with_capacity(locs.len());
+    for (start, end) in locs {
+        let first = offsets.partition_point(|o| o < start);
+        let last = offsets.partition_point(|o| o < end);
+        if first >= last {
+            continue;
+        }
+        match spans.last_mut() {
+            Some(prev) if prev.1 >= first => prev.1 = prev.1.max(last),
+            _ => spans.push((first, last)),
+        }
+    }
+    spans
+}
+
+/// Build the regex for searching for `query` with the given gap search mode. Each matching part of
+/// the query is in its own capture group, which `MatchesVec::update_matches` uses for finding the
+/// match locations. For case insensitive search, both the query and the item names should be
+/// lowercased.
+// TODO: construct regex pattern inside MatchesVec instead? - it relies now on capture
+// groups which are defined by the format!() parens here...
+pub fn search_pattern(query: &str, gap_search_mode: &GapSearchMode) -> Regex {
+    let mut regex_str = "".to_string();
+    if *gap_search_mode == GapSearchMode::NoGapSearch {
+        regex_str.push_str(&format!("^({})", regex::escape(query)));
+    } else {
+        // enable gap search. Add '^' to the regex to match only from the start if applicable.
+        if *gap_search_mode == GapSearchMode::GapSearchFromStart {
+            regex_str.push('^');
+        }
+        regex_str.push_str(
+            &query
+                .chars()
+                .map(|c| format!("({})", regex::escape(&c.to_string())))
+                .collect::<Vec<String>>()
+                .join(".*?"),
+        );
+    }
+
+    // ok to unwrap, we have escaped the regex above
+    Regex::new(&regex_str).unwrap()
+}
+
+/// Something that finds the parts of an item's name that match a search, see
+/// `MatchesVec::update_matches`.
+pub trait NameMatcher: Sync {
+    /// The byte ranges of `name` that match, or `None` if it doesn't match.
+    fn match_locations(&self, name: &str) -> Option<MatchesLocType>;
+
+    /// Whether `name` matches. This is called for every item on every search update, so it
+    /// should avoid allocating.
+    fn is_match(&self, name: &str) -> bool {
+        self.match_locations(name).is_some()
+    }
+}
+
+impl NameMatcher for Regex {
+    fn match_locations(&self, name: &str) -> Option<MatchesLocType> {
+        let mut capture_locations = self.capture_locations();
+        self.captures_read(&mut capture_locations, name)?;
+        // have to do it this way using range because capture_locations has no iter() method
+        Some(
+            (1..capture_locations.len())
+                .filter_map(|i| capture_locations.get(i))
+                .collect(),
+        )
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        Regex::is_match(self, name)
+    }
+}
+
+/// A search query that finds the same matches as the regex from `search_pattern`, but without
+/// the overhead of the regex engine, which is significant with the many capture groups of gap
+/// search and huge listings. For case insensitive search, both the query and the item names
+/// should be lowercased.
+#[derive(Clone)]
+pub struct SearchQuery {
+    query: String,
+    gaps: bool,
+    anchored: bool,
+}
+
+impl SearchQuery {
+    pub fn new(query: &str, gap_search_mode: &GapSearchMode) -> Self {
+        Self {
+            query: query.to_string(),
+            gaps: *gap_search_mode != GapSearchMode::NoGapSearch,
+            anchored: *gap_search_mode != GapSearchMode::GapSearchAnywere,
+        }
+    }
+
+    /// Find the characters of the query in `name` after `start`, where the first one was
+    /// found, each as early as possible, and add their locations to `locs` if it's given. Like
+    /// the `.*?` of the regex, the gaps between them can't contain newlines. On failure, returns
+    /// whether the end of the name was reached, in which case starting later can't succeed
+    /// either.
+    fn match_from(
+        &self,
+        name: &str,
+        start: usize,
+        first: char,
+        mut locs: Option<&mut MatchesLocType>,
+    ) -> Result<(), bool> {
+        let pos = start + first.len_utf8();
+        if let Some(locs) = locs.as_deref_mut() {
+            locs.clear();
+            locs.push((start, pos));
+        }
+        // the indices of `rest` are relative to `pos`
+        let mut rest = name[pos..].char_indices();
+        for c in self.query.chars().skip(1) {
+            loop {
+                match rest.next() {
+                    None => return Err(true),
+                    Some((i, ch)) if ch == c => {
+                        if let Some(locs) = locs.as_deref_mut() {
+                            locs.push((pos + i, pos + i + c.len_utf8()));
+                        }
+                        break;
+                    }
+                    Some((_, '\n')) => return Err(false),
+                    Some(_) => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `name` matches, adding the match locations to `locs` if it's given
+    fn find(&self, name: &str, mut locs: Option<&mut MatchesLocType>) -> bool {
+        if !self.gaps {
+            let found = name.starts_with(&self.query);
+            if let (true, Some(locs)) = (found, locs) {
+                locs.push((0, self.query.len()));
+            }
+            return found;
+        }
+        let first = match self.query.chars().next() {
+            Some(c) => c,
+            None => return true,
+        };
+        if self.anchored {
+            return name.starts_with(first) && self.match_from(name, 0, first, locs).is_ok();
+        }
+        for (start, _) in name.match_indices(first) {
+            match self.match_from(name, start, first, locs.as_deref_mut()) {
+                Ok(()) => return true,
+                Err(true) => return false,
+                Err(false) => {}
+            }
+        }
+        false
+    }
+}
+
+impl NameMatcher for SearchQuery {
+    fn match_locations(&self, name: &str) -> Option<MatchesLocType> {
+        let mut locs = Vec::with_capacity(self.query.len());
+        self.find(name, Some(&mut locs)).then_some(locs)
+    }
+
+    fn is_match(&self, name: &str) -> bool {
+        self.find(name, None)
     }
 }
 
@@ -88,27 +449,103 @@ impl From<Vec<CustomDirEntry>> for MatchesVec {
     fn from(vec: Vec<CustomDirEntry>) -> Self {
         Self {
             all_items: vec,
-            matches: BTreeMap::new(),
+            kept: vec![],
+            matches: vec![],
+            search: None,
+            previous: vec![],
+            dirs_only: false,
+        }
+    }
+}
+
+/// Split `name` into runs of graphemes that are either all inside or all outside of the match
+/// `spans`, for drawing each run at once. `offsets` are the byte offsets at which the graphemes of
+/// the name start, see `CustomDirEntry::grapheme_offsets`. Returns each run, and whether it's
+/// inside the spans. This walks the sorted spans alongside the graphemes, so it's linear in the
+/// length of the name.
+pub fn split_by_match_spans<'a>(
+    name: &'a str,
+    offsets: &[usize],
+    spans: &[(usize, usize)],
+) -> Vec<(&'a str, bool)> {
+    let byte_offset = |grapheme: usize| offsets.get(grapheme).copied().unwrap_or(name.len());
+    let mut runs = Vec::with_capacity(2 * spans.len() + 1);
+    let mut pos = 0;
+    for (start, end) in spans {
+        let (start, end) = ((*start).min(offsets.len()), (*end).min(offsets.len()));
+        if start > pos {
+            runs.push((&name[byte_offset(pos)..byte_offset(start)], false));
+        }
+        if end > start.max(pos) {
+            runs.push((&name[byte_offset(start.max(pos))..byte_offset(end)], true));
         }
+        pos = pos.max(end);
     }
+    if pos < offsets.len() {
+        runs.push((&name[byte_offset(pos)..], false));
+    }
+    runs
 }
 
 /// A stripped-down version of ``std::fs::DirEntry``.
 #[derive(Clone)]
 pub struct CustomDirEntry {
     _path: std::path::PathBuf,
-    pub metadata: Option<std::fs::Metadata>,
-    /// The symlink target is None if this entry is not a symlink
-    pub symlink_target: Option<std::path::PathBuf>,
-    _file_name: std::ffi::OsString,
+    // Whether this is a folder, or a symlink to a folder
+    _is_dir: bool,
+    // Reading the metadata requires a stat() call, which can be slow on network file systems, so
+    // it's only read when it's needed, unless it was needed anyway to find out `_is_dir`.
+    _metadata: OnceLock<Option<std::fs::Metadata>>,
+    _is_symlink: bool,
+    // Like the metadata, the target of a symlink is only read when it's needed, which is usually
+    // when the entry is shown. Folders like /usr/bin can be full of symlinks.
+    _symlink_target: OnceLock<Option<std::path::PathBuf>>,
+    // Whether following the symlink leads back to itself, which is checked when it's shown
+    _symlink_loop: OnceLock<bool>,
+    // The target of a macOS alias file, which is only resolved if resolving aliases is enabled
+    _alias_target: OnceLock<Option<std::path::PathBuf>>,
+    // The name is shared between the clones of the entry, for example the cached listings and
+    // the one that is shown. Names that aren't valid unicode are decoded with replacement
+    // characters, and the exact name is only kept in the path.
+    _file_name: Arc<str>,
+    // The lowercased name for sorting and searching, or None if the name is lowercase already
+    _lowercase_name: Option<Arc<str>>,
+    // The byte offsets at which the grapheme clusters of the name start, computed when the entry
+    // is first drawn or searched
+    _graphemes: OnceLock<Vec<usize>>,
 }
 
 impl CustomDirEntry {
     /// Return the file name of this directory entry. The file name is an OsString,
-    /// which may not be possible to convert to a String. In this case, this
-    /// function returns an empty string.
+    /// which may not be possible to convert to a String. In this case, the invalid parts
+    /// are replaced with U+FFFD, like in `to_string_lossy`. Prefer `file_name`, which doesn't
+    /// allocate.
     pub fn file_name_checked(&self) -> String {
-        self._file_name.clone().into_string().unwrap_or_default()
+        self._file_name.to_string()
+    }
+
+    /// The file name of this directory entry, which is shown and searched. If it's not valid
+    /// unicode, it's decoded lossily, like in `file_name_checked`.
+    pub fn file_name(&self) -> &str {
+        &self._file_name
+    }
+
+    /// The exact file name of this directory entry, which should be used for accessing it.
+    pub fn os_file_name(&self) -> &OsStr {
+        self._path.file_name().unwrap_or(self._path.as_os_str())
+    }
+
+    /// The file name in lowercase, for case insensitive sorting and searching.
+    pub fn lowercase_file_name(&self) -> &str {
+        self._lowercase_name.as_deref().unwrap_or(&self._file_name)
+    }
+
+    /// The shared name and lowercased name of an entry called `name`.
+    fn names(name: &OsStr) -> (Arc<str>, Option<Arc<str>>) {
+        let name = name.to_string_lossy();
+        let lowercase = name.to_lowercase();
+        let lowercase = (lowercase != name).then(|| lowercase.into());
+        (name.into(), lowercase)
     }
 
     pub fn path(&self) -> &std::path::PathBuf {
@@ -116,39 +553,452 @@ impl CustomDirEntry {
     }
 
     pub fn is_dir(&self) -> bool {
-        match &self.metadata {
-            Some(m) => m.is_dir(),
-            None => false,
+        self._is_dir
+    }
+
+    /// The byte offsets into `file_name` at which each of its grapheme clusters starts.
+    pub fn grapheme_offsets(&self) -> &[usize] {
+        self._graphemes.get_or_init(|| {
+            UnicodeSegmentation::grapheme_indices(self.file_name(), true)
+                .map(|(i, _)| i)
+                .collect()
+        })
+    }
+
+    /// Whether this entry is hidden. On Unix, the names of hidden files start with a dot. On
+    /// Windows, files that have the hidden or system attribute are hidden too, like in Explorer.
+    /// Checking the attributes requires the metadata, which is read if it hasn't been read yet.
+    pub fn is_hidden(&self) -> bool {
+        if self.file_name().starts_with('.') && self.file_name() != ".." {
+            return true;
+        }
+        #[cfg(windows)]
+        {
+            use std::os::windows::fs::MetadataExt;
+            const FILE_ATTRIBUTE_HIDDEN: u32 = 0x2;
+            const FILE_ATTRIBUTE_SYSTEM: u32 = 0x4;
+            if let Some(metadata) = self.metadata() {
+                return metadata.file_attributes() & (FILE_ATTRIBUTE_HIDDEN | FILE_ATTRIBUTE_SYSTEM) != 0;
+            }
+        }
+        false
+    }
+
+    pub fn is_symlink(&self) -> bool {
+        self._is_symlink
+    }
+
+    /// The target of the symlink, which is read the first time it's needed. `None` if this entry
+    /// is not a symlink, or if the target can't be read.
+    pub fn symlink_target(&self) -> Option<&Path> {
+        if !self._is_symlink {
+            return None;
+        }
+        self._symlink_target
+            .get_or_init(|| self._path.read_link().ok())
+            .as_deref()
+    }
+
+    /// Whether this is a symlink whose target can never be resolved, because following it leads
+    /// back to a symlink that was already followed. This is checked the first time it's needed.
+    pub fn is_symlink_loop(&self) -> bool {
+        // a symlink to a folder can be followed, since the folder was found out from its target
+        self._is_symlink
+            && !self._is_dir
+            && *self._symlink_loop.get_or_init(|| is_symlink_cycle(&self._path))
+    }
+
+    /// The target of the macOS alias file, which is resolved the first time it's needed. `None`
+    /// if this entry is not an alias file, or if it can't be resolved.
+    pub fn alias_target(&self) -> Option<&Path> {
+        if self._is_dir || self._is_symlink {
+            return None;
+        }
+        self._alias_target
+            .get_or_init(|| alias::resolve_alias(&self._path))
+            .as_deref()
+    }
+
+    /// The metadata of the file or folder (or the target of the symlink), which is read the first
+    /// time it's needed. `None` if it can't be read, for example if this is a broken symlink.
+    pub fn metadata(&self) -> Option<&std::fs::Metadata> {
+        self._metadata
+            .get_or_init(|| std::fs::metadata(&self._path).ok())
+            .as_ref()
+    }
+
+    fn from_metadata(path: PathBuf, metadata: Option<std::fs::Metadata>, is_symlink: bool) -> Self {
+        let (file_name, lowercase_name) =
+            Self::names(path.file_name().unwrap_or(path.as_os_str()));
+        Self {
+            _is_dir: metadata.as_ref().is_some_and(|m| m.is_dir()),
+            _metadata: OnceLock::from(metadata),
+            _is_symlink: is_symlink,
+            _symlink_target: OnceLock::new(),
+            _symlink_loop: OnceLock::new(),
+            _alias_target: OnceLock::new(),
+            _file_name: file_name,
+            _lowercase_name: lowercase_name,
+            _graphemes: OnceLock::new(),
+            _path: path,
         }
     }
 }
 
 impl From<std::fs::DirEntry> for CustomDirEntry {
     fn from(e: std::fs::DirEntry) -> Self {
-        Self {
-            _path: e.path(),
+        match e.file_type() {
+            // The file type is usually known without any extra system calls, so use it when
+            // possible. Symlinks have to be followed to find out if they point to a folder.
+            Ok(file_type) if !file_type.is_symlink() => {
+                let (file_name, lowercase_name) = Self::names(&e.file_name());
+                Self {
+                    _path: e.path(),
+                    _is_dir: file_type.is_dir(),
+                    _metadata: OnceLock::new(),
+                    _is_symlink: false,
+                    _symlink_target: OnceLock::new(),
+                    _symlink_loop: OnceLock::new(),
+                    _alias_target: OnceLock::new(),
+                    _file_name: file_name,
+                    _lowercase_name: lowercase_name,
+                    _graphemes: OnceLock::new(),
+                }
+            }
             // Note: this traverses symlinks, so is_dir will return true for symlinks as well.
-            metadata: std::fs::metadata(e.path()).ok(),
-            symlink_target: std::fs::read_link(e.path()).ok(),
-            _file_name: e.file_name(),
+            file_type => {
+                let path = e.path();
+                let is_symlink = file_type.map_or_else(|_| path.is_symlink(), |t| t.is_symlink());
+                Self::from_metadata(path.clone(), std::fs::metadata(&path).ok(), is_symlink)
+            }
         }
     }
 }
 
 impl From<&std::path::Path> for CustomDirEntry {
     fn from(p: &std::path::Path) -> Self {
+        Self::from_metadata(p.to_path_buf(), p.metadata().ok(), p.is_symlink())
+    }
+}
+
+impl CustomDirEntry {
+    /// Create an entry for an item in the list read from stdin. The whole path is used as the
+    /// name, since the items can be in different folders.
+    fn from_list_item(item: &str) -> Self {
+        let path = PathBuf::from(item);
+        let (file_name, lowercase_name) = Self::names(item.as_ref());
+        Self {
+            _file_name: file_name,
+            _lowercase_name: lowercase_name,
+            // the targets of symlinks are not shown for list items
+            ..Self::from_metadata(path.clone(), path.metadata().ok(), false)
+        }
+    }
+
+    /// Create an entry that is not on the file system, for a `DirSource` other than
+    /// `FsDirSource`. It has no metadata.
+    pub fn new_virtual(path: PathBuf, is_dir: bool) -> Self {
         Self {
-            _path: p.to_path_buf(),
-            metadata: p.metadata().ok(),
-            symlink_target: p.read_link().ok(),
-            _file_name: p.file_name().unwrap_or(p.as_os_str()).to_os_string(),
+            _is_dir: is_dir,
+            ..Self::from_metadata(path, None, false)
         }
     }
+
+    /// Create an entry from a listing that was saved to the listing cache file. Its metadata is
+    /// read when it's needed, like for entries read from the file system.
+    fn from_cache(path: PathBuf, is_dir: bool, is_symlink: bool) -> Self {
+        Self {
+            _is_dir: is_dir,
+            _metadata: OnceLock::new(),
+            _is_symlink: is_symlink,
+            ..Self::from_metadata(path, None, false)
+        }
+    }
+
+    /// Read the metadata and symlink targets of these entries in parallel, if they haven't been
+    /// read yet.
+    pub fn load_metadata(entries: &[&CustomDirEntry]) {
+        entries.par_iter().for_each(|entry| {
+            entry.metadata();
+            entry.symlink_target();
+        });
+    }
+}
+
+/// The usual form of the prefix of a Windows path, like `C:` or `\\server\share`. Verbatim
+/// prefixes like `\\?\UNC\server\share`, which for example `canonicalize` returns, are converted
+/// to the usual form, and drive letters are uppercased, so that a folder always has the same path
+/// in the header and in the history.
+fn normalize_prefix(prefix: PrefixComponent) -> OsString {
+    match prefix.kind() {
+        Prefix::UNC(server, share) | Prefix::VerbatimUNC(server, share) => {
+            let mut ret = OsString::from(r"\\");
+            ret.push(server);
+            ret.push(r"\");
+            ret.push(share);
+            ret
+        }
+        Prefix::Disk(letter) | Prefix::VerbatimDisk(letter) => {
+            format!("{}:", char::from(letter).to_ascii_uppercase()).into()
+        }
+        _ => prefix.as_os_str().to_owned(),
+    }
+}
+
+/// Resolve the '.' and '..' components of the absolute path `path`, without following symlinks.
+/// Going up from the root, or on Windows from the root of a drive or a network share like
+/// `\\server\share\`, stays there.
+///
+/// NOTE: have to manually normalize path because the std doesn't have that feature yet, as
+/// of December 2021.
+/// see:
+/// - https://github.com/rust-lang/rfcs/issues/2208
+/// - https://github.com/gdzx/rfcs/commit/3c69f787b5b32fb9c9960c1e785e5cabcc794238
+/// - abs_path crate
+/// - relative_path crate
+///
+/// This function is based on cargo::util::paths::normalize_path, https://docs.rs/cargo-util/0.1.1/cargo_util/paths/fn.normalize_path.html, under the MIT license
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut components = path.components().peekable();
+    let mut ret = if let Some(Component::Prefix(prefix)) = components.peek().cloned() {
+        components.next();
+        PathBuf::from(normalize_prefix(prefix))
+    } else {
+        PathBuf::new()
+    };
+
+    for component in components {
+        match component {
+            Component::Prefix(..) => unreachable!(),
+            Component::RootDir => {
+                ret.push(component.as_os_str());
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                // this does nothing at the root
+                ret.pop();
+            }
+            Component::Normal(c) => {
+                ret.push(c);
+            }
+        }
+    }
+    ret
+}
+
+/// Find out whether the file system of the folder `dir`, which contains `entries`, ignores case in
+/// names, by checking if an entry can also be accessed with the case of its name swapped. Returns
+/// `None` if none of the names contain letters that have case.
+fn ignores_case(entries: &[CustomDirEntry], dir: &Path) -> Option<bool> {
+    entries.iter().find_map(|entry| {
+        let name = entry.os_file_name().to_str()?;
+        let swapped: String = name
+            .chars()
+            .flat_map(|c| {
+                if c.is_lowercase() {
+                    c.to_uppercase().collect::<Vec<_>>()
+                } else {
+                    c.to_lowercase().collect()
+                }
+            })
+            .collect();
+        if swapped == name {
+            return None;
+        }
+        // only a case sensitive file system can have both
+        if entries.iter().any(|e| e.os_file_name() == OsStr::new(&swapped)) {
+            return Some(false);
+        }
+        Some(dir.join(swapped).symlink_metadata().is_ok())
+    })
 }
 
 /// The type of the `ls_output_buf` buffer of the app state
 type LsBufType = MatchesVec;
 
+/// A folder whose contents are being read in the background, see
+/// `TereAppState::begin_change_dir`.
+struct PendingDir {
+    reader: DirReader,
+    // Whether the folder has been opened, after which its entries are shown as they are read
+    opened: bool,
+    // The name of the item on which the cursor was placed automatically after the previous batch
+    // of entries. If the cursor is on some other item, the user has moved it, and it's kept on
+    // that item instead.
+    cursor_placed_on: Option<String>,
+    // The state to go back to if the folder can't be opened
+    previous_path: PathBuf,
+    previous_buf: LsBufType,
+    previous_listing: Vec<CustomDirEntry>,
+    previous_cursor_pos: usize,
+    previous_scroll_pos: usize,
+    // Whether all of the previous folder had been read
+    previous_complete: bool,
+    // If the current folder is being read again, the paths of the items in it before that, for
+    // finding out what changed
+    refreshed_from: Option<HashSet<PathBuf>>,
+    // When refreshing, the name of the item that was under the cursor and the row it was on, so
+    // that the cursor can be put back on it, or on the same row if the item is gone
+    keep_cursor_on: Option<(Option<String>, usize)>,
+    // When reading the folder was started, for logging how long it took
+    started: Instant,
+}
+
+/// The state of a tab that is not the current one, see `TereAppState::new_tab`. The marks, the
+/// history and the settings are shared by all tabs.
+struct Tab {
+    current_path: PathBuf,
+    ls_output_buf: LsBufType,
+    listing: Vec<CustomDirEntry>,
+    cursor_pos: usize,
+    scroll_pos: usize,
+    search_string: String,
+    pending_dir: Option<PendingDir>,
+    changed_items: HashMap<PathBuf, ItemChange>,
+}
+
+/// How an item changed when the listing of its folder was last refreshed, see
+/// `TereAppState::item_change`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ItemChange {
+    /// The item wasn't in the listing before it was refreshed
+    New,
+    /// The item was modified recently, see `TereSettings::highlight_changes`
+    Modified,
+}
+
+/// A folder to start in that was given on the command line, instead of the current folder
+pub struct StartDir {
+    pub path: PathBuf,
+    /// Where the contents of folders are read from, or `None` for the local file system
+    pub source: Option<Arc<dyn DirSource>>,
+    /// The item to put the cursor on, if a file was given instead of a folder
+    pub entry: Option<OsString>,
+}
+
+impl StartDir {
+    /// Open the folder given on the command line, if there is one. A local folder is made the
+    /// working directory. If a local file is given, its folder is opened with the cursor on it.
+    /// For an SSH URL, this connects to the remote machine, which should be done before the
+    /// terminal is set up for the UI, because ssh may ask for a password.
+    pub fn from_cli_args(cli_args: &ArgMatches) -> IOResult<Option<Self>> {
+        let path = match cli_args.value_of_os("path") {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+        if let Some(url) = path.to_str().and_then(SshUrl::parse) {
+            let (source, path) = SftpDirSource::connect(&url)?;
+            return Ok(Some(Self {
+                path,
+                source: Some(Arc::new(source)),
+                entry: None,
+            }));
+        }
+
+        let path = normalize_path(&current_dir()?.join(path));
+        // a folder inside an archive can't be the working directory, so it's checked when the
+        // folder is read instead
+        let archives = cli_args.value_of("archives").is_some_and(|mode| mode != "off");
+        if archives && archive::split_archive_path(&path).is_some() {
+            return Ok(Some(Self { path, source: None, entry: None }));
+        }
+        let (path, entry) = split_start_file(path);
+        std::env::set_current_dir(&path).map_err(|e| {
+            IOError::new(e.kind(), format!("Could not open '{}': {}", path.display(), e))
+        })?;
+        Ok(Some(Self { path, source: None, entry }))
+    }
+}
+
+/// Split `path` into its folder and file name if it's a file, so that the folder can be opened
+/// with the cursor on the file. Otherwise, `path` is returned as is.
+fn split_start_file(path: PathBuf) -> (PathBuf, Option<OsString>) {
+    if path.is_file() {
+        if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+            return (parent.to_path_buf(), Some(name.to_os_string()));
+        }
+    }
+    (path, None)
+}
+
+/// Whether following the symlink `path` leads back to a symlink that was already followed, or
+/// goes on for too long, like the operating system decides when opening it.
+fn is_symlink_cycle(path: &Path) -> bool {
+    let mut seen = HashSet::new();
+    let mut current = path.to_path_buf();
+    // the same limit as on Linux
+    for _ in 0..40 {
+        let target = match current.read_link() {
+            Ok(target) => target,
+            Err(_) => return false,
+        };
+        if !seen.insert(current.clone()) {
+            return true;
+        }
+        let dir = current.parent().unwrap_or(Path::new("/"));
+        current = normalize_path(&dir.join(target));
+    }
+    true
+}
+
+/// If `path` is a symlink to `cwd` or to one of its parents, the logical path of that folder.
+/// Following such a symlink again and again would make the path longer every time, without
+/// ever reaching anything new.
+fn symlink_loop_ancestor(cwd: &Path, path: &Path) -> Option<PathBuf> {
+    if !path.is_symlink() {
+        return None;
+    }
+    let target = path.canonicalize().ok()?;
+    cwd.ancestors()
+        .find(|dir| dir.canonicalize().is_ok_and(|dir| dir == target))
+        .map(Path::to_path_buf)
+}
+
+/// Check that `name` can be used as the name of a new item in a folder, so that it doesn't refer
+/// to the folder itself, its parent, or an item in some other folder.
+pub fn check_item_name(name: &str) -> IOResult<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains(std::path::is_separator) {
+        return Err(IOError::new(
+            ErrorKind::InvalidInput,
+            format!("invalid name '{}'", name),
+        ));
+    }
+    Ok(())
+}
+
+/// The current folder of the process. It's read from the PWD environment variable if possible,
+/// since it doesn't have symlinks resolved (which is what we want). If this fails for some reason
+/// (on windows?), default to std::env::current_dir, which has resolved symlinks.
+fn current_dir() -> IOResult<PathBuf> {
+    std::env::var("PWD")
+        .map(PathBuf::from)
+        .or_else(|_| std::env::current_dir())
+}
+
+/// Whether the folder `path` of `source` is on another machine or on a network file system
+fn is_remote_dir(source: &dyn DirSource, path: &Path) -> bool {
+    source.host().is_some() || remote_fs::is_remote(path)
+}
+
+/// Run the hook command `template` for the folder `path`, see `Hooks`. The command is run in the
+/// background, unless `wait` is true. Errors are ignored, since there is no good place to show
+/// them, and a failing hook shouldn't prevent browsing.
+fn run_hook(template: &str, path: &Path, wait: bool) {
+    let mut cmd = shell_command(&expand_command_template(template, &[path.to_path_buf()]));
+    cmd.current_dir(path)
+        .stdin(std::process::Stdio::null())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null());
+    if let Ok(mut child) = cmd.spawn() {
+        if wait {
+            let _ = child.wait();
+        } else {
+            // wait in another thread, so that the finished process doesn't linger as a zombie
+            std::thread::spawn(move || child.wait());
+        }
+    }
+}
+
 /// This struct represents the state of the application.
 pub struct TereAppState {
     // Width and height of the main window. These values have to be updated by
@@ -161,7 +1011,15 @@ pub struct TereAppState {
     // including ".." (the parent folder).
     ls_output_buf: LsBufType,
 
-    //sort_mode: SortMode // TODO: sort by date etc
+    // All entries of the current folder, sorted by name with folders first, from which the ones
+    // in `ls_output_buf` are chosen and sorted according to the view preferences of the folder
+    listing: Vec<CustomDirEntry>,
+
+    // The view preferences of the folders in which they have been changed
+    view_prefs: ViewPrefsStore,
+
+    // The index of the active filter preset in `settings.filter_presets`, if any
+    filter_preset: Option<usize>,
 
     // Have to manually keep track of the logical absolute path of our app, see https://stackoverflow.com/a/70309860/5208725
     pub current_path: PathBuf,
@@ -182,40 +1040,193 @@ pub struct TereAppState {
     pub settings: TereSettings,
 
     history: HistoryTree,
+
+    // In file selection mode, this is the file that was chosen, which is printed instead of the
+    // current folder on exit.
+    pub chosen_file: Option<PathBuf>,
+
+    // Absolute paths of the marked items, in the order in which they were marked. If there are
+    // any, these are printed on exit instead of the current folder.
+    marked: Vec<PathBuf>,
+
+    // In stdin mode, this holds the items that were read from stdin, which are shown instead of
+    // the contents of the current folder.
+    list_items: Option<Vec<CustomDirEntry>>,
+
+    // The folder that is being read in the background, if any
+    pending_dir: Option<PendingDir>,
+
+    // Listings of recently visited folders
+    dir_cache: DirCache,
+
+    // Watches the current folder for changes, if enabled with `watch_current_dir`
+    watcher: Option<DirWatcher>,
+
+    // Whether the file system of the current folder ignores case in names, for the auto case
+    // sensitivity mode. `None` if it's not known.
+    dir_ignores_case: Option<bool>,
+
+    // Whether the current folder is on a network file system
+    dir_is_remote: bool,
+
+    // The space on the file system of the current folder, if it's shown, see
+    // `TereSettings::show_disk_space`
+    disk_space: Option<DiskSpace>,
+
+    // The type of the file system of the current folder, if it's shown, see
+    // `TereSettings::show_fs_type`
+    dir_fs_type: Option<String>,
+
+    // Where the contents of folders are read from
+    source: Arc<dyn DirSource>,
+
+    // The folder that the on_enter_dir hook was last run for, which is the folder that the
+    // on_leave_dir hook is run for when it's left
+    hooked_dir: Option<PathBuf>,
+
+    // The tabs other than the current one, in order. The current tab comes after the first
+    // `current_tab` of them, and its state is in the fields above.
+    tabs: Vec<Tab>,
+    current_tab: usize,
+
+    // The items that were new or recently modified when the listing of the current folder was
+    // last refreshed
+    changed_items: HashMap<PathBuf, ItemChange>,
+
+    // If the last folder change followed a symlink that leads back to the current folder or one
+    // of its parents, the folder that was opened instead, see `take_symlink_loop`
+    symlink_loop: Option<PathBuf>,
 }
 
 impl TereAppState {
+    /// Create the app state from the command line arguments, starting from `start` if a folder
+    /// was given, otherwise from the current folder.
     pub fn init(
         cli_args: &ArgMatches,
+        start: Option<StartDir>,
         window_w: usize,
         window_h: usize,
     ) -> Result<Self, TereError> {
-        // Try to read the current folder from the PWD environment variable, since it doesn't
-        // have symlinks resolved (which is what we want). If this fails for some reason (on
-        // windows?), default to std::env::current_dir, which has resolved symlinks.
-        let cwd = std::env::var("PWD")
-            .map(PathBuf::from)
-            .or_else(|_| std::env::current_dir())?;
-        let mut ret = Self {
-            main_win_w: window_w,
-            main_win_h: window_h,
-            ls_output_buf: vec![].into(),
-            current_path: cwd.clone(),
-            cursor_pos: 0,
-            scroll_pos: 0,
-            header_msg: "".into(),
-            info_msg: "".into(),
-            search_string: "".into(),
-            settings: TereSettings::parse_cli_args(cli_args)?,
-            history: HistoryTree::from_abs_path(cwd.clone()),
+        let settings = TereSettings::parse_cli_args(cli_args)?;
+        profile::mark("parse settings");
+        // the --start-entry option takes precedence over a file given as the starting folder
+        let entry = cli_args
+            .value_of_os("start-entry")
+            .map(OsStr::to_os_string)
+            .or_else(|| start.as_ref().and_then(|start| start.entry.clone()));
+        let mut state = match start {
+            Some(StartDir {
+                path,
+                source: Some(source),
+                ..
+            }) => Self::with_dir_source(settings, path, source, window_w, window_h)?,
+            Some(StartDir { path, source: None, .. }) => Self::new(settings, path, window_w, window_h)?,
+            None => Self::new(settings, current_dir()?, window_w, window_h)?,
         };
-
+        if let Some(entry) = entry {
+            state.move_cursor_to_filename(entry);
+        }
+        Ok(state)
+    }
+
+    /// Create the app state with the given settings, starting from the folder `cwd`, which should
+    /// be an absolute path. This is used for embedding the app without the command line interface.
+    pub fn new(
+        settings: TereSettings,
+        cwd: PathBuf,
+        window_w: usize,
+        window_h: usize,
+    ) -> Result<Self, TereError> {
+        let source: Arc<dyn DirSource> = match settings.archive_mode {
+            ArchiveMode::Off => Arc::new(FsDirSource),
+            _ => Arc::new(ArchiveDirSource::new()),
+        };
+        Self::with_dir_source(settings, cwd, source, window_w, window_h)
+    }
+
+    /// Like `new`, but the contents of folders are read from `source` instead of the file system.
+    pub fn with_dir_source(
+        settings: TereSettings,
+        cwd: PathBuf,
+        source: Arc<dyn DirSource>,
+        window_w: usize,
+        window_h: usize,
+    ) -> Result<Self, TereError> {
+        let list_items = if settings.read_stdin {
+            let lines = std::io::stdin().lines().collect::<IOResult<Vec<String>>>()?;
+            Some(lines)
+        } else {
+            None
+        };
+        Self::with_list_items(settings, cwd, source, list_items, window_w, window_h)
+    }
+
+    /// Create the app state for choosing from `items` instead of the contents of a folder, like
+    /// when the items are read from stdin. `cwd` is the current folder, like in `new`.
+    pub fn from_items(
+        settings: TereSettings,
+        cwd: PathBuf,
+        items: Vec<String>,
+        window_w: usize,
+        window_h: usize,
+    ) -> Result<Self, TereError> {
+        Self::with_list_items(settings, cwd, Arc::new(FsDirSource), Some(items), window_w, window_h)
+    }
+
+    fn with_list_items(
+        mut settings: TereSettings,
+        cwd: PathBuf,
+        source: Arc<dyn DirSource>,
+        list_items: Option<Vec<String>>,
+        window_w: usize,
+        window_h: usize,
+    ) -> Result<Self, TereError> {
+        if source.host().is_some() {
+            // The history and the listing cache are for local folders, and aliases can't be
+            // resolved on other machines
+            settings.history_file = None;
+            settings.listing_cache_file = None;
+            settings.resolve_aliases = false;
+        }
+        let mut ret = Self {
+            main_win_w: window_w,
+            main_win_h: window_h,
+            ls_output_buf: vec![].into(),
+            listing: vec![],
+            view_prefs: ViewPrefsStore::default(),
+            filter_preset: None,
+            current_path: normalize_path(&cwd),
+            cursor_pos: 0,
+            scroll_pos: 0,
+            header_msg: "".into(),
+            info_msg: "".into(),
+            search_string: "".into(),
+            settings,
+            history: HistoryTree::from_abs_path(normalize_path(&cwd)),
+            chosen_file: None,
+            marked: vec![],
+            list_items: None,
+            pending_dir: None,
+            dir_cache: DirCache::default(),
+            watcher: None,
+            dir_ignores_case: None,
+            dir_is_remote: is_remote_dir(source.as_ref(), &cwd),
+            disk_space: None,
+            dir_fs_type: None,
+            source,
+            hooked_dir: None,
+            tabs: vec![],
+            current_tab: 0,
+            changed_items: HashMap::new(),
+            symlink_loop: None,
+        };
+
         //read history tree from file, if applicable
         if let Some(hist_file) = &ret.settings.history_file {
             match std::fs::read_to_string(hist_file) {
                 Ok(file_contents) => {
                     let mut tree: HistoryTree = serde_json::from_str(&file_contents)?;
-                    tree.change_dir(cwd);
+                    tree.change_dir(&ret.current_path);
                     ret.history = tree;
                 }
                 Err(ref e) if e.kind() == ErrorKind::NotFound => {
@@ -223,258 +1234,1348 @@ impl TereAppState {
                 }
                 Err(e) => return Err(e.into()),
             }
+            // the view preferences are just a convenience, so it doesn't matter if they can't
+            // be read
+            if let Ok(prefs) = ViewPrefsStore::load(&ViewPrefsStore::file_for_history_file(hist_file)) {
+                ret.view_prefs = prefs;
+            }
+            profile::mark("load history");
+        }
+
+        if let Some(cache_file) = &ret.settings.listing_cache_file {
+            // the cache is just an optimization, so it doesn't matter if it can't be read
+            if let Ok(cache) = DirCache::load(cache_file) {
+                ret.dir_cache = cache;
+            }
+            profile::mark("load listing cache");
+        }
+
+        if let Some(lines) = list_items {
+            // reading the metadata can be slow for long lists, so do it in parallel
+            let items = lines
+                .par_iter()
+                .filter(|line| !line.is_empty())
+                .map(|line| CustomDirEntry::from_list_item(line))
+                .collect();
+            ret.list_items = Some(items);
+        }
+
+        ret.update_disk_space();
+        ret.update_fs_type();
+        ret.update_header();
+        if ret.list_items.is_some() {
+            ret.update_ls_output_buf()?;
+        } else {
+            // the cache is empty, unless listings were loaded from the listing cache file
+            let entries = ret.read_dir_cached(&ret.current_path.clone(), true)?;
+            ret.set_ls_output_buf(entries);
         }
+        profile::mark(if ret.is_list_mode() { "prepare items" } else { "read first folder" });
+
+        if ret.is_list_mode() {
+            // there is no '..' entry, so start from the first item
+            return Ok(ret);
+        }
+
+        ret.move_cursor(1, false); // start out from second entry, because first entry is '..'.
+        if let Some(prev_dir) = ret.history.current_entry().last_visited_child_label() {
+            ret.move_cursor_to_filename(prev_dir);
+        }
+
+        Ok(ret)
+    }
+
+    /// Things to do when the app is about to exit.
+    pub fn on_exit(&self) -> IOResult<()> {
+        if let Some(hist_file) = &self.settings.history_file {
+            let parent_dir = hist_file.parent().ok_or_else(|| {
+                IOError::new(ErrorKind::NotFound, "history file has no parent folder")
+            })?;
+            std::fs::DirBuilder::new()
+                .recursive(true)
+                .create(parent_dir)?;
+            std::fs::write(hist_file, serde_json::to_string(&self.history)?)?;
+            self.view_prefs.save(&ViewPrefsStore::file_for_history_file(hist_file))?;
+        }
+        if let Some(cache_file) = &self.settings.listing_cache_file {
+            self.dir_cache.save(cache_file)?;
+        }
+        if let Some(hook) = &self.settings.hooks.on_exit {
+            if !self.is_list_mode() && !self.is_virtual_dir() {
+                run_hook(hook, &self.current_path, true);
+            }
+        }
+        Ok(())
+    }
+
+    /// With `ArchiveMode::Extract`, extract the items inside archives that are printed on exit,
+    /// which are the marked items, the chosen file or the current folder, and print the
+    /// extracted paths instead.
+    pub fn extract_archived_items(&mut self) -> IOResult<()> {
+        if self.settings.archive_mode != ArchiveMode::Extract || self.remote_host().is_some() {
+            return Ok(());
+        }
+        let extract = |path: &mut PathBuf| -> IOResult<()> {
+            if archive::split_archive_path(path).is_some() {
+                *path = archive::extract(path)?;
+            }
+            Ok(())
+        };
+        if !self.marked.is_empty() {
+            self.marked.iter_mut().try_for_each(extract)
+        } else if let Some(path) = self.chosen_file.as_mut() {
+            extract(path)
+        } else {
+            extract(&mut self.current_path)
+        }
+    }
+
+    ///////////////////////////////////////////
+    // Helpers for reading the current state //
+    ///////////////////////////////////////////
+
+    /// Whether the contents of the current folder are still being read in the background.
+    pub fn is_loading(&self) -> bool {
+        self.pending_dir.is_some()
+    }
+
+    /// The number of items read so far from the folder that is being read in the background, if
+    /// any. Some of them may not be in the listing yet.
+    pub fn num_loaded_items(&self) -> Option<usize> {
+        self.pending_dir.as_ref().map(|pending| pending.reader.num_read())
+    }
+
+    pub fn is_searching(&self) -> bool {
+        !self.search_string.is_empty()
+    }
+
+    pub fn search_string(&self) -> &String {
+        &self.search_string
+    }
+
+    /// The total number of items in the ls_output_buf.
+    pub fn num_total_items(&self) -> usize {
+        self.ls_output_buf.all_items.len()
+    }
+
+    /// The number of items that match the current search.
+    pub fn num_matching_items(&self) -> usize {
+        self.ls_output_buf.kept.len()
+    }
+
+    /// The number of folders and files in the listing, not counting '..'. While searching, only
+    /// the items that match the search are counted.
+    pub fn num_dirs_and_files(&self) -> (usize, usize) {
+        let buf = &self.ls_output_buf;
+        let items: Box<dyn Iterator<Item = &CustomDirEntry>> = if self.is_searching() {
+            Box::new(buf.kept.iter().filter_map(|idx| buf.all_items.get(*idx)))
+        } else {
+            Box::new(buf.all_items.iter())
+        };
+        items
+            .filter(|item| item.file_name() != "..")
+            .fold((0, 0), |(dirs, files), item| {
+                if item.is_dir() {
+                    (dirs + 1, files)
+                } else {
+                    (dirs, files + 1)
+                }
+            })
+    }
+
+    /// The number of items in the current folder that are not shown, because they are hidden
+    /// items or files that are hidden by the view preferences, or because they don't match the
+    /// search when `filter_search` is on.
+    pub fn num_hidden_items(&self) -> usize {
+        let items = &self.ls_output_buf.all_items;
+        let has_parent = items.first().is_some_and(|item| item.file_name() == "..");
+        let hidden_by_prefs = self.listing.len().saturating_sub(items.len() - usize::from(has_parent));
+        hidden_by_prefs + self.num_total_items() - self.num_visible_items()
+    }
+
+    /// Return a vector that contains the indices into the currently visible
+    /// items that contain a match
+    pub fn visible_match_indices(&self) -> Vec<usize> {
+        if self.is_searching() && self.settings.filter_search {
+            (0..self.ls_output_buf.kept.len()).collect()
+        } else {
+            // it's ok to clone here, the kept_indices will be usually quite short.
+            self.ls_output_buf.kept_indices()
+        }
+    }
+
+    /// All items that are visible with the current settings in the current search state. This
+    /// includes items that might fall outside the window.
+    pub fn visible_items(&self) -> Vec<&CustomDirEntry> {
+        if self.is_searching() && self.settings.filter_search {
+            self.ls_output_buf.kept_items()
+        } else {
+            self.ls_output_buf.all_items.iter().collect()
+        }
+    }
+
+    /// Shorthand to get the number of items without having to clone / iterate over all of them
+    pub fn num_visible_items(&self) -> usize {
+        if self.is_searching() && self.settings.filter_search {
+            self.num_matching_items()
+        } else {
+            self.num_total_items()
+        }
+    }
+
+    /// Convert a cursor position (in the range 0..window_height) to an index
+    /// into the currently visible items.
+    pub fn cursor_pos_to_visible_item_index(&self, cursor_pos: usize) -> usize {
+        cursor_pos + self.scroll_pos
+    }
+
+    /// Read the metadata and symlink targets of the items that are shown on the screen, in
+    /// parallel. Those of the other items are only read when they're needed.
+    pub fn load_visible_metadata(&self) {
+        if !self.shows_metadata() {
+            return;
+        }
+        let items: Vec<&CustomDirEntry> = (0..self.main_win_h)
+            .map_while(|row| self.get_item_at_cursor_pos(row))
+            .collect();
+        CustomDirEntry::load_metadata(&items);
+    }
+
+    /// The machine that the folders are on, like 'user@host', or `None` if they are on this
+    /// machine, see `DirSource::host`.
+    pub fn remote_host(&self) -> Option<&str> {
+        self.source.host()
+    }
+
+    /// The home folder of the user, on the machine that the folders are on
+    pub fn home_dir(&self) -> Option<PathBuf> {
+        self.source.home_dir()
+    }
+
+    /// Whether the current folder is not an actual folder on this machine, like a folder inside
+    /// an archive or on another machine, see `DirSource::is_virtual`.
+    pub fn is_virtual_dir(&self) -> bool {
+        self.source.is_virtual(&self.current_path)
+    }
+
+    /// Whether the metadata that is only needed for showing the items, like the targets of
+    /// symlinks, should be read. By default, it's not read in folders on network file systems.
+    pub fn shows_metadata(&self) -> bool {
+        self.settings.remote_metadata || !self.dir_is_remote
+    }
+
+    pub fn get_item_at_cursor_pos(&self, cursor_pos: usize) -> Option<&CustomDirEntry> {
+        self.visible_item(self.cursor_pos_to_visible_item_index(cursor_pos))
+    }
+
+    /// The item at index `idx` of the visible items, without collecting all of them like
+    /// `visible_items` does.
+    fn visible_item(&self, idx: usize) -> Option<&CustomDirEntry> {
+        if self.is_searching() && self.settings.filter_search {
+            self.ls_output_buf.nth_kept_item(idx)
+        } else {
+            self.ls_output_buf.all_items.get(idx)
+        }
+    }
+
+    /// Returns None if the visible items is empty, or if the state is
+    /// inconsistent and the cursor is outside the currently visible items.
+    fn get_item_under_cursor(&self) -> Option<&CustomDirEntry> {
+        self.get_item_at_cursor_pos(self.cursor_pos)
+    }
+
+    /// Get the index of a filename into the currently visible items. Returns
+    /// None if it's not found.
+    fn index_of_filename<S: AsRef<OsStr>>(&self, fname: S) -> Option<usize> {
+        // compare the names as they are shown, so that names that aren't valid unicode can be
+        // given in either form
+        let fname = fname.as_ref().to_string_lossy();
+        let is_match = |x: &CustomDirEntry| x.file_name() == fname;
+        if self.is_searching() && self.settings.filter_search {
+            self.ls_output_buf
+                .kept
+                .iter()
+                .position(|idx| is_match(&self.ls_output_buf.all_items[*idx]))
+        } else {
+            self.ls_output_buf.all_items.iter().position(is_match)
+        }
+    }
+
+    /// Whether the search should be case sensitive, based on the case sensitivity mode and the
+    /// current search string.
+    fn is_case_sensitive(&self) -> bool {
+        match self.settings.case_sensitive {
+            CaseSensitiveMode::IgnoreCase => false,
+            CaseSensitiveMode::CaseSensitive => true,
+            CaseSensitiveMode::SmartCase => self.search_string.chars().any(|c| c.is_uppercase()),
+            CaseSensitiveMode::Auto if self.dir_ignores_case() => false,
+            CaseSensitiveMode::Auto => self.search_string.chars().any(|c| c.is_uppercase()),
+        }
+    }
+
+    /// Whether the file system of the current folder ignores case in names, which decides the
+    /// case sensitivity in the auto mode.
+    fn dir_ignores_case(&self) -> bool {
+        self.dir_ignores_case.unwrap_or(FS_IGNORES_CASE_BY_DEFAULT)
+    }
+
+    /// The case sensitivity mode that is in use. In the auto mode, this is either ignore case or
+    /// smart case, depending on the file system of the current folder.
+    pub fn case_sensitive_mode(&self) -> &CaseSensitiveMode {
+        match self.settings.case_sensitive {
+            CaseSensitiveMode::Auto if self.dir_ignores_case() => &CaseSensitiveMode::IgnoreCase,
+            CaseSensitiveMode::Auto => &CaseSensitiveMode::SmartCase,
+            ref mode => mode,
+        }
+    }
+
+    /// Find out whether the file system of the current folder ignores case, if it's needed for
+    /// the auto case sensitivity mode and it's not known yet.
+    fn detect_dir_case(&mut self) {
+        if matches!(self.settings.case_sensitive, CaseSensitiveMode::Auto)
+            && self.dir_ignores_case.is_none()
+            && !self.is_list_mode()
+            && self.source.host().is_none()
+        {
+            self.dir_ignores_case = ignores_case(&self.ls_output_buf.all_items, &self.current_path);
+        }
+    }
+
+    /// Check whether we should automatically change to the folder matching the current search,
+    /// based on the autocd mode and timeout. This is only possible if there is exactly one match.
+    pub fn should_autocd(&self) -> bool {
+        if self.num_matching_items() != 1 || self.settings.autocd_timeout.is_none() || self.is_loading() {
+            return false;
+        }
+
+        // ok to unwrap, we just checked that there's exactly one match
+        let item = &self.ls_output_buf.all_items[self.ls_output_buf.kept[0]];
+        if self.is_list_mode() || (self.settings.choose_file && !item.is_dir()) {
+            // Choosing an item exits the app, so don't do it automatically
+            return false;
+        }
+        match self.settings.autocd_mode {
+            AutoCdMode::Always => true,
+            AutoCdMode::Never | AutoCdMode::RequireEnter => false,
+            AutoCdMode::DirsOnly => item.is_dir(),
+            AutoCdMode::ExactPrefix => {
+                if self.is_case_sensitive() {
+                    item.file_name().starts_with(&self.search_string)
+                } else {
+                    item.lowercase_file_name()
+                        .starts_with(&self.search_string.to_lowercase())
+                }
+            }
+        }
+    }
+
+    /// The match spans of the item at `cursor_pos`, see `MatchSpansType`. `None` if the item
+    /// doesn't match the current search.
+    pub fn get_match_spans_at_cursor_pos(&self, cursor_pos: usize) -> Option<&MatchSpansType> {
+        let idx = self.cursor_pos_to_visible_item_index(cursor_pos);
+        if self.settings.filter_search {
+            self.ls_output_buf.nth_match(idx).map(|m| &m.spans)
+        } else {
+            self.ls_output_buf.match_spans(idx)
+        }
+    }
+
+    pub fn get_match_locations_at_cursor_pos(&self, cursor_pos: usize) -> Option<&MatchesLocType> {
+        let idx = self.cursor_pos_to_visible_item_index(cursor_pos);
+        if self.settings.filter_search {
+            self.ls_output_buf.nth_match(idx).map(|m| &m.locations)
+        } else {
+            self.ls_output_buf.match_locations(idx)
+        }
+    }
+
+    //////////////////////////////////////
+    // Functions for updating the state //
+    //////////////////////////////////////
+
+    pub fn update_header(&mut self) {
+        self.header_msg = if self.is_list_mode() {
+            "Choose an item (read from stdin)".to_string()
+        } else {
+            match self.source.host() {
+                Some(host) => format!("{}:{}", host, self.current_path.display()),
+                None => format!("{}", self.current_path.display()),
+            }
+        };
+        if let Some(fs_type) = &self.dir_fs_type {
+            self.header_msg.push_str(&format!(" ({})", fs_type));
+        }
+        if !self.tabs.is_empty() {
+            self.header_msg.push_str(&format!(" [{}/{}]", self.current_tab + 1, self.num_tabs()));
+        }
+    }
+
+    pub fn update_main_window_dimensions(&mut self, w: usize, h: usize) {
+        let delta_h = h.saturating_sub(self.main_win_h);
+        self.main_win_w = w;
+        self.main_win_h = h;
+        self.move_cursor(0, false); // make sure that cursor is within view
+        if delta_h > 0 {
+            // height is increasing, scroll backwards as much as possible
+            let old_scroll_pos = self.scroll_pos;
+            self.scroll_pos = self.scroll_pos.saturating_sub(delta_h);
+            self.cursor_pos += old_scroll_pos - self.scroll_pos;
+        }
+    }
+
+    pub fn update_ls_output_buf(&mut self) -> IOResult<()> {
+        if let Some(items) = &self.list_items {
+            // keep the items in the order in which they were given
+            self.ls_output_buf = items.clone().into();
+            return Ok(());
+        }
+
+        let entries = self.read_dir_cached(&self.current_path.clone(), false)?;
+        self.set_ls_output_buf(entries);
+        Ok(())
+    }
+
+    /// Read the sorted entries of the folder `path`, or get them from the cache if `use_cache` is
+    /// true and the folder hasn't changed since it was cached.
+    fn read_dir_cached(&mut self, path: &Path, use_cache: bool) -> IOResult<Vec<CustomDirEntry>> {
+        if use_cache {
+            if let Some(entries) = self.dir_cache.get(self.source.as_ref(), path) {
+                return Ok(entries);
+            }
+        }
+        let mtime = self.source.modified(path);
+        let entries = read_dir_sorted(self.source.as_ref(), path)?;
+        if let Some(mtime) = mtime {
+            self.dir_cache.insert(path.to_path_buf(), mtime, entries.clone());
+        }
+        Ok(entries)
+    }
+
+    /// Forget the cached listings, so that all folders are read again. The listings are cached
+    /// only as long as the modification time of the folder doesn't change, but it's not updated
+    /// in all cases, and its resolution is low on some file systems.
+    pub fn clear_dir_cache(&mut self) {
+        self.dir_cache.clear();
+    }
+
+    /// Show these items in the main window according to the view preferences of the current
+    /// folder, after the parent folder item '..'. The items should be sorted by name with folders
+    /// first, like the ones from `read_dir_sorted`.
+    fn set_ls_output_buf(&mut self, entries: Vec<CustomDirEntry>) {
+        self.listing = entries;
+        let mut shown = self.view_prefs().apply(&self.listing);
+        if let Some(preset) = self.filter_preset() {
+            shown.retain(|item| preset.shows(item));
+        }
+        // Add the parent directory entry after sorting to make sure it's always first
+        shown.insert(
+            0,
+            CustomDirEntry::from(std::path::Path::new(&std::path::Component::ParentDir))
+        );
+
+        self.ls_output_buf = shown.into();
+        self.detect_dir_case();
+    }
+
+    /// The view preferences that are used in folders in which they haven't been changed
+    fn default_view_prefs(&self) -> ViewPrefs {
+        ViewPrefs {
+            sort_mode: self.settings.sort_mode,
+            folders_only: self.settings.folders_only,
+            ..ViewPrefs::default()
+        }
+    }
+
+    /// How the contents of the current folder are shown
+    pub fn view_prefs(&self) -> ViewPrefs {
+        self.view_prefs.get(&self.current_path, self.default_view_prefs())
+    }
+
+    /// Change how the contents of the current folder are shown, and remember it for the next
+    /// time that the folder is entered. The cursor stays on the same item if it's still shown,
+    /// and the search is kept.
+    pub fn set_view_prefs(&mut self, prefs: ViewPrefs) -> IOResult<()> {
+        if self.is_list_mode() {
+            return Err(IOError::other(
+                "Can't change the view when choosing from a list read from stdin",
+            ));
+        }
+        let default = self.default_view_prefs();
+        self.view_prefs.set(&self.current_path, prefs, default);
+        self.reapply_view();
+        Ok(())
+    }
+
+    /// Choose the shown items from the listing again after the view has changed. The cursor stays
+    /// on the same item if it's still shown, and the search is kept.
+    fn reapply_view(&mut self) {
+        let item_under_cursor = self.get_item_under_cursor().map(|item| item.file_name_checked());
+        let listing = std::mem::take(&mut self.listing);
+        self.set_ls_output_buf(listing);
+        self.update_search_matches();
+        if !item_under_cursor.is_some_and(|name| self.move_cursor_to_filename(name)) {
+            self.move_cursor(0, false);
+        }
+    }
+
+    /// The active filter preset, if any
+    pub fn filter_preset(&self) -> Option<&FilterPreset> {
+        self.filter_preset.and_then(|idx| self.settings.filter_presets.get(idx))
+    }
+
+    /// Switch to the next filter preset, or to none after the last one. The preset is used in
+    /// all folders until it's changed.
+    pub fn cycle_filter_preset(&mut self) -> IOResult<()> {
+        if self.settings.filter_presets.is_empty() {
+            return Err(IOError::other(
+                "No filter presets have been defined, see the --filter-preset option",
+            ));
+        }
+        let next = match self.filter_preset {
+            None => Some(0),
+            Some(idx) if idx + 1 < self.settings.filter_presets.len() => Some(idx + 1),
+            Some(_) => None,
+        };
+        self.set_filter_preset(next)
+    }
+
+    /// Use the filter preset with index `preset` in `settings.filter_presets`, or no filter.
+    pub fn set_filter_preset(&mut self, preset: Option<usize>) -> IOResult<()> {
+        if self.is_list_mode() {
+            return Err(IOError::other(
+                "Can't change the view when choosing from a list read from stdin",
+            ));
+        }
+        if preset.is_some_and(|idx| idx >= self.settings.filter_presets.len()) {
+            return Err(IOError::other("No such filter preset"));
+        }
+        self.filter_preset = preset;
+        self.reapply_view();
+        Ok(())
+    }
+
+    /// Switch the current folder to the next sort mode.
+    pub fn cycle_sort_mode(&mut self) -> IOResult<()> {
+        let prefs = self.view_prefs();
+        self.set_view_prefs(ViewPrefs { sort_mode: prefs.sort_mode.next(), ..prefs })
+    }
+
+    /// Sort the current folder in the opposite order.
+    pub fn toggle_reverse_sort(&mut self) -> IOResult<()> {
+        let prefs = self.view_prefs();
+        self.set_view_prefs(ViewPrefs { reverse_sort: !prefs.reverse_sort, ..prefs })
+    }
+
+    /// Show the hidden items in the current folder if they are hidden, and hide them otherwise.
+    pub fn toggle_show_hidden(&mut self) -> IOResult<()> {
+        let prefs = self.view_prefs();
+        self.set_view_prefs(ViewPrefs { show_hidden: !prefs.show_hidden, ..prefs })
+    }
+
+    /// Show only the folders in the current folder, or folders and files if only folders are
+    /// shown.
+    pub fn toggle_folders_only(&mut self) -> IOResult<()> {
+        let prefs = self.view_prefs();
+        self.set_view_prefs(ViewPrefs { folders_only: !prefs.folders_only, ..prefs })
+    }
+
+    /// The absolute path corresponding to `path`, which can be relative to the current folder. An
+    /// empty path means the item under the cursor.
+    fn resolve_path(&self, path: &str) -> PathBuf {
+        // TODO: add option to use xdg-open (or similar) on files?
+        // check out https://crates.io/crates/open
+        // (or https://docs.rs/opener/0.4.1/opener/)
+        let target_path = if path.is_empty() {
+            //TODO: error here if result is empty?
+            self.get_item_under_cursor()
+                .map_or_else(OsString::new, |s| s.os_file_name().to_owned())
+        } else {
+            self.exact_file_name(path)
+        };
+        if let Some(target) = self.alias_target_of(&target_path) {
+            return normalize_path(&target);
+        }
+        // joining an absolute path replaces the current path
+        normalize_path(&self.current_path.join(target_path))
+    }
+
+    /// If `path` is a symlink that leads back to the current folder or one of its parents, return
+    /// the logical path of that folder instead, so that the path doesn't grow every time the
+    /// loop is followed. The folder is remembered for `take_symlink_loop`.
+    fn avoid_symlink_loop(&mut self, path: PathBuf) -> PathBuf {
+        self.symlink_loop = None;
+        if !self.is_local_dir() {
+            return path;
+        }
+        match symlink_loop_ancestor(&self.current_path, &path) {
+            Some(dir) => {
+                self.symlink_loop = Some(dir.clone());
+                dir
+            }
+            None => path,
+        }
+    }
+
+    /// The folder that was opened instead of following a symlink loop on the last folder change,
+    /// if that happened, see `avoid_symlink_loop`.
+    pub fn take_symlink_loop(&mut self) -> Option<PathBuf> {
+        self.symlink_loop.take()
+    }
+
+    /// The target of the item called `name` in the current folder, if it's a macOS alias and
+    /// resolving aliases is enabled.
+    fn alias_target_of(&self, name: &OsStr) -> Option<PathBuf> {
+        if !self.settings.resolve_aliases {
+            return None;
+        }
+        self.ls_output_buf
+            .all_items
+            .iter()
+            .find(|item| item.os_file_name() == name)?
+            .alias_target()
+            .map(Path::to_path_buf)
+    }
+
+    /// The exact name of the item in the current folder that is shown as `name`, which is
+    /// different if the name is not valid unicode. If there's no such item, `name` is returned
+    /// as is, because it can also be a path. In stdin mode, the names are the paths as they were
+    /// given, so they are returned as is too.
+    fn exact_file_name(&self, name: &str) -> OsString {
+        if self.is_list_mode() {
+            return name.into();
+        }
+        self.ls_output_buf
+            .all_items
+            .iter()
+            .find(|item| item.file_name() == name)
+            .map_or_else(|| name.into(), |item| item.os_file_name().to_owned())
+    }
+
+    pub fn change_dir(&mut self, path: &str) -> IOResult<()> {
+        if self.is_list_mode() {
+            return Err(IOError::other(
+                "Can't change folder when choosing from a list read from stdin",
+            ));
+        }
+
+        let final_path = self.resolve_path(path);
+        let final_path = self.avoid_symlink_loop(final_path);
+        let entries = self.read_dir_cached(&final_path, true)?;
+        self.show_dir(final_path, entries)
+    }
+
+    /// Make `final_path` the current folder and show its entries, which have already been read.
+    fn show_dir(&mut self, final_path: PathBuf, entries: Vec<CustomDirEntry>) -> IOResult<()> {
+        let refreshed_from = (final_path == self.current_path && !self.is_loading())
+            .then(|| self.listing_paths());
+        self.enter_dir(final_path)?;
+        // this overrides any folder that was being read in the background
+        self.pending_dir = None;
+        self.clear_search();
+        self.set_ls_output_buf(entries);
+        self.update_changed_items(refreshed_from);
+        self.place_cursor_in_new_dir();
+        Ok(())
+    }
+
+    /// The paths of the items in the listing of the current folder
+    fn listing_paths(&self) -> HashSet<PathBuf> {
+        self.listing.iter().map(|e| e.path().clone()).collect()
+    }
+
+    /// Find out which items are new or were modified recently, after the listing of the current
+    /// folder has been refreshed. `previous` are the paths of the items before that, or `None`
+    /// if another folder was entered, in which case nothing has changed.
+    fn update_changed_items(&mut self, previous: Option<HashSet<PathBuf>>) {
+        self.changed_items.clear();
+        let (previous, max_age) = match (previous, self.settings.highlight_changes) {
+            (Some(previous), Some(max_age)) => (previous, max_age),
+            _ => return,
+        };
+        let recent = SystemTime::now().checked_sub(Duration::from_secs(max_age));
+        let check_mtime = max_age > 0 && self.shows_metadata();
+        if check_mtime {
+            CustomDirEntry::load_metadata(&self.listing.iter().collect::<Vec<_>>());
+        }
+        for entry in &self.listing {
+            let is_recent = || {
+                let mtime = entry.metadata().and_then(|m| m.modified().ok());
+                mtime.zip(recent).is_some_and(|(mtime, recent)| mtime >= recent)
+            };
+            let change = if !previous.contains(entry.path()) {
+                ItemChange::New
+            } else if check_mtime && is_recent() {
+                ItemChange::Modified
+            } else {
+                continue;
+            };
+            self.changed_items.insert(entry.path().clone(), change);
+        }
+    }
+
+    /// How `item` changed when the listing of the current folder was last refreshed, if it did.
+    pub fn item_change(&self, item: &CustomDirEntry) -> Option<ItemChange> {
+        self.changed_items.get(item.path()).copied()
+    }
+
+    /// Like `change_dir`, but the contents of the folder are read in a background thread, so that
+    /// this returns immediately. The current path is updated right away, and the entries are
+    /// added to the listing in batches as they are read, when `poll_dir_loading` is called. A
+    /// cached listing of the folder is used instead if it's still up to date. If this is called
+    /// again before everything has been read, the previous read is cancelled.
+    pub fn begin_change_dir(&mut self, path: &str) -> IOResult<()> {
+        if self.is_list_mode() {
+            return Err(IOError::other(
+                "Can't change folder when choosing from a list read from stdin",
+            ));
+        }
+
+        let final_path = self.resolve_path(path);
+        let final_path = self.avoid_symlink_loop(final_path);
+        let refreshed_from = (final_path == self.current_path && !self.is_loading())
+            .then(|| self.listing_paths());
+        self.changed_items.clear();
+        // even checking if the cached listing is up to date can hang, so that's done in the
+        // background too
+        let reader = DirReader::start(
+            self.source.clone(),
+            final_path.clone(),
+            self.dir_cache.get_unchecked(&final_path),
+        );
+        self.clear_search();
+
+        let pending = match self.pending_dir.take() {
+            // the folder that was being opened was never shown, so keep the state from before it
+            Some(pending) if !pending.opened => PendingDir {
+                reader,
+                refreshed_from,
+                keep_cursor_on: None,
+                started: Instant::now(),
+                ..pending
+            },
+            previous => PendingDir {
+                reader,
+                opened: false,
+                cursor_placed_on: Some("..".into()),
+                previous_path: self.current_path.clone(),
+                previous_buf: std::mem::replace(&mut self.ls_output_buf, vec![].into()),
+                previous_listing: std::mem::take(&mut self.listing),
+                previous_cursor_pos: self.cursor_pos,
+                previous_scroll_pos: self.scroll_pos,
+                previous_complete: previous.is_none(),
+                refreshed_from,
+                keep_cursor_on: None,
+                started: Instant::now(),
+            },
+        };
+        self.pending_dir = Some(pending);
+        self.current_path = final_path;
+        self.set_ls_output_buf(vec![]);
+        self.cursor_pos = 0;
+        self.scroll_pos = 0;
+        Ok(())
+    }
+
+    /// Read the current folder again in the background, like `begin_change_dir`, but without the
+    /// cache. The search is kept, and the cursor is put back on the same item once it has been
+    /// read, or on the same row if the item is gone.
+    pub fn begin_refresh(&mut self) -> IOResult<()> {
+        let item_under_cursor = self.get_item_under_cursor().map(|item| item.file_name_checked());
+        let row = self.cursor_pos + self.scroll_pos;
+        let search_string = std::mem::take(&mut self.search_string);
+        self.clear_dir_cache();
+        self.begin_change_dir(".")?;
+        self.search_string = search_string;
+        if let Some(pending) = &mut self.pending_dir {
+            pending.keep_cursor_on = Some((item_under_cursor, row));
+        }
+        Ok(())
+    }
+
+    /// Add the entries of the folder that is being read in the background (see
+    /// `begin_change_dir`) to the listing, waiting for at most `timeout` for them. Returns `None`
+    /// if nothing is being read, or if the listing didn't change. If the folder can't be opened,
+    /// the previous folder is shown again and the error is returned.
+    pub fn poll_dir_loading(&mut self, timeout: Duration) -> Option<IOResult<()>> {
+        let mut pending = self.pending_dir.take()?;
+        // the first item is always '..'
+        let num_shown = self.ls_output_buf.all_items.len().saturating_sub(1);
+
+        let (entries, done) = match pending.reader.update(timeout, num_shown) {
+            ReadUpdate::Waiting => {
+                self.pending_dir = Some(pending);
+                return None;
+            }
+            ReadUpdate::Entries(entries) => (entries, false),
+            ReadUpdate::Done(entries) => (entries, true),
+            ReadUpdate::Failed(e) => {
+                let path = pending.reader.path().display();
+                tracing::debug!(%path, error = ?e, "reading folder failed");
+                return Some(Err(self.restore_previous_dir(pending, e)));
+            }
+        };
+
+        if !pending.opened {
+            if let Err(e) = self.enter_dir(pending.reader.path().to_path_buf()) {
+                return Some(Err(self.restore_previous_dir(pending, e)));
+            }
+            pending.opened = true;
+        }
+
+        let item_under_cursor = self.get_item_under_cursor().map(|item| item.file_name_checked());
+        let cursor_moved = item_under_cursor != pending.cursor_placed_on;
+
+        let items = std::mem::take(&mut self.listing);
+        self.set_ls_output_buf(merge_sorted(items, entries));
+
+        if cursor_moved {
+            // keep the cursor on the same item, even if new items were added before it
+            if let Some(name) = item_under_cursor {
+                self.move_cursor_to_filename(name);
+            }
+        } else {
+            match &pending.keep_cursor_on {
+                Some((name, row)) => {
+                    if !name.as_ref().is_some_and(|name| self.move_cursor_to_filename(name)) {
+                        self.move_cursor_to(*row);
+                    }
+                }
+                None => self.place_cursor_in_new_dir(),
+            }
+            pending.cursor_placed_on =
+                self.get_item_under_cursor().map(|item| item.file_name_checked());
+        }
+
+        // the user may have started typing a search while the folder was being read
+        if self.is_searching() {
+            self.advance_search("");
+        }
+
+        if !done {
+            self.pending_dir = Some(pending);
+            return Some(Ok(()));
+        }
+        tracing::debug!(
+            path = %pending.reader.path().display(),
+            entries = self.listing.len(),
+            elapsed = ?pending.started.elapsed(),
+            "read folder",
+        );
+        if let Some(mtime) = pending.reader.mtime() {
+            let entries = self.listing.clone();
+            self.dir_cache.insert(pending.reader.path().to_path_buf(), mtime, entries);
+        }
+        self.update_changed_items(pending.refreshed_from);
+        Some(Ok(()))
+    }
+
+    /// Stop reading the folder that is being read in the background, and show the folder that
+    /// was shown before it again, with its cursor position. Returns false if nothing was being
+    /// read.
+    pub fn cancel_dir_loading(&mut self) -> IOResult<bool> {
+        let pending = match self.pending_dir.take() {
+            Some(pending) => pending,
+            None => return Ok(false),
+        };
+        let opened = pending.opened;
+        self.restore_previous_state(pending);
+        if opened {
+            // the new folder was already entered, so go back to the previous one
+            self.enter_dir(self.current_path.clone())?;
+        }
+        Ok(true)
+    }
+
+    /// Whether the folder that is being read in the background hasn't even been opened yet, so
+    /// nothing of it is shown.
+    pub fn is_opening_dir(&self) -> bool {
+        self.pending_dir.as_ref().is_some_and(|pending| !pending.opened)
+    }
+
+    /// Go back to the folder that was shown before `begin_change_dir`, because the new one
+    /// couldn't be opened. Returns the error that caused this.
+    fn restore_previous_dir(&mut self, pending: PendingDir, error: IOError) -> IOError {
+        self.restore_previous_state(pending);
+        error
+    }
+
+    fn restore_previous_state(&mut self, pending: PendingDir) {
+        self.current_path = pending.previous_path;
+        self.ls_output_buf = pending.previous_buf;
+        self.listing = pending.previous_listing;
+        self.cursor_pos = pending.previous_cursor_pos;
+        self.scroll_pos = pending.previous_scroll_pos;
+        // the previous search was cleared, so don't show its matches
+        self.update_search_matches();
+
+        if !pending.previous_complete {
+            // the previous folder was still being read, so start over
+            let _ = self.begin_change_dir(".");
+        }
+    }
+
+    /// Make `final_path` the current folder, and record it in the history.
+    fn enter_dir(&mut self, final_path: PathBuf) -> IOResult<()> {
+        self.source.enter_dir(&final_path)?;
+
+        // final_path is always the absolute logical path, so we can just cd to it. This causes a
+        // bit of extra work (the history tree has to go all the way from the root to the path
+        // every time), but that's not too bad. The alernative option of using history_tree.go_up()
+        // / visit() and handling the special cases where target_path is '..' or a relative path or
+        // so on, would be much more complicated and would risk having the history tree and logical
+        // path out of sync.
+        self.history.change_dir(&final_path);
+        if let Some(watcher) = &mut self.watcher {
+            watcher.watch(&final_path);
+        }
+        self.dir_is_remote = is_remote_dir(self.source.as_ref(), &final_path);
+        self.current_path = final_path;
+        self.update_disk_space();
+        self.update_fs_type();
+        self.dir_ignores_case = None;
+        self.run_dir_hooks();
+        Ok(())
+    }
+
+    /// Whether the current folder is a real folder on this machine, so that its file system can
+    /// be examined.
+    fn is_local_dir(&self) -> bool {
+        self.source.host().is_none() && !self.is_list_mode() && !self.is_virtual_dir()
+    }
 
-        ret.update_header();
-        ret.update_ls_output_buf()?;
+    /// Find out how much space there is on the file system of the current folder, if it's shown.
+    /// It can only be found out for folders on this machine.
+    fn update_disk_space(&mut self) {
+        self.disk_space = (self.settings.show_disk_space && self.is_local_dir())
+            .then(|| remote_fs::disk_space(&self.current_path))
+            .flatten();
+    }
 
-        ret.move_cursor(1, false); // start out from second entry, because first entry is '..'.
-        if let Some(prev_dir) = ret.history.current_entry().last_visited_child_label() {
-            ret.move_cursor_to_filename(prev_dir);
+    /// Find out the type of the file system of the current folder, if it's shown in the header.
+    fn update_fs_type(&mut self) {
+        self.dir_fs_type = (self.settings.show_fs_type && self.is_local_dir())
+            .then(|| remote_fs::fs_type(&self.current_path))
+            .flatten();
+    }
+
+    /// The space on the file system of the current folder, if it's shown and it could be found
+    /// out.
+    pub fn disk_space(&self) -> Option<DiskSpace> {
+        self.disk_space
+    }
+
+    /// Run the on_leave_dir hook for the folder that was left and the on_enter_dir hook for the
+    /// current folder, if the current folder has changed since they were last run. Hooks are not
+    /// run in folders that are not on this machine.
+    fn run_dir_hooks(&mut self) {
+        let new_dir = Some(&self.current_path)
+            .filter(|path| !self.is_list_mode() && !self.source.is_virtual(path));
+        if self.hooked_dir.as_ref() == new_dir {
+            return;
+        }
+        let hooks = &self.settings.hooks;
+        if let (Some(hook), Some(old_dir)) = (&hooks.on_leave_dir, &self.hooked_dir) {
+            run_hook(hook, old_dir, false);
         }
+        if let (Some(hook), Some(new_dir)) = (&hooks.on_enter_dir, new_dir) {
+            run_hook(hook, new_dir, false);
+        }
+        self.hooked_dir = new_dir.cloned();
+    }
 
-        Ok(ret)
+    /// Put the cursor on the folder that was visited last from the current folder, or on the
+    /// first item after '..' if there is no history.
+    fn place_cursor_in_new_dir(&mut self) {
+        self.cursor_pos = 0;
+        self.scroll_pos = 0;
+
+        // move cursor one position down, so we're not at '..' if we've entered a folder with no history
+        self.move_cursor(1, false);
+        if let Some(prev_dir) = self.history.current_entry().last_visited_child_label() {
+            self.move_cursor_to_filename(prev_dir);
+        }
     }
 
-    /// Things to do when the app is about to exit.
-    pub fn on_exit(&self) -> IOResult<()> {
-        if let Some(hist_file) = &self.settings.history_file {
-            let parent_dir = hist_file.parent().ok_or_else(|| {
-                IOError::new(ErrorKind::NotFound, "history file has no parent folder")
-            })?;
-            std::fs::DirBuilder::new()
-                .recursive(true)
-                .create(parent_dir)?;
-            std::fs::write(hist_file, serde_json::to_string(&self.history)?)?;
+    /// Create a new folder called `name` in the current folder, and move the cursor to it.
+    pub fn create_dir(&mut self, name: &str) -> IOResult<()> {
+        if self.is_list_mode() {
+            return Err(IOError::other(
+                "Can't create folders when choosing from a list read from stdin",
+            ));
         }
+        check_item_name(name)?;
+        std::fs::create_dir(self.current_path.join(name))?;
+
+        self.clear_search();
+        self.update_ls_output_buf()?;
+        self.move_cursor_to_filename(name);
         Ok(())
     }
 
-    ///////////////////////////////////////////
-    // Helpers for reading the current state //
-    ///////////////////////////////////////////
+    /// Create an empty file called `name` in the current folder, and move the cursor to it. If the
+    /// file already exists, only its modification time is updated, like with `touch`.
+    pub fn create_file(&mut self, name: &str) -> IOResult<()> {
+        if self.is_list_mode() {
+            return Err(IOError::other(
+                "Can't create files when choosing from a list read from stdin",
+            ));
+        }
+        check_item_name(name)?;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(self.current_path.join(name))?;
+        file.set_modified(std::time::SystemTime::now())?;
 
-    pub fn is_searching(&self) -> bool {
-        !self.search_string.is_empty()
+        self.clear_search();
+        self.update_ls_output_buf()?;
+        self.move_cursor_to_filename(name);
+        Ok(())
     }
 
-    pub fn search_string(&self) -> &String {
-        &self.search_string
-    }
+    /// Rename the item called `old_name` in the current folder to `new_name`, and move the cursor
+    /// to it. The new name must not contain path separators, and an existing item is never
+    /// overwritten.
+    pub fn rename_item(&mut self, old_name: &str, new_name: &str) -> IOResult<()> {
+        if self.is_list_mode() {
+            return Err(IOError::other(
+                "Can't rename items when choosing from a list read from stdin",
+            ));
+        }
+        check_item_name(new_name)?;
+
+        let old_path = self.current_path.join(self.exact_file_name(old_name));
+        let new_path = self.current_path.join(new_name);
+        // rename() would silently replace an existing file on unix, so check for collisions
+        // first. Use symlink_metadata so that broken symlinks are also detected.
+        if new_path.symlink_metadata().is_ok() {
+            return Err(IOError::new(
+                ErrorKind::AlreadyExists,
+                format!("'{}' already exists", new_name),
+            ));
+        }
+        std::fs::rename(&old_path, &new_path)?;
 
-    /// The total number of items in the ls_output_buf.
-    pub fn num_total_items(&self) -> usize {
-        self.ls_output_buf.all_items.len()
+        if let Some(marked) = self.marked.iter_mut().find(|p| **p == old_path) {
+            *marked = new_path;
+        }
+
+        self.clear_search();
+        self.update_ls_output_buf()?;
+        self.move_cursor_to_filename(new_name);
+        Ok(())
     }
 
-    /// The number of items that match the current search.
-    pub fn num_matching_items(&self) -> usize {
-        self.ls_output_buf.matches.len()
+    /// Update the listing after files have been changed by a file operation, for example when
+    /// items have been moved to the trash. Marks of items that no longer exist are removed, and
+    /// the cursor stays on the same row, or on the last item if it was below that.
+    pub fn refresh_after_file_op(&mut self) -> IOResult<()> {
+        self.update_disk_space();
+        self.marked.retain(|p| p.symlink_metadata().is_ok());
+        self.clear_search();
+        self.update_ls_output_buf()?;
+        self.move_cursor(0, false);
+        Ok(())
     }
 
-    /// Return a vector that contains the indices into the currently visible
-    /// items that contain a match
-    pub fn visible_match_indices(&self) -> Vec<usize> {
-        if self.is_searching() && self.settings.filter_search {
-            (0..self.ls_output_buf.matches.len()).collect()
-        } else {
-            // it's ok to clone here, the kept_indices will be usually quite short.
-            self.ls_output_buf.kept_indices()
+    /// Start watching the current folder (and every folder that is changed to after this) for
+    /// changes made by other programs, see `refresh_if_changed`. Does nothing in stdin mode, for
+    /// folders on other machines, or if watching is not supported.
+    pub fn watch_current_dir(&mut self) {
+        if self.is_list_mode() || self.watcher.is_some() || self.source.host().is_some() {
+            return;
+        }
+        if let Ok(mut watcher) = DirWatcher::new() {
+            watcher.watch(&self.current_path);
+            self.watcher = Some(watcher);
         }
     }
 
-    /// All items that are visible with the current settings in the current search state. This
-    /// includes items that might fall outside the window.
-    pub fn visible_items(&self) -> Vec<&CustomDirEntry> {
-        if self.is_searching() && self.settings.filter_search {
-            self.ls_output_buf.kept_items()
-        } else {
-            self.ls_output_buf.all_items.iter().collect()
-        }
+    pub fn is_watching(&self) -> bool {
+        self.watcher.is_some()
     }
 
-    /// Shorthand to get the number of items without having to clone / iterate over all of them
-    pub fn num_visible_items(&self) -> usize {
-        if self.is_searching() && self.settings.filter_search {
-            self.num_matching_items()
-        } else {
-            self.num_total_items()
+    /// Update the listing if entries have been created or removed in the current folder since the
+    /// last call. The cursor stays on the same item (or on the same row, if it was removed), and
+    /// the search is kept. Returns true if the listing was updated.
+    pub fn refresh_if_changed(&mut self) -> IOResult<bool> {
+        // a folder that is being read is up to date anyway, and the watcher is switched to it
+        // once it's opened
+        if self.is_loading() {
+            return Ok(false);
+        }
+        if !self.watcher.as_ref().is_some_and(|watcher| watcher.poll()) {
+            return Ok(false);
         }
+
+        self.reload_listing()?;
+        Ok(true)
     }
 
-    /// Convert a cursor position (in the range 0..window_height) to an index
-    /// into the currently visible items.
-    pub fn cursor_pos_to_visible_item_index(&self, cursor_pos: usize) -> usize {
-        (cursor_pos + self.scroll_pos) as usize
+    /// Read the current folder again without the cache, and wait until it has been read. The
+    /// cursor stays on the same item (or on the same row, if it was removed), and the search is
+    /// kept. See `begin_refresh` for reading it in the background.
+    pub fn refresh(&mut self) -> IOResult<()> {
+        self.clear_dir_cache();
+        self.reload_listing()
     }
 
-    pub fn get_item_at_cursor_pos(&self, cursor_pos: usize) -> Option<&CustomDirEntry> {
-        let idx = self.cursor_pos_to_visible_item_index(cursor_pos) as usize;
-        self.visible_items().get(idx).copied()
+    /// Update the listing of the current folder, keeping the place of the cursor and the search.
+    fn reload_listing(&mut self) -> IOResult<()> {
+        let item_under_cursor = self.get_item_under_cursor().map(|item| item.file_name_checked());
+        let previous = self.listing_paths();
+        self.marked.retain(|p| p.symlink_metadata().is_ok());
+        self.update_ls_output_buf()?;
+        self.update_changed_items(Some(previous));
+        self.update_search_matches();
+        if !item_under_cursor.is_some_and(|name| self.move_cursor_to_filename(name)) {
+            self.move_cursor(0, false);
+        }
+        Ok(())
     }
 
-    /// Returns None if the visible items is empty, or if the state is
-    /// inconsistent and the cursor is outside the currently visible items.
-    fn get_item_under_cursor(&self) -> Option<&CustomDirEntry> {
-        self.get_item_at_cursor_pos(self.cursor_pos)
+    /// The number of tabs, including the current one
+    pub fn num_tabs(&self) -> usize {
+        self.tabs.len() + 1
     }
 
-    /// Get the index of a filename into the currently visible items. Returns
-    /// None if it's not found.
-    fn index_of_filename<S: AsRef<OsStr>>(&self, fname: S) -> Option<usize> {
-        self.visible_items()
-            .iter()
-            .position(|x| AsRef::<OsStr>::as_ref(&x.file_name_checked()) == fname.as_ref())
+    /// The index of the current tab, starting from zero
+    pub fn current_tab(&self) -> usize {
+        self.current_tab
     }
 
-    pub fn get_match_locations_at_cursor_pos(&self, cursor_pos: usize) -> Option<&MatchesLocType> {
-        let idx = self.cursor_pos_to_visible_item_index(cursor_pos) as usize;
-        if self.settings.filter_search {
-            // NOTE: we assume that the matches is a sorted map
-            self.ls_output_buf.matches.values().nth(idx)
-        } else {
-            self.ls_output_buf.matches.get(&idx)
+    /// Open a new tab after the current one, and switch to it. The new tab shows the same folder,
+    /// with the cursor on the same item, but it's navigated independently of the other tabs.
+    pub fn new_tab(&mut self) -> IOResult<()> {
+        if self.is_list_mode() {
+            return Err(IOError::other(
+                "Can't open tabs when choosing from a list read from stdin",
+            ));
         }
+        let item_under_cursor = self.get_item_under_cursor().map(|item| item.file_name_checked());
+        let tab = Tab {
+            current_path: self.current_path.clone(),
+            ls_output_buf: vec![].into(),
+            listing: vec![],
+            cursor_pos: 0,
+            scroll_pos: 0,
+            search_string: String::new(),
+            pending_dir: None,
+            changed_items: HashMap::new(),
+        };
+        self.tabs.insert(self.current_tab, tab);
+        self.switch_to_tab(self.current_tab + 1)?;
+        match item_under_cursor {
+            Some(name) if self.move_cursor_to_filename(&name) => {}
+            _ => self.place_cursor_in_new_dir(),
+        }
+        Ok(())
     }
 
-    //////////////////////////////////////
-    // Functions for updating the state //
-    //////////////////////////////////////
+    /// Close the current tab, and switch to the next one, or to the previous one if this was the
+    /// last tab.
+    pub fn close_tab(&mut self) -> IOResult<()> {
+        if self.tabs.is_empty() {
+            return Err(IOError::other("Can't close the only tab"));
+        }
+        let idx = self.current_tab.min(self.tabs.len() - 1);
+        let tab = self.tabs.remove(idx);
+        self.current_tab = idx;
+        self.show_tab(tab)
+    }
 
-    pub fn update_header(&mut self) {
-        self.header_msg = format!("{}", self.current_path.display());
+    /// Switch to the next tab, or to the first one from the last tab.
+    pub fn next_tab(&mut self) -> IOResult<()> {
+        self.switch_to_tab((self.current_tab + 1) % self.num_tabs())
     }
 
-    pub fn update_main_window_dimensions(&mut self, w: usize, h: usize) {
-        let delta_h = h.saturating_sub(self.main_win_h);
-        self.main_win_w = w;
-        self.main_win_h = h;
-        self.move_cursor(0, false); // make sure that cursor is within view
-        if delta_h > 0 {
-            // height is increasing, scroll backwards as much as possible
-            let old_scroll_pos = self.scroll_pos;
-            self.scroll_pos = self.scroll_pos.saturating_sub(delta_h);
-            self.cursor_pos += old_scroll_pos - self.scroll_pos;
+    /// Switch to the previous tab, or to the last one from the first tab.
+    pub fn previous_tab(&mut self) -> IOResult<()> {
+        self.switch_to_tab((self.current_tab + self.num_tabs() - 1) % self.num_tabs())
+    }
+
+    /// Make the tab with the index `idx` the current one.
+    fn switch_to_tab(&mut self, idx: usize) -> IOResult<()> {
+        if idx == self.current_tab || idx >= self.num_tabs() {
+            return Ok(());
         }
+        let current = Tab {
+            current_path: self.current_path.clone(),
+            ls_output_buf: std::mem::replace(&mut self.ls_output_buf, vec![].into()),
+            listing: std::mem::take(&mut self.listing),
+            cursor_pos: self.cursor_pos,
+            scroll_pos: self.scroll_pos,
+            search_string: std::mem::take(&mut self.search_string),
+            pending_dir: self.pending_dir.take(),
+            changed_items: std::mem::take(&mut self.changed_items),
+        };
+        self.tabs.insert(self.current_tab, current);
+        let tab = self.tabs.remove(idx);
+        self.current_tab = idx;
+        self.show_tab(tab)
     }
 
-    pub fn update_ls_output_buf(&mut self) -> IOResult<()> {
-        let entries = std::fs::read_dir(std::path::Component::CurDir)?;
+    /// Show the state of `tab` as the current one. The folder of the tab is entered again, and
+    /// its listing is updated in case it changed while the tab wasn't shown.
+    fn show_tab(&mut self, tab: Tab) -> IOResult<()> {
+        self.current_path = tab.current_path;
+        self.ls_output_buf = tab.ls_output_buf;
+        self.listing = tab.listing;
+        self.cursor_pos = tab.cursor_pos;
+        self.scroll_pos = tab.scroll_pos;
+        self.search_string = tab.search_string;
+        self.pending_dir = tab.pending_dir;
+        self.changed_items = tab.changed_items;
+        self.update_header();
+        if self.is_opening_dir() {
+            // the folder is entered once it has been opened, see `poll_dir_loading`
+            return Ok(());
+        }
 
-        let mut entries: Box<dyn Iterator<Item = CustomDirEntry>> = Box::new(
-            //TODO: sort by date etc... - collect into vector of PathBuf's instead of strings (check out `Pathbuf::metadata()`)
-            entries.filter_map(|e| e.ok()).map(CustomDirEntry::from),
-        );
+        self.enter_dir(self.current_path.clone())?;
+        if self.is_loading() {
+            return Ok(());
+        }
+        let item_under_cursor = self.get_item_under_cursor().map(|item| item.file_name_checked());
+        let entries = self.read_dir_cached(&self.current_path.clone(), true)?;
+        self.set_ls_output_buf(entries);
+        self.update_search_matches();
+        if !item_under_cursor.is_some_and(|name| self.move_cursor_to_filename(name)) {
+            self.move_cursor(0, false);
+        }
+        Ok(())
+    }
 
-        if self.settings.folders_only {
-            entries = Box::new(entries.filter(|e| e.path().is_dir()));
+    /// Mark the item under the cursor, or unmark it if it's already marked. The parent folder
+    /// item '..' can't be marked. Returns false if nothing could be marked.
+    pub fn toggle_mark_under_cursor(&mut self) -> bool {
+        let path = match self.get_item_under_cursor() {
+            Some(item) if item.file_name() != ".." => self.entry_path(item),
+            _ => return false,
+        };
+        if let Some(idx) = self.marked.iter().position(|p| *p == path) {
+            self.marked.remove(idx);
+        } else {
+            self.marked.push(path);
         }
+        true
+    }
 
-        let mut new_output_buf: Vec<CustomDirEntry> = entries.collect();
+    pub fn clear_marks(&mut self) {
+        self.marked.clear();
+    }
 
-        new_output_buf.sort_by(|a, b| {
-            match (a.is_dir(), b.is_dir()) {
-                (true, true) | (false, false) => {
-                    // both are dirs or files, compare by name.
-                    // partial_cmp for strings always returns Some, so unwrap is ok here
-                    a.file_name_checked()
-                        .to_lowercase()
-                        .partial_cmp(&b.file_name_checked().to_lowercase())
-                        .unwrap()
-                }
-                // Otherwise, put folders first
-                (true, false) => std::cmp::Ordering::Less,
-                (false, true) => std::cmp::Ordering::Greater,
+    /// Move the cursor to the next marked item after the cursor, or to the previous one if `dir`
+    /// is negative, wrapping around at the ends of the listing. Returns false if none of the
+    /// visible items is marked.
+    pub fn move_cursor_to_adjacent_mark(&mut self, dir: isize) -> bool {
+        if self.marked.is_empty() {
+            return false;
+        }
+        let marked: Vec<usize> = self
+            .visible_items()
+            .iter()
+            .enumerate()
+            .filter(|(_, item)| self.is_marked(item))
+            .map(|(i, _)| i)
+            .collect();
+        let cur_idx = self.cursor_pos_to_visible_item_index(self.cursor_pos);
+        let target = if dir < 0 {
+            marked.iter().rev().find(|i| **i < cur_idx).or(marked.last())
+        } else {
+            marked.iter().find(|i| **i > cur_idx).or(marked.first())
+        };
+        match target {
+            Some(&idx) => {
+                self.move_cursor_to(idx);
+                true
             }
-        });
+            None => false,
+        }
+    }
 
-        // Add the parent directory entry after sorting to make sure it's always first
-        new_output_buf.insert(
-            0,
-            CustomDirEntry::from(std::path::Path::new(&std::path::Component::ParentDir))
-        );
+    /// Unmark the item with the given absolute path, if it's marked.
+    pub fn unmark(&mut self, path: &Path) {
+        self.marked.retain(|p| p != path);
+    }
 
-        self.ls_output_buf = new_output_buf.into();
-        Ok(())
+    pub fn is_marked(&self, item: &CustomDirEntry) -> bool {
+        !self.marked.is_empty()
+            && self.marked.contains(&self.entry_path(item))
     }
 
-    pub fn change_dir(&mut self, path: &str) -> IOResult<()> {
-        // TODO: add option to use xdg-open (or similar) on files?
-        // check out https://crates.io/crates/open
-        // (or https://docs.rs/opener/0.4.1/opener/)
-        let target_path = if path.is_empty() {
-            //TODO: error here if result is empty?
-            self.get_item_under_cursor()
-                .map_or("".to_string(), |s| s.file_name_checked())
-        } else {
-            path.to_string()
-        };
-        let target_path = PathBuf::from(target_path);
-
-        // NOTE: have to manually normalize path because the std doesn't have that feature yet, as
-        // of December 2021.
-        // see:
-        // - https://github.com/rust-lang/rfcs/issues/2208
-        // - https://github.com/gdzx/rfcs/commit/3c69f787b5b32fb9c9960c1e785e5cabcc794238
-        // - abs_path crate
-        // - relative_path crate
-        // This function is copy-pasted from cargo::util::paths::normalize_path, https://docs.rs/cargo-util/0.1.1/cargo_util/paths/fn.normalize_path.html, under the MIT license
-        fn normalize_path(path: &Path) -> PathBuf {
-            let mut components = path.components().peekable();
-            let mut ret = if let Some(c @ Component::Prefix(..)) = components.peek().cloned() {
-                components.next();
-                PathBuf::from(c.as_os_str())
-            } else {
-                PathBuf::new()
-            };
+    /// The absolute path of the item under the cursor (or as given, in stdin mode).
+    pub fn item_under_cursor_path(&self) -> Option<PathBuf> {
+        self.get_item_under_cursor()
+            .map(|item| self.entry_path(item))
+    }
 
-            for component in components {
-                match component {
-                    Component::Prefix(..) => unreachable!(),
-                    Component::RootDir => {
-                        ret.push(component.as_os_str());
-                    }
-                    Component::CurDir => {}
-                    Component::ParentDir => {
-                        ret.pop();
-                    }
-                    Component::Normal(c) => {
-                        ret.push(c);
-                    }
-                }
-            }
-            ret
+    /// Whether the items were read from stdin, instead of from the current folder.
+    pub fn is_list_mode(&self) -> bool {
+        self.list_items.is_some()
+    }
+
+    /// The path of the item with the given name. In stdin mode, the name is the path as it was
+    /// given, otherwise it's relative to the current folder.
+    fn item_path(&self, fname: &OsStr) -> PathBuf {
+        if self.is_list_mode() {
+            PathBuf::from(fname)
+        } else {
+            self.current_path.join(fname)
         }
+    }
 
-        let final_path = if target_path.is_absolute() {
-            target_path
+    /// The path of `item`, like in `item_path`.
+    fn entry_path(&self, item: &CustomDirEntry) -> PathBuf {
+        if self.is_list_mode() {
+            item.path().clone()
         } else {
-            normalize_path(&self.current_path.join(target_path))
-        };
+            self.current_path.join(item.os_file_name())
+        }
+    }
 
-        self.clear_search();
-        std::env::set_current_dir(&final_path)?;
-        self.current_path = PathBuf::from(&final_path);
-        self.update_ls_output_buf()?;
+    pub fn marked_items(&self) -> &[PathBuf] {
+        &self.marked
+    }
 
-        self.cursor_pos = 0;
-        self.scroll_pos = 0;
+    /// In file selection mode, choose the file `fname` if it is a file (and not a folder). In
+    /// stdin mode, any item can be chosen. If `fname` is empty, use the item under the cursor,
+    /// like in `change_dir`. Returns true if a file was chosen, in which case the app should exit.
+    pub fn choose_file(&mut self, fname: &str) -> bool {
+        if !self.settings.choose_file && !self.is_list_mode() {
+            return false;
+        }
 
-        // final_path is always the absolute logical path, so we can just cd to it. This causes a
-        // bit of extra work (the history tree has to go all the way from the root to the path
-        // every time), but that's not too bad. The alernative option of using history_tree.go_up()
-        // / visit() and handling the special cases where target_path is '..' or a relative path or
-        // so on, would be much more complicated and would risk having the history tree and logical
-        // path out of sync.
-        self.history.change_dir(&final_path);
+        let fname = if fname.is_empty() {
+            match self.get_item_under_cursor() {
+                Some(item) if self.is_list_mode() => item.path().as_os_str().to_owned(),
+                Some(item) => item.os_file_name().to_owned(),
+                None => return false,
+            }
+        } else {
+            self.exact_file_name(fname)
+        };
 
-        // move cursor one position down, so we're not at '..' if we've entered a folder with no history
-        self.move_cursor(1, false);
-        if let Some(prev_dir) = self.history.current_entry().last_visited_child_label() {
-            self.move_cursor_to_filename(prev_dir);
+        // an alias to a folder is entered instead
+        let path = self
+            .alias_target_of(&fname)
+            .unwrap_or_else(|| self.item_path(&fname));
+        // the items inside archives and on other machines are not files on this machine, so
+        // only the listing tells whether they are files
+        let is_file = match self.is_virtual_dir() {
+            true => self
+                .ls_output_buf
+                .all_items
+                .iter()
+                .any(|item| item.os_file_name() == fname && !item.is_dir()),
+            false => path.is_file(),
+        };
+        if self.is_list_mode() || is_file {
+            self.chosen_file = Some(path);
+            true
+        } else {
+            false
         }
-
-        Ok(())
     }
 
     /////////////////////////////////////
@@ -484,15 +2585,23 @@ impl TereAppState {
     /// Move the cursor up (positive amount) or down (negative amount) in the
     /// currently visible items, and update the scroll position as necessary
     pub fn move_cursor(&mut self, amount: isize, wrap: bool) {
-        let old_cursor_pos = self.cursor_pos;
-        let old_scroll_pos = self.scroll_pos;
-        let visible_items = self.visible_items();
-        let n_visible_items = visible_items.len();
+        let n_visible_items = self.num_visible_items();
         let max_y = self.main_win_h;
 
+        // If the number of visible items has decreased (for example when filtering), the scroll
+        // position may be too far down. Scroll up as much as necessary, keeping the cursor on
+        // the same item.
+        let max_scroll_pos = n_visible_items.saturating_sub(max_y);
+        if self.scroll_pos > max_scroll_pos {
+            self.cursor_pos += self.scroll_pos - max_scroll_pos;
+            self.scroll_pos = max_scroll_pos;
+        }
+
+        let old_cursor_pos = self.cursor_pos;
+        let old_scroll_pos = self.scroll_pos;
         let mut new_cursor_pos: isize = (old_cursor_pos as isize).saturating_add(amount);
 
-        if wrap && !visible_items.is_empty() {
+        if wrap && n_visible_items > 0 {
             let offset = self.scroll_pos as isize;
             new_cursor_pos =
                 (offset + new_cursor_pos).rem_euclid(n_visible_items as isize) - offset;
@@ -500,7 +2609,7 @@ impl TereAppState {
 
         if new_cursor_pos < 0 {
             // attempting to go above the current view, scroll up
-            self.scroll_pos = self.scroll_pos.saturating_sub(new_cursor_pos.abs() as usize);
+            self.scroll_pos = self.scroll_pos.saturating_sub(new_cursor_pos.unsigned_abs());
             self.cursor_pos = 0;
         } else if new_cursor_pos as usize + old_scroll_pos >= n_visible_items {
             // attempting to go below content
@@ -522,6 +2631,17 @@ impl TereAppState {
         }
     }
 
+    /// The number of rows to move the cursor by when scrolling by one page, or by half a page
+    /// if `half` is true. This is always at least one.
+    pub fn page_scroll_amount(&self, half: bool) -> usize {
+        let amount = if half {
+            self.main_win_h / 2
+        } else {
+            self.main_win_h.saturating_sub(self.settings.page_overlap)
+        };
+        std::cmp::max(amount, 1)
+    }
+
     /// Move the cursor so that it is at the location `row` in the
     /// currently visible items, and update the scroll position as necessary
     pub fn move_cursor_to(&mut self, row: usize) {
@@ -556,7 +2676,7 @@ impl TereAppState {
                 self.move_cursor(dir.signum(), true);
             } else {
                 let cur_idx = self.cursor_pos_to_visible_item_index(self.cursor_pos);
-                let kept_indices = &self.ls_output_buf.kept_indices();
+                let kept_indices = &self.ls_output_buf.kept;
                 let (cur_idx_in_kept, cur_idx_in_all) = kept_indices
                     .iter()
                     .enumerate()
@@ -586,81 +2706,172 @@ impl TereAppState {
         }
     }
 
+    /// Move the cursor by one page while searching. If dir is positive, move to the first match
+    /// below the current view, and if it's negative, move to the last match above the current
+    /// view. If there are no such matches, move to the last / first match instead. If the
+    /// listing contains only matches (or no matches at all), just move by one page (or half a
+    /// page, if `half` is true).
+    pub fn move_cursor_to_match_beyond_view(&mut self, dir: isize, half: bool) {
+        if !self.is_searching() {
+            return;
+        }
+
+        let page = (self.page_scroll_amount(half) as isize) * dir.signum();
+
+        if self.settings.filter_search || self.num_matching_items() == 0 {
+            self.move_cursor(page, false);
+            return;
+        }
+
+        let kept_indices = &self.ls_output_buf.kept;
+        let view_start = self.scroll_pos;
+        let view_end = self.scroll_pos + self.main_win_h;
+
+        // ok to unwrap first() and last(), kept_indices is not empty based on the check above
+        let new_row = if dir < 0 {
+            kept_indices
+                .iter()
+                .rev()
+                .find(|i| **i < view_start)
+                .unwrap_or_else(|| kept_indices.first().unwrap())
+        } else {
+            kept_indices
+                .iter()
+                .find(|i| **i >= view_end)
+                .unwrap_or_else(|| kept_indices.last().unwrap())
+        };
+
+        self.move_cursor_to(*new_row);
+    }
+
     ///////////
     // Seach //
     ///////////
 
-    fn update_search_matches(&mut self) {
-        let is_case_sensitive = match self.settings.case_sensitive {
-            CaseSensitiveMode::IgnoreCase => false,
-            CaseSensitiveMode::CaseSensitive => true,
-            CaseSensitiveMode::SmartCase => self.search_string.chars().any(|c| c.is_uppercase()),
-        };
+    /// The current search query, and whether it's case sensitive. This also sets whether files
+    /// can match it, since without a query everything matches.
+    fn search_query(&mut self) -> (SearchQuery, bool) {
+        let dirs_only = self.settings.search_folders_only && !self.search_string.is_empty();
+        self.ls_output_buf.set_dirs_only(dirs_only);
+        let is_case_sensitive = self.is_case_sensitive();
         let search_string = if is_case_sensitive {
             self.search_string.clone()
         } else {
             self.search_string.to_lowercase()
         };
+        let query = SearchQuery::new(&search_string, &self.settings.gap_search_mode);
+        (query, is_case_sensitive)
+    }
 
-        // TODO: construct regex pattern inside MatchesVec instead? - it relies now on capture
-        // groups which are defined by the format!() parens here...
-        let mut regex_str = "".to_string();
-        if self.settings.gap_search_mode == GapSearchMode::NoGapSearch {
-            regex_str.push_str(&format!("^({})", regex::escape(&search_string)));
-        } else {
-            // enable gap search. Add '^' to the regex to match only from the start if applicable.
-            if self.settings.gap_search_mode == GapSearchMode::GapSearchFromStart {
-                regex_str.push('^');
+    fn update_search_matches(&mut self) {
+        let (query, is_case_sensitive) = self.search_query();
+        self.ls_output_buf.update_matches(&query, is_case_sensitive);
+    }
+
+    /// Like `update_search_matches`, but only the items that matched the previous search are
+    /// tested, which is only correct if characters have just been added to the search.
+    fn narrow_search_matches(&mut self) {
+        let (query, is_case_sensitive) = self.search_query();
+        self.ls_output_buf.narrow_matches(&query, is_case_sensitive);
+    }
+
+    /// Switch to the next case sensitivity mode, and update the search results accordingly.
+    pub fn cycle_case_sensitive_mode(&mut self) {
+        self.settings.case_sensitive = match self.settings.case_sensitive {
+            CaseSensitiveMode::IgnoreCase => CaseSensitiveMode::CaseSensitive,
+            CaseSensitiveMode::CaseSensitive => CaseSensitiveMode::SmartCase,
+            CaseSensitiveMode::SmartCase => CaseSensitiveMode::Auto,
+            CaseSensitiveMode::Auto => CaseSensitiveMode::IgnoreCase,
+        };
+        self.detect_dir_case();
+        self.advance_search("");
+    }
+
+    /// Switch between showing only the matching items while searching and showing all items,
+    /// keeping the cursor on the same item.
+    pub fn toggle_filter_search(&mut self) {
+        let previous_item_under_cursor = self.get_item_under_cursor().map(|itm| itm.file_name_checked());
+        self.settings.filter_search = !self.settings.filter_search;
+        if let Some(fname) = previous_item_under_cursor {
+            if !self.move_cursor_to_filename(fname) {
+                self.move_cursor_to(0);
             }
-            regex_str.push_str(
-                &search_string
-                    .chars()
-                    .map(|c| format!("({})", regex::escape(&c.to_string())))
-                    .collect::<Vec<String>>()
-                    .join(".*?"),
-            );
         }
+    }
 
-        // ok to unwrap, we have escaped the regex above
-        let search_ptn = Regex::new(&regex_str).unwrap();
-        self.ls_output_buf.update_matches(&search_ptn, is_case_sensitive);
+    /// Switch to the next gap search mode, and update the search results accordingly.
+    pub fn cycle_gap_search_mode(&mut self) {
+        self.settings.gap_search_mode = match self.settings.gap_search_mode {
+            GapSearchMode::GapSearchFromStart => GapSearchMode::NoGapSearch,
+            GapSearchMode::NoGapSearch => GapSearchMode::GapSearchAnywere,
+            GapSearchMode::GapSearchAnywere => GapSearchMode::GapSearchFromStart,
+        };
+        self.advance_search("");
     }
 
     pub fn clear_search(&mut self) {
-        let previous_item_under_cursor = self.get_item_under_cursor().cloned();
+        let previous_item_under_cursor = self.get_item_under_cursor().map(|itm| itm.file_name_checked());
         self.search_string.clear();
-        previous_item_under_cursor.map(|itm| self.move_cursor_to_filename(itm.file_name_checked()));
+        previous_item_under_cursor.map(|fname| self.move_cursor_to_filename(fname));
     }
 
-    pub fn advance_search(&mut self, query: &str) {
-        let previous_item_under_cursor = self.get_item_under_cursor().cloned();
-
-        self.search_string.push_str(query);
-
-        self.update_search_matches();
+    /// Add the characters of `query` to the search. Returns false if some of them were discarded,
+    /// because nothing would have matched with them and `settings.no_match` is
+    /// `NoMatchBehavior::Discard`.
+    pub fn advance_search(&mut self, query: &str) -> bool {
+        let previous_item_under_cursor = self.get_item_under_cursor().map(|itm| itm.file_name_checked());
+        let mut all_added = true;
+
+        // With an empty query, this is used for updating the matches after something else than
+        // the query has changed. Otherwise, whatever matches now also matched before, even if
+        // the search became case sensitive because of an uppercase character. The matches are
+        // narrowed one character at a time, so that erasing a character can go back to the
+        // previous ones.
+        if query.is_empty() {
+            self.update_search_matches();
+        }
+        for c in query.chars() {
+            let narrow = !self.search_string.is_empty();
+            self.search_string.push(c);
+            if narrow {
+                self.narrow_search_matches();
+            } else {
+                self.update_search_matches();
+            }
+            if self.settings.no_match == NoMatchBehavior::Discard && self.num_matching_items() == 0 {
+                self.search_string.pop();
+                if !self.ls_output_buf.restore_previous_matches() {
+                    self.update_search_matches();
+                }
+                all_added = false;
+            }
+        }
 
         if self.settings.filter_search {
-            if let Some(item) = previous_item_under_cursor {
-                if !self.move_cursor_to_filename(item.file_name_checked()) {
+            if let Some(fname) = previous_item_under_cursor {
+                if !self.move_cursor_to_filename(fname) {
                     self.move_cursor_to(0);
                 }
             }
         } else {
             self.move_cursor_to_adjacent_match(0);
         }
+        all_added
     }
 
     pub fn erase_search_char(&mut self) {
-        let previous_item_under_cursor = self.get_item_under_cursor().cloned();
+        let previous_item_under_cursor = self.get_item_under_cursor().map(|itm| itm.file_name_checked());
 
         if self.search_string.pop().is_some() {
             //TODO: keep cursor position when there were no matches? should somehow push cursor position onto some stack when advancing search.
 
-            self.update_search_matches();
+            if !self.ls_output_buf.restore_previous_matches() {
+                self.update_search_matches();
+            }
 
             if self.settings.filter_search {
-                if let Some(item) = previous_item_under_cursor {
-                    if !self.move_cursor_to_filename(item.file_name_checked()) {
+                if let Some(fname) = previous_item_under_cursor {
+                    if !self.move_cursor_to_filename(fname) {
                         self.move_cursor_to(0);
                     }
                 }
@@ -674,6 +2885,7 @@ impl TereAppState {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_util::{TempDir, TestFsDirSource};
 
     fn create_test_filenames(n: usize) -> LsBufType {
         let fnames: Vec<_> = (1..=n).map(|i| format!("file {}", i)).collect();
@@ -700,14 +2912,39 @@ mod tests {
             main_win_w: 10,
             current_path: "/".into(),
             ls_output_buf: buf,
+            listing: vec![],
+            view_prefs: ViewPrefsStore::default(),
+            filter_preset: None,
             header_msg: "".into(),
             info_msg: "".into(),
             search_string: "".into(),
             settings: Default::default(),
             history: HistoryTree::from_abs_path("/"),
+            chosen_file: None,
+            marked: vec![],
+            list_items: None,
+            pending_dir: None,
+            dir_cache: DirCache::default(),
+            watcher: None,
+            dir_ignores_case: None,
+            dir_is_remote: false,
+            disk_space: None,
+            dir_fs_type: None,
+            source: Arc::new(TestFsDirSource),
+            hooked_dir: None,
+            tabs: vec![],
+            current_tab: 0,
+            changed_items: HashMap::new(),
+            symlink_loop: None,
         }
     }
 
+    /// The app state for the folder `cwd` on the file system. Changing the folder doesn't change
+    /// the working directory of the process, which is shared by the tests running in parallel.
+    fn create_fs_test_state(settings: TereSettings, cwd: PathBuf) -> TereAppState {
+        TereAppState::with_dir_source(settings, cwd, Arc::new(TestFsDirSource), 10, 10).unwrap()
+    }
+
     #[test]
     fn test_scrolling_bufsize_less_than_window_size() {
         let mut state = create_test_state(10, 4);
@@ -914,6 +3151,117 @@ mod tests {
         assert_eq!(s.cursor_pos, 3);
     }
 
+    #[test]
+    fn test_match_spans() {
+        let mut s = create_test_state_with_buf(
+            5,
+            strings_to_ls_buf(vec!["..", "äbcäbc", "xyz"]),
+        );
+        s.settings.gap_search_mode = GapSearchMode::GapSearchAnywere;
+        s.advance_search("bc");
+        // the adjacent matches of 'b' and 'c' are merged, and the spans count graphemes, not bytes
+        assert_eq!(s.get_match_spans_at_cursor_pos(1), Some(&vec![(1, 3)]));
+        assert_eq!(s.get_match_spans_at_cursor_pos(2), None);
+
+        s.advance_search("ä");
+        assert_eq!(s.get_match_spans_at_cursor_pos(1), Some(&vec![(1, 4)]));
+
+        assert_eq!(grapheme_spans(&[0, 1, 3], &vec![(0, 1), (3, 4)]), vec![(0, 1), (2, 3)]);
+    }
+
+    #[test]
+    fn test_search_query_matches_regex() {
+        let names = [
+            "", "a", "abc", "cab", "aXbXc", "xaxbxc", "a\nbc", "ba\nabc", "äbäc", "a.b*c", "ab",
+            "cba", "aaa", "a\nb\nc",
+        ];
+        let queries = ["", "a", "ab", "abc", "bc", "ä", "äc", ".*", "\n", "a\nb", "aa"];
+        let modes = [
+            GapSearchMode::NoGapSearch,
+            GapSearchMode::GapSearchFromStart,
+            GapSearchMode::GapSearchAnywere,
+        ];
+        for mode in &modes {
+            for query in queries {
+                let regex = search_pattern(query, mode);
+                let fast = SearchQuery::new(query, mode);
+                for name in names {
+                    assert_eq!(
+                        fast.match_locations(name),
+                        regex.match_locations(name),
+                        "query {:?} in {:?}",
+                        query,
+                        name,
+                    );
+                    assert_eq!(fast.is_match(name), regex.is_match(name));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_narrow_matches() {
+        let mut s = create_test_state_with_buf(
+            5,
+            strings_to_ls_buf(vec!["..", "Abc", "abd", "xyz", "aBc"]),
+        );
+        s.advance_search("a");
+        assert_eq!(s.num_matching_items(), 3);
+        s.advance_search("b");
+        assert_eq!(s.num_matching_items(), 3);
+        // smart case
+        s.advance_search("C");
+        assert_eq!(s.ls_output_buf.kept_indices(), Vec::<usize>::new());
+        s.erase_search_char();
+        s.advance_search("c");
+        assert_eq!(s.ls_output_buf.kept_indices(), vec![1, 4]);
+
+        // the listing changes while searching
+        s.ls_output_buf = strings_to_ls_buf(vec!["..", "abc", "abcd"]);
+        s.advance_search("d");
+        assert_eq!(s.ls_output_buf.kept_indices(), vec![2]);
+        s.erase_search_char();
+        assert_eq!(s.ls_output_buf.kept_indices(), vec![1, 2]);
+
+        // erasing goes back to the previous matches, also after adding several characters
+        s.erase_search_char();
+        s.erase_search_char();
+        s.advance_search("bcd");
+        assert_eq!(s.ls_output_buf.kept_indices(), vec![2]);
+        s.erase_search_char();
+        assert_eq!(s.ls_output_buf.kept_indices(), vec![1, 2]);
+        assert_eq!(s.get_match_locations_at_cursor_pos(1), Some(&vec![(0, 1), (1, 2), (2, 3)]));
+    }
+
+    #[test]
+    fn test_entry_names() {
+        let entry = CustomDirEntry::from_list_item("/foo/Bär");
+        assert_eq!(entry.file_name(), "/foo/Bär");
+        assert_eq!(entry.lowercase_file_name(), "/foo/bär");
+        let clone = entry.clone();
+        assert!(Arc::ptr_eq(&entry._file_name, &clone._file_name));
+
+        let entry = CustomDirEntry::from_list_item("bar");
+        assert_eq!(entry.lowercase_file_name(), "bar");
+        assert!(entry._lowercase_name.is_none());
+    }
+
+    #[test]
+    fn test_split_by_match_spans() {
+        let name = "äbcäbc";
+        let offsets = [0, 2, 3, 4, 6, 7];
+        assert_eq!(split_by_match_spans(name, &offsets, &[]), vec![("äbcäbc", false)]);
+        assert_eq!(
+            split_by_match_spans(name, &offsets, &[(1, 3), (4, 5)]),
+            vec![("ä", false), ("bc", true), ("ä", false), ("b", true), ("c", false)],
+        );
+        assert_eq!(
+            split_by_match_spans(name, &offsets, &[(0, 1), (5, 6)]),
+            vec![("ä", true), ("bcäb", false), ("c", true)],
+        );
+        assert_eq!(split_by_match_spans("", &[], &[]), vec![]);
+    }
+
     #[test]
     fn test_advance_search_wrap() {
         let mut s = create_test_state_with_buf(
@@ -988,6 +3336,885 @@ mod tests {
         assert_eq!(s.cursor_pos, 4);
     }
 
+    #[test]
+    fn test_page_up_down_while_searching() {
+        let mut s = create_test_state_with_buf(
+            3,
+            strings_to_ls_buf(vec!["..", "foo", "bar", "baz", "frob", "boo", "fizz"]),
+        );
+
+        s.advance_search("f");
+
+        // current state: ('|' shows the window position)
+        //   ..    |
+        // > foo   |
+        //   bar   |
+        //   baz
+        //   frob
+        //   boo
+        //   fizz
+
+        assert_eq!(s.cursor_pos, 1);
+        assert_eq!(s.scroll_pos, 0);
+
+        // jump to the first match below the view, 'frob'
+        s.move_cursor_to_match_beyond_view(1, false);
+        assert_eq!(s.cursor_pos + s.scroll_pos, 4);
+
+        // then to 'fizz'
+        s.move_cursor_to_match_beyond_view(1, false);
+        assert_eq!(s.cursor_pos + s.scroll_pos, 6);
+
+        // no more matches below the view, stay at the last match
+        s.move_cursor_to_match_beyond_view(1, false);
+        assert_eq!(s.cursor_pos + s.scroll_pos, 6);
+
+        // jump to the last match above the view, 'foo'
+        s.move_cursor_to_match_beyond_view(-1, false);
+        assert_eq!(s.cursor_pos + s.scroll_pos, 1);
+
+        // no more matches above the view, stay at the first match
+        s.move_cursor_to_match_beyond_view(-1, false);
+        assert_eq!(s.cursor_pos + s.scroll_pos, 1);
+    }
+
+    #[test]
+    fn test_page_scroll_amount() {
+        let mut s = create_test_state(10, 20);
+        assert_eq!(s.page_scroll_amount(false), 10);
+        assert_eq!(s.page_scroll_amount(true), 5);
+
+        s.settings.page_overlap = 3;
+        assert_eq!(s.page_scroll_amount(false), 7);
+        assert_eq!(s.page_scroll_amount(true), 5);
+
+        // always move by at least one row
+        s.settings.page_overlap = 100;
+        assert_eq!(s.page_scroll_amount(false), 1);
+        s.update_main_window_dimensions(10, 1);
+        assert_eq!(s.page_scroll_amount(true), 1);
+    }
+
+    #[test]
+    fn test_is_hidden() {
+        let entries = strings_to_ls_buf(vec!["..", ".git", "foo", "foo.txt"]).all_items;
+        let hidden: Vec<_> = entries.iter().map(|e| e.is_hidden()).collect();
+        assert_eq!(hidden, vec![false, true, false, false]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_is_hidden_attribute() {
+        use std::os::windows::process::CommandExt;
+
+        let tmp = TempDir::new("is-hidden");
+        std::fs::write(tmp.join("shown"), "").unwrap();
+        std::fs::write(tmp.join("hidden"), "").unwrap();
+        std::process::Command::new("attrib")
+            .raw_arg(format!("+h \"{}\"", tmp.join("hidden").display()))
+            .status()
+            .unwrap();
+
+        assert!(!CustomDirEntry::from(tmp.join("shown").as_path()).is_hidden());
+        assert!(CustomDirEntry::from(tmp.join("hidden").as_path()).is_hidden());
+    }
+
+    #[test]
+    fn test_auto_case() {
+        let tmp = TempDir::new("auto-case");
+        std::fs::write(tmp.join("FOO"), "").unwrap();
+
+        let entries = |names: Vec<&str>| strings_to_ls_buf(names).all_items;
+        assert_eq!(ignores_case(&entries(vec!["..", "123"]), &tmp), None);
+        assert_eq!(ignores_case(&entries(vec!["..", "bar", "BAR"]), &tmp), Some(false));
+        assert_eq!(ignores_case(&entries(vec!["..", "bar"]), &tmp), Some(false));
+        // 'FOO' can also be accessed as 'foo', like on a case insensitive file system
+        assert_eq!(ignores_case(&entries(vec!["..", "foo"]), &tmp), Some(true));
+
+        let mut s = create_test_state(6, 0);
+        s.current_path = tmp.to_path_buf();
+        s.settings.case_sensitive = CaseSensitiveMode::Auto;
+        s.set_ls_output_buf(entries(vec!["foo", "Fob"]));
+        assert!(matches!(s.case_sensitive_mode(), CaseSensitiveMode::IgnoreCase));
+        s.advance_search("FO");
+        assert_eq!(s.num_matching_items(), 2);
+
+        s.clear_search();
+        s.enter_dir(tmp.to_path_buf()).unwrap();
+        s.set_ls_output_buf(entries(vec!["bar", "foo", "Fob"]));
+        assert!(matches!(s.case_sensitive_mode(), CaseSensitiveMode::SmartCase));
+        s.advance_search("Fo");
+        assert_eq!(s.num_matching_items(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hooks() {
+        let tmp = TempDir::new("hooks");
+        std::fs::create_dir_all(tmp.join("a")).unwrap();
+        std::fs::create_dir_all(tmp.join("b")).unwrap();
+        let log = tmp.join("log");
+        let log_cmd = |event| format!("echo {} {{}} >> '{}'", event, log.display());
+
+        let mut s = create_test_state(6, 0);
+        s.settings.hooks = Hooks {
+            on_enter_dir: Some(log_cmd("enter")),
+            on_leave_dir: Some(log_cmd("leave")),
+            on_exit: Some(log_cmd("exit")),
+        };
+        s.enter_dir(tmp.join("a")).unwrap();
+        // entering the same folder again, like when refreshing, doesn't run the hooks
+        s.enter_dir(tmp.join("a")).unwrap();
+        s.enter_dir(tmp.join("b")).unwrap();
+        s.on_exit().unwrap();
+
+        // the enter and leave hooks run in the background, so wait for them
+        let expected = vec![
+            format!("enter {}", tmp.join("a").display()),
+            format!("enter {}", tmp.join("b").display()),
+            format!("exit {}", tmp.join("b").display()),
+            format!("leave {}", tmp.join("a").display()),
+        ];
+        let mut lines = vec![];
+        for _ in 0..100 {
+            lines = std::fs::read_to_string(&log).unwrap_or_default().lines().map(String::from).collect();
+            if lines.len() >= expected.len() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        lines.sort();
+        assert_eq!(lines, expected);
+    }
+
+    #[test]
+    fn test_view_prefs() {
+        let tmp = TempDir::new("view-prefs-state");
+        std::fs::create_dir_all(tmp.join("dl/sub")).unwrap();
+        for name in ["a", "b", ".c"] {
+            std::fs::write(tmp.join("dl").join(name), "").unwrap();
+        }
+        let settings = || TereSettings {
+            history_file: Some(tmp.join("hist/history.json")),
+            ..Default::default()
+        };
+        let names = |s: &TereAppState| -> Vec<String> {
+            s.visible_items().iter().map(|e| e.file_name_checked()).collect()
+        };
+
+        let mut s = create_fs_test_state(settings(), tmp.join("dl"));
+        assert_eq!(names(&s), vec!["..", "sub", ".c", "a", "b"]);
+        s.move_cursor_to_filename("b");
+        s.toggle_show_hidden().unwrap();
+        assert_eq!(names(&s), vec!["..", "sub", "a", "b"]);
+        // the cursor stays on the same item
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("dl/b")));
+        s.toggle_folders_only().unwrap();
+        assert_eq!(names(&s), vec!["..", "sub"]);
+        s.toggle_folders_only().unwrap();
+        s.cycle_sort_mode().unwrap();
+        assert_eq!(s.view_prefs().sort_mode, SortMode::Modified);
+        s.cycle_sort_mode().unwrap();
+        s.cycle_sort_mode().unwrap();
+        s.toggle_reverse_sort().unwrap();
+        assert_eq!(names(&s), vec!["..", "sub", "b", "a"]);
+        // reversing keeps the sort mode
+        s.cycle_sort_mode().unwrap();
+        assert!(s.view_prefs().reverse_sort);
+        s.cycle_sort_mode().unwrap();
+        s.cycle_sort_mode().unwrap();
+        s.toggle_reverse_sort().unwrap();
+
+        // the view is only changed in that folder, and restored when it's entered again
+        s.change_dir("sub").unwrap();
+        assert_eq!(s.view_prefs(), ViewPrefs::default());
+        s.change_dir("..").unwrap();
+        assert_eq!(names(&s), vec!["..", "sub", "a", "b"]);
+        s.on_exit().unwrap();
+
+        // also in the next run
+        let s = create_fs_test_state(settings(), tmp.join("dl"));
+        assert!(!s.view_prefs().show_hidden);
+        assert_eq!(names(&s), vec!["..", "sub", "a", "b"]);
+
+        // the default sort order only applies where it hasn't been changed
+        let by_mtime = || TereSettings { sort_mode: SortMode::Modified, ..settings() };
+        let s = create_fs_test_state(by_mtime(), tmp.join("dl"));
+        assert_eq!(s.view_prefs().sort_mode, SortMode::Name);
+        let s = create_fs_test_state(by_mtime(), tmp.join("dl/sub"));
+        assert_eq!(s.view_prefs().sort_mode, SortMode::Modified);
+    }
+
+    #[test]
+    fn test_highlight_changes() {
+        let tmp = TempDir::new("highlight-changes");
+        std::fs::create_dir_all(tmp.join("sub")).unwrap();
+        for name in ["old", "recent"] {
+            std::fs::write(tmp.join(name), "").unwrap();
+        }
+        let an_hour_ago = SystemTime::now() - Duration::from_secs(3600);
+        for name in ["old", "sub"] {
+            std::fs::File::open(tmp.join(name)).unwrap().set_modified(an_hour_ago).unwrap();
+        }
+        let changes = |s: &TereAppState| -> Vec<(String, ItemChange)> {
+            let mut ret: Vec<_> = s
+                .listing
+                .iter()
+                .filter_map(|e| Some((e.file_name_checked(), s.item_change(e)?)))
+                .collect();
+            ret.sort_by(|a, b| a.0.cmp(&b.0));
+            ret
+        };
+        let refresh = |s: &mut TereAppState| {
+            s.clear_dir_cache();
+            s.change_dir(".").unwrap();
+        };
+
+        let settings = TereSettings { highlight_changes: Some(60), ..Default::default() };
+        let mut s = create_fs_test_state(settings, tmp.to_path_buf());
+        // nothing has changed when a folder is entered
+        assert!(changes(&s).is_empty());
+
+        std::fs::write(tmp.join("new"), "").unwrap();
+        refresh(&mut s);
+        assert_eq!(
+            changes(&s),
+            vec![("new".to_string(), ItemChange::New), ("recent".to_string(), ItemChange::Modified)],
+        );
+        s.change_dir("sub").unwrap();
+        assert!(changes(&s).is_empty());
+        s.change_dir("..").unwrap();
+        assert!(changes(&s).is_empty());
+
+        // with 0, only new items are highlighted
+        s.settings.highlight_changes = Some(0);
+        std::fs::write(tmp.join("new2"), "").unwrap();
+        refresh(&mut s);
+        assert_eq!(changes(&s), vec![("new2".to_string(), ItemChange::New)]);
+
+        s.settings.highlight_changes = None;
+        std::fs::write(tmp.join("new3"), "").unwrap();
+        refresh(&mut s);
+        assert!(changes(&s).is_empty());
+    }
+
+    #[test]
+    fn test_file_system_info() {
+        let tmp = TempDir::new("file-system-info");
+
+        let s = create_fs_test_state(TereSettings::default(), tmp.to_path_buf());
+        assert_eq!(s.disk_space(), None);
+        assert_eq!(s.header_msg, tmp.display().to_string());
+
+        let settings = TereSettings { show_disk_space: true, show_fs_type: true, ..Default::default() };
+        let s = create_fs_test_state(settings, tmp.to_path_buf());
+        assert_eq!(s.disk_space(), remote_fs::disk_space(&tmp));
+        if let Some(fs_type) = remote_fs::fs_type(&tmp) {
+            assert_eq!(s.header_msg, format!("{} ({})", tmp.display(), fs_type));
+        }
+        #[cfg(target_os = "linux")]
+        assert!(s.disk_space().is_some() && s.dir_fs_type.is_some());
+    }
+
+    #[test]
+    fn test_tabs() {
+        let tmp = TempDir::new("tabs");
+        for dir in ["a/x", "a/y", "b"] {
+            std::fs::create_dir_all(tmp.join(dir)).unwrap();
+        }
+
+        let mut s = create_fs_test_state(Default::default(), tmp.join("a"));
+        s.move_cursor_to_filename("y");
+        assert!(s.close_tab().is_err());
+        s.new_tab().unwrap();
+        assert_eq!((s.current_tab(), s.num_tabs()), (1, 2));
+        assert!(s.header_msg.ends_with(" [2/2]"));
+        // the new tab starts in the same place
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("a/y")));
+
+        // each tab has its own folder and search
+        s.change_dir(&tmp.join("b").to_string_lossy()).unwrap();
+        s.next_tab().unwrap();
+        assert_eq!(s.current_tab(), 0);
+        assert_eq!(s.current_path, tmp.join("a"));
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("a/y")));
+        s.advance_search("x");
+        s.previous_tab().unwrap();
+        assert_eq!(s.current_path, tmp.join("b"));
+        assert!(!s.is_searching());
+        s.previous_tab().unwrap();
+        assert_eq!(s.search_string(), "x");
+        assert_eq!(s.num_matching_items(), 1);
+
+        // closing the first tab switches to the next one
+        s.close_tab().unwrap();
+        assert_eq!((s.current_tab(), s.num_tabs()), (0, 1));
+        assert_eq!(s.current_path, tmp.join("b"));
+        assert!(!s.header_msg.contains('['));
+    }
+
+    #[test]
+    fn test_should_autocd() {
+        let mut s = create_test_state_with_buf(
+            6,
+            strings_to_ls_buf(vec!["..", "foo", "frob", "bar", "baz"]),
+        );
+        s.settings.autocd_timeout = Some(200);
+        s.settings.gap_search_mode = GapSearchMode::GapSearchAnywere;
+
+        s.advance_search("f");
+        assert!(!s.should_autocd()); // two matches
+
+        s.advance_search("b");
+        assert!(s.should_autocd()); // only 'frob' matches
+
+        s.settings.autocd_mode = AutoCdMode::Never;
+        assert!(!s.should_autocd());
+        s.settings.autocd_mode = AutoCdMode::RequireEnter;
+        assert!(!s.should_autocd());
+
+        // 'fb' matches 'frob' only with gaps
+        s.settings.autocd_mode = AutoCdMode::ExactPrefix;
+        assert!(!s.should_autocd());
+        s.clear_search();
+        s.advance_search("FR");
+        s.settings.case_sensitive = CaseSensitiveMode::IgnoreCase;
+        s.advance_search("");
+        assert!(s.should_autocd());
+
+        // 'frob' is not a folder
+        s.settings.autocd_mode = AutoCdMode::DirsOnly;
+        assert!(!s.should_autocd());
+
+        // files are not chosen automatically in file selection mode
+        s.settings.autocd_mode = AutoCdMode::Always;
+        s.settings.choose_file = true;
+        assert!(!s.should_autocd());
+        s.settings.choose_file = false;
+
+        s.settings.autocd_timeout = None;
+        assert!(!s.should_autocd());
+    }
+
+    #[test]
+    fn test_toggle_mark() {
+        let mut s = create_test_state_with_buf(
+            6,
+            strings_to_ls_buf(vec!["..", "foo", "bar"]),
+        );
+
+        // the parent folder can't be marked
+        assert!(!s.toggle_mark_under_cursor());
+        assert!(s.marked_items().is_empty());
+
+        s.move_cursor(1, false);
+        assert!(s.toggle_mark_under_cursor());
+        s.move_cursor(1, false);
+        assert!(s.toggle_mark_under_cursor());
+        assert_eq!(s.marked_items(), &[PathBuf::from("/foo"), PathBuf::from("/bar")]);
+        assert!(s.is_marked(s.get_item_at_cursor_pos(1).unwrap()));
+        assert!(!s.is_marked(s.get_item_at_cursor_pos(0).unwrap()));
+
+        // marking again unmarks
+        assert!(s.toggle_mark_under_cursor());
+        assert_eq!(s.marked_items(), &[PathBuf::from("/foo")]);
+
+        s.clear_marks();
+        assert!(s.marked_items().is_empty());
+    }
+
+    #[test]
+    fn test_move_cursor_to_adjacent_mark() {
+        let mut s = create_test_state_with_buf(
+            6,
+            strings_to_ls_buf(vec!["..", "foo", "bar", "baz", "qux"]),
+        );
+        assert!(!s.move_cursor_to_adjacent_mark(1));
+
+        s.move_cursor_to(1);
+        s.toggle_mark_under_cursor();
+        s.move_cursor_to(3);
+        s.toggle_mark_under_cursor();
+        s.move_cursor_to(0);
+
+        assert!(s.move_cursor_to_adjacent_mark(1));
+        assert_eq!(s.cursor_pos, 1);
+        assert!(s.move_cursor_to_adjacent_mark(1));
+        assert_eq!(s.cursor_pos, 3);
+        // wraps around at the end
+        assert!(s.move_cursor_to_adjacent_mark(1));
+        assert_eq!(s.cursor_pos, 1);
+        assert!(s.move_cursor_to_adjacent_mark(-1));
+        assert_eq!(s.cursor_pos, 3);
+        assert!(s.move_cursor_to_adjacent_mark(-1));
+        assert_eq!(s.cursor_pos, 1);
+    }
+
+    #[test]
+    fn test_create_dir() {
+        let tmp = TempDir::new("create-dir");
+        std::fs::create_dir_all(tmp.join("a")).unwrap();
+
+        let mut s = create_test_state(6, 0);
+        s.current_path = tmp.to_path_buf();
+        s.advance_search("x");
+
+        s.create_dir("b").unwrap();
+        assert!(tmp.join("b").is_dir());
+        assert!(!s.is_searching());
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("b")));
+
+        // the folder already exists
+        assert!(s.create_dir("a").is_err());
+
+        for name in ["", ".", "..", "a/c", "../c", "/c"] {
+            let err = s.create_dir(name).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", name);
+        }
+        assert!(!tmp.join("a").join("c").exists());
+    }
+
+    #[test]
+    fn test_begin_change_dir() {
+        let tmp = TempDir::new("begin-change-dir");
+        std::fs::create_dir_all(tmp.join("a/b")).unwrap();
+
+        let mut s = create_test_state(6, 0);
+        s.current_path = tmp.to_path_buf();
+        s.update_ls_output_buf().unwrap();
+
+        s.begin_change_dir("a").unwrap();
+        assert!(s.is_loading());
+        assert_eq!(s.current_path, tmp.join("a"));
+        // typing while the folder is being read
+        s.advance_search("b");
+        while s.is_loading() {
+            if let Some(res) = s.poll_dir_loading(Duration::from_secs(5)) {
+                res.unwrap();
+            }
+        }
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("a/b")));
+        assert_eq!(s.num_matching_items(), 1);
+
+        // reading a folder that doesn't exist fails, and the previous folder is shown again
+        s.begin_change_dir("nonexistent").unwrap();
+        assert!(s.poll_dir_loading(Duration::from_secs(5)).unwrap().is_err());
+        assert_eq!(s.current_path, tmp.join("a"));
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("a/b")));
+        assert!(s.poll_dir_loading(Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn test_num_dirs_and_files() {
+        let tmp = TempDir::new("num-dirs-and-files");
+        std::fs::create_dir_all(tmp.join("bar")).unwrap();
+        std::fs::create_dir_all(tmp.join("baz")).unwrap();
+        for name in ["foo", "bar.txt", "quux"] {
+            std::fs::write(tmp.join(name), "").unwrap();
+        }
+
+        let mut s = create_test_state(10, 0);
+        s.current_path = tmp.to_path_buf();
+        s.update_ls_output_buf().unwrap();
+        assert_eq!(s.num_dirs_and_files(), (2, 3));
+        s.advance_search("ba");
+        assert_eq!(s.num_dirs_and_files(), (2, 1));
+        s.advance_search("r");
+        assert_eq!(s.num_dirs_and_files(), (1, 1));
+        s.clear_search();
+        s.toggle_folders_only().unwrap();
+        assert_eq!(s.num_dirs_and_files(), (2, 0));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_loop() {
+        use std::os::unix::fs::symlink;
+        let tmp = TempDir::new("symlink-loop");
+        std::fs::create_dir_all(tmp.join("a/b")).unwrap();
+        symlink("..", tmp.join("a/b/up")).unwrap();
+        symlink(".", tmp.join("a/b/self")).unwrap();
+        symlink("y", tmp.join("a/x")).unwrap();
+        symlink("x", tmp.join("a/y")).unwrap();
+
+        let mut s = create_test_state(10, 0);
+        s.current_path = tmp.join("a/b");
+        s.update_ls_output_buf().unwrap();
+
+        // following a link to a parent goes to the parent, instead of making the path longer
+        s.change_dir("up").unwrap();
+        assert_eq!(s.current_path, tmp.join("a"));
+        assert_eq!(s.take_symlink_loop(), Some(tmp.join("a")));
+        assert_eq!(s.take_symlink_loop(), None);
+        s.change_dir("b").unwrap();
+        assert_eq!(s.take_symlink_loop(), None);
+        s.change_dir("self").unwrap();
+        assert_eq!(s.current_path, tmp.join("a/b"));
+        assert!(s.take_symlink_loop().is_some());
+
+        // links that lead to each other can't be resolved
+        let entries = s.read_dir_cached(&tmp.join("a"), true).unwrap();
+        let is_loop = |name: &str| entries.iter().find(|e| e.file_name() == name).unwrap().is_symlink_loop();
+        assert!(is_loop("x"));
+        assert!(!is_loop("b"));
+        assert!(is_symlink_cycle(&tmp.join("a/y")));
+        assert!(!is_symlink_cycle(&tmp.join("a/b/up")));
+    }
+
+    #[test]
+    fn test_split_start_file() {
+        let tmp = TempDir::new("split-start-file");
+        std::fs::create_dir_all(tmp.join("foo")).unwrap();
+        std::fs::write(tmp.join("bar.txt"), "").unwrap();
+
+        assert_eq!(split_start_file(tmp.join("foo")), (tmp.join("foo"), None));
+        assert_eq!(
+            split_start_file(tmp.join("bar.txt")),
+            (tmp.to_path_buf(), Some(OsString::from("bar.txt"))),
+        );
+        // a path that doesn't exist is left for opening it to fail
+        assert_eq!(split_start_file(tmp.join("baz")), (tmp.join("baz"), None));
+
+        let mut s = create_test_state(10, 0);
+        s.current_path = tmp.to_path_buf();
+        s.update_ls_output_buf().unwrap();
+        assert!(s.move_cursor_to_filename("bar.txt"));
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("bar.txt")));
+    }
+
+    #[test]
+    fn test_num_hidden_items() {
+        let tmp = TempDir::new("num-hidden-items");
+        std::fs::create_dir_all(tmp.join("bar")).unwrap();
+        std::fs::create_dir_all(tmp.join(".baz")).unwrap();
+        for name in ["foo", ".bar.txt"] {
+            std::fs::write(tmp.join(name), "").unwrap();
+        }
+
+        let mut s = create_test_state(10, 0);
+        s.current_path = tmp.to_path_buf();
+        s.update_ls_output_buf().unwrap();
+        assert_eq!(s.num_hidden_items(), 0);
+        s.toggle_show_hidden().unwrap();
+        assert_eq!(s.num_hidden_items(), 2);
+        s.toggle_folders_only().unwrap();
+        assert_eq!(s.num_hidden_items(), 3);
+        s.toggle_folders_only().unwrap();
+
+        // the items that don't match are hidden only with filter search
+        s.advance_search("f");
+        assert_eq!(s.num_hidden_items(), 2);
+        s.settings.filter_search = true;
+        // '..' and 'bar' don't match
+        assert_eq!(s.num_hidden_items(), 4);
+    }
+
+    #[test]
+    fn test_cycle_filter_preset() {
+        let tmp = TempDir::new("cycle-filter-preset");
+        std::fs::create_dir_all(tmp.join("photos")).unwrap();
+        for name in ["a.jpg", "b.txt"] {
+            std::fs::write(tmp.join(name), "").unwrap();
+        }
+
+        let mut s = create_test_state(10, 0);
+        assert!(s.cycle_filter_preset().is_err());
+        s.settings.filter_presets = vec![
+            FilterPreset::parse("media:*.jpg").unwrap(),
+            FilterPreset::parse("folders:dirs").unwrap(),
+        ];
+        s.current_path = tmp.to_path_buf();
+        s.update_ls_output_buf().unwrap();
+        assert_eq!(s.num_dirs_and_files(), (1, 2));
+
+        s.cycle_filter_preset().unwrap();
+        assert_eq!(s.filter_preset().unwrap().name, "media");
+        assert_eq!(s.num_dirs_and_files(), (1, 1));
+        assert_eq!(s.num_hidden_items(), 1);
+        s.cycle_filter_preset().unwrap();
+        assert_eq!(s.num_dirs_and_files(), (1, 0));
+        s.cycle_filter_preset().unwrap();
+        assert!(s.filter_preset().is_none());
+        assert_eq!(s.num_dirs_and_files(), (1, 2));
+    }
+
+    #[test]
+    fn test_refresh_keeps_place() {
+        let tmp = TempDir::new("refresh-keeps-place");
+        for name in ["a1", "a2", "b1", "b2", "b3"] {
+            std::fs::create_dir(tmp.join(name)).unwrap();
+        }
+
+        let mut s = create_test_state(10, 0);
+        s.current_path = tmp.to_path_buf();
+        s.update_ls_output_buf().unwrap();
+        s.advance_search("b");
+        s.move_cursor_to_filename("b2");
+
+        // an item is added before the cursor, which stays on the same item
+        std::fs::create_dir(tmp.join("a0")).unwrap();
+        s.begin_refresh().unwrap();
+        while s.is_loading() {
+            if let Some(res) = s.poll_dir_loading(Duration::from_secs(5)) {
+                res.unwrap();
+            }
+        }
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("b2")));
+        assert_eq!(s.search_string, "b");
+        assert_eq!(s.num_matching_items(), 3);
+
+        // the item under the cursor is removed, so the cursor stays on the same row
+        std::fs::remove_dir(tmp.join("b2")).unwrap();
+        s.refresh().unwrap();
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("b3")));
+        assert_eq!(s.search_string, "b");
+        assert_eq!(s.num_matching_items(), 2);
+    }
+
+    #[test]
+    fn test_refresh_if_changed() {
+        let tmp = TempDir::new("refresh-if-changed");
+        for name in ["a", "c", "cc"] {
+            std::fs::write(tmp.join(name), "").unwrap();
+        }
+
+        let mut s = create_test_state(6, 0);
+        s.current_path = tmp.to_path_buf();
+        s.update_ls_output_buf().unwrap();
+        s.watch_current_dir();
+        s.advance_search("c");
+        s.move_cursor_to_filename("cc");
+
+        std::fs::write(tmp.join("b"), "").unwrap();
+        std::fs::write(tmp.join("ccc"), "").unwrap();
+        let deadline = std::time::Instant::now() + Duration::from_secs(2);
+        while !s.refresh_if_changed().unwrap() && std::time::Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(s.num_total_items(), 6);
+        // the search and the cursor are kept
+        assert_eq!(s.search_string(), "c");
+        assert_eq!(s.num_matching_items(), 3);
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("cc")));
+    }
+
+    #[test]
+    fn test_dir_source() {
+        let source = Arc::new(MemoryDirSource::new());
+        source.add_file("/home/user/notes.txt");
+        source.add_file("/home/user/docs/a.md");
+        source.add_dir("/home/other");
+        let mut s = TereAppState::with_dir_source(
+            Default::default(),
+            "/home/user".into(),
+            source.clone(),
+            10,
+            10,
+        )
+        .unwrap();
+        let names = |s: &TereAppState| -> Vec<String> {
+            s.visible_items().iter().map(|e| e.file_name_checked()).collect()
+        };
+        assert_eq!(names(&s), vec!["..", "docs", "notes.txt"]);
+        assert_eq!(s.item_under_cursor_path(), Some("/home/user/docs".into()));
+
+        s.change_dir("..").unwrap();
+        assert_eq!(s.current_path, PathBuf::from("/home"));
+        assert_eq!(s.item_under_cursor_path(), Some("/home/user".into()));
+        assert!(s.change_dir("nonexistent").is_err());
+        assert_eq!(s.current_path, PathBuf::from("/home"));
+
+        // the cached listing is not used after the folder has changed
+        source.add_file("/home/user/todo.txt");
+        s.change_dir("user").unwrap();
+        assert_eq!(names(&s), vec!["..", "docs", "notes.txt", "todo.txt"]);
+
+        s.begin_change_dir("docs").unwrap();
+        while s.is_loading() {
+            if let Some(res) = s.poll_dir_loading(Duration::from_secs(1)) {
+                res.unwrap();
+            }
+        }
+        assert_eq!(s.current_path, PathBuf::from("/home/user/docs"));
+        assert_eq!(names(&s), vec!["..", "a.md"]);
+    }
+
+    #[test]
+    fn test_normalize_path() {
+        assert_eq!(normalize_path(Path::new("/foo/./bar/../baz")), PathBuf::from("/foo/baz"));
+        assert_eq!(normalize_path(Path::new("/foo/../..")), PathBuf::from("/"));
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_normalize_unc_path() {
+        assert_eq!(
+            normalize_path(Path::new(r"\\server\share\foo\..\..")),
+            PathBuf::from(r"\\server\share\")
+        );
+        assert_eq!(
+            normalize_path(Path::new(r"\\?\UNC\server\share\foo")),
+            PathBuf::from(r"\\server\share\foo")
+        );
+        assert_eq!(normalize_path(Path::new(r"\\?\c:\foo")), PathBuf::from(r"C:\foo"));
+    }
+
+    #[test]
+    fn test_create_file() {
+        let tmp = TempDir::new("create-file");
+        std::fs::write(tmp.join("a"), "foo").unwrap();
+
+        let mut s = create_test_state(6, 0);
+        s.current_path = tmp.to_path_buf();
+
+        s.create_file("b").unwrap();
+        assert!(tmp.join("b").is_file());
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("b")));
+
+        // existing files are not truncated
+        s.create_file("a").unwrap();
+        assert_eq!(std::fs::read_to_string(tmp.join("a")).unwrap(), "foo");
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("a")));
+
+        for name in ["", ".", "..", "c/d", "../d", "/d"] {
+            let err = s.create_file(name).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidInput, "{}", name);
+        }
+    }
+
+    // other file systems, like APFS, don't allow names that aren't valid unicode
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_non_utf8_names() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let tmp = TempDir::new("non-utf8-names");
+        let name = OsStr::from_bytes(b"caf\xe9");
+        std::fs::create_dir(tmp.join(name)).unwrap();
+
+        let mut s = create_test_state(6, 0);
+        s.change_dir(tmp.to_str().unwrap()).unwrap();
+        let names: Vec<_> = s.visible_items().iter().map(|e| e.file_name_checked()).collect();
+        assert_eq!(names, vec!["..", "caf\u{FFFD}"]);
+
+        s.advance_search("caf");
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join(name)));
+        assert!(s.toggle_mark_under_cursor());
+        assert_eq!(s.marked_items(), &[tmp.join(name)]);
+
+        // the name is given as it's shown, for example when clicking on the item
+        s.change_dir("caf\u{FFFD}").unwrap();
+        assert_eq!(s.current_path, tmp.join(name));
+        s.change_dir("..").unwrap();
+
+        s.rename_item("caf\u{FFFD}", "cafe").unwrap();
+        assert!(tmp.join("cafe").is_dir());
+        assert_eq!(s.marked_items(), &[tmp.join("cafe")]);
+    }
+
+    #[test]
+    fn test_rename_item() {
+        let tmp = TempDir::new("rename-item");
+        std::fs::create_dir_all(tmp.join("a")).unwrap();
+        std::fs::create_dir_all(tmp.join("b")).unwrap();
+
+        let mut s = create_test_state(6, 0);
+        s.current_path = tmp.to_path_buf();
+        s.update_ls_output_buf().unwrap();
+        s.move_cursor_to_filename("a");
+        s.toggle_mark_under_cursor();
+
+        s.rename_item("a", "c").unwrap();
+        assert!(tmp.join("c").is_dir());
+        assert!(!tmp.join("a").exists());
+        assert_eq!(s.item_under_cursor_path(), Some(tmp.join("c")));
+        assert_eq!(s.marked_items(), &[tmp.join("c")]);
+
+        // existing items are not overwritten
+        assert_eq!(s.rename_item("c", "b").unwrap_err().kind(), ErrorKind::AlreadyExists);
+        for name in ["", "..", "x/y"] {
+            assert_eq!(s.rename_item("c", name).unwrap_err().kind(), ErrorKind::InvalidInput);
+        }
+        assert!(tmp.join("c").is_dir());
+    }
+
+    #[test]
+    fn test_list_mode() {
+        let mut s = create_test_state(6, 0);
+        s.list_items = Some(
+            ["foo/bar", "/baz"].iter().map(|x| CustomDirEntry::from_list_item(x)).collect()
+        );
+        s.update_ls_output_buf().unwrap();
+        assert_eq!(s.num_visible_items(), 2);
+
+        s.settings.autocd_timeout = Some(200);
+        s.settings.gap_search_mode = GapSearchMode::GapSearchAnywere;
+        s.advance_search("baz");
+        assert_eq!(s.num_matching_items(), 1);
+        assert!(!s.should_autocd()); // choosing exits the app, so never automatically
+        s.clear_search();
+
+        // the items are chosen as-is, and not relative to the current folder
+        assert!(s.choose_file(""));
+        assert_eq!(s.chosen_file, Some(PathBuf::from("/baz")));
+
+        s.move_cursor(-1, false);
+        assert!(s.toggle_mark_under_cursor());
+        assert_eq!(s.marked_items(), &[PathBuf::from("foo/bar")]);
+
+        assert!(s.change_dir("..").is_err());
+    }
+
+    #[test]
+    fn test_no_match_discard() {
+        let mut s = create_test_state_with_buf(6, strings_to_ls_buf(vec!["..", "bar", "baz", "foo"]));
+        s.settings.no_match = NoMatchBehavior::Discard;
+
+        assert!(s.advance_search("ba"));
+        assert_eq!(s.num_matching_items(), 2);
+        assert!(!s.advance_search("x"));
+        assert_eq!(s.search_string(), "ba");
+        assert_eq!(s.num_matching_items(), 2);
+        // the characters that do match are still added
+        assert!(!s.advance_search("xr"));
+        assert_eq!(s.search_string(), "bar");
+        assert_eq!(s.num_matching_items(), 1);
+
+        s.clear_search();
+        assert!(!s.advance_search("q"));
+        assert_eq!(s.search_string(), "");
+
+        s.settings.no_match = NoMatchBehavior::Keep;
+        assert!(s.advance_search("q"));
+        assert_eq!(s.num_matching_items(), 0);
+    }
+
+    #[test]
+    fn test_search_folders_only() {
+        let items: Vec<_> = [("..", true), ("foo.txt", false), ("food", true), ("fox", false)]
+            .iter()
+            .map(|(name, is_dir)| CustomDirEntry::new_virtual(PathBuf::from(name), *is_dir))
+            .collect();
+        let mut s = create_test_state_with_buf(6, items.into());
+        s.settings.search_folders_only = true;
+
+        // without a query, the files are there as usual
+        s.advance_search("");
+        assert_eq!(s.num_matching_items(), 4);
+
+        s.advance_search("fo");
+        assert_eq!(s.num_matching_items(), 1);
+        assert_eq!(s.cursor_pos, 2);
+        s.move_cursor_to_adjacent_match(1);
+        assert_eq!(s.cursor_pos, 2);
+        // the files are still shown
+        assert_eq!(s.visible_items().len(), 4);
+
+        s.erase_search_char();
+        s.erase_search_char();
+        assert_eq!(s.num_matching_items(), 4);
+    }
+
     #[test]
     fn test_advance_and_erase_with_filter_search() {
         let mut s = create_test_state_with_buf(
@@ -0,0 +1,712 @@
+/// Browsing zip and tar archives as if they were folders. A path inside an archive is the path of
+/// the archive file followed by the path of the item in it, like '/home/me/src.zip/src/main.rs'.
+/// The list of items in an archive is read once when it's entered, and kept until the archive file
+/// changes. The contents of the items are only read when they are extracted. Compressed tar files
+/// are decompressed in memory, since the whole file has to be read to list it anyway, so their
+/// size is limited.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::ffi::OsStr;
+use std::hash::{Hash, Hasher};
+use std::io::{Error as IOError, ErrorKind, Read, Result as IOResult};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use flate2::read::{DeflateDecoder, MultiGzDecoder};
+
+use super::{CustomDirEntry, DirBatches, DirSource, FsDirSource};
+
+/// The kinds of archives that can be browsed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveKind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+impl ArchiveKind {
+    /// The kind of the archive `name`, based on its extension
+    fn from_name(name: &OsStr) -> Option<Self> {
+        let name = name.to_str()?.to_lowercase();
+        if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else if name.ends_with(".tar") {
+            Some(Self::Tar)
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else {
+            None
+        }
+    }
+}
+
+fn invalid(kind: &str, msg: &str) -> IOError {
+    IOError::new(ErrorKind::InvalidData, format!("Invalid {} archive: {}", kind, msg))
+}
+
+/// An item in an archive
+struct ArchiveEntry {
+    /// The path of the item in the archive, relative to its root
+    path: PathBuf,
+    is_dir: bool,
+    /// Where the contents of a file are, and how to decompress them. `None` for folders, and for
+    /// items that can't be extracted, like symlinks.
+    data: Option<EntryData>,
+}
+
+#[derive(Clone, Copy)]
+enum EntryData {
+    /// The contents are stored as they are at this offset of the (decompressed) archive
+    Stored { offset: usize, len: usize },
+    /// The contents are compressed with deflate at this offset of the archive
+    Deflated { offset: usize, len: usize },
+}
+
+/// The path of an item in an archive, without components like '..' that would point outside of
+/// it. `None` if nothing is left.
+fn clean_entry_path(name: &str) -> Option<PathBuf> {
+    let path: PathBuf = Path::new(name)
+        .components()
+        .filter_map(|c| match c {
+            Component::Normal(c) => Some(c),
+            _ => None,
+        })
+        .collect();
+    (!path.as_os_str().is_empty()).then_some(path)
+}
+
+/// List the items of the zip archive `data` from its central directory
+fn read_zip(data: &[u8]) -> IOResult<Vec<ArchiveEntry>> {
+    let u16_at = |pos: usize| -> IOResult<usize> {
+        data.get(pos..pos + 2)
+            .map(|b| u16::from_le_bytes(b.try_into().unwrap()) as usize)
+            .ok_or_else(|| invalid("zip", "truncated file"))
+    };
+    let u32_at = |pos: usize| -> IOResult<usize> {
+        data.get(pos..pos + 4)
+            .map(|b| u32::from_le_bytes(b.try_into().unwrap()) as usize)
+            .ok_or_else(|| invalid("zip", "truncated file"))
+    };
+
+    // the end of central directory record is at the end, followed by a comment of up to 64 KiB
+    const EOCD_SIGNATURE: &[u8] = b"PK\x05\x06";
+    let search_start = data.len().saturating_sub(22 + 0xffff);
+    let eocd = data[search_start..]
+        .windows(4)
+        .rposition(|w| w == EOCD_SIGNATURE)
+        .map(|pos| search_start + pos)
+        .ok_or_else(|| invalid("zip", "no central directory"))?;
+    let n_entries = u16_at(eocd + 10)?;
+    let mut pos = u32_at(eocd + 16)?;
+    if pos == 0xffff_ffff {
+        return Err(invalid("zip", "ZIP64 archives are not supported"));
+    }
+
+    let mut entries = vec![];
+    for _ in 0..n_entries {
+        if data.get(pos..pos + 4) != Some(b"PK\x01\x02") {
+            return Err(invalid("zip", "broken central directory"));
+        }
+        let method = u16_at(pos + 10)?;
+        let compressed_len = u32_at(pos + 20)?;
+        let name_len = u16_at(pos + 28)?;
+        let extra_len = u16_at(pos + 30)?;
+        let comment_len = u16_at(pos + 32)?;
+        let local_header = u32_at(pos + 42)?;
+        let name = data
+            .get(pos + 46..pos + 46 + name_len)
+            .ok_or_else(|| invalid("zip", "truncated file"))?;
+        let name = String::from_utf8_lossy(name);
+        pos += 46 + name_len + extra_len + comment_len;
+
+        let path = match clean_entry_path(&name) {
+            Some(path) => path,
+            None => continue,
+        };
+        let is_dir = name.ends_with('/');
+        // the local header has its own name and extra field, which can differ from the ones in
+        // the central directory
+        let offset = local_header + 30 + u16_at(local_header + 26)? + u16_at(local_header + 28)?;
+        let data = match method {
+            _ if is_dir => None,
+            0 => Some(EntryData::Stored { offset, len: compressed_len }),
+            8 => Some(EntryData::Deflated { offset, len: compressed_len }),
+            _ => None,
+        };
+        entries.push(ArchiveEntry { path, is_dir, data });
+    }
+    Ok(entries)
+}
+
+/// The value of a numeric field of a tar header, which is in octal
+fn tar_number(field: &[u8]) -> IOResult<usize> {
+    let digits = std::str::from_utf8(field)
+        .map_err(|_| invalid("tar", "bad number"))?
+        .trim_matches(|c: char| c == '\0' || c == ' ');
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    usize::from_str_radix(digits, 8).map_err(|_| invalid("tar", "bad number"))
+}
+
+/// A string field of a tar header, which ends at the first NUL
+fn tar_string(field: &[u8]) -> &[u8] {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..len]
+}
+
+/// List the items of the uncompressed tar archive `data`
+fn read_tar(data: &[u8]) -> IOResult<Vec<ArchiveEntry>> {
+    const BLOCK: usize = 512;
+    let mut entries = vec![];
+    let mut pos = 0;
+    // the name of the next item, from a GNU long name or a pax header
+    let mut long_name: Option<String> = None;
+    while pos + BLOCK <= data.len() {
+        let header = &data[pos..pos + BLOCK];
+        if header.iter().all(|&b| b == 0) {
+            break;
+        }
+        let size = tar_number(&header[124..136])?;
+        let type_flag = header[156];
+        let offset = pos + BLOCK;
+        let contents = data
+            .get(offset..offset + size)
+            .ok_or_else(|| invalid("tar", "truncated file"))?;
+        pos = offset + size.div_ceil(BLOCK) * BLOCK;
+
+        match type_flag {
+            b'L' => {
+                long_name = Some(String::from_utf8_lossy(tar_string(contents)).into_owned());
+                continue;
+            }
+            b'x' => {
+                // pax records are like '<length> <key>=<value>\n'
+                let records = String::from_utf8_lossy(contents);
+                long_name = records
+                    .lines()
+                    .filter_map(|record| record.split_once(' ')?.1.strip_prefix("path="))
+                    .next()
+                    .map(String::from)
+                    .or(long_name);
+                continue;
+            }
+            b'g' => continue,
+            _ => {}
+        }
+
+        let name = match long_name.take() {
+            Some(name) => name,
+            None => {
+                let name = String::from_utf8_lossy(tar_string(&header[0..100])).into_owned();
+                // ustar archives can have the beginning of long paths in a separate prefix
+                match tar_string(&header[345..500]) {
+                    prefix if &header[257..262] == b"ustar" && !prefix.is_empty() => {
+                        format!("{}/{}", String::from_utf8_lossy(prefix), name)
+                    }
+                    _ => name,
+                }
+            }
+        };
+        let path = match clean_entry_path(&name) {
+            Some(path) => path,
+            None => continue,
+        };
+        let is_dir = type_flag == b'5' || (type_flag == b'0' && name.ends_with('/'));
+        let data = match type_flag {
+            b'0' | b'\0' | b'7' if !is_dir => Some(EntryData::Stored { offset, len: size }),
+            _ => None,
+        };
+        entries.push(ArchiveEntry { path, is_dir, data });
+    }
+    Ok(entries)
+}
+
+/// The largest size that an archive or an item in it is decompressed to. They are decompressed
+/// in memory, and a small file can decompress to a huge one.
+const MAX_DECOMPRESSED_SIZE: u64 = 512 << 20;
+
+/// Read everything from `reader`, which decompresses something of the given kind, failing if
+/// there's more than `limit` bytes
+fn read_limited(reader: impl Read, kind: &str, limit: u64) -> IOResult<Vec<u8>> {
+    let mut out = vec![];
+    reader
+        .take(limit + 1)
+        .read_to_end(&mut out)
+        .map_err(|e| invalid(kind, &e.to_string()))?;
+    if out.len() as u64 > limit {
+        return Err(invalid(
+            kind,
+            &format!("larger than {} when decompressed", crate::file_ops::format_size(limit)),
+        ));
+    }
+    Ok(out)
+}
+
+/// Decompress the gzip file `data`
+fn gunzip(data: &[u8]) -> IOResult<Vec<u8>> {
+    read_limited(MultiGzDecoder::new(data), "gzip", MAX_DECOMPRESSED_SIZE)
+}
+
+/// Decompress the raw deflate stream `data`
+fn inflate(data: &[u8]) -> IOResult<Vec<u8>> {
+    read_limited(DeflateDecoder::new(data), "deflate", MAX_DECOMPRESSED_SIZE)
+}
+
+/// The contents of an archive file, decompressed if it's a compressed tar file, and its items
+struct Archive {
+    data: Vec<u8>,
+    entries: Vec<ArchiveEntry>,
+}
+
+impl Archive {
+    fn open(path: &Path, kind: ArchiveKind) -> IOResult<Self> {
+        let data = std::fs::read(path)?;
+        let (data, entries) = match kind {
+            ArchiveKind::Zip => {
+                let entries = read_zip(&data)?;
+                (data, entries)
+            }
+            ArchiveKind::Tar => {
+                let entries = read_tar(&data)?;
+                (data, entries)
+            }
+            ArchiveKind::TarGz => {
+                let data = gunzip(&data)?;
+                let entries = read_tar(&data)?;
+                (data, entries)
+            }
+        };
+        Ok(Self { data, entries })
+    }
+
+    /// The contents of the file `entry`
+    fn contents(&self, data: EntryData) -> IOResult<Vec<u8>> {
+        let get = |offset: usize, len: usize| {
+            self.data
+                .get(offset..offset + len)
+                .ok_or_else(|| invalid("zip", "truncated file"))
+        };
+        match data {
+            EntryData::Stored { offset, len } => Ok(get(offset, len)?.to_vec()),
+            EntryData::Deflated { offset, len } => inflate(get(offset, len)?),
+        }
+    }
+}
+
+/// The names of the items in each folder of an archive, and whether they are folders. The root
+/// of the archive is the empty path.
+struct ArchiveIndex {
+    dirs: HashMap<PathBuf, Vec<(PathBuf, bool)>>,
+}
+
+impl ArchiveIndex {
+    fn new(entries: &[ArchiveEntry]) -> Self {
+        let mut dirs: HashMap<PathBuf, Vec<(PathBuf, bool)>> = HashMap::new();
+        dirs.insert(PathBuf::new(), vec![]);
+        for entry in entries {
+            // archives don't always have entries for the folders, so add the parents of every
+            // item too
+            let mut path = entry.path.as_path();
+            let mut is_dir = entry.is_dir;
+            while let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+                let items = dirs.entry(parent.to_path_buf()).or_default();
+                match items.iter_mut().find(|(n, _)| n == name) {
+                    Some(item) => {
+                        item.1 |= is_dir;
+                        break;
+                    }
+                    None => items.push((name.into(), is_dir)),
+                }
+                if is_dir {
+                    dirs.entry(path.to_path_buf()).or_default();
+                }
+                path = parent;
+                is_dir = true;
+            }
+            if is_dir {
+                dirs.entry(path.to_path_buf()).or_default();
+            }
+        }
+        Self { dirs }
+    }
+}
+
+/// The file system, where zip and tar archives can be entered like folders. They are listed as
+/// folders, so that they can be entered like any other folder.
+#[derive(Default)]
+pub struct ArchiveDirSource {
+    // The indexes of the archives that have been opened
+    indexes: Mutex<HashMap<PathBuf, CachedIndex>>,
+}
+
+/// The index of an archive, and the modification time of the archive file when it was read
+type CachedIndex = (Option<SystemTime>, Arc<ArchiveIndex>);
+
+impl ArchiveDirSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn index(&self, archive: &Path, kind: ArchiveKind) -> IOResult<Arc<ArchiveIndex>> {
+        let mtime = std::fs::metadata(archive).and_then(|m| m.modified()).ok();
+        if let Some((cached_mtime, index)) = self.indexes.lock().unwrap().get(archive) {
+            if mtime.is_some() && *cached_mtime == mtime {
+                return Ok(index.clone());
+            }
+        }
+        let index = Arc::new(ArchiveIndex::new(&Archive::open(archive, kind)?.entries));
+        self.indexes
+            .lock()
+            .unwrap()
+            .insert(archive.to_path_buf(), (mtime, index.clone()));
+        Ok(index)
+    }
+}
+
+/// Split a path inside an archive into the path of the archive file and the path of the item
+/// in it. Returns `None` if the path is not inside an archive, or if it's an archive file that
+/// is not an actual file.
+pub fn split_archive_path(path: &Path) -> Option<(PathBuf, PathBuf)> {
+    // the path of a folder on the file system can't be inside an archive, so only one call is
+    // needed for most paths
+    if path.is_dir() {
+        return None;
+    }
+    path.ancestors()
+        .find(|p| p.file_name().and_then(ArchiveKind::from_name).is_some() && p.is_file())
+        .map(|archive| {
+            // ok to unwrap, archive is an ancestor of path
+            let inner = path.strip_prefix(archive).unwrap().to_path_buf();
+            (archive.to_path_buf(), inner)
+        })
+}
+
+fn not_found(path: &Path) -> IOError {
+    IOError::new(
+        ErrorKind::NotFound,
+        format!("No such folder: '{}'", path.display()),
+    )
+}
+
+impl DirSource for ArchiveDirSource {
+    fn read_dir(&self, path: &Path) -> IOResult<DirBatches> {
+        if let Some((archive, inner)) = split_archive_path(path) {
+            // ok to unwrap, split_archive_path only finds archives with a known kind
+            let kind = archive.file_name().and_then(ArchiveKind::from_name).unwrap();
+            let index = self.index(&archive, kind)?;
+            let items = index.dirs.get(&inner).ok_or_else(|| not_found(path))?;
+            let entries = items
+                .iter()
+                .map(|(name, is_dir)| CustomDirEntry::new_virtual(path.join(name), *is_dir))
+                .collect();
+            return Ok(Box::new(std::iter::once(entries)));
+        }
+        let batches = FsDirSource.read_dir(path)?;
+        Ok(Box::new(batches.map(|entries| {
+            entries
+                .into_iter()
+                .map(|entry| {
+                    if !entry.is_dir() && ArchiveKind::from_name(entry.os_file_name()).is_some() {
+                        CustomDirEntry::new_virtual(entry.path().to_path_buf(), true)
+                    } else {
+                        entry
+                    }
+                })
+                .collect()
+        })))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        match split_archive_path(path) {
+            // the items in an archive can only change if the archive file changes
+            Some((archive, _)) => FsDirSource.modified(&archive),
+            None => FsDirSource.modified(path),
+        }
+    }
+
+    fn enter_dir(&self, path: &Path) -> IOResult<()> {
+        match split_archive_path(path) {
+            Some((archive, inner)) => {
+                // ok to unwrap, like in read_dir
+                let kind = archive.file_name().and_then(ArchiveKind::from_name).unwrap();
+                match self.index(&archive, kind)?.dirs.contains_key(&inner) {
+                    true => Ok(()),
+                    false => Err(not_found(path)),
+                }
+            }
+            None => FsDirSource.enter_dir(path),
+        }
+    }
+
+    fn is_virtual(&self, path: &Path) -> bool {
+        split_archive_path(path).is_some()
+    }
+}
+
+/// The folder where archived items are extracted to. Only the current user can access it, so
+/// that other users can't read the extracted files, or replace them with symlinks beforehand.
+fn extract_dir() -> IOResult<PathBuf> {
+    let dir = dirs::cache_dir()
+        .ok_or_else(|| IOError::new(ErrorKind::NotFound, "No cache folder to extract archives to"))?
+        .join(env!("CARGO_PKG_NAME"))
+        .join("archives");
+    let mut builder = std::fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(&dir)?;
+    check_no_symlinks(dir.parent().unwrap_or(&dir), &dir)?;
+    // the folder may have been created earlier with other permissions
+    #[cfg(unix)]
+    std::fs::set_permissions(&dir, std::os::unix::fs::PermissionsExt::from_mode(0o700))?;
+    Ok(dir)
+}
+
+/// Check that none of the items from `base` to `path` (excluding `base`) are symlinks, so that
+/// writing to `path` doesn't end up somewhere else
+fn check_no_symlinks(base: &Path, path: &Path) -> IOResult<()> {
+    let mut current = base.to_path_buf();
+    for component in path.strip_prefix(base).unwrap_or(path).components() {
+        current.push(component);
+        match current.symlink_metadata() {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                return Err(IOError::new(
+                    ErrorKind::InvalidInput,
+                    format!("Refusing to extract through the symlink '{}'", current.display()),
+                ));
+            }
+            Ok(_) => {}
+            // nothing further down exists either
+            Err(_) => break,
+        }
+    }
+    Ok(())
+}
+
+/// Extract the item `path` inside an archive, and everything in it if it's a folder, to a
+/// folder in the cache folder. Returns the path of the extracted item. Items that can't be
+/// extracted, like symlinks, are skipped.
+pub fn extract(path: &Path) -> IOResult<PathBuf> {
+    extract_to(path, &extract_dir()?)
+}
+
+/// Extract the item `path` like in `extract`, to a subfolder of `base`.
+fn extract_to(path: &Path, base: &Path) -> IOResult<PathBuf> {
+    let (archive_path, inner) = split_archive_path(path).ok_or_else(|| not_found(path))?;
+    // ok to unwrap, like in read_dir
+    let kind = archive_path.file_name().and_then(ArchiveKind::from_name).unwrap();
+    let archive = Archive::open(&archive_path, kind)?;
+
+    // archives with the same name in different folders are extracted to different folders
+    let mut hasher = DefaultHasher::new();
+    archive_path.hash(&mut hasher);
+    let dest = base
+        .join(format!("{:016x}", hasher.finish()))
+        // ok to unwrap, split_archive_path only returns archives with file names
+        .join(archive_path.file_name().unwrap());
+
+    for entry in &archive.entries {
+        if !entry.path.starts_with(&inner) {
+            continue;
+        }
+        let target = dest.join(&entry.path);
+        check_no_symlinks(base, &target)?;
+        match entry.data {
+            _ if entry.is_dir => std::fs::create_dir_all(&target)?,
+            Some(data) => {
+                if let Some(parent) = target.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&target, archive.contents(data)?)?;
+            }
+            None => {}
+        }
+    }
+    // joining an empty path would add a trailing slash
+    let ret = match inner.as_os_str().is_empty() {
+        true => dest,
+        false => dest.join(&inner),
+    };
+    if !ret.exists() {
+        // an empty folder that only exists as the parent of other items
+        check_no_symlinks(base, &ret)?;
+        std::fs::create_dir_all(&ret)?;
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    // A tar archive with 'dir/a.txt' containing "hello\n" and 'b.txt' containing "tere\n",
+    // compressed with gzip
+    const TAR_GZ: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xed, 0xd4, 0x41, 0x0a, 0x83,
+        0x30, 0x10, 0x85, 0xe1, 0x59, 0x7b, 0x0a, 0x4f, 0x50, 0x25, 0x75, 0x92, 0xf3, 0x28, 0x06,
+        0x5a, 0x10, 0x0a, 0x69, 0x0a, 0x3d, 0x7e, 0x07, 0x97, 0x41, 0xc4, 0x4d, 0x84, 0xe2, 0xff,
+        0x6d, 0x5e, 0x76, 0xb3, 0x78, 0xbc, 0xcc, 0xcf, 0xd4, 0x49, 0x65, 0xbd, 0x09, 0xaa, 0x6b,
+        0x9a, 0x32, 0x37, 0xde, 0x5e, 0xdd, 0x5d, 0x5a, 0x95, 0x13, 0x7c, 0xde, 0x79, 0x4c, 0x76,
+        0x52, 0xae, 0x69, 0xb6, 0xfe, 0xc7, 0x5b, 0xfe, 0xe6, 0xca, 0xfd, 0xfb, 0x61, 0xd8, 0xe9,
+        0xdf, 0x17, 0xfd, 0x07, 0xb5, 0x68, 0x7b, 0xfa, 0xaf, 0xee, 0x11, 0x97, 0xe5, 0xd5, 0x08,
+        0x2e, 0x6a, 0xaa, 0xbc, 0xfd, 0x63, 0xfb, 0xd7, 0xf2, 0xff, 0x0f, 0xce, 0xb1, 0xff, 0x33,
+        0xe4, 0x98, 0x22, 0xf3, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0xe0, 0xbf, 0xfd, 0x00, 0xc3, 0xc1, 0x9b, 0x96, 0x00, 0x28, 0x00, 0x00,
+    ];
+
+    // A zip archive with the folder 'src/', 'src/main.rs' containing `fn main() {}` stored
+    // as it is, and 'notes.txt' containing "tere " repeated 20 times, deflated
+    const ZIP: &[u8] = &[
+        0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x50, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x00, 0x00, 0x00,
+        0x73, 0x72, 0x63, 0x2f, 0x50, 0x4b, 0x03, 0x04, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x21, 0x50, 0x8e, 0x7f, 0x41, 0x9a, 0x0c, 0x00, 0x00, 0x00, 0x0c, 0x00, 0x00, 0x00,
+        0x0b, 0x00, 0x00, 0x00, 0x73, 0x72, 0x63, 0x2f, 0x6d, 0x61, 0x69, 0x6e, 0x2e, 0x72, 0x73,
+        0x66, 0x6e, 0x20, 0x6d, 0x61, 0x69, 0x6e, 0x28, 0x29, 0x20, 0x7b, 0x7d, 0x50, 0x4b, 0x03,
+        0x04, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00, 0x00, 0x00, 0x21, 0x50, 0xc6, 0x48, 0x3a, 0xe3,
+        0x0a, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00, 0x00, 0x09, 0x00, 0x00, 0x00, 0x6e, 0x6f, 0x74,
+        0x65, 0x73, 0x2e, 0x74, 0x78, 0x74, 0x2b, 0x49, 0x2d, 0x4a, 0x55, 0x28, 0xa1, 0x2d, 0x01,
+        0x00, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x21, 0x50, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, 0x00, 0x00,
+        0x00, 0x00, 0x73, 0x72, 0x63, 0x2f, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x21, 0x50, 0x8e, 0x7f, 0x41, 0x9a, 0x0c, 0x00, 0x00, 0x00,
+        0x0c, 0x00, 0x00, 0x00, 0x0b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x80, 0x01, 0x22, 0x00, 0x00, 0x00, 0x73, 0x72, 0x63, 0x2f, 0x6d, 0x61, 0x69, 0x6e,
+        0x2e, 0x72, 0x73, 0x50, 0x4b, 0x01, 0x02, 0x14, 0x03, 0x14, 0x00, 0x00, 0x00, 0x08, 0x00,
+        0x00, 0x00, 0x21, 0x50, 0xc6, 0x48, 0x3a, 0xe3, 0x0a, 0x00, 0x00, 0x00, 0x64, 0x00, 0x00,
+        0x00, 0x09, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01,
+        0x57, 0x00, 0x00, 0x00, 0x6e, 0x6f, 0x74, 0x65, 0x73, 0x2e, 0x74, 0x78, 0x74, 0x50, 0x4b,
+        0x05, 0x06, 0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x03, 0x00, 0xa2, 0x00, 0x00, 0x00, 0x88,
+        0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn test_inflate() {
+        // a stored block, and a fixed Huffman block with a back-reference
+        assert_eq!(inflate(&[0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c']).unwrap(), b"abc");
+        assert_eq!(
+            inflate(&[0x2b, 0x49, 0x2d, 0x4a, 0x55, 0x28, 0xa1, 0x2d, 0x01, 0x00]).unwrap(),
+            b"tere ".repeat(20)
+        );
+        assert!(inflate(&[0x2b, 0x49, 0x2d]).is_err());
+        // decompressing stops at the limit
+        let data: &[u8] = &[0x2b, 0x49, 0x2d, 0x4a, 0x55, 0x28, 0xa1, 0x2d, 0x01, 0x00];
+        assert!(read_limited(DeflateDecoder::new(data), "deflate", 99).is_err());
+        assert_eq!(read_limited(DeflateDecoder::new(data), "deflate", 100).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_read_archives() {
+        let data = gunzip(TAR_GZ).unwrap();
+        let entries = read_tar(&data).unwrap();
+        let archive = Archive { data, entries };
+        let names: Vec<_> = archive.entries.iter().map(|e| (e.path.clone(), e.is_dir)).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("dir".into(), true),
+                ("dir/a.txt".into(), false),
+                ("b.txt".into(), false),
+            ]
+        );
+        assert_eq!(archive.contents(archive.entries[1].data.unwrap()).unwrap(), b"hello\n");
+
+        let entries = read_zip(ZIP).unwrap();
+        let archive = Archive { data: ZIP.to_vec(), entries };
+        let names: Vec<_> = archive.entries.iter().map(|e| (e.path.clone(), e.is_dir)).collect();
+        assert_eq!(
+            names,
+            vec![
+                ("src".into(), true),
+                ("src/main.rs".into(), false),
+                ("notes.txt".into(), false),
+            ]
+        );
+        assert_eq!(archive.contents(archive.entries[1].data.unwrap()).unwrap(), b"fn main() {}");
+        assert_eq!(archive.contents(archive.entries[2].data.unwrap()).unwrap(), b"tere ".repeat(20));
+
+        assert!(read_zip(b"not a zip file").is_err());
+        assert!(gunzip(b"not a gzip file").is_err());
+    }
+
+    #[test]
+    fn test_archive_index() {
+        let entry = |path: &str, is_dir| ArchiveEntry { path: path.into(), is_dir, data: None };
+        let index = ArchiveIndex::new(&[
+            entry("a/b/c.txt", false),
+            entry("a/b", true),
+            entry("d.txt", false),
+            entry("../../etc/passwd", false),
+        ]);
+        assert_eq!(index.dirs[Path::new("")], vec![("a".into(), true), ("d.txt".into(), false)]);
+        assert_eq!(index.dirs[Path::new("a")], vec![("b".into(), true)]);
+        assert_eq!(index.dirs[Path::new("a/b")], vec![("c.txt".into(), false)]);
+        assert_eq!(clean_entry_path("../../etc/passwd"), Some("etc/passwd".into()));
+        assert_eq!(clean_entry_path("/"), None);
+    }
+
+    #[test]
+    fn test_archive_dir_source() {
+        let tmp = TempDir::new("archive-dir-source");
+        std::fs::create_dir_all(tmp.join("folder")).unwrap();
+        std::fs::write(tmp.join("code.zip"), ZIP).unwrap();
+        std::fs::write(tmp.join("files.tgz"), TAR_GZ).unwrap();
+        std::fs::write(tmp.join("broken.zip"), "not a zip file").unwrap();
+
+        let source = ArchiveDirSource::new();
+        let names = |path: &Path| -> Vec<(String, bool)> {
+            let mut names: Vec<_> = source
+                .read_dir(path)
+                .unwrap()
+                .flatten()
+                .map(|e| (e.file_name_checked(), e.is_dir()))
+                .collect();
+            names.sort();
+            names
+        };
+        assert_eq!(
+            names(&tmp),
+            vec![
+                ("broken.zip".into(), true),
+                ("code.zip".into(), true),
+                ("files.tgz".into(), true),
+                ("folder".into(), true),
+            ]
+        );
+        assert_eq!(
+            names(&tmp.join("code.zip")),
+            vec![("notes.txt".into(), false), ("src".into(), true)]
+        );
+        assert_eq!(names(&tmp.join("files.tgz/dir")), vec![("a.txt".into(), false)]);
+        assert!(source.enter_dir(&tmp.join("code.zip/src")).is_ok());
+        assert!(source.enter_dir(&tmp.join("code.zip/nonexistent")).is_err());
+        assert!(source.read_dir(&tmp.join("broken.zip")).is_err());
+        assert!(source.is_virtual(&tmp.join("code.zip/src")));
+        assert!(!source.is_virtual(&tmp.join("folder")));
+        assert_eq!(
+            source.modified(&tmp.join("code.zip/src")),
+            source.modified(&tmp.join("code.zip"))
+        );
+
+        let base = tmp.join("extracted");
+        let file = extract_to(&tmp.join("files.tgz/dir/a.txt"), &base).unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "hello\n");
+        let dir = extract_to(&tmp.join("code.zip/src"), &base).unwrap();
+        assert_eq!(std::fs::read_to_string(dir.join("main.rs")).unwrap(), "fn main() {}");
+        assert!(!dir.parent().unwrap().join("notes.txt").exists());
+
+        // symlinks planted in the extraction folder are not followed
+        #[cfg(unix)]
+        {
+            std::fs::remove_dir_all(&dir).unwrap();
+            std::os::unix::fs::symlink(tmp.join("folder"), &dir).unwrap();
+            assert!(extract_to(&tmp.join("code.zip/src"), &base).is_err());
+            assert!(!tmp.join("folder/main.rs").exists());
+        }
+    }
+}
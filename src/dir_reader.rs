@@ -0,0 +1,530 @@
+/// Reading the contents of folders from a `DirSource`, optionally in a background thread so that
+/// slow file systems (like network mounts) and huge folders don't freeze the UI. In the
+/// background, the entries are streamed in batches, so that the first ones can be shown before
+/// the whole folder has been read. Listings of recently visited folders are cached, so that going
+/// back to them is instant.
+use std::io::{Error as IOError, Result as IOResult};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, TryRecvError};
+use std::sync::Arc;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use serde_json::{json, Value};
+
+use super::{CustomDirEntry, DirSource};
+
+/// How often the background thread sends the entries it has read so far
+const BATCH_INTERVAL: Duration = Duration::from_millis(10);
+
+/// New entries are merged into the listing once there are at least this many of them, or a
+/// quarter of the number of entries in the listing, whichever is more. Merging less often as
+/// the listing grows keeps the total cost of merging linear in the number of entries.
+const MIN_MERGE_SIZE: usize = 1000;
+
+/// The maximum number of folders whose listings are cached
+const MAX_CACHED_DIRS: usize = 32;
+
+/// The maximum total number of entries in the listings that are saved to the listing cache file.
+/// Loading the file has to stay much faster than reading the folders again.
+const MAX_SAVED_ENTRIES: usize = 100_000;
+
+/// The version of the format of the listing cache file. Files with other versions are ignored.
+/// Version 1 files could contain listings without files.
+const CACHE_FILE_VERSION: u64 = 2;
+
+/// The maximum total number of entries in the cached listings, to limit the memory usage
+const MAX_CACHED_ENTRIES: usize = 500_000;
+
+/// The key by which entries are sorted: folders first, and then by name, ignoring case. Names
+/// that only differ by case are sorted by the path, so that the order doesn't depend on the
+/// order in which the file system lists the entries.
+fn sort_key(entry: &CustomDirEntry) -> (bool, &str, &Path) {
+    (!entry.is_dir(), entry.lowercase_file_name(), entry.path())
+}
+
+/// Sort folders first, and then by name, ignoring case.
+fn sort_entries(entries: &mut [CustomDirEntry]) {
+    entries.sort_by(|a, b| sort_key(a).cmp(&sort_key(b)));
+}
+
+/// Merge two lists of entries that are both sorted with `sort_entries`.
+pub fn merge_sorted(a: Vec<CustomDirEntry>, b: Vec<CustomDirEntry>) -> Vec<CustomDirEntry> {
+    let mut merged = Vec::with_capacity(a.len() + b.len());
+    let mut a = a.into_iter().peekable();
+    let mut b = b.into_iter().peekable();
+    loop {
+        let take_a = match (a.peek(), b.peek()) {
+            (Some(x), Some(y)) => sort_key(x) <= sort_key(y),
+            (Some(_), None) => true,
+            (None, Some(_)) => false,
+            (None, None) => break,
+        };
+        // ok to unwrap, we just peeked
+        merged.push(if take_a { a.next() } else { b.next() }.unwrap());
+    }
+    merged
+}
+
+/// Read the contents of the folder `path` from `source`, sorted with folders first. The parent
+/// folder item '..' is not included. Files are always included, hiding them is up to the view of
+/// the folder, see `ViewPrefs`.
+pub fn read_dir_sorted(source: &dyn DirSource, path: &Path) -> IOResult<Vec<CustomDirEntry>> {
+    let mut entries: Vec<_> = source.read_dir(path)?.flatten().collect();
+    sort_entries(&mut entries);
+    Ok(entries)
+}
+
+/// Listings of recently visited folders. A listing is only used if the modification time of the
+/// folder is the same as when it was read, otherwise it's read again.
+#[derive(Default)]
+pub struct DirCache {
+    /// The path, modification time and sorted entries of each folder, the most recently used last
+    listings: Vec<(PathBuf, SystemTime, Vec<CustomDirEntry>)>,
+}
+
+impl DirCache {
+    /// The cached entries of the folder `path`, if they are up to date according to the
+    /// modification time from `source`.
+    pub fn get(&mut self, source: &dyn DirSource, path: &Path) -> Option<Vec<CustomDirEntry>> {
+        let idx = self.listings.iter().position(|(p, _, _)| p == path)?;
+        if source.modified(path) != Some(self.listings[idx].1) {
+            self.listings.remove(idx);
+            return None;
+        }
+        self.get_unchecked(path).map(|(_, entries)| entries)
+    }
+
+    /// The cached entries of the folder `path` and its modification time when they were read,
+    /// without checking if they are still up to date. Checking requires reading the
+    /// modification time, which can block on an unresponsive network file system, so it's left
+    /// to the caller, see `DirReader::start`.
+    pub fn get_unchecked(&mut self, path: &Path) -> Option<(SystemTime, Vec<CustomDirEntry>)> {
+        let idx = self.listings.iter().position(|(p, _, _)| p == path)?;
+        let listing = self.listings.remove(idx);
+        let entries = listing
+            .2
+            .iter()
+            .map(|entry| CustomDirEntry {
+                // the metadata of the entries may have changed even if the folder hasn't
+                _metadata: OnceLock::new(),
+                ..entry.clone()
+            })
+            .collect();
+        let mtime = listing.1;
+        self.listings.push(listing);
+        Some((mtime, entries))
+    }
+
+    /// Cache the entries of the folder `path`, which were read when its modification time was
+    /// `mtime`.
+    pub fn insert(&mut self, path: PathBuf, mtime: SystemTime, entries: Vec<CustomDirEntry>) {
+        self.listings.retain(|(p, _, _)| *p != path);
+        if entries.len() > MAX_CACHED_ENTRIES {
+            return;
+        }
+        self.listings.push((path, mtime, entries));
+
+        let mut total: usize = self.listings.iter().map(|(_, _, e)| e.len()).sum();
+        while self.listings.len() > MAX_CACHED_DIRS || total > MAX_CACHED_ENTRIES {
+            total -= self.listings.remove(0).2.len();
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.listings.clear();
+    }
+
+    /// Write the most recently used listings to `file`, so that the next run can start with
+    /// them, see `load`.
+    pub fn save(&self, file: &Path) -> IOResult<()> {
+        let mut total = 0;
+        let mut listings = vec![];
+        for (path, mtime, entries) in self.listings.iter().rev() {
+            total += entries.len();
+            if total > MAX_SAVED_ENTRIES {
+                break;
+            }
+            let (path, mtime) = match (path.to_str(), mtime.duration_since(UNIX_EPOCH)) {
+                (Some(path), Ok(mtime)) => (path, mtime),
+                _ => continue,
+            };
+            // names that aren't valid unicode can't be saved
+            if entries.iter().any(|e| e.os_file_name().to_str().is_none()) {
+                continue;
+            }
+            let entries: Vec<_> = entries
+                .iter()
+                .map(|e| json!([e.file_name(), e.is_dir(), e.is_symlink()]))
+                .collect();
+            listings.push(json!({
+                "path": path,
+                "mtime": [mtime.as_secs(), mtime.subsec_nanos()],
+                "entries": entries,
+            }));
+        }
+        // the most recently used listing is last, like in `listings`
+        listings.reverse();
+        let contents = json!({
+            "version": CACHE_FILE_VERSION,
+            "listings": listings,
+        });
+
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(file, contents.to_string())
+    }
+
+    /// Read the listings that were saved to `file` with `save`. Like the listings in memory,
+    /// they are only used if the modification times of the folders haven't changed. The file is
+    /// ignored if it was written by another version of tere with a different format.
+    pub fn load(file: &Path) -> IOResult<Self> {
+        let contents: Value = serde_json::from_slice(&std::fs::read(file)?)?;
+        let mut ret = Self::default();
+        if contents["version"] != CACHE_FILE_VERSION {
+            return Ok(ret);
+        }
+        let listings = contents["listings"].as_array().map_or(&[][..], |l| l.as_slice());
+        for listing in listings {
+            let (path, secs, nanos) = match (
+                listing["path"].as_str(),
+                listing["mtime"][0].as_u64(),
+                listing["mtime"][1].as_u64(),
+            ) {
+                (Some(path), Some(secs), Some(nanos)) => (PathBuf::from(path), secs, nanos),
+                _ => continue,
+            };
+            let mtime = UNIX_EPOCH + Duration::new(secs, nanos as u32);
+            let entries = listing["entries"].as_array().map_or(&[][..], |e| e.as_slice());
+            let entries: Option<Vec<_>> = entries
+                .iter()
+                .map(|entry| {
+                    let name = entry[0].as_str()?;
+                    let is_dir = entry[1].as_bool()?;
+                    let is_symlink = entry[2].as_bool()?;
+                    Some(CustomDirEntry::from_cache(path.join(name), is_dir, is_symlink))
+                })
+                .collect();
+            if let Some(entries) = entries {
+                ret.insert(path, mtime, entries);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Messages sent from the background thread to the `DirReader`
+enum ReaderMessage {
+    /// The folder was opened successfully. This is its modification time before reading it.
+    Opened(Option<SystemTime>),
+    /// More entries, in the order in which they were read
+    Entries(Vec<CustomDirEntry>),
+    /// The folder could not be opened
+    Failed(IOError),
+}
+
+/// What happened since the previous call to `DirReader::update`.
+pub enum ReadUpdate {
+    /// Nothing to show yet
+    Waiting,
+    /// The folder was opened, and these new entries should be merged into the listing. They are
+    /// sorted, and there may be none of them.
+    Entries(Vec<CustomDirEntry>),
+    /// The whole folder has been read, and these are the remaining entries, sorted.
+    Done(Vec<CustomDirEntry>),
+    /// The folder could not be read
+    Failed(IOError),
+}
+
+/// Reads a folder in a background thread. Dropping the reader cancels the reading, although a
+/// system call that hangs, for example on an unresponsive network file system, keeps the thread
+/// alive until it returns.
+pub struct DirReader {
+    path: PathBuf,
+    receiver: Receiver<ReaderMessage>,
+    cancel_flag: Arc<AtomicBool>,
+    opened: bool,
+    mtime: Option<SystemTime>,
+    /// Entries that have been received, but not yet returned from `update`
+    unmerged: Vec<CustomDirEntry>,
+    num_read: usize,
+}
+
+impl DirReader {
+    /// Start reading `path`. If the modification time of the folder is still the same as that
+    /// of the `cached` listing, the cached entries are used instead of reading the folder again.
+    pub fn start(
+        source: Arc<dyn DirSource>,
+        path: PathBuf,
+        cached: Option<(SystemTime, Vec<CustomDirEntry>)>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        let cancel_flag = Arc::new(AtomicBool::new(false));
+
+        let thread_path = path.clone();
+        let thread_cancel_flag = cancel_flag.clone();
+        std::thread::spawn(move || {
+            let mtime = source.modified(&thread_path);
+            if let Some((cached_mtime, entries)) = cached {
+                if mtime == Some(cached_mtime) {
+                    let _ = sender.send(ReaderMessage::Opened(mtime));
+                    let _ = sender.send(ReaderMessage::Entries(entries));
+                    return;
+                }
+            }
+            let batches = match source.read_dir(&thread_path) {
+                Ok(batches) => batches,
+                Err(e) => {
+                    let _ = sender.send(ReaderMessage::Failed(e));
+                    return;
+                }
+            };
+            if sender.send(ReaderMessage::Opened(mtime)).is_err() {
+                return;
+            }
+
+            let mut batch = vec![];
+            let mut last_sent = Instant::now();
+            for entries in batches {
+                if thread_cancel_flag.load(Ordering::Relaxed) {
+                    return;
+                }
+                batch.extend(entries);
+                if last_sent.elapsed() >= BATCH_INTERVAL {
+                    last_sent = Instant::now();
+                    // the receiver is gone if the reading was cancelled
+                    if sender.send(ReaderMessage::Entries(std::mem::take(&mut batch))).is_err() {
+                        return;
+                    }
+                }
+            }
+            let _ = sender.send(ReaderMessage::Entries(batch));
+            // dropping the sender tells the reader that everything is done
+        });
+
+        Self {
+            path,
+            receiver,
+            cancel_flag,
+            opened: false,
+            mtime: None,
+            unmerged: vec![],
+            num_read: 0,
+        }
+    }
+
+    /// The folder that is being read
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The modification time of the folder from just before it was read, for caching the listing.
+    /// `None` if the folder hasn't been opened yet.
+    pub fn mtime(&self) -> Option<SystemTime> {
+        self.mtime
+    }
+
+    /// The number of entries read so far, including ones that haven't been returned by `update`
+    pub fn num_read(&self) -> usize {
+        self.num_read
+    }
+
+    fn handle_message(&mut self, msg: ReaderMessage) -> Option<ReadUpdate> {
+        match msg {
+            ReaderMessage::Opened(mtime) => {
+                self.opened = true;
+                self.mtime = mtime;
+                // show the folder right away, even if it's empty so far
+                return Some(ReadUpdate::Entries(vec![]));
+            }
+            ReaderMessage::Entries(entries) => {
+                self.num_read += entries.len();
+                self.unmerged.extend(entries);
+            }
+            ReaderMessage::Failed(e) => return Some(ReadUpdate::Failed(e)),
+        }
+        None
+    }
+
+    fn take_unmerged(&mut self) -> Vec<CustomDirEntry> {
+        let mut entries = std::mem::take(&mut self.unmerged);
+        sort_entries(&mut entries);
+        entries
+    }
+
+    /// Wait at most `timeout` for something new from the background thread. `num_shown` is the
+    /// number of entries that are already in the listing, which is used to decide whether it's
+    /// worth merging new entries into it yet.
+    pub fn update(&mut self, timeout: Duration, num_shown: usize) -> ReadUpdate {
+        let first = match self.receiver.recv_timeout(timeout) {
+            Ok(msg) => Ok(msg),
+            Err(RecvTimeoutError::Timeout) => return ReadUpdate::Waiting,
+            Err(RecvTimeoutError::Disconnected) => Err(TryRecvError::Disconnected),
+        };
+        let mut next = first;
+        loop {
+            match next {
+                Ok(msg) => {
+                    if let Some(update) = self.handle_message(msg) {
+                        return update;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) if self.opened => {
+                    return ReadUpdate::Done(self.take_unmerged())
+                }
+                Err(TryRecvError::Disconnected) => {
+                    // the thread panicked before opening the folder
+                    return ReadUpdate::Failed(IOError::other(format!(
+                        "Could not read '{}'",
+                        self.path.display()
+                    )));
+                }
+            }
+            next = self.receiver.try_recv();
+        }
+
+        if self.unmerged.len() >= MIN_MERGE_SIZE.max(num_shown / 4)
+            || (num_shown == 0 && !self.unmerged.is_empty())
+        {
+            ReadUpdate::Entries(self.take_unmerged())
+        } else {
+            ReadUpdate::Waiting
+        }
+    }
+}
+
+impl Drop for DirReader {
+    fn drop(&mut self) {
+        self.cancel_flag.store(true, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::app_state::{FsDirSource, MemoryDirSource};
+
+    #[test]
+    fn test_dir_reader() {
+        let tmp = TempDir::new("dir-reader");
+        std::fs::create_dir_all(tmp.join("B")).unwrap();
+        for name in ["c", "a", "D"] {
+            std::fs::write(tmp.join(name), "").unwrap();
+        }
+
+        let mut reader = DirReader::start(Arc::new(FsDirSource), tmp.to_path_buf(), None);
+        let mut listing = vec![];
+        loop {
+            match reader.update(Duration::from_secs(5), listing.len()) {
+                ReadUpdate::Waiting => {}
+                ReadUpdate::Entries(new) => listing = merge_sorted(listing, new),
+                ReadUpdate::Done(new) => {
+                    listing = merge_sorted(listing, new);
+                    break;
+                }
+                ReadUpdate::Failed(e) => panic!("{}", e),
+            }
+        }
+        let names: Vec<String> = listing.iter().map(|e| e.file_name_checked()).collect();
+        assert_eq!(names, vec!["B", "a", "c", "D"]);
+        assert_eq!(reader.num_read(), 4);
+        let sync_names: Vec<String> = read_dir_sorted(&FsDirSource, &tmp)
+            .unwrap()
+            .iter()
+            .map(|e| e.file_name_checked())
+            .collect();
+        assert_eq!(names, sync_names);
+
+        // symlinks to folders are sorted with the folders
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(tmp.join("B"), tmp.join("e")).unwrap();
+            let entries = read_dir_sorted(&FsDirSource, &tmp).unwrap();
+            let names: Vec<String> = entries.iter().map(|e| e.file_name_checked()).collect();
+            assert_eq!(names, vec!["B", "e", "a", "c", "D"]);
+            assert_eq!(entries[1].symlink_target(), Some(tmp.join("B").as_path()));
+            assert!(entries[0].metadata().unwrap().is_dir());
+        }
+
+        let mut reader = DirReader::start(Arc::new(FsDirSource), tmp.join("nonexistent"), None);
+        assert!(matches!(reader.update(Duration::from_secs(5), 0), ReadUpdate::Failed(_)));
+    }
+
+    #[test]
+    fn test_sort_case_ties() {
+        // the order is the same regardless of the order in which the entries are listed
+        for names in [["x", "X", "a"], ["X", "a", "x"]] {
+            let source = MemoryDirSource::new();
+            for name in names {
+                source.add_file(Path::new("/d").join(name));
+            }
+            let sorted: Vec<String> = read_dir_sorted(&source, Path::new("/d"))
+                .unwrap()
+                .iter()
+                .map(|e| e.file_name_checked())
+                .collect();
+            assert_eq!(sorted, vec!["a", "X", "x"]);
+        }
+    }
+
+    #[test]
+    fn test_dir_cache() {
+        let tmp = TempDir::new("dir-cache");
+        std::fs::write(tmp.join("a"), "").unwrap();
+
+        let mut cache = DirCache::default();
+        let mtime = FsDirSource.modified(&tmp).unwrap();
+        cache.insert(tmp.to_path_buf(), mtime, read_dir_sorted(&FsDirSource, &tmp).unwrap());
+        assert_eq!(cache.get(&FsDirSource, &tmp).unwrap().len(), 1);
+        assert!(cache.get(&FsDirSource, &tmp.join("a")).is_none());
+
+        // adding an item changes the modification time of the folder
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(tmp.join("b"), "").unwrap();
+        assert!(cache.get(&FsDirSource, &tmp).is_none());
+
+        cache.insert(tmp.to_path_buf(), FsDirSource.modified(&tmp).unwrap(), vec![]);
+        assert!(cache.get(&FsDirSource, &tmp).is_some());
+        cache.clear();
+        assert!(cache.get(&FsDirSource, &tmp).is_none());
+    }
+
+    #[test]
+    fn test_dir_cache_file() {
+        let tmp = TempDir::new("dir-cache-file");
+        std::fs::create_dir_all(tmp.join("dir/sub")).unwrap();
+        std::fs::write(tmp.join("dir/file"), "").unwrap();
+        let dir = tmp.join("dir");
+        let cache_file = tmp.join("cache/listings.json");
+
+        let mut cache = DirCache::default();
+        let mtime = FsDirSource.modified(&dir).unwrap();
+        cache.insert(dir.clone(), mtime, read_dir_sorted(&FsDirSource, &dir).unwrap());
+        cache.save(&cache_file).unwrap();
+
+        let mut loaded = DirCache::load(&cache_file).unwrap();
+        let entries = loaded.get(&FsDirSource, &dir).unwrap();
+        let names: Vec<_> = entries.iter().map(|e| (e.file_name(), e.is_dir())).collect();
+        assert_eq!(names, vec![("sub", true), ("file", false)]);
+        assert_eq!(entries[1].path(), &dir.join("file"));
+        assert!(entries[1].metadata().unwrap().is_file());
+
+        // files in the format of an older version are not used
+        let old = std::fs::read_to_string(&cache_file).unwrap().replace(
+            &format!("\"version\":{}", CACHE_FILE_VERSION),
+            "\"version\":1",
+        );
+        std::fs::write(&cache_file, old).unwrap();
+        assert!(DirCache::load(&cache_file).unwrap().listings.is_empty());
+        cache.save(&cache_file).unwrap();
+
+        // nor are listings of folders that have changed
+        std::thread::sleep(Duration::from_millis(10));
+        std::fs::write(dir.join("new"), "").unwrap();
+        assert!(DirCache::load(&cache_file).unwrap().get(&FsDirSource, &dir).is_none());
+    }
+}
\ No newline at end of file
@@ -0,0 +1,218 @@
+/// Where the contents of folders are read from. Normally this is the file system, but the app
+/// state can also browse other sources, like an in-memory folder tree in tests, or another machine
+/// over SFTP. Only reading is abstracted: creating, renaming and deleting items always happens on
+/// the file system, so it's disabled for sources on other machines.
+use std::collections::HashMap;
+use std::io::{Error as IOError, ErrorKind, Result as IOResult};
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+use rayon::prelude::*;
+
+use super::CustomDirEntry;
+
+/// How many entries the file system source converts in parallel at once. Converting may require
+/// system calls, while just listing the names is fast.
+const PARALLEL_CHUNK_SIZE: usize = 256;
+
+/// The entries of a folder in batches, see `DirSource::read_dir`.
+pub type DirBatches = Box<dyn Iterator<Item = Vec<CustomDirEntry>> + Send>;
+
+/// A source of folder listings for `TereAppState`, see `TereAppState::with_dir_source`. The
+/// paths are always absolute.
+pub trait DirSource: Send + Sync {
+    /// Open the folder `path` for reading its entries. The entries can be in any order, and the
+    /// parent folder '..' is not included. They are returned in batches, between which reading
+    /// can be cancelled, or the entries read so far can be shown.
+    fn read_dir(&self, path: &Path) -> IOResult<DirBatches>;
+
+    /// The modification time of the folder `path`, which has to change when entries are added to
+    /// it or removed from it. Listings are only cached if this is known.
+    fn modified(&self, path: &Path) -> Option<SystemTime>;
+
+    /// Called when `path` becomes the current folder. Fails if it can't be entered.
+    fn enter_dir(&self, path: &Path) -> IOResult<()>;
+
+    /// The machine that the folders are on, like 'user@host', or `None` if they are on this
+    /// machine. Things that work with local files, like running commands, are disabled for
+    /// folders on other machines.
+    fn host(&self) -> Option<&str> {
+        None
+    }
+
+    /// The home folder of the user
+    fn home_dir(&self) -> Option<PathBuf> {
+        dirs::home_dir()
+    }
+
+    /// Whether the folder `path` is not an actual folder on this machine, like a folder inside an
+    /// archive or on another machine, so that programs can't be run in it.
+    fn is_virtual(&self, _path: &Path) -> bool {
+        self.host().is_some()
+    }
+}
+
+/// The file system. Entering a folder also makes it the working directory of the process.
+pub struct FsDirSource;
+
+impl DirSource for FsDirSource {
+    fn read_dir(&self, path: &Path) -> IOResult<DirBatches> {
+        let mut entries = std::fs::read_dir(path)?.filter_map(|e| e.ok());
+        Ok(Box::new(std::iter::from_fn(move || {
+            let chunk: Vec<_> = entries.by_ref().take(PARALLEL_CHUNK_SIZE).collect();
+            // the file types and symlink targets may take a round trip to the server on network
+            // file systems, so convert the entries in parallel
+            (!chunk.is_empty()).then(|| chunk.into_par_iter().map(CustomDirEntry::from).collect())
+        })))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn enter_dir(&self, path: &Path) -> IOResult<()> {
+        std::env::set_current_dir(path)
+    }
+}
+
+/// A folder in a `MemoryDirSource`
+#[derive(Default)]
+struct MemoryDir {
+    // The names of the entries, and whether they are folders
+    entries: Vec<(String, bool)>,
+    // A counter that is increased on every change, and used as the modification time
+    mtime: u64,
+}
+
+/// A folder tree that only exists in memory. Its entries have no metadata. It can be changed
+/// while it's being browsed, like a file system.
+#[derive(Default)]
+pub struct MemoryDirSource {
+    dirs: RwLock<HashMap<PathBuf, MemoryDir>>,
+}
+
+impl MemoryDirSource {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add the folder `path`, and its parent folders if they don't exist yet
+    pub fn add_dir<P: AsRef<Path>>(&self, path: P) {
+        Self::add(&mut self.dirs.write().unwrap(), path.as_ref(), true);
+    }
+
+    /// Add the file `path`, and its parent folders if they don't exist yet
+    pub fn add_file<P: AsRef<Path>>(&self, path: P) {
+        Self::add(&mut self.dirs.write().unwrap(), path.as_ref(), false);
+    }
+
+    /// Remove the file or folder `path`, including the contents of the folder
+    pub fn remove<P: AsRef<Path>>(&self, path: P) {
+        let path = path.as_ref();
+        let mut dirs = self.dirs.write().unwrap();
+        dirs.retain(|p, _| !p.starts_with(path));
+        if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+            if let Some(dir) = dirs.get_mut(parent) {
+                dir.entries.retain(|(n, _)| *n != name.to_string_lossy());
+                dir.mtime += 1;
+            }
+        }
+    }
+
+    fn add(dirs: &mut HashMap<PathBuf, MemoryDir>, path: &Path, is_dir: bool) {
+        if let (Some(parent), Some(name)) = (path.parent(), path.file_name()) {
+            if !dirs.contains_key(parent) {
+                Self::add(dirs, parent, true);
+            }
+            let name = name.to_string_lossy();
+            // ok to unwrap, the parent was added above
+            let dir = dirs.get_mut(parent).unwrap();
+            if !dir.entries.iter().any(|(n, _)| *n == name) {
+                dir.entries.push((name.into_owned(), is_dir));
+                dir.mtime += 1;
+            }
+        }
+        if is_dir {
+            dirs.entry(path.to_path_buf()).or_default();
+        }
+    }
+}
+
+fn not_found(path: &Path) -> IOError {
+    IOError::new(
+        ErrorKind::NotFound,
+        format!("No such folder: '{}'", path.display()),
+    )
+}
+
+impl DirSource for MemoryDirSource {
+    fn read_dir(&self, path: &Path) -> IOResult<DirBatches> {
+        let dirs = self.dirs.read().unwrap();
+        let dir = dirs.get(path).ok_or_else(|| not_found(path))?;
+        let entries = dir
+            .entries
+            .iter()
+            .map(|(name, is_dir)| CustomDirEntry::new_virtual(path.join(name), *is_dir))
+            .collect();
+        Ok(Box::new(std::iter::once(entries)))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        let dirs = self.dirs.read().unwrap();
+        dirs.get(path)
+            .map(|dir| SystemTime::UNIX_EPOCH + Duration::from_secs(dir.mtime))
+    }
+
+    fn enter_dir(&self, path: &Path) -> IOResult<()> {
+        if self.dirs.read().unwrap().contains_key(path) {
+            Ok(())
+        } else {
+            Err(not_found(path))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(source: &dyn DirSource, path: &str) -> Vec<(String, bool)> {
+        let mut names: Vec<_> = source
+            .read_dir(Path::new(path))
+            .unwrap()
+            .flatten()
+            .map(|e| (e.file_name_checked(), e.is_dir()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_memory_dir_source() {
+        let source = MemoryDirSource::new();
+        source.add_file("/a/b/c.txt");
+        source.add_dir("/a/d");
+        assert_eq!(names(&source, "/"), vec![("a".into(), true)]);
+        assert_eq!(
+            names(&source, "/a"),
+            vec![("b".into(), true), ("d".into(), true)]
+        );
+        assert_eq!(names(&source, "/a/b"), vec![("c.txt".into(), false)]);
+        assert!(source.read_dir(Path::new("/a/b/c.txt")).is_err());
+        assert!(source.enter_dir(Path::new("/a/b")).is_ok());
+        assert!(source.enter_dir(Path::new("/a/x")).is_err());
+
+        let entry = source.read_dir(Path::new("/a/b")).unwrap().flatten().next().unwrap();
+        assert_eq!(entry.path(), Path::new("/a/b/c.txt"));
+        assert!(entry.metadata().is_none());
+
+        let mtime = source.modified(Path::new("/a")).unwrap();
+        source.add_dir("/a/d");
+        assert_eq!(source.modified(Path::new("/a")), Some(mtime));
+        source.remove("/a/b");
+        assert_ne!(source.modified(Path::new("/a")), Some(mtime));
+        assert_eq!(names(&source, "/a"), vec![("d".into(), true)]);
+        assert!(source.modified(Path::new("/a/b")).is_none());
+    }
+}
@@ -0,0 +1,113 @@
+/// Watching the current folder for changes made by other programs, so that the listing can be
+/// updated automatically. Only the creation, removal and renaming of entries are of interest, not
+/// changes to their contents.
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver};
+
+use notify::event::{EventKind, ModifyKind};
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+pub struct DirWatcher {
+    watcher: RecommendedWatcher,
+    receiver: Receiver<notify::Result<Event>>,
+    // The folder that is being watched, if watching it succeeded
+    path: Option<PathBuf>,
+}
+
+/// Whether the event means that entries were added to or removed from the folder
+fn is_listing_change(event: &Event) -> bool {
+    match event.kind {
+        EventKind::Create(_) | EventKind::Remove(_) | EventKind::Any | EventKind::Other => true,
+        EventKind::Modify(ModifyKind::Name(_)) | EventKind::Modify(ModifyKind::Any) => true,
+        // Reading the folder generates access events, which must not cause it to be read again
+        EventKind::Access(_) | EventKind::Modify(_) => event.need_rescan(),
+    }
+}
+
+impl DirWatcher {
+    pub fn new() -> notify::Result<Self> {
+        let (sender, receiver) = mpsc::channel();
+        let watcher = notify::recommended_watcher(sender)?;
+        Ok(Self {
+            watcher,
+            receiver,
+            path: None,
+        })
+    }
+
+    /// Stop watching the previous folder, and start watching `path` instead. Failing to watch it
+    /// (for example on file systems that don't support it, or if the system limit on the number
+    /// of watches has been reached) is not an error, the listing just isn't updated automatically.
+    pub fn watch(&mut self, path: &Path) {
+        if self.path.as_deref() == Some(path) {
+            return;
+        }
+        if let Some(old_path) = self.path.take() {
+            let _ = self.watcher.unwatch(&old_path);
+        }
+        if self.watcher.watch(path, RecursiveMode::NonRecursive).is_ok() {
+            self.path = Some(path.to_path_buf());
+        }
+        // forget the changes to the previous folder
+        while self.receiver.try_recv().is_ok() {}
+    }
+
+    /// Whether the contents of the watched folder have changed since the last call. This doesn't
+    /// block.
+    pub fn poll(&self) -> bool {
+        let mut changed = false;
+        while let Ok(res) = self.receiver.try_recv() {
+            // an error means that some events may have been lost
+            changed |= res.map_or(true, |event| is_listing_change(&event));
+        }
+        changed && self.path.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::time::{Duration, Instant};
+
+    /// Poll until a change is seen, or until a timeout
+    fn wait_for_change(watcher: &DirWatcher) -> bool {
+        let deadline = Instant::now() + Duration::from_secs(2);
+        while Instant::now() < deadline {
+            if watcher.poll() {
+                return true;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        false
+    }
+
+    #[test]
+    fn test_dir_watcher() {
+        let tmp = TempDir::new("dir-watcher");
+        std::fs::create_dir_all(tmp.join("sub")).unwrap();
+
+        let mut watcher = DirWatcher::new().unwrap();
+        watcher.watch(&tmp);
+        assert!(!watcher.poll());
+
+        std::fs::write(tmp.join("foo"), "").unwrap();
+        assert!(wait_for_change(&watcher));
+
+        // reading the folder is not a change
+        let _ = std::fs::read_dir(&tmp).unwrap().count();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!watcher.poll());
+
+        std::fs::remove_file(tmp.join("foo")).unwrap();
+        assert!(wait_for_change(&watcher));
+
+        // changes in the previous folder are ignored after switching
+        watcher.watch(&tmp.join("sub"));
+        std::fs::write(tmp.join("bar"), "").unwrap();
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!watcher.poll());
+        std::fs::write(tmp.join("sub").join("baz"), "").unwrap();
+        assert!(wait_for_change(&watcher));
+    }
+}
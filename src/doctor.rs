@@ -0,0 +1,459 @@
+//! Checks of the environment that tere runs in, printed by `tere doctor`. Most problems that
+//! people run into are not in tere itself, but in how it's set up: the shell function is missing
+//! or doesn't `cd`, the history can't be saved, or the terminal or locale can't show everything.
+//! Each check results in a [`Finding`], which says what was found and how to fix it.
+//!
+//! The checks get the environment variables through a function, so that they can be tested
+//! without changing the environment of the test process.
+
+use std::fmt;
+use std::fs::OpenOptions;
+use std::path::{Path, PathBuf};
+
+/// How serious a finding is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// Something that is good to know, but doesn't have to be fixed
+    Info,
+    /// Something that may cause problems, like garbled characters
+    Warning,
+    /// Something that breaks tere, like a missing shell function
+    Problem,
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self {
+            Self::Ok => "ok",
+            Self::Info => "info",
+            Self::Warning => "warning",
+            Self::Problem => "problem",
+        };
+        // pad manually, because the padding parameters are ignored by write_str
+        write!(f, "{:<8}", label)
+    }
+}
+
+/// The result of a single check
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Finding {
+    pub status: Status,
+    /// What was checked, like "Shell integration"
+    pub topic: &'static str,
+    pub message: String,
+    /// What to do about it, if anything
+    pub advice: Option<String>,
+}
+
+impl Finding {
+    fn new(status: Status, topic: &'static str, message: impl Into<String>) -> Self {
+        Self { status, topic, message: message.into(), advice: None }
+    }
+
+    fn advice(mut self, advice: impl Into<String>) -> Self {
+        self.advice = Some(advice.into());
+        self
+    }
+}
+
+/// Run all checks. `history_file` is the history file from the settings, or `None` if the history
+/// is disabled.
+pub fn run_checks(history_file: Option<&Path>) -> Vec<Finding> {
+    let env = |var: &str| std::env::var(var).ok().filter(|value| !value.is_empty());
+    let mut ret = check_shell_integration(&env);
+    ret.extend(check_executable());
+    ret.push(check_history_file(history_file));
+    ret.extend(check_terminal(&env, is_terminal()));
+    ret.push(check_locale(&env));
+    ret
+}
+
+/// Whether any of the findings is a problem that has to be fixed
+pub fn has_problems(findings: &[Finding]) -> bool {
+    findings.iter().any(|f| f.status == Status::Problem)
+}
+
+/// Format the findings for printing, one per line, with the advice indented below them.
+pub fn report(findings: &[Finding]) -> String {
+    let mut ret = String::new();
+    for finding in findings {
+        ret += &format!("{} {}: {}\n", finding.status, finding.topic, finding.message);
+        if let Some(advice) = &finding.advice {
+            ret += &format!("{:8}   {}\n", "", advice);
+        }
+    }
+    ret
+}
+
+fn is_terminal() -> bool {
+    use std::io::IsTerminal;
+    // the UI is drawn on stderr, because stdout is captured by the shell function
+    std::io::stderr().is_terminal()
+}
+
+/// The shell that tere was started from, as one of the names accepted by `tere init`
+pub(crate) fn detect_shell(env: &dyn Fn(&str) -> Option<String>) -> Option<&'static str> {
+    // these are set by the shells themselves, so they're more reliable than $SHELL, which is the
+    // login shell
+    if env("NU_VERSION").is_some() {
+        return Some("nushell");
+    }
+    if env("XONSH_VERSION").is_some() {
+        return Some("xonsh");
+    }
+    if env("PSModulePath").is_some() && env("SHELL").is_none() {
+        return Some("powershell");
+    }
+    let shell = env("SHELL")?;
+    let name = Path::new(&shell).file_name()?.to_str()?;
+    match name.trim_end_matches(".exe") {
+        "bash" => Some("bash"),
+        "zsh" => Some("zsh"),
+        "fish" => Some("fish"),
+        "nu" => Some("nushell"),
+        "xonsh" => Some("xonsh"),
+        "pwsh" | "powershell" => Some("powershell"),
+        _ => None,
+    }
+}
+
+/// The configuration files of `shell` where the shell function is usually set up
+pub(crate) fn shell_config_files(shell: &str, env: &dyn Fn(&str) -> Option<String>) -> Vec<PathBuf> {
+    let home = dirs::home_dir().unwrap_or_default();
+    let config = dirs::config_dir().unwrap_or_default();
+    match shell {
+        "bash" => vec![home.join(".bashrc"), home.join(".bash_profile"), home.join(".profile")],
+        "zsh" => {
+            let dir = env("ZDOTDIR").map(PathBuf::from).unwrap_or(home);
+            vec![dir.join(".zshrc"), dir.join(".zshenv")]
+        }
+        "fish" => vec![
+            config.join("fish").join("config.fish"),
+            config.join("fish").join("functions").join("tere.fish"),
+        ],
+        "nushell" => vec![config.join("nushell").join("config.nu")],
+        "xonsh" => vec![home.join(".xonshrc"), config.join("xonsh").join("rc.xsh")],
+        "powershell" => {
+            let profile = "Microsoft.PowerShell_profile.ps1";
+            let mut ret = vec![config.join("powershell").join(profile)];
+            if let Some(docs) = dirs::document_dir() {
+                ret.push(docs.join("PowerShell").join(profile));
+                ret.push(docs.join("WindowsPowerShell").join(profile));
+            }
+            ret
+        }
+        _ => vec![],
+    }
+}
+
+/// The line that sets up the shell function for `shell`, as shown in the README
+pub(crate) fn init_line(shell: &str) -> String {
+    match shell {
+        "fish" => "tere init fish | source".into(),
+        "xonsh" => "execx($(tere init xonsh))".into(),
+        "powershell" => "Invoke-Expression (& tere init powershell | Out-String)".into(),
+        "nushell" => "source ~/.tere.nu (after 'tere init nushell | save -f ~/.tere.nu')".into(),
+        shell => format!("eval \"$(tere init {})\"", shell),
+    }
+}
+
+/// Check what the configuration file `contents` of `shell` say about tere
+pub(crate) fn check_shell_config(shell: &str, file: &Path, contents: &str) -> Option<Finding> {
+    let topic = "Shell integration";
+    let file = file.display();
+    let lines = contents.lines().map(str::trim).filter(|line| !line.starts_with('#'));
+    let lines: Vec<_> = lines.filter(|line| line.contains("tere")).collect();
+    if lines.is_empty() {
+        return None;
+    }
+    if lines.iter().any(|line| line.contains("tere init")) {
+        return Some(Finding::new(Status::Ok, topic, format!("'tere init' is used in {}", file)));
+    }
+    // the nushell function is sourced from a file
+    if shell == "nushell" && lines.iter().any(|line| line.starts_with("source")) {
+        return Some(Finding::new(Status::Ok, topic, format!("a tere script is sourced in {}", file)));
+    }
+    // a function written by hand, which should not cd if tere was cancelled or failed
+    let msg = format!("{} has a tere function that was not made with 'tere init'", file);
+    let finding = if contents.contains("cd") || contents.contains("Set-Location") {
+        Finding::new(Status::Info, topic, msg)
+            .advice(format!("Consider replacing it with '{}', which doesn't cd if tere is cancelled or fails, and is updated with tere.", init_line(shell)))
+    } else {
+        Finding::new(Status::Problem, topic, format!("{}, and it doesn't seem to cd", msg))
+            .advice(format!("Replace it with '{}'.", init_line(shell)))
+    };
+    Some(finding)
+}
+
+fn check_shell_integration(env: &dyn Fn(&str) -> Option<String>) -> Vec<Finding> {
+    let topic = "Shell integration";
+    let mut ret = vec![];
+
+    if let Some(pane) = env("TMUX_PANE") {
+        ret.push(Finding::new(Status::Info, "tmux", format!("running in tmux pane {}", pane))
+            .advice("Run 'tere init tmux >> ~/.tmux.conf' to open tere in a popup with prefix T."));
+    }
+
+    let shell = match detect_shell(env) {
+        Some(shell) => shell,
+        None => {
+            ret.push(Finding::new(Status::Info, topic, "could not detect which shell is used")
+                .advice(format!("Set up the function that changes the folder with 'tere init <shell>', where <shell> is one of {}.", crate::shell_init::SUPPORTED_SHELLS.join(", "))));
+            return ret;
+        }
+    };
+
+    let files = shell_config_files(shell, env);
+    let found = files.iter().find_map(|file| {
+        let contents = std::fs::read_to_string(file).ok()?;
+        check_shell_config(shell, file, &contents)
+    });
+    ret.push(found.unwrap_or_else(|| {
+        let files: Vec<_> = files.iter().map(|f| f.display().to_string()).collect();
+        Finding::new(Status::Problem, topic, format!("tere is not set up for {} in {}", shell, files.join(", ")))
+            .advice(format!("Without it, tere can't change the folder of the shell. Add '{}' to your shell configuration.", init_line(shell)))
+    }));
+    ret
+}
+
+/// Find `name` from the folders in the `PATH` environment variable
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    let exe = format!("{}{}", name, std::env::consts::EXE_SUFFIX);
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|dir| dir.join(&exe))
+        .find(|path| path.is_file())
+}
+
+/// Check that the tere that the shell function runs is this one
+fn check_executable() -> Option<Finding> {
+    let topic = "Executable";
+    let current = std::env::current_exe().ok()?;
+    let found = match find_in_path(env!("CARGO_PKG_NAME")) {
+        Some(found) => found,
+        None => return Some(Finding::new(Status::Problem, topic, "tere was not found in PATH")
+            .advice(format!("The shell function runs tere from PATH, so add {} to PATH.",
+                            current.parent().unwrap_or(&current).display()))),
+    };
+    let same = std::fs::canonicalize(&found).ok() == std::fs::canonicalize(&current).ok();
+    let finding = if same {
+        Finding::new(Status::Ok, topic, format!("{}, version {}", found.display(), env!("CARGO_PKG_VERSION")))
+    } else {
+        Finding::new(Status::Warning, topic, format!("the tere in PATH is {}, but this is {}", found.display(), current.display()))
+            .advice("The shell function runs the one in PATH, so remove the other one or change the order of PATH.")
+    };
+    Some(finding)
+}
+
+/// Check that `dir` is writable by creating and removing a file in it
+fn dir_is_writable(dir: &Path) -> bool {
+    let probe = dir.join(format!(".tere-doctor-{}", std::process::id()));
+    let ok = OpenOptions::new().write(true).create_new(true).open(&probe).is_ok();
+    if ok {
+        let _ = std::fs::remove_file(&probe);
+    }
+    ok
+}
+
+fn check_history_file(file: Option<&Path>) -> Finding {
+    let topic = "History file";
+    let file = match file {
+        Some(file) => file,
+        None => return Finding::new(Status::Info, topic, "the history is disabled, so it's not saved"),
+    };
+    let shown = file.display();
+
+    if file.exists() {
+        if let Err(e) = OpenOptions::new().append(true).open(file) {
+            return Finding::new(Status::Problem, topic, format!("{} is not writable: {}", shown, e))
+                .advice("Fix the permissions of the file, or use --history-file to save the history elsewhere.");
+        }
+        return match std::fs::read_to_string(file).map(|s| serde_json::from_str::<serde_json::Value>(&s)) {
+            Ok(Ok(_)) => Finding::new(Status::Ok, topic, format!("{} is writable", shown)),
+            _ => Finding::new(Status::Warning, topic, format!("{} is not valid JSON, so the history is lost", shown))
+                .advice("Remove the file to start a new history."),
+        };
+    }
+
+    // the folders are created on exit, so check the closest one that exists
+    match file.ancestors().skip(1).find(|dir| dir.is_dir()) {
+        Some(dir) if dir_is_writable(dir) => Finding::new(Status::Ok, topic, format!("{} will be created on exit", shown)),
+        Some(dir) => Finding::new(Status::Problem, topic, format!("{} can't be created, because {} is not writable", shown, dir.display()))
+            .advice("Fix the permissions of the folder, or use --history-file to save the history elsewhere."),
+        None => Finding::new(Status::Problem, topic, format!("{} can't be created", shown))
+            .advice("Use --history-file to save the history elsewhere."),
+    }
+}
+
+/// Whether the terminal is known to support the kitty keyboard protocol, which reports key
+/// combinations like Ctrl+Tab that are the same as other keys in the traditional encoding
+fn supports_kitty_keyboard(env: &dyn Fn(&str) -> Option<String>) -> bool {
+    env("KITTY_WINDOW_ID").is_some()
+        || env("TERM").is_some_and(|term| term == "xterm-kitty" || term == "xterm-ghostty")
+        || env("TERM_PROGRAM").is_some_and(|prog| ["WezTerm", "ghostty", "iTerm.app"].contains(&prog.as_str()))
+}
+
+fn check_terminal(env: &dyn Fn(&str) -> Option<String>, is_terminal: bool) -> Vec<Finding> {
+    let mut ret = vec![];
+    let term = env("TERM");
+
+    if !is_terminal {
+        // not a problem, since this report may have been redirected to a file on purpose
+        ret.push(Finding::new(Status::Info, "Terminal", "stderr is not a terminal")
+            .advice("tere draws its UI on stderr, so don't redirect it when running tere."));
+    }
+    match term.as_deref() {
+        Some("dumb") => ret.push(Finding::new(Status::Problem, "Terminal", "TERM is 'dumb', so the terminal can't move the cursor")
+            .advice("Run tere in a terminal emulator, or set TERM to for example xterm-256color.")),
+        // Windows terminals don't set TERM
+        None if cfg!(unix) => ret.push(Finding::new(Status::Warning, "Terminal", "TERM is not set")
+            .advice("Set TERM to match your terminal, for example xterm-256color.")),
+        _ => {
+            let name = env("TERM_PROGRAM").or_else(|| term.clone()).unwrap_or_else(|| "unknown".into());
+            ret.push(Finding::new(Status::Ok, "Terminal", name));
+        }
+    }
+
+    let colors = if env("NO_COLOR").is_some() {
+        Finding::new(Status::Info, "Colors", "NO_COLOR is set, so the monochrome theme is used unless --theme is given")
+    } else if env("COLORTERM").is_some_and(|c| c == "truecolor" || c == "24bit") {
+        Finding::new(Status::Ok, "Colors", "24-bit colors")
+    } else if term.as_deref().is_some_and(|t| t.contains("256color")) {
+        Finding::new(Status::Ok, "Colors", "256 colors")
+    } else {
+        Finding::new(Status::Ok, "Colors", "basic colors, which is enough for the default theme")
+    };
+    ret.push(colors);
+
+    let mouse = if env("TMUX").is_some() {
+        Finding::new(Status::Info, "Mouse", "running in tmux, which has to pass on mouse events")
+            .advice("Add 'set -g mouse on' to ~/.tmux.conf and run tere with --mouse=on to use the mouse.")
+    } else {
+        Finding::new(Status::Info, "Mouse", "the mouse is off by default")
+            .advice("Run tere with --mouse=on to use the mouse.")
+    };
+    ret.push(mouse);
+
+    let keyboard = if supports_kitty_keyboard(env) {
+        Finding::new(Status::Ok, "Keyboard", "the terminal supports the kitty keyboard protocol")
+    } else {
+        Finding::new(Status::Info, "Keyboard", "the terminal doesn't seem to support the kitty keyboard protocol")
+            .advice("Some key combinations like Ctrl+Tab may be the same as other keys. Use Ctrl+PageDown and Ctrl+PageUp to switch tabs.")
+    };
+    ret.push(keyboard);
+    ret
+}
+
+fn check_locale(env: &dyn Fn(&str) -> Option<String>) -> Finding {
+    let topic = "Locale";
+    if cfg!(windows) {
+        return Finding::new(Status::Ok, topic, "Windows terminals use Unicode");
+    }
+    // the character encoding is chosen by LC_CTYPE, not LC_MESSAGES
+    let locale = ["LC_ALL", "LC_CTYPE", "LANG"].iter().find_map(|var| Some((*var, env(var)?)));
+    match locale {
+        Some((var, value)) => {
+            let lower = value.to_lowercase();
+            if lower.ends_with("utf-8") || lower.ends_with("utf8") || lower.contains(".utf-8@") || lower.contains(".utf8@") {
+                Finding::new(Status::Ok, topic, format!("{}={}", var, value))
+            } else {
+                Finding::new(Status::Warning, topic, format!("{}={} is not a UTF-8 locale", var, value))
+                    .advice(format!("Names with non-ASCII characters may be shown incorrectly. Set {} to a UTF-8 locale, for example en_US.UTF-8.", var))
+            }
+        }
+        None => Finding::new(Status::Warning, topic, "no locale is set")
+            .advice("Names with non-ASCII characters may be shown incorrectly. Set LANG to a UTF-8 locale, for example en_US.UTF-8."),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use std::collections::HashMap;
+
+    fn env_from(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> =
+            vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        move |var| vars.get(var).cloned()
+    }
+
+    #[test]
+    fn test_detect_shell() {
+        assert_eq!(detect_shell(&env_from(&[("SHELL", "/usr/bin/zsh")])), Some("zsh"));
+        assert_eq!(detect_shell(&env_from(&[("SHELL", "/bin/bash"), ("NU_VERSION", "0.90")])), Some("nushell"));
+        assert_eq!(detect_shell(&env_from(&[("SHELL", "/bin/tcsh")])), None);
+        assert_eq!(detect_shell(&env_from(&[])), None);
+    }
+
+    #[test]
+    fn test_check_shell_config() {
+        let file = Path::new(".bashrc");
+        assert_eq!(check_shell_config("bash", file, "alias ls='ls -l'\n"), None);
+        assert_eq!(check_shell_config("bash", file, "# eval \"$(tere init bash)\"\n"), None);
+        let ok = check_shell_config("bash", file, "eval \"$(tere init bash)\"\n").unwrap();
+        assert_eq!(ok.status, Status::Ok);
+
+        let custom = "tere() {\n  local result=$(/opt/tere \"$@\")\n  [ -n \"$result\" ] && cd -- \"$result\"\n}\n";
+        let info = check_shell_config("bash", file, custom).unwrap();
+        assert_eq!(info.status, Status::Info);
+        assert!(info.advice.unwrap().contains("eval \"$(tere init bash)\""));
+
+        let broken = check_shell_config("bash", file, "alias tere=/opt/tere\n").unwrap();
+        assert_eq!(broken.status, Status::Problem);
+    }
+
+    #[test]
+    fn test_check_history_file() {
+        let tmp = TempDir::new("doctor-history");
+        assert_eq!(check_history_file(None).status, Status::Info);
+
+        let file = tmp.join("tere").join("history.json");
+        assert_eq!(check_history_file(Some(&file)).status, Status::Ok);
+
+        std::fs::create_dir(tmp.join("tere")).unwrap();
+        std::fs::write(&file, "{\"not\": ").unwrap();
+        assert_eq!(check_history_file(Some(&file)).status, Status::Warning);
+        std::fs::write(&file, "[]").unwrap();
+        assert_eq!(check_history_file(Some(&file)).status, Status::Ok);
+    }
+
+    #[test]
+    fn test_check_terminal() {
+        let findings = check_terminal(&env_from(&[("TERM", "dumb")]), true);
+        assert!(has_problems(&findings));
+
+        let findings = check_terminal(&env_from(&[("TERM", "xterm-kitty"), ("COLORTERM", "truecolor")]), true);
+        assert!(!has_problems(&findings));
+        assert!(findings.iter().all(|f| f.status == Status::Ok || f.topic == "Mouse"));
+
+        let findings = check_terminal(&env_from(&[("TERM", "xterm-256color")]), false);
+        assert!(!has_problems(&findings));
+        assert_eq!(findings[0].message, "stderr is not a terminal");
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_check_locale() {
+        assert_eq!(check_locale(&env_from(&[("LANG", "fi_FI.UTF-8")])).status, Status::Ok);
+        assert_eq!(check_locale(&env_from(&[("LANG", "en_US.utf8")])).status, Status::Ok);
+        // LC_ALL overrides LANG
+        let finding = check_locale(&env_from(&[("LANG", "en_US.UTF-8"), ("LC_ALL", "C")]));
+        assert_eq!(finding.status, Status::Warning);
+        assert!(finding.message.contains("LC_ALL=C"));
+        assert_eq!(check_locale(&env_from(&[])).status, Status::Warning);
+    }
+
+    #[test]
+    fn test_report() {
+        let findings = vec![
+            Finding::new(Status::Ok, "Colors", "256 colors"),
+            Finding::new(Status::Problem, "Terminal", "bad").advice("Fix it."),
+        ];
+        assert_eq!(
+            report(&findings),
+            "ok       Colors: 256 colors\nproblem  Terminal: bad\n           Fix it.\n",
+        );
+        assert!(has_problems(&findings));
+    }
+}
@@ -1,5 +1,6 @@
 /// Custom error type
 #[derive(Debug)]
+#[allow(dead_code)] // the fields are only read through Debug, when main() returns an error
 pub enum TereError {
     Io(std::io::Error),
     Clap(clap::Error),
@@ -0,0 +1,577 @@
+//! Copying, moving and linking files and folders, for pasting marked items. The functions report
+//! their progress in bytes through a callback, so that the UI can show it for large transfers.
+//! `FileJob` runs these operations (and moving items to the trash) in a background thread, so
+//! that the UI stays responsive, and the operation can be cancelled.
+
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{Error as IOError, ErrorKind, Read, Result as IOResult, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::Arc;
+
+use crate::ui::i18n::{tr, tr_fmt};
+
+/// Size of the chunks in which files are copied, and how often the progress is reported.
+const CHUNK_SIZE: usize = 1 << 20;
+
+/// The callback through which the progress of an operation is reported, in bytes. If it returns
+/// an error, the operation is stopped and the error is returned, which is used for cancelling.
+pub type ProgressFn<'a> = dyn FnMut(u64) -> IOResult<()> + 'a;
+
+fn copy_file(src: &Path, dst: &Path, progress: &mut ProgressFn) -> IOResult<()> {
+    let mut reader = File::open(src)?;
+    let mut writer = File::create(dst)?;
+    let mut buf = vec![0; CHUNK_SIZE];
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        progress(n as u64)?;
+    }
+    writer.set_permissions(reader.metadata()?.permissions())
+}
+
+fn already_exists_error(path: &Path) -> IOError {
+    IOError::new(
+        ErrorKind::AlreadyExists,
+        format!("'{}' already exists", path.display()),
+    )
+}
+
+/// Copy `src` to `dst`, recursively if it's a folder. Symlinks are copied as symlinks on unix,
+/// elsewhere the target is copied. `progress` is called with the number of bytes copied since
+/// the last call. Fails if `dst` already exists. If the copying fails midway, the partial copy
+/// is removed.
+pub fn copy_item(src: &Path, dst: &Path, progress: &mut ProgressFn) -> IOResult<()> {
+    if dst.starts_with(src) {
+        return Err(IOError::new(
+            ErrorKind::InvalidInput,
+            "can't copy a folder into itself",
+        ));
+    }
+    if dst.symlink_metadata().is_ok() {
+        return Err(already_exists_error(dst));
+    }
+
+    let res = copy_item_recursive(src, dst, progress);
+    if res.is_err() {
+        // don't leave a partial copy behind. Nothing was at `dst` before, we checked that above.
+        let _ = remove_item(dst);
+    }
+    res
+}
+
+fn copy_item_recursive(src: &Path, dst: &Path, progress: &mut ProgressFn) -> IOResult<()> {
+    // report even empty files, so that copying many of them can be cancelled too
+    progress(0)?;
+
+    let metadata = src.symlink_metadata()?;
+    if metadata.file_type().is_symlink() {
+        #[cfg(unix)]
+        return std::os::unix::fs::symlink(fs::read_link(src)?, dst);
+    }
+
+    if src.is_dir() {
+        fs::create_dir(dst)?;
+        for entry in fs::read_dir(src)? {
+            let entry = entry?;
+            copy_item_recursive(&entry.path(), &dst.join(entry.file_name()), progress)?;
+        }
+        Ok(())
+    } else {
+        copy_file(src, dst, progress)
+    }
+}
+
+/// Move `src` to `dst`. If they are on different file systems, `src` is copied and then removed.
+/// Fails if `dst` already exists.
+pub fn move_item(src: &Path, dst: &Path, progress: &mut ProgressFn) -> IOResult<()> {
+    if dst.starts_with(src) {
+        return Err(IOError::new(
+            ErrorKind::InvalidInput,
+            "can't move a folder into itself",
+        ));
+    }
+    // rename() would replace an existing file
+    if dst.symlink_metadata().is_ok() {
+        return Err(already_exists_error(dst));
+    }
+
+    match fs::rename(src, dst) {
+        Ok(()) => Ok(()),
+        // rename() can't move between file systems, copy instead. This removes a partial copy
+        // if it fails, the original is still there.
+        Err(e) if e.kind() == ErrorKind::CrossesDevices => {
+            copy_item(src, dst, progress)?;
+            remove_item(src)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Create a symlink at `dst` that points to `src`.
+pub fn link_item(src: &Path, dst: &Path) -> IOResult<()> {
+    #[cfg(unix)]
+    return std::os::unix::fs::symlink(src, dst);
+
+    #[cfg(windows)]
+    return if src.is_dir() {
+        std::os::windows::fs::symlink_dir(src, dst)
+    } else {
+        std::os::windows::fs::symlink_file(src, dst)
+    };
+
+    #[cfg(not(any(unix, windows)))]
+    return Err(IOError::new(
+        ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ));
+}
+
+/// What to do when pasting marked items to a folder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteMode {
+    Copy,
+    Move,
+    Link,
+}
+
+impl PasteMode {
+    /// Paste `src` to `dst` in this mode.
+    pub fn apply(&self, src: &Path, dst: &Path, progress: &mut ProgressFn) -> IOResult<()> {
+        match self {
+            PasteMode::Copy => copy_item(src, dst, progress),
+            PasteMode::Move => move_item(src, dst, progress),
+            PasteMode::Link => link_item(src, dst),
+        }
+    }
+
+    /// The translated verb describing the operation while it's in progress, like "Copying"
+    pub fn present_participle(&self) -> &'static str {
+        match self {
+            PasteMode::Copy => tr("Copying"),
+            PasteMode::Move => tr("Moving"),
+            PasteMode::Link => tr("Linking"),
+        }
+    }
+
+    /// The translated verb describing the operation after it's done, like "Copied"
+    pub fn past_participle(&self) -> &'static str {
+        match self {
+            PasteMode::Copy => tr("Copied"),
+            PasteMode::Move => tr("Moved"),
+            PasteMode::Link => tr("Linked"),
+        }
+    }
+}
+
+/// Remove a file, symlink or folder (recursively).
+pub fn remove_item(path: &Path) -> IOResult<()> {
+    if path.symlink_metadata()?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Find a name that doesn't exist yet in `dir`, by adding a number to `name`, before the
+/// extension if there is one. For example `foo.txt` becomes `foo (1).txt`. The paths in `taken`
+/// are avoided too, even if nothing exists there yet.
+pub fn unique_name(dir: &Path, name: &OsStr, taken: &[PathBuf]) -> OsString {
+    let path = Path::new(name);
+    let (stem, ext) = match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(ext)) => (stem, Some(ext)),
+        _ => (name, None),
+    };
+    (1..)
+        .map(|i| {
+            let mut candidate = stem.to_os_string();
+            candidate.push(format!(" ({})", i));
+            if let Some(ext) = ext {
+                candidate.push(".");
+                candidate.push(ext);
+            }
+            candidate
+        })
+        .find(|candidate| {
+            let path = dir.join(candidate);
+            path.symlink_metadata().is_err() && !taken.contains(&path)
+        })
+        // ok to unwrap, the iterator is infinite
+        .unwrap()
+}
+
+/// Format a number of bytes for humans, like `1.5 MB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// The kind of operation performed by a `FileJob`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum JobKind {
+    Paste(PasteMode),
+    Trash,
+}
+
+impl JobKind {
+    pub fn present_participle(&self) -> &'static str {
+        match self {
+            JobKind::Paste(mode) => mode.present_participle(),
+            JobKind::Trash => tr("Trashing"),
+        }
+    }
+
+    pub fn past_participle(&self) -> &'static str {
+        match self {
+            JobKind::Paste(mode) => mode.past_participle(),
+            JobKind::Trash => tr("Moved to trash"),
+        }
+    }
+}
+
+/// A single item to be processed by a `FileJob`.
+#[derive(Debug, Clone)]
+pub struct JobItem {
+    pub src: PathBuf,
+    /// Where to paste the item. Unused when moving items to the trash.
+    pub dst: PathBuf,
+    /// Remove an existing item at `dst` before pasting
+    pub overwrite: bool,
+}
+
+/// Messages sent from the worker thread to the `FileJob`
+enum JobMessage {
+    /// Started processing the item with this index
+    Started(usize),
+    /// This many more bytes have been processed
+    Progress(u64),
+    /// The item with this index was processed successfully
+    Done(usize),
+    /// Processing the item with this index failed, and the job was stopped
+    Failed(usize, IOError),
+}
+
+fn cancelled_error() -> IOError {
+    IOError::new(ErrorKind::Interrupted, "cancelled")
+}
+
+fn run_item(kind: JobKind, item: &JobItem, progress: &mut ProgressFn) -> IOResult<()> {
+    let mode = match kind {
+        JobKind::Paste(mode) => mode,
+        JobKind::Trash => {
+            return trash::delete(&item.src).map_err(|e| IOError::other(e.to_string()))
+        }
+    };
+    if item.overwrite {
+        remove_item(&item.dst)?;
+    }
+    mode.apply(&item.src, &item.dst, progress)
+}
+
+/// A file operation running in a background thread. Call `update` periodically to receive its
+/// progress, and to find out when it has finished.
+pub struct FileJob {
+    kind: JobKind,
+    items: Vec<JobItem>,
+    receiver: Receiver<JobMessage>,
+    cancel_flag: Arc<AtomicBool>,
+    /// The index of the item being processed
+    current: usize,
+    /// Bytes processed of the current item
+    current_bytes: u64,
+    /// Bytes processed in total
+    total_bytes: u64,
+    /// Indices of the items that were processed successfully
+    done: Vec<usize>,
+    error: Option<(usize, IOError)>,
+    finished: bool,
+}
+
+impl FileJob {
+    /// Start processing `items` in a background thread. The items are processed in order, and
+    /// the job stops at the first error.
+    pub fn start(kind: JobKind, items: Vec<JobItem>) -> Self {
+        Self::start_with_cancel_flag(kind, items, Arc::new(AtomicBool::new(false)))
+    }
+
+    fn start_with_cancel_flag(
+        kind: JobKind,
+        items: Vec<JobItem>,
+        cancel_flag: Arc<AtomicBool>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+
+        let thread_items = items.clone();
+        let thread_cancel_flag = cancel_flag.clone();
+        std::thread::spawn(move || {
+            for (i, item) in thread_items.iter().enumerate() {
+                if thread_cancel_flag.load(Ordering::Relaxed) {
+                    let _ = sender.send(JobMessage::Failed(i, cancelled_error()));
+                    return;
+                }
+                let _ = sender.send(JobMessage::Started(i));
+                let mut progress = |n: u64| {
+                    if thread_cancel_flag.load(Ordering::Relaxed) {
+                        return Err(cancelled_error());
+                    }
+                    if n > 0 {
+                        let _ = sender.send(JobMessage::Progress(n));
+                    }
+                    Ok(())
+                };
+                match run_item(kind, item, &mut progress) {
+                    Ok(()) => {
+                        let _ = sender.send(JobMessage::Done(i));
+                    }
+                    Err(e) => {
+                        let _ = sender.send(JobMessage::Failed(i, e));
+                        return;
+                    }
+                }
+            }
+            // dropping the sender tells the job that everything is done
+        });
+
+        Self {
+            kind,
+            items,
+            receiver,
+            cancel_flag,
+            current: 0,
+            current_bytes: 0,
+            total_bytes: 0,
+            done: vec![],
+            error: None,
+            finished: false,
+        }
+    }
+
+    fn handle_message(&mut self, msg: JobMessage) {
+        match msg {
+            JobMessage::Started(i) => {
+                self.current = i;
+                self.current_bytes = 0;
+            }
+            JobMessage::Progress(n) => {
+                self.current_bytes += n;
+                self.total_bytes += n;
+            }
+            JobMessage::Done(i) => self.done.push(i),
+            JobMessage::Failed(i, e) => self.error = Some((i, e)),
+        }
+    }
+
+    /// Process the messages from the worker thread without blocking. Returns true once the job
+    /// has finished.
+    pub fn update(&mut self) -> bool {
+        while !self.finished {
+            match self.receiver.try_recv() {
+                Ok(msg) => self.handle_message(msg),
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => self.finished = true,
+            }
+        }
+        self.finished
+    }
+
+    /// Block until the job has finished.
+    pub fn wait(&mut self) {
+        while let Ok(msg) = self.receiver.recv() {
+            self.handle_message(msg);
+        }
+        self.finished = true;
+    }
+
+    /// Ask the worker thread to stop. It stops after the current chunk of data, and removes a
+    /// partially copied item. Items that are already done are left as they are.
+    pub fn cancel(&self) {
+        self.cancel_flag.store(true, Ordering::Relaxed);
+    }
+
+    pub fn kind(&self) -> JobKind {
+        self.kind
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel_flag.load(Ordering::Relaxed)
+    }
+
+    /// The items that were processed successfully
+    pub fn done_items(&self) -> impl Iterator<Item = &JobItem> {
+        self.done.iter().map(move |i| &self.items[*i])
+    }
+
+    pub fn num_items(&self) -> usize {
+        self.items.len()
+    }
+
+    /// The error that stopped the job, if any, and the item that caused it. This is also set when
+    /// the job was cancelled.
+    pub fn error(&self) -> Option<(&JobItem, &IOError)> {
+        self.error.as_ref().map(|(i, e)| (&self.items[*i], e))
+    }
+
+    /// Whether any of the items are in `dir`, or are pasted to it.
+    pub fn affects_folder(&self, dir: &Path) -> bool {
+        self.items.iter().any(|item| {
+            item.src.parent() == Some(dir)
+                || (self.kind != JobKind::Trash && item.dst.parent() == Some(dir))
+        })
+    }
+
+    /// A description of the progress for showing to the user, like
+    /// "Copying 2/5: 'foo.txt' (1.5 MB, 12.0 MB total)".
+    pub fn status(&self) -> String {
+        let name = self
+            .items
+            .get(self.current)
+            .and_then(|item| item.src.file_name())
+            .unwrap_or_default()
+            .to_string_lossy();
+        let mut status = tr_fmt(
+            "{} {}/{}: '{}'",
+            &[
+                &self.kind.present_participle(),
+                &(self.current + 1).min(self.items.len()),
+                &self.items.len(),
+                &name,
+            ],
+        );
+        if self.total_bytes > 0 {
+            status.push_str(&tr_fmt(
+                " ({}, {} total)",
+                &[&format_size(self.current_bytes), &format_size(self.total_bytes)],
+            ));
+        }
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_copy_and_move() {
+        let tmp = TempDir::new("file-ops");
+        fs::create_dir_all(tmp.join("src/sub")).unwrap();
+        fs::write(tmp.join("src/a.txt"), "hello").unwrap();
+        fs::write(tmp.join("src/sub/b.txt"), "world").unwrap();
+
+        let mut copied = 0;
+        copy_item(&tmp.join("src"), &tmp.join("copy"), &mut |n| {
+            copied += n;
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(copied, 10);
+        assert_eq!(fs::read_to_string(tmp.join("copy/sub/b.txt")).unwrap(), "world");
+        assert!(tmp.join("src/a.txt").exists());
+
+        move_item(&tmp.join("copy"), &tmp.join("moved"), &mut |_| Ok(())).unwrap();
+        assert!(!tmp.join("copy").exists());
+        assert_eq!(fs::read_to_string(tmp.join("moved/a.txt")).unwrap(), "hello");
+
+        PasteMode::Link.apply(&tmp.join("moved"), &tmp.join("link"), &mut |_| Ok(())).unwrap();
+        assert_eq!(fs::read_link(tmp.join("link")).unwrap(), tmp.join("moved"));
+        assert_eq!(fs::read_to_string(tmp.join("link/a.txt")).unwrap(), "hello");
+
+        assert!(copy_item(&tmp.join("src"), &tmp.join("src/sub/x"), &mut |_| Ok(())).is_err());
+        assert!(move_item(&tmp.join("src"), &tmp.join("src/x"), &mut |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_paste_onto_existing_item() {
+        let tmp = TempDir::new("paste-existing");
+        fs::create_dir_all(tmp.join("src/sub")).unwrap();
+        fs::create_dir_all(tmp.join("dst/sub")).unwrap();
+        fs::write(tmp.join("dst/sub/keep.txt"), "keep").unwrap();
+
+        // an existing folder is neither merged into nor removed
+        for mode in [PasteMode::Copy, PasteMode::Move] {
+            let e = mode.apply(&tmp.join("src/sub"), &tmp.join("dst/sub"), &mut |_| Ok(())).unwrap_err();
+            assert_eq!(e.kind(), ErrorKind::AlreadyExists);
+            assert_eq!(fs::read_to_string(tmp.join("dst/sub/keep.txt")).unwrap(), "keep");
+            assert!(tmp.join("src/sub").exists());
+        }
+    }
+
+    #[test]
+    fn test_file_job() {
+        let tmp = TempDir::new("file-job");
+        fs::create_dir_all(tmp.join("dst")).unwrap();
+        fs::write(tmp.join("a.txt"), "hello").unwrap();
+        fs::write(tmp.join("b.txt"), "world!").unwrap();
+        fs::write(tmp.join("dst/b.txt"), "old").unwrap();
+
+        let item = |name: &str, overwrite| JobItem {
+            src: tmp.join(name),
+            dst: tmp.join("dst").join(name),
+            overwrite,
+        };
+        let items = vec![item("a.txt", false), item("b.txt", true), item("c.txt", false)];
+        let mut job = FileJob::start(JobKind::Paste(PasteMode::Copy), items);
+        job.wait();
+        assert!(job.update());
+        assert_eq!(fs::read_to_string(tmp.join("dst/b.txt")).unwrap(), "world!");
+        assert_eq!(job.done_items().count(), 2);
+        let (failed, _) = job.error().unwrap();
+        assert_eq!(failed.src, tmp.join("c.txt"));
+        assert!(job.affects_folder(&tmp.join("dst")));
+        assert!(!job.affects_folder(&tmp.join("foo")));
+
+        // a cancelled job doesn't process any more items. Cancel it before it starts, so that the
+        // worker thread can't finish the item before seeing the flag.
+        let cancel_flag = Arc::new(AtomicBool::new(true));
+        let items = vec![item("a.txt", true)];
+        let kind = JobKind::Paste(PasteMode::Move);
+        let mut job = FileJob::start_with_cancel_flag(kind, items, cancel_flag);
+        job.wait();
+        assert!(job.is_cancelled());
+        assert_eq!(job.done_items().count(), 0);
+        let (failed, e) = job.error().unwrap();
+        assert_eq!(failed.src, tmp.join("a.txt"));
+        assert_eq!(e.kind(), ErrorKind::Interrupted);
+        assert!(tmp.join("a.txt").exists());
+        assert_eq!(fs::read_to_string(tmp.join("dst/a.txt")).unwrap(), "hello");
+    }
+
+    #[test]
+    fn test_unique_name() {
+        let tmp = TempDir::new("unique-name");
+        fs::write(tmp.join("foo.txt"), "").unwrap();
+        fs::write(tmp.join("foo (1).txt"), "").unwrap();
+
+        assert_eq!(unique_name(&tmp, OsStr::new("foo.txt"), &[]), "foo (2).txt");
+        assert_eq!(unique_name(&tmp, OsStr::new("bar"), &[]), "bar (1)");
+        // names that are about to be used are skipped too
+        let taken = [tmp.join("foo (2).txt")];
+        assert_eq!(unique_name(&tmp, OsStr::new("foo.txt"), &taken), "foo (3).txt");
+    }
+
+    #[test]
+    fn test_format_size() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(999), "999 B");
+        assert_eq!(format_size(1500), "1.5 kB");
+        assert_eq!(format_size(2_000_000_000), "2.0 GB");
+    }
+}
@@ -0,0 +1,174 @@
+/// Named filters that hide items in every folder, defined with the `--filter-preset` option and
+/// switched between with `Action::CycleFilterPreset`. Unlike the view preferences, the active
+/// preset is not remembered for each folder, since presets are meant for a task like looking for
+/// media files, which spans many folders.
+use super::CustomDirEntry;
+
+/// A single rule of a preset
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    /// Show only folders
+    DirsOnly,
+    /// Show only files
+    FilesOnly,
+    /// Hide hidden items
+    NoHidden,
+    /// Show only the items that match one of the patterns, see `FilterPreset::parse`
+    Include(Pattern),
+    /// Hide the items that match the pattern
+    Exclude(Pattern),
+}
+
+/// A glob pattern for names, which applies only to folders if it ends with '/', and otherwise
+/// only to files
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    /// The lowercased glob, without the trailing '/'
+    glob: String,
+    for_dirs: bool,
+}
+
+impl Pattern {
+    fn new(pattern: &str) -> Self {
+        let glob = pattern.trim_end_matches('/');
+        Self { glob: glob.to_lowercase(), for_dirs: glob.len() != pattern.len() }
+    }
+
+    fn applies_to(&self, item: &CustomDirEntry) -> bool {
+        self.for_dirs == item.is_dir()
+    }
+
+    fn matches(&self, item: &CustomDirEntry) -> bool {
+        self.applies_to(item) && glob_match(&self.glob, item.lowercase_file_name())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterPreset {
+    pub name: String,
+    rules: Vec<Rule>,
+}
+
+impl FilterPreset {
+    /// Parse a preset in the format 'NAME:RULE RULE ...'. A rule is 'dirs' or 'files' to show
+    /// only folders or files, 'no-hidden' to hide hidden items, a glob pattern like '*.jpg' to
+    /// show only the items that match one of the patterns, or a pattern starting with '!' to hide
+    /// the items that match it. Patterns apply only to files, or only to folders if they end with
+    /// '/', and they ignore case.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, rules) = spec
+            .split_once(':')
+            .ok_or_else(|| "expected NAME:RULES".to_string())?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("the name is empty".to_string());
+        }
+        let rules: Vec<Rule> = rules
+            .split_whitespace()
+            .map(|rule| match rule {
+                "dirs" => Rule::DirsOnly,
+                "files" => Rule::FilesOnly,
+                "no-hidden" => Rule::NoHidden,
+                rule => match rule.strip_prefix('!') {
+                    Some(pattern) => Rule::Exclude(Pattern::new(pattern)),
+                    None => Rule::Include(Pattern::new(rule)),
+                },
+            })
+            .collect();
+        if rules.is_empty() {
+            return Err(format!("the preset '{}' has no rules", name));
+        }
+        Ok(Self { name: name.to_string(), rules })
+    }
+
+    /// Whether `item` is shown with this preset
+    pub fn shows(&self, item: &CustomDirEntry) -> bool {
+        let mut includes = self.rules.iter().filter_map(|rule| match rule {
+            Rule::Include(pattern) if pattern.applies_to(item) => Some(pattern),
+            _ => None,
+        }).peekable();
+        if includes.peek().is_some() && !includes.any(|pattern| pattern.matches(item)) {
+            return false;
+        }
+        self.rules.iter().all(|rule| match rule {
+            Rule::DirsOnly => item.is_dir(),
+            Rule::FilesOnly => !item.is_dir(),
+            Rule::NoHidden => !item.is_hidden(),
+            Rule::Include(_) => true,
+            Rule::Exclude(pattern) => !pattern.matches(item),
+        })
+    }
+}
+
+/// Whether `name` matches `glob`, in which '*' matches any number of characters and '?' matches
+/// a single character.
+fn glob_match(glob: &str, name: &str) -> bool {
+    let (glob, name): (Vec<char>, Vec<char>) = (glob.chars().collect(), name.chars().collect());
+    // the position after the last '*', and the position in the name that it was matched up to
+    let (mut g, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g + 1, n));
+                g += 1;
+            }
+            Some(c) if *c == '?' || *c == name[n] => {
+                g += 1;
+                n += 1;
+            }
+            _ => match star {
+                // let the last '*' match one more character
+                Some((star_g, star_n)) => {
+                    star = Some((star_g, star_n + 1));
+                    g = star_g;
+                    n = star_n + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    glob[g..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn item(name: &str, is_dir: bool) -> CustomDirEntry {
+        CustomDirEntry::new_virtual(PathBuf::from(name), is_dir)
+    }
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("*.jpg", "photo.jpg"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("a?c*", "abcdef"));
+        assert!(glob_match("*a*b", "xaxxab"));
+        assert!(!glob_match("*.jpg", "photo.jpeg"));
+        assert!(!glob_match("a?c", "ac"));
+    }
+
+    #[test]
+    fn test_filter_preset() {
+        let media = FilterPreset::parse("media: *.jpg *.PNG !tmp/").unwrap();
+        assert_eq!(media.name, "media");
+        assert!(media.shows(&item("a.jpg", false)));
+        assert!(media.shows(&item("B.png", false)));
+        assert!(!media.shows(&item("c.txt", false)));
+        // the file patterns don't hide folders
+        assert!(media.shows(&item("photos", true)));
+        assert!(!media.shows(&item("tmp", true)));
+
+        let code = FilterPreset::parse("code dirs:dirs no-hidden !target/ !node_modules/").unwrap();
+        assert!(code.shows(&item("src", true)));
+        assert!(!code.shows(&item(".git", true)));
+        assert!(!code.shows(&item("target", true)));
+        assert!(!code.shows(&item("main.rs", false)));
+
+        assert!(FilterPreset::parse("media").is_err());
+        assert!(FilterPreset::parse("media:").is_err());
+        assert!(FilterPreset::parse(":dirs").is_err());
+    }
+}
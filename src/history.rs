@@ -1,10 +1,20 @@
 use std::cell::RefCell;
 use std::rc::{Rc, Weak};
-use std::path::Path;
+use std::path::{Component, Path};
 use serde::ser::{Serialize, Serializer, SerializeMap};
 use serde::de::{Deserialize, Deserializer, Visitor, MapAccess, Error as deError};
 
 
+/// The labels of the entries of the history tree on the way to the absolute path `path`, from the
+/// root. On Windows, the first one is the prefix of the path, like `C:` or `\\server\share`, so
+/// that each drive and network share has its own subtree.
+fn path_labels(path: &Path) -> impl Iterator<Item = String> + '_ {
+    path.components().filter_map(|component| match component {
+        Component::RootDir | Component::CurDir => None,
+        c => Some(c.as_os_str().to_string_lossy().into_owned()),
+    })
+}
+
 // Tree struct based on https://doc.rust-lang.org/stable/book/ch15-06-reference-cycles.html
 pub struct HistoryTreeEntry {
     label: String,
@@ -56,9 +66,7 @@ impl HistoryTree {
            current_entry: root,
        };
 
-       path.as_ref().components()
-           .skip(1) // skip root component (NOTE: this will cause problems on windows...)
-           .for_each(|component| tree.visit(&component.as_os_str().to_string_lossy()));
+       path_labels(path.as_ref()).for_each(|label| tree.visit(&label));
        tree
     }
 
@@ -95,8 +103,8 @@ impl HistoryTree {
     /// Change directory completely to a new absolute path
     pub fn change_dir<P: AsRef<Path>>(&mut self, abs_path: P) {
         self.go_to_root();
-        for component in abs_path.as_ref().components().skip(1) {
-            self.visit(&component.as_os_str().to_string_lossy())
+        for label in path_labels(abs_path.as_ref()) {
+            self.visit(&label)
         }
     }
 
@@ -308,9 +316,9 @@ mod tests_for_history_tree {
     fn test_tree_pointer_counts() {
         let mut tree = init_history_tree();
         tree.visit("foo");
-        let foo = Rc::downgrade(&tree.current_entry());
+        let foo = Rc::downgrade(tree.current_entry());
         tree.visit("bar");
-        let bar = Rc::downgrade(&tree.current_entry());
+        let bar = Rc::downgrade(tree.current_entry());
 
         assert_eq!(Rc::weak_count(&tree.root), 1); // the child (foo)
 
@@ -338,6 +346,19 @@ mod tests_for_history_tree {
         assert!(Rc::ptr_eq(&foo, &tree.current_entry().last_visited_child.borrow().as_ref().unwrap().upgrade().unwrap()));
     }
 
+    #[test]
+    fn test_path_labels() {
+        let labels: Vec<_> = path_labels(Path::new("/foo/bar")).collect();
+        assert_eq!(labels, vec!["foo", "bar"]);
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_unc_path_labels() {
+        let labels: Vec<_> = path_labels(Path::new(r"\\server\share\foo")).collect();
+        assert_eq!(labels, vec![r"\\server\share", "foo"]);
+    }
+
     #[test]
     fn test_go_to_root() {
         let mut tree = init_history_tree();
@@ -382,7 +403,7 @@ mod tests_for_history_tree {
         assert_eq!(tree.current_entry().last_visited_child_label(), Some("baz".to_string()));
 
         tree.change_dir("/");
-        assert!(Rc::ptr_eq(&tree.current_entry(), &tree.root));
+        assert!(Rc::ptr_eq(tree.current_entry(), &tree.root));
 
         tree.change_dir("/foo/bax");
 
@@ -0,0 +1,47 @@
+//! The core of tere, i.e. the navigation and search logic, which can be reused by other tools
+//! without the terminal UI.
+//!
+//! The main entry point is [`TereAppState`], which keeps track of the current folder, its
+//! contents, the cursor position and the search query. It is created from a [`TereSettings`],
+//! and updated by calling methods like [`TereAppState::change_dir`],
+//! [`TereAppState::advance_search`] and [`TereAppState::move_cursor`]. The items that match the
+//! current search can be read with [`TereAppState::visible_items`] and
+//! [`TereAppState::visible_match_indices`].
+//!
+//! By default, the contents of folders are read from the file system. Other sources, like the
+//! in-memory [`MemoryDirSource`], can be browsed by implementing [`DirSource`] and creating the
+//! state with [`TereAppState::with_dir_source`].
+//!
+//! The search can also be used on its own: build a pattern with [`search_pattern`], and filter a
+//! list of items with [`MatchesVec::update_matches`].
+//!
+//! The types exported at the top level of this crate are considered the stable API. The modules
+//! are public so that the `tere` binary can use them, but their other contents may change
+//! between versions.
+
+pub mod app_state;
+pub mod doctor;
+pub mod error;
+pub mod file_ops;
+pub mod logging;
+pub mod output;
+pub mod profile;
+pub mod setup;
+pub mod shell_init;
+pub mod ui;
+
+#[cfg(test)]
+mod test_util;
+
+#[cfg(feature = "ratatui")]
+pub mod widget;
+
+pub use app_state::{
+    search_pattern, ArchiveDirSource, ArchiveMode, AutoCdMode, CaseSensitiveMode, CustomDirEntry,
+    DirSource, FsDirSource, GapSearchMode, Hooks, MatchesVec, MemoryDirSource, NoMatchBehavior,
+    TereAppState,
+    TereSettings, UnboundKeyBehavior,
+};
+pub use error::TereError;
+pub use ui::action::{Action, ActionContext};
+pub use ui::theme::Theme;
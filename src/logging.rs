@@ -0,0 +1,48 @@
+/// Debug logging to a file, enabled with `--log-file` or the `TERE_LOG` environment variable.
+/// The log has the key presses and other input events, the results of changing folders and how
+/// long the steps of starting up and reading folders took, so that it can be attached to bug
+/// reports about problems that only happen with some terminals or file systems. The events are
+/// recorded with the macros of the `tracing` crate from wherever they happen, and they do nothing
+/// unless logging has been enabled with `init`.
+use std::fs::OpenOptions;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+
+use tracing::level_filters::LevelFilter;
+
+/// The environment variable that enables logging to the file that it's set to, like
+/// `--log-file`
+pub const LOG_ENV_VAR: &str = "TERE_LOG";
+
+/// The file to log to, from the `--log-file` option `option` if it was given, or otherwise from
+/// the `TERE_LOG` environment variable. An empty path disables logging.
+pub fn log_file(option: Option<&str>) -> Option<PathBuf> {
+    let file = match option {
+        Some(file) => PathBuf::from(file),
+        None => PathBuf::from(std::env::var_os(LOG_ENV_VAR)?),
+    };
+    (!file.as_os_str().is_empty()).then_some(file)
+}
+
+/// Start logging to `file`, appending to it if it exists. Only the first call has an effect.
+pub fn init(file: &Path) -> IOResult<()> {
+    let file = OpenOptions::new().create(true).append(true).open(file)?;
+    let subscriber = tracing_subscriber::fmt()
+        .with_writer(std::sync::Mutex::new(file))
+        .with_ansi(false)
+        .with_max_level(LevelFilter::DEBUG)
+        .finish();
+    let _ = tracing::subscriber::set_global_default(subscriber);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_file() {
+        assert_eq!(log_file(Some("/tmp/tere.log")), Some(PathBuf::from("/tmp/tere.log")));
+        assert_eq!(log_file(Some("")), None);
+    }
+}
@@ -1,4 +1,5 @@
 use std::io::Write;
+use std::time::Instant;
 use crossterm::{
     execute,
     terminal,
@@ -10,18 +11,21 @@ use clap::{App, Arg};
 //TODO: rustfmt
 //TODO: clippy
 
-mod app_state;
-
-mod ui;
-use ui::TereTui;
-
-mod error;
-use error::TereError;
+use tere::app_state::{StartDir, TereSettings};
+use tere::ui::{self, TereTui};
+use tere::ui::theme::Theme;
+use tere::doctor;
+use tere::error::TereError;
+use tere::logging;
+use tere::output::{self, ExitReason, OutputSettings, OutputTarget};
+use tere::profile;
+use tere::setup;
+use tere::shell_init;
 
 
 macro_rules! case_sensitive_template {
-    ($x:tt, $y:tt) => {
-        format!("This overrides the --{} and --{} options. You can also change the case sensitivity mode while the program is running with the keyboard shortcut ALT+C.", $x, $y)
+    ($x:tt, $y:tt, $z:tt) => {
+        format!("This overrides the --{}, --{} and --{} options. You can also change the case sensitivity mode while the program is running with the keyboard shortcut ALT+C.", $x, $y, $z)
     }
 }
 
@@ -32,6 +36,7 @@ macro_rules! gap_search_mode_template {
 }
 
 fn main() -> Result<(), TereError> {
+    let start = Instant::now();
 
     let cli_args = App::new(env!("CARGO_PKG_NAME"))
         .version(env!("CARGO_PKG_VERSION"))
@@ -54,12 +59,31 @@ fn main() -> Result<(), TereError> {
              .long_help("Show all items in the listing even when searching (default). This overrides the --filter-search option.")
              .overrides_with_all(&["filter-search", "no-filter-search"])
             )
+        .arg(Arg::new("no-match")
+             .long("no-match")
+             .help("What to do when a typed character leaves nothing matching the search")
+             .long_help("What to do when a character is typed that leaves no items matching the search. With 'keep' (the default), the character is added to the search, and a message says that nothing matches. With 'discard', the character is ignored and the previous matches are kept, like in some fuzzy finders.")
+             .takes_value(true)
+             .value_name("BEHAVIOR")
+             .possible_values(["keep", "discard"])
+             .default_value("keep")
+             .overrides_with("no-match")
+            )
+        .arg(Arg::new("theme")
+             .long("theme")
+             .help("The colors of the listing")
+             .long_help("The colors of the listing. 'default' highlights the item under the cursor in grey, 'high-contrast' uses brighter colors and doesn't dim the names of files, 'monochrome' uses only bold, underlined and reversed text instead of colors, and 'light' uses darker colors for terminals with a light background. If this is not given and the NO_COLOR environment variable is set, 'monochrome' is used.")
+             .takes_value(true)
+             .value_name("THEME")
+             .possible_values(Theme::NAMES)
+             .overrides_with("theme")
+            )
         .arg(Arg::new("folders-only")
              .long("folders-only")
              //.visible_alias("fo") //TODO: consider
              .short('d')
              .help("Show only folders in the listing")
-             .long_help("Show only folders (and symlinks pointing to folders) in the listing. This overrides the --no-folders-only option.")
+             .long_help("Show only folders (and symlinks pointing to folders) in the listing. This can be changed for each folder with Alt+Shift+d, which is remembered for the folder. This overrides the --no-folders-only option.")
              .overrides_with("folders-only")
              )
         .arg(Arg::new("no-folders-only")
@@ -70,29 +94,87 @@ fn main() -> Result<(), TereError> {
              .long_help("Show both files and folders in the listing. This is the default view mode. This overrides the --folders-only option.")
              .overrides_with_all(&["folders-only", "no-folders-only"])
              )
+        .arg(Arg::new("search-folders-only")
+             .long("search-folders-only")
+             .help("Match only folders when searching, even if files are shown")
+             .long_help("When searching, match only folders (and symlinks pointing to folders), even if files are shown in the listing. The files stay visible, but the cursor never moves to them while searching, and they are never the only match that is changed to automatically. This overrides the --no-search-folders-only option.")
+             .overrides_with("search-folders-only")
+             )
+        .arg(Arg::new("no-search-folders-only")
+             .long("no-search-folders-only")
+             .help("Match both files and folders when searching (default)")
+             .long_help("Match both files and folders when searching. This is the default. This overrides the --search-folders-only option.")
+             .overrides_with_all(&["search-folders-only", "no-search-folders-only"])
+             )
+        .arg(Arg::new("browse-mode")
+             .long("browse-mode")
+             .help("Use letters for navigating, and start searching with '/'")
+             .long_help("Browse mode: instead of searching, plain letters are navigation keys like in vim. h goes to the parent folder, j and k move the cursor, l enters the folder under the cursor, g and G go to the first and last item, ~ goes to the home folder and q exits. To search, press '/' first. Browse mode can also be toggled with Alt+b. This overrides the --no-browse-mode option.")
+             .overrides_with("browse-mode")
+             )
+        .arg(Arg::new("no-browse-mode")
+             .long("no-browse-mode")
+             .help("Search by typing letters (default)")
+             .long_help("Start searching by typing letters. This is the default. This overrides the --browse-mode option.")
+             .overrides_with_all(&["browse-mode", "no-browse-mode"])
+             )
+        .arg(Arg::new("sort")
+             .long("sort")
+             .help("How to sort the items in folders where it hasn't been changed")
+             .long_help("Sort the items by name, by modification time (newest first) or by size (largest first). Folders are always listed first. This is only the default, since the sort order can be changed for each folder with Ctrl+s and reversed with Alt+Shift+s, which is remembered for the folder.")
+             .takes_value(true)
+             .value_name("ORDER")
+             .possible_values(["name", "modified", "size"])
+             .default_value("name")
+             .overrides_with("sort")
+            )
+        .arg(Arg::new("choose-file")
+             .long("choose-file")
+             .help("Exit and print the path of a file when trying to enter it")
+             .long_help("File selection mode: when trying to enter a file (for example by pressing Enter when the cursor is on it), exit and print the path of the file, instead of showing an error. This way, tere can be used to pick a file, for example with '$EDITOR \"$(tere --choose-file)\"'. Exiting in any other way prints the current folder as usual. Auto-cd is never applied to files in this mode. This overrides the --no-choose-file option.")
+             .overrides_with("choose-file")
+            )
+        .arg(Arg::new("no-choose-file")
+             .long("no-choose-file")
+             .help("Show an error when trying to enter a file (default)")
+             .long_help("Show an error when trying to enter a file. This is the default. This overrides the --choose-file option.")
+             .overrides_with_all(&["choose-file", "no-choose-file"])
+            )
+        .arg(Arg::new("stdin")
+             .long("stdin")
+             .help("Choose from a list of paths read from stdin")
+             .long_help("Read a list of paths from stdin, one per line, and show them instead of the contents of the current folder. Pressing Enter on an item exits and prints the item, for example 'fd -t d | tere --stdin'. It is not possible to change folders in this mode, and exiting without choosing an item (or marking items) is treated as cancelling.")
+            )
         .arg(Arg::new("case-sensitive")
              .long("case-sensitive")
              .short('s')  // same as ripgrep
              .help("Case sensitive search")
              .long_help(format!("Enable case-sensitive search.\n\n{}",
-                        case_sensitive_template!("ignore-case", "smart-case")).as_str())
-             .overrides_with_all(&["ignore-case", "smart-case", "case-sensitive"])
+                        case_sensitive_template!("ignore-case", "smart-case", "auto-case")).as_str())
+             .overrides_with_all(&["ignore-case", "smart-case", "auto-case", "case-sensitive"])
             )
         .arg(Arg::new("ignore-case")
              .long("ignore-case")
              .short('i') // same as ripgrep
              .help("Ignore case when searching")
              .long_help(format!("Enable case-insensitive search.\n\n{}",
-                        case_sensitive_template!("case-sensitive", "smart-case")).as_str())
-             .overrides_with_all(&["smart-case", "ignore-case"])
+                        case_sensitive_template!("case-sensitive", "smart-case", "auto-case")).as_str())
+             .overrides_with_all(&["smart-case", "auto-case", "ignore-case"])
             )
         .arg(Arg::new("smart-case")
              .long("smart-case")
              .short('S') // same as ripgrep
              .help("Smart case search (default)")
              .long_help(format!("Enable smart-case search. If the search query contains only lowercase letters, search case insensitively. Otherwise search case sensitively. This is the default search mode.\n\n{}",
-                        case_sensitive_template!("case-sensitive", "ignore-case")).as_str())
-             .overrides_with("smart-case")
+                        case_sensitive_template!("case-sensitive", "ignore-case", "auto-case")).as_str())
+             .overrides_with_all(&["auto-case", "smart-case"])
+            )
+        .arg(Arg::new("auto-case")
+             .long("auto-case")
+             .help("Ignore case if the file system does")
+             .long_help(format!("Choose the case sensitivity mode based on the file system of the current folder. If the file system ignores case in file names, which is common on macOS and Windows, search case insensitively. Otherwise, use smart case.\n\n{}",
+                        case_sensitive_template!("case-sensitive", "ignore-case", "smart-case")).as_str())
+             .overrides_with("auto-case")
             )
         .arg(Arg::new("gap-search")
              .long("gap-search")
@@ -125,6 +207,32 @@ fn main() -> Result<(), TereError> {
              .value_name("TIMEOUT or 'off'")
              .overrides_with("autocd-timeout")
             )
+        .arg(Arg::new("read-timeout")
+             .long("read-timeout")
+             .help("Give up opening a folder after this many milliseconds. Defaults to 'off'.")
+             .long_help("If opening a folder takes longer than this many milliseconds, for example because it's on a network file system that doesn't respond, give up and show the previous folder again. Reading a folder can always be cancelled with Esc. If the value is 'off' (the default), tere waits until the folder has been opened or the reading is cancelled.")
+             .default_value("off")
+             .value_name("TIMEOUT or 'off'")
+             .overrides_with("read-timeout")
+            )
+        .arg(Arg::new("highlight-changes")
+             .long("highlight-changes")
+             .help("When refreshing, highlight items modified in the last this many seconds. Defaults to 60.")
+             .long_help("When the listing of the current folder is refreshed, either with Ctrl+r or automatically because the folder changed, highlight the items that weren't in the previous listing, and the items that were modified in the last this many seconds. With 0, only the new items are highlighted, and with 'off', nothing is. In folders on network file systems, only the new items are highlighted, unless --remote-metadata is given.")
+             .default_value("60")
+             .value_name("SECONDS or 'off'")
+             .overrides_with("highlight-changes")
+            )
+        .arg(Arg::new("autocd")
+             .long("autocd")
+             .help("When to automatically cd to the only match of a search")
+             .long_help("Control when to automatically change to the folder that is the only match of the current search. With 'always' (the default), change to the match after the auto-cd timeout. With 'prefix', change only if the search query matches the beginning of the name without gaps. With 'dirs', change only if the match is a folder. With 'enter', only highlight the match, and wait for Enter to be pressed. With 'never', don't do anything special when there is only one match.")
+             .takes_value(true)
+             .value_name("MODE")
+             .possible_values(["always", "never", "prefix", "dirs", "enter"])
+             .default_value("always")
+             .overrides_with("autocd")
+            )
         .arg(Arg::new("history-file")
              .long("history-file")
              .help("Save history to the file at this absolute path. Set to empty to disable.")
@@ -132,13 +240,71 @@ fn main() -> Result<(), TereError> {
              .takes_value(true)
              .value_name("FILE or ''")
             )
+        .arg(Arg::new("listing-cache")
+             .long("listing-cache")
+             .help("Save the listings of recently visited folders for faster startup")
+             .long_help("Save the listings of recently visited folders to '$CACHE_DIR/tere/listings.json' when exiting, and start with them the next time. A saved listing is only used if the modification time of the folder hasn't changed, so this makes starting up in a huge folder, or in a folder on a slow network drive, almost instant. Note that the file reveals the names of the items in the folders if it can be read by someone else. This overrides the --no-listing-cache option.")
+             .overrides_with("listing-cache")
+            )
+        .arg(Arg::new("no-listing-cache")
+             .long("no-listing-cache")
+             .help("Don't save the listings of folders (default)")
+             .long_help("Don't save the listings of recently visited folders when exiting. This is the default. This overrides the --listing-cache option.")
+             .overrides_with_all(&["listing-cache", "no-listing-cache"])
+            )
+        .arg(Arg::new("remote-metadata")
+             .long("remote-metadata")
+             .help("Show symlink targets also on network file systems")
+             .long_help("Read the metadata of the items that is only needed for showing them, like the targets of symlinks and macOS aliases, also in folders on network file systems, like NFS, SMB and sshfs. By default, it's not read for them, because it takes a round trip to the server for every item, which can make browsing slow. This overrides the --no-remote-metadata option.")
+             .overrides_with("remote-metadata")
+            )
+        .arg(Arg::new("no-remote-metadata")
+             .long("no-remote-metadata")
+             .help("Don't show symlink targets on network file systems (default)")
+             .long_help("Don't read the metadata of the items that is only needed for showing them in folders on network file systems. This is the default. This overrides the --remote-metadata option.")
+             .overrides_with_all(&["remote-metadata", "no-remote-metadata"])
+            )
+        .arg(Arg::new("disk-space")
+             .long("disk-space")
+             .help("Show the free space of the current folder's file system in the footer")
+             .long_help("Show the free and total space of the file system of the current folder in the footer. It's updated when changing folders and after file operations. This overrides the --no-disk-space option.")
+             .overrides_with("disk-space")
+            )
+        .arg(Arg::new("no-disk-space")
+             .long("no-disk-space")
+             .help("Don't show the free space in the footer (default)")
+             .long_help("Don't show the free and total space of the file system of the current folder in the footer. This is the default. This overrides the --disk-space option.")
+             .overrides_with_all(&["disk-space", "no-disk-space"])
+            )
+        .arg(Arg::new("fs-type")
+             .long("fs-type")
+             .help("Show the type of the current folder's file system in the header")
+             .long_help("Show the type of the file system of the current folder after its path in the header, like 'ext4', 'nfs4' or 'NTFS'. On Linux, this is the type of the mount that the folder is on. This overrides the --no-fs-type option.")
+             .overrides_with("fs-type")
+            )
+        .arg(Arg::new("no-fs-type")
+             .long("no-fs-type")
+             .help("Don't show the file system type in the header (default)")
+             .long_help("Don't show the type of the file system of the current folder in the header. This is the default. This overrides the --fs-type option.")
+             .overrides_with_all(&["fs-type", "no-fs-type"])
+            )
+        .arg(Arg::new("archives")
+             .long("archives")
+             .help("Browse zip and tar files like folders")
+             .long_help("Show zip, tar, tar.gz and tgz files as folders, and allow entering them to browse the items in them. The archives are read-only. With 'browse', the items inside archives are printed like 'archive.zip/inner/path' on exit. With 'extract', they are extracted to a private folder in the cache folder on exit, and the extracted paths are printed instead, so that they can be used like any other file or folder. With 'off' (the default), archives are files like any other.")
+             .takes_value(true)
+             .value_name("MODE")
+             .possible_values(["off", "browse", "extract"])
+             .default_value("off")
+             .overrides_with("archives")
+            )
         .arg(Arg::new("mouse")
              .long("mouse")
              .help("Enable mouse navigation")
-             .long_help("Enable mouse navigation. If enabled, you can browse by clicking around with the mouse.")
+             .long_help("Enable mouse navigation. If enabled, you can browse by clicking around with the mouse. Mouse navigation can also be toggled while the program is running with the keyboard shortcut ALT+M.")
              .takes_value(true)
              .value_name("'on' or 'off'")
-             .possible_values(&["on", "off"])
+             .possible_values(["on", "off"])
              .hide_possible_values(true)
              .default_value("off")
              .multiple_occurrences(true)
@@ -148,11 +314,250 @@ fn main() -> Result<(), TereError> {
              .help("Esc will exit without cd")
              .long_help("If enabled, pressing Esc will exit with an error to avoid changing directory; and pressing Enter will change directory.")
              )
+        .arg(Arg::new("cd-on-signal")
+             .long("cd-on-signal")
+             .help("Change folder when terminated by a signal")
+             .long_help("If tere is terminated by SIGTERM, SIGINT or SIGHUP, exit normally and change to the current folder. By default, tere exits without changing folder, like with --esc-is-cancel.")
+             )
+        .arg(Arg::new("resolve-aliases")
+             .long("resolve-aliases")
+             .help("Enter macOS aliases like symlinks")
+             .long_help("Show the targets of the alias files created by the macOS Finder, and enter the target when an alias is entered, like with symlinks. Aliases are shown in a different color than symlinks. This has no effect on other platforms.")
+             )
         .arg(Arg::new("enter-is-cd-and-exit")
              .long("enter-is-cd-and-exit")
              .help("Enter will cd and exit")
              .long_help("If enabled, pressing Enter will change directory into the selected folder.")
              )
+        .arg(Arg::new("no-file-ops")
+             .long("no-file-ops")
+             .help("Disable actions that modify files")
+             .long_help("Disable all actions that create, rename or delete files and folders, so that they can't be triggered by accident.")
+             )
+        .arg(Arg::new("unbound-key")
+             .long("unbound-key")
+             .help("What to do when a key without a shortcut is pressed")
+             .long_help("What to do when a key that is not bound to any action (and is not a character that can be searched for) is pressed. With 'message' (the default), show a message saying that the key is not bound. With 'ignore', do nothing. Otherwise, the value should be the name of an action (see the --map option), which is performed when any unbound key is pressed.")
+             .takes_value(true)
+             .value_name("'message', 'ignore' or ACTION")
+             .default_value("message")
+             .overrides_with("unbound-key")
+            )
+        .arg(Arg::new("half-page-scroll")
+             .long("half-page-scroll")
+             .help("Ctrl+U and Ctrl+D scroll by half a screen")
+             .long_help("Make Ctrl+U / Ctrl+D (and Alt+U / Alt+D) move the cursor by half a screen, like in Vim. Page Up and Page Down always move by a full screen. This overrides the --no-half-page-scroll option.")
+             .overrides_with("half-page-scroll")
+            )
+        .arg(Arg::new("no-half-page-scroll")
+             .long("no-half-page-scroll")
+             .help("Ctrl+U and Ctrl+D scroll by a full screen (default)")
+             .long_help("Make Ctrl+U / Ctrl+D (and Alt+U / Alt+D) move the cursor by a full screen, like Page Up and Page Down. This is the default. This overrides the --half-page-scroll option.")
+             .overrides_with_all(&["half-page-scroll", "no-half-page-scroll"])
+            )
+        .arg(Arg::new("page-overlap")
+             .long("page-overlap")
+             .help("Number of rows to keep in view when scrolling by a full screen")
+             .long_help("When scrolling by a full screen (for example with Page Up or Page Down), move the cursor by the height of the screen minus this many rows, so that some of the previous screen stays in view.")
+             .default_value("1")
+             .value_name("ROWS")
+             .overrides_with("page-overlap")
+            )
+        .arg(Arg::new("print0")
+             .long("print0")
+             .short('0')
+             .help("Print a NUL character instead of a newline after the path on exit")
+             .long_help("Print a NUL character instead of a newline after the final path when exiting, so that paths containing newlines can be handled safely by scripts. This overrides the --output-delimiter option.")
+             .overrides_with_all(&["print0", "output-delimiter"])
+            )
+        .arg(Arg::new("output-delimiter")
+             .long("output-delimiter")
+             .help("Print this string after the path on exit, instead of a newline")
+             .long_help("Print this string after the final path when exiting, instead of a newline. The escape sequences \\n, \\t, \\0 and \\\\ are replaced by a newline, tab, NUL character and backslash, respectively. This overrides the --print0 option.")
+             .takes_value(true)
+             .value_name("STRING")
+             .allow_hyphen_values(true)
+             .overrides_with_all(&["print0", "output-delimiter"])
+            )
+        .arg(Arg::new("porcelain")
+             .long("porcelain")
+             .help("Print the result in a machine-readable format")
+             .long_help("Print the result in a machine-readable format, for tools that are built on top of tere. With 'nvim', which is meant for editor plugins, each printed path is on its own line, prefixed with 'cd' or 'edit' (for folders and files, respectively) and a tab character, and the paths are always absolute. It also implies --enter-is-cd-and-exit and --choose-file, and hides the welcome message. With 'json', a JSON object is printed on one line, with the fields 'reason' (one of 'cd', 'cancel' or 'error'), 'path' (the current folder), 'query' (the search query), 'selected' (the marked items or the chosen file) and 'error' (the error message, if any). The JSON object is printed also when cancelling, or when an error occurs after the command line options have been parsed.")
+             .takes_value(true)
+             .value_name("FORMAT")
+             .possible_values(["nvim", "json"])
+             .overrides_with("porcelain")
+            )
+        .arg(Arg::new("output")
+             .long("output")
+             .help("Whether to print an absolute or relative path on exit")
+             .long_help("Print the final path as an absolute path (the default), or relative to the folder where tere was started (or to the folder given by --relative-to).")
+             .takes_value(true)
+             .value_name("FORMAT")
+             .possible_values(["absolute", "relative"])
+             .overrides_with("output")
+            )
+        .arg(Arg::new("relative-to")
+             .long("relative-to")
+             .help("Print the final path relative to this folder")
+             .long_help("Print the final path relative to this folder instead of the folder where tere was started. Implies --output=relative, unless --output=absolute is given explicitly.")
+             .takes_value(true)
+             .value_name("DIR")
+             .overrides_with("relative-to")
+            )
+        .arg(Arg::new("print-to-fd")
+             .long("print-to-fd")
+             .help("Print the final path to this file descriptor instead of stdout")
+             .long_help("Print the final path to this (already open) file descriptor instead of stdout. For example, with '--print-to-fd 3', a shell wrapper can capture the path with '3>&1 >/dev/tty'. Only supported on Unix. This overrides the --output-file and --tmux-send-keys options.")
+             .takes_value(true)
+             .value_name("FD")
+             .overrides_with_all(&["print-to-fd", "output-file", "tmux-send-keys"])
+            )
+        .arg(Arg::new("output-file")
+             .long("output-file")
+             .help("Write the final path to this file instead of stdout")
+             .long_help("Write the final path to this file instead of stdout. The file is created if it doesn't exist, and overwritten if it does. This overrides the --print-to-fd and --tmux-send-keys options.")
+             .takes_value(true)
+             .value_name("FILE")
+             .overrides_with_all(&["print-to-fd", "output-file", "tmux-send-keys"])
+            )
+        .arg(Arg::new("tmux-send-keys")
+             .long("tmux-send-keys")
+             .help("Type the result into this tmux pane instead of printing it")
+             .long_help("Instead of printing the result, type it into this tmux pane with 'tmux send-keys'. This is meant for running tere in a tmux popup, see 'tere init tmux'. If the result is a folder, a 'cd' command is typed and run, otherwise the (quoted) paths of the chosen or marked items are inserted into the command line. This overrides the --print-to-fd and --output-file options.")
+             .takes_value(true)
+             .value_name("PANE")
+             .overrides_with_all(&["print-to-fd", "output-file", "tmux-send-keys"])
+            )
+        .arg(Arg::new("choosedir")
+             .long("choosedir")
+             .visible_alias("last-dir-path")
+             .help("Also write the final folder to this file on exit")
+             .long_help("In addition to printing it, write the final folder to this file on exit, like the --choosedir option of ranger and the -last-dir-path option of lf. The path is always absolute and written without a trailing newline. Nothing is written if tere exits without changing the folder.")
+             .takes_value(true)
+             .value_name("FILE")
+             .overrides_with("choosedir")
+            )
+        .arg(Arg::new("cancel-exit-code")
+             .long("cancel-exit-code")
+             .help("Exit code to use when exiting without changing the folder")
+             .long_help("The exit code to use when exiting without changing the folder, for example with CTRL+C. The exit code is 0 on success, and 1 (or 2 for some invalid arguments) if an error occurs. The default value is 1, use a different value to distinguish between cancelling and errors in scripts.")
+             .takes_value(true)
+             .value_name("CODE")
+             .default_value("1")
+             .overrides_with("cancel-exit-code")
+            )
+        .arg(Arg::new("map")
+             .long("map")
+             .short('m')
+             .help("Change a keyboard shortcut, for example 'ctrl-j:cursor-down'")
+             .long_help(format!("Bind a key combination to an action, in the format KEY:ACTION or KEY:ACTION:CONTEXT. KEY is a key name (like 'a', 'enter', 'pageup' or 'f3'), optionally prefixed with modifiers, like 'ctrl-alt-h' or 'alt-shift-g'. CONTEXT is one of 'any' (the default), 'searching' or 'not-searching', and determines when the mapping applies. Use 'none' as the ACTION to remove a mapping. This option can be given multiple times. The available actions are: {}.",
+                        ui::action::Action::all().iter().map(|a| a.name()).collect::<Vec<_>>().join(", ")).as_str())
+             .takes_value(true)
+             .value_name("KEY:ACTION[:CONTEXT]")
+             .multiple_occurrences(true)
+            )
+        .arg(Arg::new("profile-startup")
+             .long("profile-startup")
+             .help("Print how long the steps of starting up took")
+             .long_help("When exiting, print how long parsing the settings, loading the history, reading the first folder and drawing the first frame took, to find out why tere starts slowly, for example if the home folder is on a network drive.")
+            )
+        .arg(Arg::new("log-file")
+             .long("log-file")
+             .help("Write a debug log to this file")
+             .long_help("Append a debug log to this file, with the key presses and other input, the folders that were changed to and how long reading them took. This is useful for reporting bugs, especially ones that only happen with some terminals. Note that the log contains what you type and the paths of the folders you visit. Can also be set with the TERE_LOG environment variable. Set to empty to disable.")
+             .takes_value(true)
+             .value_name("FILE or ''")
+            )
+        .arg(Arg::new("run")
+             .long("run")
+             .help("Bind a key to a shell command, for example 'f3:du -sh {}'")
+             .long_help("Bind a key combination to a shell command, in the format KEY:COMMAND, where KEY is like in the --map option. Each {} in COMMAND is replaced by the paths of the marked items, or by the path of the item under the cursor if no items are marked. If COMMAND doesn't contain {}, the paths are added to the end. The command is run in the current folder, and its output is shown until a key is pressed. This option can be given multiple times.")
+             .takes_value(true)
+             .value_name("KEY:COMMAND")
+             .multiple_occurrences(true)
+            )
+        .arg(Arg::new("filter-preset")
+             .long("filter-preset")
+             .help("Define a filter that can be switched on with Alt+Shift+f, for example 'media:*.jpg *.png'")
+             .long_help("Define a named filter that hides items in all folders, in the format NAME:RULE RULE ... A rule is 'dirs' or 'files' to show only folders or only files, 'no-hidden' to hide hidden items, a pattern like '*.jpg' to show only the items that match one of the patterns, or a pattern starting with '!' like '!*.tmp' to hide the items that match it. In patterns, '*' matches any characters and '?' matches one character, and case is ignored. Patterns apply only to files, or only to folders if they end with '/', so for example 'media:*.jpg *.mp4 !cache/' still shows all folders except 'cache'. The presets are switched between with Alt+Shift+f, in the order in which they are given, and the active one is shown in the footer. This option can be given multiple times.")
+             .takes_value(true)
+             .value_name("NAME:RULES")
+             .multiple_occurrences(true)
+            )
+        .arg(Arg::new("command")
+             .long("command")
+             .help("Define a named command, for example 'git-root:cd-output git rev-parse --show-toplevel'")
+             .long_help("Define a command that combines several steps under a name, in the format NAME:STEP; STEP; ... Each step is the name of an action (see --map), 'cd PATH' to change to a folder, 'run COMMAND' to run a shell command like with --run, or 'cd-output COMMAND' to run a shell command and change to the folder that it prints. Since shell commands can contain ';', 'run' and 'cd-output' have to be the last step. The command can be bound to a key with --map, using NAME as the action. This option can be given multiple times.")
+             .takes_value(true)
+             .value_name("NAME:STEPS")
+             .multiple_occurrences(true)
+            )
+        .arg(Arg::new("script")
+             .long("script")
+             .help("Load custom actions from a Rhai script")
+             .long_help("Load custom actions from a script file written in the Rhai language. The script defines functions, and binds them to keys with bind(KEY, FUNCTION), where KEY is like in the --map option. The functions can read the state with current_dir(), item_under_cursor(), marked(), search() and home_dir(), and act with cd(PATH), select(NAME), message(TEXT), error(TEXT) and exit_app(). Paths can be handled with exists(PATH), is_dir(PATH), parent(PATH), file_name(PATH), join(PATH, NAME) and env(NAME). Only available if tere was built with the 'rhai' feature.")
+             .takes_value(true)
+             .value_name("FILE")
+             .overrides_with("script")
+            )
+        .arg(Arg::new("on-enter-dir")
+             .long("on-enter-dir")
+             .help("Run a shell command after entering a folder, for example 'echo {} >> ~/visited'")
+             .long_help("Run a shell command in the background after entering a folder. Each {} in COMMAND is replaced by the path of the folder, and if COMMAND doesn't contain {}, the path is added to the end. The command is run in the folder, and its input and output are discarded. Hooks are not run in stdin mode, in folders on other machines or inside archives. Set to empty to disable.")
+             .takes_value(true)
+             .value_name("COMMAND")
+             .overrides_with("on-enter-dir")
+            )
+        .arg(Arg::new("on-leave-dir")
+             .long("on-leave-dir")
+             .help("Run a shell command when leaving a folder for another one")
+             .long_help("Run a shell command in the background when leaving a folder for another one, with {} replaced by the path of the folder that was left, like in --on-enter-dir. Set to empty to disable.")
+             .takes_value(true)
+             .value_name("COMMAND")
+             .overrides_with("on-leave-dir")
+            )
+        .arg(Arg::new("on-exit")
+             .long("on-exit")
+             .help("Run a shell command when exiting, with {} replaced by the final folder")
+             .long_help("Run a shell command when exiting normally (not when cancelling), with {} replaced by the path of the final folder, like in --on-enter-dir. tere waits for the command to finish before exiting. Set to empty to disable.")
+             .takes_value(true)
+             .value_name("COMMAND")
+             .overrides_with("on-exit")
+            )
+        .arg(Arg::new("start-entry")
+             .long("start-entry")
+             .help("Start with the cursor on this item of the starting folder")
+             .long_help("Start with the cursor on the item with this name in the starting folder, instead of on the first item. If there is no such item, the cursor starts on the first item as usual. This is useful for wrappers that want to return to where tere was left the last time.")
+             .takes_value(true)
+             .value_name("NAME")
+             .allow_invalid_utf8(true)
+             .overrides_with("start-entry")
+            )
+        .arg(Arg::new("path")
+             .help("The folder to start in, or ssh://[user@]host[:port]/path to browse another machine")
+             .long_help("Start in this folder instead of the current folder. If this is a file, start in its folder with the cursor on the file. To browse the folders of another machine over SFTP, give an URL like 'ssh://user@host:port/path', where the user, port and path are optional, and a path starting with '/~/' is relative to the home folder. The connection is made with the ssh command, so the settings in ~/.ssh/config are used. On exit, the remote path is printed like 'user@host:/path', so that it can be used with scp. Modifying files and running commands are disabled on other machines, and the history is not saved.")
+             .value_name("PATH or URL")
+             .allow_invalid_utf8(true)
+             .conflicts_with("stdin")
+            )
+        .subcommand(App::new("init")
+                    .about("Print a shell function that runs tere and changes to the folder it prints")
+                    .long_about("Print a shell function that runs tere and changes to the folder it prints on exit. For example, add 'eval \"$(tere init bash)\"' to your .bashrc to set it up.")
+                    .arg(Arg::new("shell")
+                         .required(true)
+                         .possible_values(shell_init::SUPPORTED_SHELLS)
+                        )
+                   )
+        .subcommand(App::new("doctor")
+                    .about("Check the shell integration, history file, terminal and locale")
+                    .long_about("Check that the shell function is set up, that the history file can be saved, what the terminal supports and that the locale uses UTF-8, and print what to do about any problems found. The options given before 'doctor', like --history-file, are taken into account. The exit code is 1 if a problem that breaks tere was found.")
+                   )
+        .subcommand(App::new("setup")
+                    .about("Set up the shell function that changes to the folder that tere prints")
+                    .long_about("Detect the shell, and add the line that sets up the shell function to its configuration file, or print it for adding it by hand. This is also offered the first time tere is run without the shell function.")
+                   )
+        .setting(clap::AppSettings::DisableHelpSubcommand)
         .try_get_matches()
         .unwrap_or_else(|err| {
             // custom error handling: clap writes '--help' and '--version'
@@ -162,8 +567,64 @@ fn main() -> Result<(), TereError> {
             std::process::exit(1);
         });
 
+    if cli_args.is_present("profile-startup") {
+        profile::enable(start);
+    }
+    if let Some(file) = logging::log_file(cli_args.value_of("log-file")) {
+        logging::init(&file)?;
+        tracing::info!(
+            version = env!("CARGO_PKG_VERSION"),
+            args = ?std::env::args().skip(1).collect::<Vec<_>>(),
+            term = ?std::env::var("TERM").ok(),
+            term_program = ?std::env::var("TERM_PROGRAM").ok(),
+            terminal_size = ?terminal::size().ok(),
+            "starting",
+        );
+    }
+    profile::mark("parse arguments");
+
+    if let Some(init_args) = cli_args.subcommand_matches("init") {
+        // the shell name has already been validated by clap
+        let shell = init_args.value_of("shell").unwrap_or_default();
+        print!("{}", shell_init::init_script(shell).unwrap_or_default());
+        return Ok(());
+    }
+
+    if cli_args.subcommand_matches("doctor").is_some() {
+        let settings = TereSettings::parse_cli_args(&cli_args).unwrap_or_else(|e| e.exit());
+        let findings = doctor::run_checks(settings.history_file.as_deref());
+        // print to stderr like the UI, so that nothing is cd'd to if run through the shell function
+        eprint!("{}", doctor::report(&findings));
+        if doctor::has_problems(&findings) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if cli_args.subcommand_matches("setup").is_some() {
+        setup::run()?;
+        return Ok(());
+    }
+
+    let output_settings = OutputSettings::parse_cli_args(&cli_args).unwrap_or_else(|e| e.exit());
+
+    let prints_to_stdout = matches!(output_settings.target, OutputTarget::Stdout)
+        && output_settings.porcelain.is_none();
+    if setup::should_offer(prints_to_stdout) {
+        setup::run()?;
+    }
+
+    ui::i18n::init();
+
+    // Open the starting folder before setting up the terminal, because connecting to another
+    // machine may ask for a password
+    let start = StartDir::from_cli_args(&cli_args)?;
+
     let mut stderr = std::io::stderr();
 
+    // From now on, termination signals are handled by the UI, which restores the terminal
+    let signals = ui::signals::TerminationSignals::register()?;
+
     //TODO: should this alternate screen etc initialization (and teardown) be done by the UI?
     //Now the mouse capture enabling (which is kind of similar) is handled there.
     execute!(
@@ -175,12 +636,16 @@ fn main() -> Result<(), TereError> {
     // we are now inside the alternate screen, so collect all errors and attempt
     // to leave the alt screen in case of an error
 
-    let res: Result<std::path::PathBuf, TereError> = terminal::enable_raw_mode()
+    // the state is saved also if the app exits with an error, for the porcelain output
+    let mut exit_state = None;
+    let res: Result<(), TereError> = terminal::enable_raw_mode()
         .and_then(|_| stderr.flush()).map_err(TereError::from)
-        .and_then(|_| TereTui::init(&cli_args, &mut stderr)) // actually run the app
+        .and_then(|_| TereTui::init(&cli_args, start, &mut stderr)) // actually run the app
+        .map(|ui| ui.with_termination_signals(signals))
         .and_then(|mut ui| {
-            ui.main_event_loop()
-                .map(|_| ui.current_path())
+            let res = ui.main_event_loop();
+            exit_state = Some(ui.exit_state());
+            res
         });
 
     // Always disable raw mode
@@ -194,27 +659,36 @@ fn main() -> Result<(), TereError> {
         cursor::Show,
         )?;
 
+    if let Some(report) = profile::report() {
+        eprint!("{}", report);
+    }
+
     // Check if there was an error
-    let final_path = match res {
-        Err(err) => {
-            match err {
-                // Print pretty error message if the error was in arg parsing
-                TereError::Clap(e) => e.exit(),
-
-                TereError::ExitWithoutCd(msg) => {
-                    eprintln!("{}", msg);
-                    std::process::exit(1);
-                },
-
-                // exit in case of any other error
-                e => return Err(e),
-            }
+    if let Err(err) = res {
+        let reason = match &err {
+            TereError::ExitWithoutCd(_) => ExitReason::Cancel,
+            TereError::Clap(e) => ExitReason::Error(e.to_string()),
+            e => ExitReason::Error(format!("{:?}", e)),
+        };
+        output::write_exit_reason(&reason, exit_state.as_ref(), &output_settings)?;
+
+        match err {
+            // Print pretty error message if the error was in arg parsing
+            TereError::Clap(e) => e.exit(),
+
+            TereError::ExitWithoutCd(msg) => {
+                eprintln!("{}", msg);
+                std::process::exit(output_settings.cancel_exit_code);
+            },
+
+            // exit in case of any other error
+            e => return Err(e),
         }
-        Ok(path) => path
-    };
+    }
 
-    // No error, print cwd, as returned by the app state
-    println!("{}", final_path.display());
+    // No error, print cwd (or the marked items), as returned by the app state. Ok to unwrap,
+    // the state is always set if the app exits without an error.
+    output::write_output(&exit_state.unwrap(), &output_settings)?;
 
     Ok(())
 }
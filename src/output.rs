@@ -0,0 +1,574 @@
+/// Module for printing the result of the app (i.e. the final folder) when it exits, and the
+/// settings (command line arguments) related to that.
+use std::ffi::OsString;
+use std::io::{Result as IOResult, Write};
+use std::path::{Component, Path, PathBuf};
+
+use clap::ArgMatches;
+
+use crate::ui::external::shell_quote;
+
+/// Where to write the final path
+pub enum OutputTarget {
+    Stdout,
+    /// An already open file descriptor, e.g. one set up by a shell wrapper with `3>&1`
+    #[cfg(unix)]
+    Fd(i32),
+    /// A file that is created (or truncated) on exit
+    File(PathBuf),
+    /// Type the result into this tmux pane with `tmux send-keys`
+    TmuxPane(String),
+}
+
+/// Machine-readable output formats, for tools that are built on top of tere
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PorcelainFormat {
+    /// One line per path, prefixed with 'cd' or 'edit' and a tab, for editor plugins
+    Nvim,
+    /// A JSON object with the final folder, the reason for exiting, and so on
+    Json,
+}
+
+/// Why the app exited
+pub enum ExitReason {
+    /// Exited normally, so the result should be used
+    Cd,
+    /// Exited without changing the folder
+    Cancel,
+    /// Exited because of an error, with this message
+    Error(String),
+}
+
+impl ExitReason {
+    fn name(&self) -> &'static str {
+        match self {
+            ExitReason::Cd => "cd",
+            ExitReason::Cancel => "cancel",
+            ExitReason::Error(_) => "error",
+        }
+    }
+}
+
+/// The state of the app when it exited, from which the output is determined
+pub struct ExitState {
+    /// The current folder when exiting
+    pub final_path: PathBuf,
+    /// The marked items, or the chosen file in file selection mode
+    pub selected: Vec<PathBuf>,
+    pub search_query: String,
+    /// The machine that the paths are on, like 'user@host', if it's not this machine
+    pub host: Option<String>,
+}
+
+impl ExitState {
+    /// The paths to print, i.e. the selected items if there are any, otherwise the final folder.
+    /// Paths on other machines are printed like 'user@host:/path', for scp and ssh.
+    pub fn output_paths(&self) -> Vec<PathBuf> {
+        let paths = if self.selected.is_empty() {
+            vec![self.final_path.clone()]
+        } else {
+            self.selected.clone()
+        };
+        match &self.host {
+            Some(host) => paths
+                .into_iter()
+                .map(|path| {
+                    let mut remote = OsString::from(format!("{}:", host));
+                    remote.push(path);
+                    remote.into()
+                })
+                .collect(),
+            None => paths,
+        }
+    }
+}
+
+pub struct OutputSettings {
+    /// This is written after each printed path
+    pub delimiter: Vec<u8>,
+    /// If this is set, the printed paths are made relative to this folder
+    pub relative_to: Option<PathBuf>,
+    pub target: OutputTarget,
+    /// If this is set, the final folder is also written to this file, ranger-style
+    pub choosedir: Option<PathBuf>,
+    /// The exit code to use when exiting without changing the folder
+    pub cancel_exit_code: i32,
+    /// If this is set, the paths are printed in this format instead of one by one
+    pub porcelain: Option<PorcelainFormat>,
+}
+
+impl Default for OutputSettings {
+    fn default() -> Self {
+        Self {
+            delimiter: b"\n".to_vec(),
+            relative_to: None,
+            target: OutputTarget::Stdout,
+            choosedir: None,
+            cancel_exit_code: 1,
+            porcelain: None,
+        }
+    }
+}
+
+impl OutputSettings {
+    pub fn parse_cli_args(args: &ArgMatches) -> Result<Self, clap::Error> {
+        let delimiter = if args.is_present("print0") {
+            vec![0]
+        } else if let Some(delim) = args.value_of("output-delimiter") {
+            unescape(delim).into_bytes()
+        } else {
+            b"\n".to_vec()
+        };
+
+        let relative = match args.value_of("output") {
+            Some("relative") => true,
+            Some("absolute") => false,
+            Some(x) => return Err(clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("Invalid value for 'output': '{}'\n", x),
+            )),
+            // --relative-to implies relative output
+            None => args.is_present("relative-to"),
+        };
+
+        let relative_to = if relative {
+            // Same logic as in the app state, use PWD so that symlinks are not resolved
+            let cwd = std::env::var("PWD")
+                .map(PathBuf::from)
+                .or_else(|_| std::env::current_dir())
+                .map_err(|e| clap::Error::raw(
+                    clap::ErrorKind::Io,
+                    format!("Could not determine the current directory: {}\n", e),
+                ))?;
+            Some(match args.value_of("relative-to") {
+                Some(base) => cwd.join(base),
+                None => cwd,
+            })
+        } else {
+            None
+        };
+
+        let target = if let Some(fd) = args.value_of("print-to-fd") {
+            Self::parse_fd(fd)?
+        } else if let Some(path) = args.value_of("output-file") {
+            OutputTarget::File(path.into())
+        } else if let Some(pane) = args.value_of("tmux-send-keys") {
+            OutputTarget::TmuxPane(pane.to_string())
+        } else {
+            OutputTarget::Stdout
+        };
+
+        let choosedir = args.value_of("choosedir").map(PathBuf::from);
+
+        let cancel_exit_code = match args.value_of("cancel-exit-code") {
+            Some(code) => code.parse::<u8>().map(i32::from).map_err(|_| clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("Invalid value for 'cancel-exit-code': '{}' is not an integer between 0 and 255\n", code),
+            ))?,
+            None => 1,
+        };
+
+        let porcelain = match args.value_of("porcelain") {
+            Some("nvim") => Some(PorcelainFormat::Nvim),
+            Some("json") => Some(PorcelainFormat::Json),
+            Some(x) => return Err(clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("Invalid value for 'porcelain': '{}'\n", x),
+            )),
+            None => None,
+        };
+
+        Ok(Self { delimiter, relative_to, target, choosedir, cancel_exit_code, porcelain })
+    }
+
+    #[cfg(unix)]
+    fn parse_fd(fd: &str) -> Result<OutputTarget, clap::Error> {
+        match fd.parse::<i32>() {
+            Ok(fd) if fd >= 0 => Ok(OutputTarget::Fd(fd)),
+            _ => Err(clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("Invalid value for 'print-to-fd': '{}' is not a file descriptor\n", fd),
+            )),
+        }
+    }
+
+    #[cfg(not(unix))]
+    fn parse_fd(_fd: &str) -> Result<OutputTarget, clap::Error> {
+        Err(clap::Error::raw(
+            clap::ErrorKind::InvalidValue,
+            "The --print-to-fd option is only supported on Unix\n",
+        ))
+    }
+
+    /// Open the output target for writing. Returns `None` for targets that are not written to
+    /// directly.
+    fn open_target(&self) -> IOResult<Option<Box<dyn Write>>> {
+        Ok(Some(match &self.target {
+            OutputTarget::Stdout => Box::new(std::io::stdout()),
+            #[cfg(unix)]
+            OutputTarget::Fd(fd) => {
+                use std::os::unix::io::FromRawFd;
+                // SAFETY: the fd is not used for anything else in the app. It is closed when the
+                // returned file is dropped, which is fine since we're about to exit.
+                let file = unsafe { std::fs::File::from_raw_fd(*fd) };
+                // Check that the fd is actually open. If it isn't, we must not drop the file,
+                // because closing an invalid fd is an error.
+                if let Err(e) = file.metadata() {
+                    std::mem::forget(file);
+                    return Err(e);
+                }
+                Box::new(file)
+            },
+            OutputTarget::File(path) => Box::new(std::fs::File::create(path)?),
+            OutputTarget::TmuxPane(_) => return Ok(None),
+        }))
+    }
+
+    /// Format a path for printing, i.e. make it relative if requested. Paths that are not
+    /// absolute, like paths on other machines, are printed as they are.
+    pub fn format_path(&self, path: &Path) -> PathBuf {
+        match &self.relative_to {
+            Some(base) if path.is_absolute() => relative_path(path, base),
+            _ => path.to_path_buf(),
+        }
+    }
+}
+
+/// Write the final folder to the choosedir file, if requested. Like ranger and lf do, the path is
+/// written as-is, without a trailing newline and regardless of the other output settings, so
+/// that existing shell integrations for these can be reused.
+pub fn write_choosedir(path: &Path, settings: &OutputSettings) -> IOResult<()> {
+    if let Some(file) = &settings.choosedir {
+        let mut file = std::fs::File::create(file)?;
+        #[cfg(unix)]
+        file.write_all(std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()))?;
+        #[cfg(not(unix))]
+        file.write_all(path.to_string_lossy().as_bytes())?;
+    }
+    Ok(())
+}
+
+/// Compute a path that points to `path` when interpreted relative to `base`. Both should be
+/// absolute. This is done purely lexically, without accessing the file system, so that symlinks
+/// in the paths are preserved.
+fn relative_path(path: &Path, base: &Path) -> PathBuf {
+    // Normalize away '.' components and trailing slashes
+    let path: Vec<Component> = path.components().filter(|c| *c != Component::CurDir).collect();
+    let base: Vec<Component> = base.components().filter(|c| *c != Component::CurDir).collect();
+
+    let n_common = path.iter().zip(base.iter()).take_while(|(a, b)| a == b).count();
+
+    let mut ret = PathBuf::new();
+    for _ in n_common..base.len() {
+        ret.push(Component::ParentDir);
+    }
+    for c in &path[n_common..] {
+        ret.push(c);
+    }
+
+    if ret.as_os_str().is_empty() {
+        ret.push(Component::CurDir);
+    }
+    ret
+}
+
+/// Replace the escape sequences `\n`, `\t`, `\0` and `\\` with the corresponding characters,
+/// because it's not easy to pass these to a program in all shells.
+fn unescape(s: &str) -> String {
+    let mut ret = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some('n') => ret.push('\n'),
+                Some('t') => ret.push('\t'),
+                Some('0') => ret.push('\0'),
+                Some('\\') => ret.push('\\'),
+                Some(c) => {
+                    ret.push('\\');
+                    ret.push(c);
+                }
+                None => ret.push('\\'),
+            }
+        } else {
+            ret.push(c);
+        }
+    }
+    ret
+}
+
+/// Output the result of the app, i.e. the paths to print and the final folder, according to the
+/// settings.
+pub fn write_output(state: &ExitState, settings: &OutputSettings) -> IOResult<()> {
+    let paths = state.output_paths();
+    match settings.open_target()? {
+        Some(mut out) => match settings.porcelain {
+            Some(format) => write_porcelain(&mut out, &ExitReason::Cd, Some(state), format)?,
+            None => write_paths(&mut out, &paths, settings)?,
+        },
+        None => {
+            if let OutputTarget::TmuxPane(pane) = &settings.target {
+                tmux_send_keys(pane, &state.final_path, &paths, settings)?;
+            }
+        }
+    }
+    // the choosedir file is for changing to the folder in the shell, which isn't possible for
+    // folders on other machines
+    if state.host.is_none() {
+        write_choosedir(&state.final_path, settings)?;
+    }
+    Ok(())
+}
+
+/// Output the reason for exiting without a result, if the output format supports it. The state is
+/// `None` if the app exited before it was initialized.
+pub fn write_exit_reason(
+    reason: &ExitReason,
+    state: Option<&ExitState>,
+    settings: &OutputSettings,
+) -> IOResult<()> {
+    if let Some(format) = settings.porcelain {
+        // The tmux target can't show this, so fall back to stdout
+        let mut out = settings.open_target()?.unwrap_or_else(|| Box::new(std::io::stdout()));
+        write_porcelain(&mut out, reason, state, format)?;
+    }
+    Ok(())
+}
+
+/// Write the result in a machine-readable format. The paths are always absolute, and the other
+/// output settings are ignored, so that the format stays stable.
+fn write_porcelain<W: Write>(
+    out: &mut W,
+    reason: &ExitReason,
+    state: Option<&ExitState>,
+    format: PorcelainFormat,
+) -> IOResult<()> {
+    match format {
+        PorcelainFormat::Nvim => {
+            // There's nothing to :cd or :edit unless exiting normally
+            let paths = match (reason, state) {
+                (ExitReason::Cd, Some(state)) => state.output_paths(),
+                _ => vec![],
+            };
+            for path in paths {
+                let kind = if path.is_dir() { "cd" } else { "edit" };
+                out.write_all(kind.as_bytes())?;
+                out.write_all(b"\t")?;
+                #[cfg(unix)]
+                out.write_all(std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()))?;
+                #[cfg(not(unix))]
+                out.write_all(path.to_string_lossy().as_bytes())?;
+                out.write_all(b"\n")?;
+            }
+        }
+        PorcelainFormat::Json => {
+            let mut obj = serde_json::json!({
+                "reason": reason.name(),
+                "path": state.map(|s| s.final_path.to_string_lossy()),
+                "query": state.map(|s| s.search_query.as_str()),
+                "selected": state
+                    .map(|s| s.selected.iter().map(|p| p.to_string_lossy()).collect::<Vec<_>>())
+                    .unwrap_or_default(),
+            });
+            if let ExitReason::Error(msg) = reason {
+                obj["error"] = msg.as_str().into();
+            }
+            if let Some(host) = state.and_then(|s| s.host.as_deref()) {
+                obj["host"] = host.into();
+            }
+            writeln!(out, "{}", obj)?;
+        }
+    }
+    out.flush()
+}
+
+/// The text to type into a tmux pane, and whether to press enter after it. If the result is just
+/// the final folder, this is a `cd` command, otherwise the (quoted) paths are inserted to the
+/// command line, so that they can be used in a command.
+fn tmux_text(final_path: &Path, paths: &[PathBuf], settings: &OutputSettings) -> (String, bool) {
+    let quoted = paths
+        .iter()
+        .map(|p| shell_quote(&settings.format_path(p).to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if paths == [final_path] {
+        (format!("cd -- {}", quoted), true)
+    } else {
+        (quoted, false)
+    }
+}
+
+fn tmux_send_keys(
+    pane: &str,
+    final_path: &Path,
+    paths: &[PathBuf],
+    settings: &OutputSettings,
+) -> IOResult<()> {
+    let (text, enter) = tmux_text(final_path, paths, settings);
+    let mut cmd = std::process::Command::new("tmux");
+    cmd.args(["send-keys", "-t", pane, "-l", &text]);
+    if !cmd.status()?.success() {
+        return Err(std::io::Error::other("tmux send-keys failed"));
+    }
+    if enter {
+        std::process::Command::new("tmux")
+            .args(["send-keys", "-t", pane, "Enter"])
+            .status()?;
+    }
+    Ok(())
+}
+
+/// Write the paths to `out`, each followed by the delimiter. On Unix, the paths are written as
+/// raw bytes, so that paths that are not valid UTF-8 are printed correctly.
+pub fn write_paths<W: Write>(
+    out: &mut W,
+    paths: &[PathBuf],
+    settings: &OutputSettings,
+) -> IOResult<()> {
+    for path in paths {
+        let path = settings.format_path(path);
+        #[cfg(unix)]
+        out.write_all(std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()))?;
+        #[cfg(not(unix))]
+        out.write_all(path.to_string_lossy().as_bytes())?;
+
+        out.write_all(&settings.delimiter)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unescape() {
+        assert_eq!(unescape("foo"), "foo");
+        assert_eq!(unescape(r"\n\t\0"), "\n\t\0");
+        assert_eq!(unescape(r"a\\b"), r"a\b");
+        assert_eq!(unescape(r"\x\"), r"\x\");
+    }
+
+    #[test]
+    fn test_write_paths() {
+        let paths = vec![PathBuf::from("/foo"), PathBuf::from("/bar\nbaz")];
+
+        let mut out = vec![];
+        write_paths(&mut out, &paths, &OutputSettings { delimiter: vec![0], ..Default::default() }).unwrap();
+        assert_eq!(out, b"/foo\0/bar\nbaz\0");
+
+        let mut out = vec![];
+        write_paths(&mut out, &paths[..1], &OutputSettings::default()).unwrap();
+        assert_eq!(out, b"/foo\n");
+
+        let mut out = vec![];
+        let settings = OutputSettings {
+            relative_to: Some("/foo".into()),
+            ..Default::default()
+        };
+        write_paths(&mut out, &paths, &settings).unwrap();
+        assert_eq!(out, b".\n../bar\nbaz\n");
+    }
+
+    #[test]
+    fn test_remote_output_paths() {
+        let mut state = ExitState {
+            final_path: "/srv/www".into(),
+            selected: vec![],
+            search_query: "".into(),
+            host: Some("me@example.com".into()),
+        };
+        assert_eq!(state.output_paths(), vec![PathBuf::from("me@example.com:/srv/www")]);
+        state.selected = vec!["/srv/a".into(), "/srv/b".into()];
+        assert_eq!(
+            state.output_paths(),
+            vec![PathBuf::from("me@example.com:/srv/a"), PathBuf::from("me@example.com:/srv/b")]
+        );
+
+        // remote paths are never made relative to a local folder
+        let mut out = vec![];
+        let settings = OutputSettings {
+            relative_to: Some("/srv".into()),
+            ..Default::default()
+        };
+        write_paths(&mut out, &state.output_paths(), &settings).unwrap();
+        assert_eq!(out, b"me@example.com:/srv/a\nme@example.com:/srv/b\n");
+    }
+
+    #[test]
+    fn test_write_porcelain_nvim() {
+        let dir = std::env::temp_dir();
+        let file = dir.join("tere-test-nonexistent-file");
+        let state = ExitState {
+            final_path: "/foo".into(),
+            selected: vec![dir.clone(), file.clone()],
+            search_query: "".into(),
+            host: None,
+        };
+        let mut out = vec![];
+        write_porcelain(&mut out, &ExitReason::Cd, Some(&state), PorcelainFormat::Nvim).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            format!("cd\t{}\nedit\t{}\n", dir.display(), file.display()),
+        );
+
+        let mut out = vec![];
+        write_porcelain(&mut out, &ExitReason::Cancel, Some(&state), PorcelainFormat::Nvim).unwrap();
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn test_write_porcelain_json() {
+        let state = ExitState {
+            final_path: "/foo".into(),
+            selected: vec![],
+            search_query: "ba".into(),
+            host: None,
+        };
+        let mut out = vec![];
+        write_porcelain(&mut out, &ExitReason::Cd, Some(&state), PorcelainFormat::Json).unwrap();
+        let obj: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(obj, serde_json::json!({
+            "reason": "cd",
+            "path": "/foo",
+            "query": "ba",
+            "selected": [],
+        }));
+
+        let mut out = vec![];
+        let reason = ExitReason::Error("oops".into());
+        write_porcelain(&mut out, &reason, None, PorcelainFormat::Json).unwrap();
+        let obj: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(obj, serde_json::json!({
+            "reason": "error",
+            "path": null,
+            "query": null,
+            "selected": [],
+            "error": "oops",
+        }));
+    }
+
+    #[test]
+    fn test_tmux_text() {
+        let settings = OutputSettings::default();
+        let folder = Path::new("/foo bar");
+        assert_eq!(
+            tmux_text(folder, &[folder.to_path_buf()], &settings),
+            ("cd -- '/foo bar'".to_string(), true),
+        );
+        assert_eq!(
+            tmux_text(folder, &["/foo bar/a".into(), "/b".into()], &settings),
+            ("'/foo bar/a' '/b'".to_string(), false),
+        );
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let rel = |p: &str, b: &str| relative_path(Path::new(p), Path::new(b));
+        assert_eq!(rel("/a/b/c", "/a/b"), PathBuf::from("c"));
+        assert_eq!(rel("/a/b", "/a/b/c/d"), PathBuf::from("../.."));
+        assert_eq!(rel("/a/x/y", "/a/b/c"), PathBuf::from("../../x/y"));
+        assert_eq!(rel("/a/b/", "/a/./b"), PathBuf::from("."));
+        assert_eq!(rel("/", "/a"), PathBuf::from(".."));
+    }
+}
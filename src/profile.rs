@@ -0,0 +1,103 @@
+/// Timing of the steps of starting up, which is printed with `--profile-startup`. This helps to
+/// find out why tere starts slowly, for example when the home folder is on a network drive. The
+/// steps are recorded with `mark` from wherever they happen, which does nothing unless profiling
+/// has been enabled with `enable`.
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+static PROFILE: OnceLock<Mutex<StartupProfile>> = OnceLock::new();
+
+/// The durations of the steps of starting up, in the order in which they happened
+pub struct StartupProfile {
+    start: Instant,
+    last: Instant,
+    steps: Vec<(&'static str, Duration)>,
+}
+
+impl StartupProfile {
+    /// Start timing from `start`, which should be the start of the program.
+    pub fn new(start: Instant) -> Self {
+        Self {
+            start,
+            last: start,
+            steps: vec![],
+        }
+    }
+
+    /// Record that the step `name` ended now. It took the time since the previous step ended.
+    pub fn mark_at(&mut self, name: &'static str, now: Instant) {
+        self.steps.push((name, now.saturating_duration_since(self.last)));
+        self.last = now;
+    }
+
+    pub fn steps(&self) -> &[(&'static str, Duration)] {
+        &self.steps
+    }
+
+    /// The time from the start to the end of the last step
+    pub fn total(&self) -> Duration {
+        self.last.saturating_duration_since(self.start)
+    }
+}
+
+impl fmt::Display for StartupProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.steps.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+        let width = width.max("total".len());
+        writeln!(f, "{} startup profile:", env!("CARGO_PKG_NAME"))?;
+        let ms = |d: &Duration| d.as_secs_f64() * 1000.0;
+        for (name, duration) in &self.steps {
+            writeln!(f, "  {:width$}  {:8.1} ms", name, ms(duration), width = width)?;
+        }
+        writeln!(f, "  {:width$}  {:8.1} ms", "total", ms(&self.total()), width = width)
+    }
+}
+
+/// Start recording the steps of starting up, timed from `start`. Only the first call has an
+/// effect.
+pub fn enable(start: Instant) {
+    let _ = PROFILE.set(Mutex::new(StartupProfile::new(start)));
+}
+
+/// Record that the step `name` ended now, if profiling is enabled. It's also logged, see
+/// `crate::logging`.
+pub fn mark(name: &'static str) {
+    tracing::debug!(step = name, "startup step done");
+    if let Some(profile) = PROFILE.get() {
+        profile.lock().unwrap().mark_at(name, Instant::now());
+    }
+}
+
+/// The profile recorded so far, formatted for printing, or `None` if profiling is not enabled.
+pub fn report() -> Option<String> {
+    PROFILE.get().map(|profile| profile.lock().unwrap().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_startup_profile() {
+        let start = Instant::now();
+        let mut profile = StartupProfile::new(start);
+        profile.mark_at("parse arguments", start + Duration::from_millis(2));
+        profile.mark_at("read first folder", start + Duration::from_millis(15));
+        assert_eq!(
+            profile.steps(),
+            &[
+                ("parse arguments", Duration::from_millis(2)),
+                ("read first folder", Duration::from_millis(13)),
+            ]
+        );
+        assert_eq!(profile.total(), Duration::from_millis(15));
+        assert_eq!(
+            profile.to_string(),
+            "tere startup profile:\n  \
+             parse arguments         2.0 ms\n  \
+             read first folder      13.0 ms\n  \
+             total                  15.0 ms\n"
+        );
+    }
+}
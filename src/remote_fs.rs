@@ -0,0 +1,280 @@
+/// Detecting folders on network file systems, like NFS, SMB and sshfs. Reading the metadata of
+/// every entry takes a round trip to the server on these, so the metadata that is not needed for
+/// browsing, like the targets of symlinks, is not read for them by default. This also finds out
+/// other things about the file system of a folder, like how much space is free on it.
+use std::path::Path;
+
+/// The space on a file system, in bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskSpace {
+    /// The space that is available to the user, which can be less than what is actually free,
+    /// because some of it may be reserved for the superuser
+    pub free: u64,
+    pub total: u64,
+}
+
+/// Whether the folder `path` is on a network file system. This is false if it can't be found out.
+#[cfg(target_os = "linux")]
+pub fn is_remote(path: &Path) -> bool {
+    // The magic numbers of network file systems in statfs(2). FUSE is included, because it's
+    // mostly used for remote file systems like sshfs.
+    const REMOTE_FS_TYPES: &[u32] = &[
+        0x6969,     // NFS
+        0x517b,     // SMB
+        0xff534d42, // CIFS
+        0xfe534d42, // SMB2
+        0x65735546, // FUSE
+        0x5346414f, // AFS
+        0x00c36400, // Ceph
+        0x01021997, // 9P
+    ];
+    // The type of f_type depends on the architecture
+    statfs(path).is_some_and(|st| REMOTE_FS_TYPES.contains(&(st.f_type as u32)))
+}
+
+#[cfg(target_os = "macos")]
+pub fn is_remote(path: &Path) -> bool {
+    use std::ffi::CStr;
+
+    statfs(path).is_some_and(|st| {
+        // FUSE file systems like sshfs are marked as local
+        let fs_type = unsafe { CStr::from_ptr(st.f_fstypename.as_ptr()) }.to_bytes();
+        st.f_flags & libc::MNT_LOCAL as u32 == 0
+            || fs_type.starts_with(b"macfuse")
+            || fs_type.starts_with(b"osxfuse")
+    })
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+fn statfs(path: &Path) -> Option<libc::statfs> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut st = MaybeUninit::<libc::statfs>::uninit();
+    // statfs fills in the struct if it succeeds
+    if unsafe { libc::statfs(path.as_ptr(), st.as_mut_ptr()) } == 0 {
+        Some(unsafe { st.assume_init() })
+    } else {
+        None
+    }
+}
+
+#[cfg(windows)]
+pub fn is_remote(path: &Path) -> bool {
+    use std::os::windows::ffi::OsStrExt;
+    use std::path::{Component, Prefix};
+
+    use windows_sys::Win32::Storage::FileSystem::GetDriveTypeW;
+    use windows_sys::Win32::System::WindowsProgramming::DRIVE_REMOTE;
+
+    let drive = match path.components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::UNC(..) | Prefix::VerbatimUNC(..) => return true,
+            _ => prefix.as_os_str().to_owned(),
+        },
+        _ => return false,
+    };
+    // the root of the drive, like C:\, as a null-terminated wide string
+    let mut root: Vec<u16> = drive.encode_wide().collect();
+    root.extend_from_slice(&['\\' as u16, 0]);
+    unsafe { GetDriveTypeW(root.as_ptr()) == DRIVE_REMOTE }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn is_remote(_path: &Path) -> bool {
+    false
+}
+
+/// The space on the file system of the folder `path`, or `None` if it can't be found out.
+#[cfg(unix)]
+// the conversions are needed on platforms where the fields are 32 bits
+#[allow(clippy::useless_conversion)]
+pub fn disk_space(path: &Path) -> Option<DiskSpace> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut st = MaybeUninit::<libc::statvfs>::uninit();
+    // statvfs fills in the struct if it succeeds
+    if unsafe { libc::statvfs(path.as_ptr(), st.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let st = unsafe { st.assume_init() };
+    let block_size = u64::from(st.f_frsize);
+    Some(DiskSpace {
+        free: u64::from(st.f_bavail).saturating_mul(block_size),
+        total: u64::from(st.f_blocks).saturating_mul(block_size),
+    })
+}
+
+#[cfg(windows)]
+pub fn disk_space(path: &Path) -> Option<DiskSpace> {
+    use std::os::windows::ffi::OsStrExt;
+
+    use windows_sys::Win32::Storage::FileSystem::GetDiskFreeSpaceExW;
+
+    let mut wide: Vec<u16> = path.as_os_str().encode_wide().collect();
+    wide.push(0);
+    let (mut free, mut total) = (0, 0);
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(wide.as_ptr(), &mut free, &mut total, std::ptr::null_mut())
+    };
+    (ok != 0).then_some(DiskSpace { free, total })
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn disk_space(_path: &Path) -> Option<DiskSpace> {
+    None
+}
+
+/// The type of the file system of the folder `path`, like 'ext4', 'nfs4' or 'NTFS', or `None` if
+/// it can't be found out. On Linux, this is the type of the mount that the folder is on, so bind
+/// mounts and FUSE file systems like 'fuse.sshfs' are shown as they were mounted.
+#[cfg(target_os = "linux")]
+pub fn fs_type(path: &Path) -> Option<String> {
+    let path = path.canonicalize().ok()?;
+    let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+    fs_type_from_mountinfo(&mountinfo, &path)
+}
+
+/// The file system type of the mount that `path` is on, according to the contents of
+/// /proc/self/mountinfo. The path should be canonical.
+#[cfg(any(target_os = "linux", test))]
+fn fs_type_from_mountinfo(mountinfo: &str, path: &Path) -> Option<String> {
+    // The lines are like '36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw',
+    // where the fifth field is the mount point, and the type comes after the optional fields
+    // that end with '-'. Mounts can be on top of each other, and the last one is visible, so use
+    // the last of the longest matching mount points.
+    mountinfo
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let mount_point = unescape_mountinfo(fields.nth(4)?);
+            let fs_type = fields.skip_while(|field| *field != "-").nth(1)?;
+            Some((mount_point, fs_type))
+        })
+        .filter(|(mount_point, _)| path.starts_with(mount_point))
+        .max_by_key(|(mount_point, _)| mount_point.len())
+        .map(|(_, fs_type)| fs_type.to_string())
+}
+
+/// Replace the octal escapes like '\040' in a field of /proc/self/mountinfo by the characters
+/// that they stand for. Spaces, tabs, newlines and backslashes are escaped like this.
+#[cfg(any(target_os = "linux", test))]
+fn unescape_mountinfo(field: &str) -> String {
+    let mut ret = String::new();
+    let mut rest = field;
+    while let Some(idx) = rest.find('\\') {
+        ret.push_str(&rest[..idx]);
+        let escaped = rest.get(idx + 1..idx + 4).and_then(|oct| u8::from_str_radix(oct, 8).ok());
+        match escaped {
+            Some(byte) => {
+                ret.push(char::from(byte));
+                rest = &rest[idx + 4..];
+            }
+            None => {
+                ret.push('\\');
+                rest = &rest[idx + 1..];
+            }
+        }
+    }
+    ret.push_str(rest);
+    ret
+}
+
+#[cfg(target_os = "macos")]
+pub fn fs_type(path: &Path) -> Option<String> {
+    use std::ffi::CStr;
+
+    let st = statfs(path)?;
+    let name = unsafe { CStr::from_ptr(st.f_fstypename.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+#[cfg(windows)]
+pub fn fs_type(path: &Path) -> Option<String> {
+    use std::os::windows::ffi::OsStrExt;
+
+    use windows_sys::Win32::Storage::FileSystem::{GetVolumeInformationW, GetVolumePathNameW};
+
+    let mut wide: Vec<u16> = path.as_os_str().encode_wide().collect();
+    wide.push(0);
+    // the root of the volume that the folder is on, like C:\ or \\server\share\
+    let mut root = [0u16; 1024];
+    if unsafe { GetVolumePathNameW(wide.as_ptr(), root.as_mut_ptr(), root.len() as u32) } == 0 {
+        return None;
+    }
+    let mut name = [0u16; 64];
+    let ok = unsafe {
+        GetVolumeInformationW(
+            root.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            name.as_mut_ptr(),
+            name.len() as u32,
+        )
+    };
+    if ok == 0 {
+        return None;
+    }
+    let len = name.iter().position(|c| *c == 0).unwrap_or(name.len());
+    Some(String::from_utf16_lossy(&name[..len]))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+pub fn fs_type(_path: &Path) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_remote() {
+        assert!(!is_remote(&std::env::temp_dir().join("tere-test-does-not-exist")));
+        #[cfg(target_os = "linux")]
+        assert!(!is_remote(Path::new("/proc")));
+    }
+
+    #[test]
+    fn test_fs_type_from_mountinfo() {
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+23 22 0:21 / /proc rw,nosuid shared:5 - proc proc rw
+40 22 0:35 / /mnt/my\\040share rw,relatime - cifs //server/share rw
+41 22 8:1 /srv/data /home/user/data rw,relatime shared:1 - ext4 /dev/sda1 rw
+42 22 0:36 / /tmp rw - tmpfs tmpfs rw
+43 42 0:37 / /tmp rw master:2 - fuse.sshfs user@host:/ rw
+";
+        let fs_type = |path: &str| fs_type_from_mountinfo(mountinfo, Path::new(path));
+        assert_eq!(fs_type("/").as_deref(), Some("ext4"));
+        assert_eq!(fs_type("/proc/self").as_deref(), Some("proc"));
+        // the mount points are compared by path components, not as strings
+        assert_eq!(fs_type("/procedures").as_deref(), Some("ext4"));
+        assert_eq!(fs_type("/mnt/my share/docs").as_deref(), Some("cifs"));
+        assert_eq!(fs_type("/home/user/data").as_deref(), Some("ext4"));
+        // the last mount on top of another is the visible one
+        assert_eq!(fs_type("/tmp/x").as_deref(), Some("fuse.sshfs"));
+        assert_eq!(fs_type_from_mountinfo("", Path::new("/")), None);
+
+        assert_eq!(unescape_mountinfo("a\\040b\\134c\\"), "a b\\c\\");
+    }
+
+    #[test]
+    fn test_disk_space() {
+        assert_eq!(disk_space(&std::env::temp_dir().join("tere-test-does-not-exist")), None);
+        #[cfg(any(unix, windows))]
+        {
+            let space = disk_space(&std::env::temp_dir()).unwrap();
+            assert!(space.total > 0);
+            assert!(space.free <= space.total);
+        }
+    }
+}
@@ -3,19 +3,25 @@ use std::fmt;
 use std::path::PathBuf;
 use std::str::FromStr;
 use clap::ArgMatches;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::app_state::{FilterPreset, SortMode};
+use crate::ui::action::{parse_key_combination, Action, ActionContext, KeyMap};
+use crate::ui::named_command::NamedCommand;
+#[cfg(feature = "rhai")]
+use crate::ui::script::Script;
+use crate::ui::theme::Theme;
 
 //TODO: config file?
 
+#[derive(Default)]
 pub enum CaseSensitiveMode {
     IgnoreCase,
     CaseSensitive,
+    #[default]
     SmartCase,
-}
-
-impl Default for CaseSensitiveMode {
-    fn default() -> Self {
-        Self::SmartCase
-    }
+    /// Ignore case if the file system of the current folder does, otherwise use smart case
+    Auto,
 }
 
 impl fmt::Display for CaseSensitiveMode {
@@ -24,24 +30,20 @@ impl fmt::Display for CaseSensitiveMode {
             CaseSensitiveMode::IgnoreCase    => "ignore case",
             CaseSensitiveMode::CaseSensitive => "case sensitive",
             CaseSensitiveMode::SmartCase     => "smart case",
+            CaseSensitiveMode::Auto          => "auto case",
         };
         write!(f, "{}", text)
     }
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Default)]
 pub enum GapSearchMode {
+    #[default]
     GapSearchFromStart,
     NoGapSearch,
     GapSearchAnywere,
 }
 
-impl Default for GapSearchMode {
-    fn default() -> Self {
-        Self::GapSearchFromStart
-    }
-}
-
 impl fmt::Display for GapSearchMode {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let text = match self {
@@ -53,19 +55,108 @@ impl fmt::Display for GapSearchMode {
     }
 }
 
+/// When to automatically change to the folder that is the only match of the current search.
+#[derive(PartialEq, Default)]
+pub enum AutoCdMode {
+    /// Always change to the only match after the timeout
+    #[default]
+    Always,
+    /// Never change automatically
+    Never,
+    /// Change only if the search query is an exact prefix of the matching name
+    ExactPrefix,
+    /// Change only if the matching item is a folder
+    DirsOnly,
+    /// Don't change automatically, only highlight the match and wait for Enter
+    RequireEnter,
+}
+
+/// What to do when a key that is not bound to any action is pressed.
+#[derive(Default)]
+pub enum UnboundKeyBehavior {
+    /// Don't do anything
+    Ignore,
+    /// Show a message telling that the key is not bound
+    #[default]
+    Message,
+    /// Perform the given action
+    Action(Action),
+}
+
+/// What to do when a character is typed that leaves no items matching the search
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NoMatchBehavior {
+    /// Add the character to the search anyway, and show that nothing matches
+    #[default]
+    Keep,
+    /// Ignore the character, keeping the previous matches
+    Discard,
+}
+
+/// Whether archive files like zip and tar files can be entered like folders, and what to print
+/// for the items in them on exit.
+#[derive(PartialEq, Default)]
+pub enum ArchiveMode {
+    /// Archives are files like any other
+    #[default]
+    Off,
+    /// Archives are shown as folders, and the items in them are printed like
+    /// 'archive.zip/inner/path'
+    Browse,
+    /// Like `Browse`, but the printed items are extracted to a private folder in the cache folder,
+    /// and the extracted paths are printed
+    Extract,
+}
+
+/// Command templates that are run in the background on navigation events. Each `{}` in them is
+/// replaced by the path of the folder, like in `TereSettings::user_commands`.
+#[derive(Default, Clone)]
+pub struct Hooks {
+    /// Run after a folder has been entered, with the path of the new folder
+    pub on_enter_dir: Option<String>,
+    /// Run when leaving a folder for another one, with the path of the folder that was left
+    pub on_leave_dir: Option<String>,
+    /// Run when exiting the app normally, with the path of the final folder
+    pub on_exit: Option<String>,
+}
+
 #[derive(Default)]
 pub struct TereSettings {
-    /// If true, show only folders, not files in the listing
+    /// If true, show only folders, not files in the listing, unless it has been changed for the
+    /// folder with `Action::ToggleFoldersOnly`
     pub folders_only: bool,
+    /// How to sort the items in folders in which the sort order hasn't been changed with
+    /// `Action::ChangeSortMode` or `Action::ReverseSort`
+    pub sort_mode: SortMode,
     /// If true, show only items matching the search in listing
     pub filter_search: bool,
+    /// If true, only folders match the search, even when files are shown in the listing
+    pub search_folders_only: bool,
+    /// What to do when a character is typed that leaves nothing matching the search
+    pub no_match: NoMatchBehavior,
+    /// If true, letters are navigation keys, and searching is started with '/'
+    pub browse_mode: bool,
+    /// The colors of the listing
+    pub theme: Theme,
 
     pub case_sensitive: CaseSensitiveMode,
 
     pub autocd_timeout: Option<u64>,
 
+    pub autocd_mode: AutoCdMode,
+
+    /// How long to wait for a folder to be opened before giving up, in milliseconds
+    pub read_timeout: Option<u64>,
+
+    /// When the listing is refreshed, highlight the new items, and the items that were modified
+    /// in the last this many seconds. `None` if nothing is highlighted.
+    pub highlight_changes: Option<u64>,
+
     pub history_file: Option<PathBuf>,
 
+    /// The file to which the listings of recently visited folders are saved, if any
+    pub listing_cache_file: Option<PathBuf>,
+
     /// whether to allow matches with gaps in them, and if we have to match from beginning
     pub gap_search_mode: GapSearchMode,
 
@@ -76,6 +167,75 @@ pub struct TereSettings {
 
     /// change behaviour of esc keybinding to exit with error (and not cd)
     pub esc_is_cancel: bool,
+
+    /// If true, exit normally (changing the folder) when a termination signal is received,
+    /// instead of exiting without changing the folder
+    pub cd_on_signal: bool,
+
+    /// If true, macOS alias files are shown with their targets, and entering them enters the
+    /// target like with symlinks
+    pub resolve_aliases: bool,
+
+    /// If true, read the metadata that is only needed for showing the items, like the targets of
+    /// symlinks, also in folders on network file systems
+    pub remote_metadata: bool,
+
+    /// If true, the free and total space of the file system of the current folder are shown in
+    /// the footer
+    pub show_disk_space: bool,
+
+    /// If true, the type of the file system of the current folder is shown in the header
+    pub show_fs_type: bool,
+
+    /// Whether archive files can be entered like folders
+    pub archive_mode: ArchiveMode,
+
+    /// If true, ctrl+u / ctrl+d scroll by half a page instead of a full page
+    pub half_page_scroll: bool,
+
+    /// The number of rows from the previous page that are kept in view when scrolling by a full
+    /// page
+    pub page_overlap: usize,
+
+    /// The active key bindings, including the ones overridden by the user
+    pub keymap: KeyMap,
+
+    /// What to do when a key without a binding is pressed
+    pub unbound_key: UnboundKeyBehavior,
+
+    /// If true, entering a file exits the app and prints the path of the file
+    pub choose_file: bool,
+
+    /// User-defined command templates, which can be run with `Action::RunCommand`
+    pub user_commands: Vec<String>,
+
+    /// Commands that are run when entering and leaving folders and when exiting
+    pub hooks: Hooks,
+
+    /// Filters that hide items in all folders, which are switched between with
+    /// `Action::CycleFilterPreset`
+    pub filter_presets: Vec<FilterPreset>,
+
+    /// Commands defined by the user, which can be run with `Action::NamedCommand`
+    pub named_commands: Vec<NamedCommand>,
+
+    /// The names of the script functions that are bound to keys, which can be run with
+    /// `Action::RunScript`
+    pub script_functions: Vec<String>,
+
+    /// The script loaded with the `--script` option
+    #[cfg(feature = "rhai")]
+    pub script: Option<Script>,
+
+    /// If true, read a list of items to choose from from stdin, instead of showing the contents
+    /// of the current folder
+    pub read_stdin: bool,
+
+    /// If true, don't show the welcome message on startup
+    pub no_banner: bool,
+
+    /// If true, actions that create, rename or delete files are disabled
+    pub no_file_ops: bool,
 }
 
 impl TereSettings {
@@ -86,6 +246,41 @@ impl TereSettings {
             ret.folders_only = true;
         }
 
+        // ok to unwrap, because sort has a default value and its possible values are checked
+        ret.sort_mode = SortMode::from_name(args.value_of("sort").unwrap()).unwrap();
+
+        if args.is_present("choose-file") {
+            ret.choose_file = true;
+        }
+
+        if args.is_present("stdin") {
+            ret.read_stdin = true;
+        }
+
+        if args.is_present("search-folders-only") {
+            ret.search_folders_only = true;
+        }
+
+        if args.is_present("browse-mode") {
+            ret.browse_mode = true;
+        }
+
+        // ok to unwrap, because no-match has a default value and its possible values are checked
+        ret.no_match = match args.value_of("no-match").unwrap() {
+            "discard" => NoMatchBehavior::Discard,
+            _ => NoMatchBehavior::Keep,
+        };
+
+        // ok to unwrap, because the possible values of theme are checked. Without a theme, the
+        // colors are left out if NO_COLOR is set, see https://no-color.org
+        ret.theme = match args.value_of("theme") {
+            Some(name) => Theme::from_name(name).unwrap(),
+            None if std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) => {
+                Theme::from_name("monochrome").unwrap()
+            }
+            None => Theme::default(),
+        };
+
         if args.is_present("filter-search") {
             ret.filter_search = true;
         }
@@ -96,6 +291,8 @@ impl TereSettings {
             ret.case_sensitive = CaseSensitiveMode::IgnoreCase;
         } else if args.is_present("smart-case") {
             ret.case_sensitive = CaseSensitiveMode::SmartCase;
+        } else if args.is_present("auto-case") {
+            ret.case_sensitive = CaseSensitiveMode::Auto;
         }
 
         if args.is_present("gap-search") {
@@ -110,7 +307,7 @@ impl TereSettings {
             .values_of("autocd-timeout")
             // ok to unwrap because autocd-timeout has a default value which is always present
             .unwrap()
-            .last()
+            .next_back()
             .unwrap()
         {
             "off" => None,
@@ -127,6 +324,46 @@ impl TereSettings {
                 .into(),
         };
 
+        ret.read_timeout = match args
+            .values_of("read-timeout")
+            // ok to unwrap because read-timeout has a default value which is always present
+            .unwrap()
+            .next_back()
+            .unwrap()
+        {
+            "off" => None,
+            x => u64::from_str(x)
+                .map_err(|_| {
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!("Invalid value for 'read-timeout': '{}'\n", x),
+                    )
+                })?
+                .into(),
+        };
+
+        // ok to unwrap, because highlight-changes has a default value
+        ret.highlight_changes = match args.value_of("highlight-changes").unwrap() {
+            "off" => None,
+            x => u64::from_str(x)
+                .map_err(|_| {
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!("Invalid value for 'highlight-changes': '{}'\n", x),
+                    )
+                })?
+                .into(),
+        };
+
+        // ok to unwrap, because autocd has a default value
+        ret.autocd_mode = match args.value_of("autocd").unwrap() {
+            "never" => AutoCdMode::Never,
+            "prefix" => AutoCdMode::ExactPrefix,
+            "dirs" => AutoCdMode::DirsOnly,
+            "enter" => AutoCdMode::RequireEnter,
+            _ => AutoCdMode::Always,
+        };
+
         if let Some(hist_file) = args.value_of("history-file") {
             ret.history_file = if hist_file.is_empty() {
                 None
@@ -138,8 +375,20 @@ impl TereSettings {
                 .map(|path| path.join(env!("CARGO_PKG_NAME")).join("history.json"));
         }
 
+        let hook = |name| args.value_of(name).filter(|cmd| !cmd.trim().is_empty()).map(String::from);
+        ret.hooks = Hooks {
+            on_enter_dir: hook("on-enter-dir"),
+            on_leave_dir: hook("on-leave-dir"),
+            on_exit: hook("on-exit"),
+        };
+
+        if args.is_present("listing-cache") {
+            ret.listing_cache_file = dirs::cache_dir()
+                .map(|path| path.join(env!("CARGO_PKG_NAME")).join("listings.json"));
+        }
+
         // ok to unwrap, because mouse has the default value of 'off'
-        if args.values_of("mouse").unwrap().last().unwrap() == "on" {
+        if args.values_of("mouse").unwrap().next_back().unwrap() == "on" {
             ret.mouse_enabled = true;
         }
 
@@ -147,10 +396,321 @@ impl TereSettings {
             ret.esc_is_cancel = true;
         }
 
+        if args.is_present("cd-on-signal") {
+            ret.cd_on_signal = true;
+        }
+
+        if args.is_present("resolve-aliases") {
+            ret.resolve_aliases = true;
+        }
+
+        if args.is_present("remote-metadata") {
+            ret.remote_metadata = true;
+        }
+
+        if args.is_present("disk-space") {
+            ret.show_disk_space = true;
+        }
+
+        if args.is_present("fs-type") {
+            ret.show_fs_type = true;
+        }
+
         if args.is_present("enter-is-cd-and-exit") {
             ret.enter_is_cd_and_exit = true;
         }
 
+        if args.is_present("no-file-ops") {
+            ret.no_file_ops = true;
+        }
+
+        if args.value_of("porcelain") == Some("nvim") {
+            // Running inside an editor's terminal, where the result is used to :cd or :edit
+            ret.enter_is_cd_and_exit = true;
+            ret.choose_file = true;
+            ret.no_banner = true;
+        }
+
+        if args.is_present("half-page-scroll") {
+            ret.half_page_scroll = true;
+        }
+
+        // ok to unwrap, because page-overlap has a default value
+        let page_overlap = args.value_of("page-overlap").unwrap();
+        ret.page_overlap = usize::from_str(page_overlap).map_err(|_| {
+            clap::Error::raw(
+                clap::ErrorKind::InvalidValue,
+                format!("Invalid value for 'page-overlap': '{}'\n", page_overlap),
+            )
+        })?;
+
+        // ok to unwrap, because archives has a default value
+        ret.archive_mode = match args.value_of("archives").unwrap() {
+            "browse" => ArchiveMode::Browse,
+            "extract" => ArchiveMode::Extract,
+            _ => ArchiveMode::Off,
+        };
+
+        // ok to unwrap, because unbound-key has a default value
+        ret.unbound_key = match args.value_of("unbound-key").unwrap() {
+            "ignore" => UnboundKeyBehavior::Ignore,
+            "message" => UnboundKeyBehavior::Message,
+            action => UnboundKeyBehavior::Action(Action::from_str(action).map_err(|e| {
+                clap::Error::raw(
+                    clap::ErrorKind::InvalidValue,
+                    format!("Invalid value for 'unbound-key': {}\n", e),
+                )
+            })?),
+        };
+
+        if let Some(presets) = args.values_of("filter-preset") {
+            for preset in presets {
+                ret.filter_presets.push(FilterPreset::parse(preset).map_err(|e| {
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!("Invalid value for 'filter-preset': '{}': {}\n", preset, e),
+                    )
+                })?);
+            }
+        }
+
+        if let Some(commands) = args.values_of("command") {
+            for command in commands {
+                ret.add_named_command(command).map_err(|e| {
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!("Invalid value for 'command': '{}': {}\n", command, e),
+                    )
+                })?;
+            }
+        }
+
+        ret.keymap = ret.default_keymap();
+        if let Some(mappings) = args.values_of("map") {
+            for mapping in mappings {
+                ret.apply_key_mapping(mapping).map_err(|e| {
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!("Invalid value for 'map': '{}': {}\n", mapping, e),
+                    )
+                })?;
+            }
+        }
+
+        if let Some(commands) = args.values_of("run") {
+            for command in commands {
+                ret.add_user_command(command).map_err(|e| {
+                    clap::Error::raw(
+                        clap::ErrorKind::InvalidValue,
+                        format!("Invalid value for 'run': '{}': {}\n", command, e),
+                    )
+                })?;
+            }
+        }
+
+        if let Some(file) = args.value_of("script") {
+            ret.load_script(file).map_err(|e| {
+                clap::Error::raw(
+                    clap::ErrorKind::InvalidValue,
+                    format!("Invalid value for 'script': '{}': {}\n", file, e),
+                )
+            })?;
+        }
+
         Ok(ret)
     }
+
+    /// The default key bindings, which depend on some of the other settings.
+    pub(crate) fn default_keymap(&self) -> KeyMap {
+        use Action::*;
+        use ActionContext::*;
+
+        let alt = KeyModifiers::ALT;
+        let ctrl = KeyModifiers::CONTROL;
+        let none = KeyModifiers::NONE;
+        let key = |code, modifiers| KeyEvent::new(code, modifiers);
+
+        let enter_action = if self.enter_is_cd_and_exit {
+            ChangeDirAndExit
+        } else if self.esc_is_cancel {
+            Exit
+        } else {
+            ChangeDir
+        };
+        let esc_action = if self.esc_is_cancel { ExitWithoutCd } else { Exit };
+        let (page_up, page_down) = if self.half_page_scroll {
+            (CursorUpHalfScreen, CursorDownHalfScreen)
+        } else {
+            (CursorUpScreen, CursorDownScreen)
+        };
+
+        let mut keymap = KeyMap::default();
+        for (k, context, action) in [
+            (key(KeyCode::Up, none), Any, CursorUp),
+            (key(KeyCode::Char('k'), alt), Any, CursorUp),
+            (key(KeyCode::Down, none), Any, CursorDown),
+            (key(KeyCode::Char('j'), alt), Any, CursorDown),
+            (key(KeyCode::Enter, none), Any, enter_action),
+            (key(KeyCode::Right, none), Any, ChangeDir),
+            (key(KeyCode::Down, alt), Any, ChangeDir),
+            (key(KeyCode::Char('l'), alt), Any, ChangeDir),
+            (key(KeyCode::Char(' '), none), NotSearching, ChangeDir),
+            (key(KeyCode::Left, none), Any, ChangeDirParent),
+            (key(KeyCode::Up, alt), Any, ChangeDirParent),
+            (key(KeyCode::Char('h'), alt), Any, ChangeDirParent),
+            (key(KeyCode::Backspace, none), NotSearching, ChangeDirParent),
+            (key(KeyCode::Char('-'), none), NotSearching, ChangeDirParent),
+            (key(KeyCode::Esc, none), NotSearching, esc_action),
+            (key(KeyCode::Char('q'), alt), Any, Exit),
+            (key(KeyCode::Char('c'), ctrl), Any, ExitWithoutCd),
+            (key(KeyCode::Char('~'), none), Any, ChangeDirHome),
+            (key(KeyCode::Home, ctrl), Any, ChangeDirHome),
+            (key(KeyCode::Char('h'), ctrl | alt), Any, ChangeDirHome),
+            (key(KeyCode::Char('/'), none), Any, ChangeDirRoot),
+            (key(KeyCode::Char('r'), alt), Any, ChangeDirRoot),
+            (key(KeyCode::Char('r'), ctrl), Any, RefreshListing),
+            (key(KeyCode::Char('l'), ctrl), Any, GoToPath),
+            (key(KeyCode::Char('t'), ctrl), Any, NewTab),
+            (key(KeyCode::Char('w'), ctrl), Any, CloseTab),
+            (key(KeyCode::Tab, ctrl), Any, NextTab),
+            (key(KeyCode::PageDown, ctrl), Any, NextTab),
+            (key(KeyCode::BackTab, ctrl | KeyModifiers::SHIFT), Any, PreviousTab),
+            (key(KeyCode::PageUp, ctrl), Any, PreviousTab),
+            (key(KeyCode::PageUp, none), Any, CursorUpScreen),
+            (key(KeyCode::Char('u'), ctrl), Any, page_up),
+            (key(KeyCode::Char('u'), alt), Any, page_up),
+            (key(KeyCode::PageDown, none), Any, CursorDownScreen),
+            (key(KeyCode::Char('d'), ctrl), Any, page_down),
+            (key(KeyCode::Char('d'), alt), Any, page_down),
+            (key(KeyCode::Home, none), Any, CursorTop),
+            (key(KeyCode::Char('g'), alt), Any, CursorTop),
+            (key(KeyCode::End, none), Any, CursorBottom),
+            (key(KeyCode::Char('G'), alt), Any, CursorBottom),
+            (key(KeyCode::Backspace, none), Searching, EraseSearchChar),
+            (key(KeyCode::Esc, none), Searching, ClearSearch),
+            (key(KeyCode::Char('c'), alt), Any, ChangeCaseSensitiveMode),
+            (key(KeyCode::Char('f'), ctrl), Any, ChangeGapSearchMode),
+            (key(KeyCode::Char('s'), ctrl), Any, ChangeSortMode),
+            (key(KeyCode::Char('S'), alt), Any, ReverseSort),
+            (key(KeyCode::Char('.'), alt), Any, ToggleHidden),
+            (key(KeyCode::Char('D'), alt), Any, ToggleFoldersOnly),
+            (key(KeyCode::Char('F'), alt), Any, CycleFilterPreset),
+            (key(KeyCode::Char('v'), alt), Any, ViewMenu),
+            (key(KeyCode::Char('b'), alt), Any, ToggleBrowseMode),
+            (key(KeyCode::Char('J'), alt), Any, JumpToLabel),
+            (key(KeyCode::Char('m'), alt), Any, ToggleMouseCapture),
+            (key(KeyCode::Tab, none), Any, ToggleMark),
+            (key(KeyCode::Char('x'), alt), Any, ClearMarks),
+            (key(KeyCode::Char('a'), alt), Any, NextMark),
+            (key(KeyCode::Char('A'), alt), Any, PreviousMark),
+            (key(KeyCode::Char('y'), alt), Any, CopyPath),
+            (key(KeyCode::Char('Y'), alt), Any, CopyItemPath),
+            (key(KeyCode::Char('s'), alt), Any, SpawnShell),
+            (key(KeyCode::Char('e'), alt), Any, OpenInEditor),
+            (key(KeyCode::Char('o'), alt), Any, OpenWithDefaultApp),
+            (key(KeyCode::Char('f'), alt), Any, OpenFileManager),
+            (key(KeyCode::Char('n'), alt), Any, CreateDir),
+            (key(KeyCode::Char('N'), alt), Any, CreateDirAndEnter),
+            (key(KeyCode::Char('t'), alt), Any, CreateFile),
+            (key(KeyCode::F(2), none), Any, Rename),
+            (key(KeyCode::Delete, none), Any, Delete),
+            (key(KeyCode::Char('p'), alt), Any, CopyMarkedHere),
+            (key(KeyCode::Char('P'), alt), Any, MoveMarkedHere),
+            (key(KeyCode::Char('L'), alt), Any, LinkMarkedHere),
+            (key(KeyCode::Char('?'), none), Any, Help),
+            (key(KeyCode::F(1), none), Any, CheatSheet),
+            (key(KeyCode::Char('E'), alt), Any, ShowLastError),
+            (key(KeyCode::Char('p'), ctrl), Any, CommandPalette),
+        ] {
+            keymap.bind(k, context, action);
+        }
+        keymap
+    }
+
+    /// Apply a key mapping of the form `KEY:ACTION` or `KEY:ACTION:CONTEXT` to the key map. If
+    /// the action is `none`, the key is unbound.
+    fn apply_key_mapping(&mut self, mapping: &str) -> Result<(), String> {
+        // the ':' key has to be handled separately, since it's also the separator
+        let (key, rest) = if let Some(rest) = mapping.strip_prefix("::") {
+            (":", rest)
+        } else {
+            mapping
+                .split_once(':')
+                .ok_or_else(|| "expected KEY:ACTION or KEY:ACTION:CONTEXT".to_string())?
+        };
+        let (action, context) = rest.split_once(':').unwrap_or((rest, "any"));
+
+        let key = parse_key_combination(key)?;
+        let context = ActionContext::from_str(context)?;
+        if action == "none" {
+            self.keymap.unbind(key, context);
+        } else {
+            self.keymap.bind(key, context, self.parse_action(action)?);
+        }
+        Ok(())
+    }
+
+    /// Parse the name of a built-in action or a named command.
+    fn parse_action(&self, name: &str) -> Result<Action, String> {
+        match self.named_commands.iter().position(|cmd| cmd.name == name) {
+            Some(idx) => Ok(Action::NamedCommand(idx)),
+            None => Action::from_str(name),
+        }
+    }
+
+    /// Parse a named command in the format 'NAME:STEPS', see `NamedCommand::parse`. A command
+    /// with the same name as an earlier one replaces it.
+    fn add_named_command(&mut self, spec: &str) -> Result<(), String> {
+        let command = NamedCommand::parse(spec)?;
+        match self.named_commands.iter_mut().find(|cmd| cmd.name == command.name) {
+            Some(existing) => *existing = command,
+            None => self.named_commands.push(command),
+        }
+        Ok(())
+    }
+
+    /// The actions defined by the user: the commands given with `--run`, the script functions
+    /// and the named commands.
+    pub fn user_actions(&self) -> impl Iterator<Item = Action> {
+        (0..self.user_commands.len())
+            .map(Action::RunCommand)
+            .chain((0..self.script_functions.len()).map(Action::RunScript))
+            .chain((0..self.named_commands.len()).map(Action::NamedCommand))
+    }
+
+    /// Parse a user-defined command in the format 'KEY:COMMAND' and bind it to the key.
+    fn add_user_command(&mut self, spec: &str) -> Result<(), String> {
+        let (key, command) = if let Some(rest) = spec.strip_prefix("::") {
+            (":", rest)
+        } else {
+            spec.split_once(':')
+                .ok_or_else(|| "expected KEY:COMMAND".to_string())?
+        };
+        if command.trim().is_empty() {
+            return Err("the command is empty".to_string());
+        }
+
+        let key = parse_key_combination(key)?;
+        self.user_commands.push(command.to_string());
+        self.keymap.bind(key, ActionContext::Any, Action::RunCommand(self.user_commands.len() - 1));
+        Ok(())
+    }
+
+    /// Load the script file `file`, and bind its functions to keys like it says.
+    #[cfg(feature = "rhai")]
+    fn load_script(&mut self, file: &str) -> Result<(), String> {
+        let (script, bindings) = Script::load(std::path::Path::new(file))?;
+        for (key, function) in bindings {
+            let key = parse_key_combination(&key)?;
+            self.script_functions.push(function);
+            self.keymap.bind(key, ActionContext::Any, Action::RunScript(self.script_functions.len() - 1));
+        }
+        self.script = Some(script);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "rhai"))]
+    fn load_script(&mut self, _file: &str) -> Result<(), String> {
+        Err(format!("{} was built without scripting support (the 'rhai' feature)", env!("CARGO_PKG_NAME")))
+    }
 }
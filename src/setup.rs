@@ -0,0 +1,203 @@
+//! The guided setup of the shell function, which is offered the first time that tere is run
+//! interactively without it, and can be run again with `tere setup`. It detects the shell, and
+//! either appends the line that sets up the function to the shell's configuration file, or prints
+//! it for adding it by hand.
+//!
+//! The setup is a plain question on the terminal, asked before the UI is started, so the prompts
+//! are written to stderr like the UI, and the answer is read from stdin.
+
+use std::io::{BufRead, Result as IOResult, Write};
+use std::path::{Path, PathBuf};
+
+use crate::doctor;
+use crate::shell_init::SUPPORTED_SHELLS;
+
+/// The file whose existence means that the setup has been offered already
+fn marker_file() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join(env!("CARGO_PKG_NAME")).join("shell-setup-offered"))
+}
+
+fn env(var: &str) -> Option<String> {
+    std::env::var(var).ok().filter(|value| !value.is_empty())
+}
+
+/// Whether `shell` already has tere set up in one of its configuration files
+fn is_set_up(shell: &str, files: &[PathBuf]) -> bool {
+    files.iter().any(|file| {
+        std::fs::read_to_string(file)
+            .is_ok_and(|contents| doctor::check_shell_config(shell, file, &contents).is_some())
+    })
+}
+
+/// Whether the setup should be offered on startup. `prints_to_stdout` should be false if the
+/// result is written somewhere else than stdout, because then tere is run by another program.
+pub fn should_offer(prints_to_stdout: bool) -> bool {
+    use std::io::IsTerminal;
+    // If stdout is not a terminal, it's captured by a shell function or another program
+    if !prints_to_stdout
+        || !std::io::stdout().is_terminal()
+        || !std::io::stdin().is_terminal()
+        || !std::io::stderr().is_terminal()
+    {
+        return false;
+    }
+    let marker = match marker_file() {
+        Some(marker) if !marker.exists() => marker,
+        _ => return false,
+    };
+    // only offer it once, also if it's declined
+    let _ = marker.parent().map(std::fs::create_dir_all);
+    let _ = std::fs::write(&marker, "");
+
+    match doctor::detect_shell(&env) {
+        Some(shell) => !is_set_up(shell, &doctor::shell_config_files(shell, &env)),
+        None => true,
+    }
+}
+
+/// The configuration file of `shell` that the line is added to: the first one that exists, or
+/// the usual one if none of them do.
+fn config_file(shell: &str) -> Option<PathBuf> {
+    let files = doctor::shell_config_files(shell, &env);
+    files.iter().find(|f| f.is_file()).or_else(|| files.first()).cloned()
+}
+
+/// Append the line that sets up the function for `shell` to `file`. Nushell can't run the output
+/// of `tere init` on startup, so for it, the script is saved next to `file` and sourced.
+fn install(shell: &str, file: &Path) -> IOResult<()> {
+    let line = if shell == "nushell" {
+        let script = file.with_file_name("tere.nu");
+        std::fs::write(&script, crate::shell_init::init_script(shell).unwrap_or_default())?;
+        format!("source {}", script.display())
+    } else {
+        doctor::init_line(shell)
+    };
+    if let Some(dir) = file.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let old = std::fs::read_to_string(file).unwrap_or_default();
+    let sep = if old.is_empty() || old.ends_with('\n') { "" } else { "\n" };
+    let mut f = std::fs::OpenOptions::new().create(true).append(true).open(file)?;
+    write!(f, "{}\n# Added by 'tere setup'\n{}\n", sep, line)
+}
+
+fn read_answer(input: &mut impl BufRead) -> IOResult<String> {
+    let mut answer = String::new();
+    input.read_line(&mut answer)?;
+    Ok(answer.trim().to_lowercase())
+}
+
+/// Ask which shell is used, if it couldn't be detected. Returns `None` if the setup was skipped.
+fn ask_shell(input: &mut impl BufRead, output: &mut impl Write) -> IOResult<Option<&'static str>> {
+    // tmux is not a shell, its setup is separate
+    let shells: Vec<_> = SUPPORTED_SHELLS.iter().filter(|s| **s != "tmux").collect();
+    loop {
+        write!(output, "Which shell do you use ({}), or press Enter to skip? ", shells.iter().map(|s| **s).collect::<Vec<_>>().join(", "))?;
+        output.flush()?;
+        let answer = read_answer(input)?;
+        if answer.is_empty() {
+            return Ok(None);
+        }
+        if let Some(shell) = shells.iter().find(|s| ***s == answer) {
+            return Ok(Some(**shell));
+        }
+        writeln!(output, "'{}' is not supported.", answer)?;
+    }
+}
+
+/// Run the setup, asking the questions on `output` and reading the answers from `input`.
+/// `shell` and `file` are the detected shell and its configuration file, if any.
+pub fn run_with(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    shell: Option<&'static str>,
+    file: Option<PathBuf>,
+) -> IOResult<()> {
+    writeln!(output, "tere can change the folder of your shell when it exits, but this needs a shell function, which doesn't seem to be set up yet.")?;
+
+    let (shell, file) = match shell {
+        Some(shell) => {
+            writeln!(output, "Your shell seems to be {}.", shell)?;
+            (shell, file)
+        }
+        None => match ask_shell(input, output)? {
+            Some(shell) => (shell, config_file(shell)),
+            None => return Ok(()),
+        },
+    };
+    let line = doctor::init_line(shell);
+
+    let choices = match &file {
+        Some(file) => format!("[a]dd it to {}, [p]rint it, or [s]kip", file.display()),
+        None => "[p]rint it, or [s]kip".to_string(),
+    };
+    loop {
+        write!(output, "Set it up with '{}'? {}: ", line, choices)?;
+        output.flush()?;
+        match (read_answer(input)?.as_str(), &file) {
+            ("a", Some(file)) => {
+                install(shell, file)?;
+                writeln!(output, "Added to {}. Open a new shell to start using it.", file.display())?;
+                return Ok(());
+            }
+            ("p", _) => {
+                writeln!(output, "Add this line to the configuration file of your shell:\n\n    {}\n", line)?;
+                return Ok(());
+            }
+            // an empty answer is also an EOF, so that the question doesn't loop forever
+            ("s", _) | ("", _) => {
+                writeln!(output, "Skipped. You can run 'tere setup' later.")?;
+                return Ok(());
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Run the setup interactively on the terminal
+pub fn run() -> IOResult<()> {
+    let shell = doctor::detect_shell(&env);
+    if let Some(shell) = shell {
+        if is_set_up(shell, &doctor::shell_config_files(shell, &env)) {
+            eprintln!("tere is already set up for {}. Run 'tere doctor' if it doesn't work.", shell);
+            return Ok(());
+        }
+    }
+    let file = shell.and_then(config_file);
+    run_with(&mut std::io::stdin().lock(), &mut std::io::stderr(), shell, file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    fn run_test(answers: &str, shell: Option<&'static str>, file: Option<PathBuf>) -> String {
+        let mut output = vec![];
+        run_with(&mut answers.as_bytes(), &mut output, shell, file).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_setup() {
+        let tmp = TempDir::new("setup");
+        let rc = tmp.join(".bashrc");
+
+        let out = run_test("p\n", Some("bash"), Some(rc.clone()));
+        assert!(out.contains("    eval \"$(tere init bash)\"\n"));
+        assert!(!rc.exists());
+
+        let out = run_test("x\ns\n", Some("bash"), Some(rc.clone()));
+        assert!(out.contains("Skipped"));
+        assert!(!rc.exists());
+
+        std::fs::write(&rc, "alias ll='ls -l'").unwrap();
+        run_test("a\n", Some("bash"), Some(rc.clone()));
+        let contents = std::fs::read_to_string(&rc).unwrap();
+        assert_eq!(contents, "alias ll='ls -l'\n\n# Added by 'tere setup'\neval \"$(tere init bash)\"\n");
+        assert!(is_set_up("bash", &[rc]));
+
+        let out = run_test("tcsh\n\n", None, None);
+        assert!(out.contains("'tcsh' is not supported"));
+    }
+}
@@ -0,0 +1,722 @@
+/// Browsing folders on another machine over SFTP, with URLs like 'ssh://user@host/path'. The
+/// connection is made by running the `ssh` command with the SFTP subsystem, so that the keys,
+/// agents, host aliases and other settings of the user's SSH configuration work as usual, and ssh
+/// can ask for a password if it needs one. Only the few requests of SFTP version 3 that are needed
+/// for listing folders are implemented.
+use std::convert::TryInto;
+use std::ffi::OsString;
+use std::io::{BufReader, Error as IOError, ErrorKind, Read, Result as IOResult, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use super::{CustomDirEntry, DirBatches, DirSource};
+
+// Packet types, see https://datatracker.ietf.org/doc/html/draft-ietf-secsh-filexfer-02
+const SSH_FXP_INIT: u8 = 1;
+const SSH_FXP_VERSION: u8 = 2;
+const SSH_FXP_CLOSE: u8 = 4;
+const SSH_FXP_OPENDIR: u8 = 11;
+const SSH_FXP_READDIR: u8 = 12;
+const SSH_FXP_REALPATH: u8 = 16;
+const SSH_FXP_STAT: u8 = 17;
+const SSH_FXP_STATUS: u8 = 101;
+const SSH_FXP_HANDLE: u8 = 102;
+const SSH_FXP_NAME: u8 = 104;
+const SSH_FXP_ATTRS: u8 = 105;
+
+// Status codes
+const SSH_FX_OK: u32 = 0;
+const SSH_FX_EOF: u32 = 1;
+const SSH_FX_NO_SUCH_FILE: u32 = 2;
+const SSH_FX_PERMISSION_DENIED: u32 = 3;
+
+// Flags for which attributes are present
+const SSH_FILEXFER_ATTR_SIZE: u32 = 0x1;
+const SSH_FILEXFER_ATTR_UIDGID: u32 = 0x2;
+const SSH_FILEXFER_ATTR_PERMISSIONS: u32 = 0x4;
+const SSH_FILEXFER_ATTR_ACMODTIME: u32 = 0x8;
+const SSH_FILEXFER_ATTR_EXTENDED: u32 = 0x8000_0000;
+
+// The file type bits of the permissions, like in stat(2)
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Packets larger than this are treated as a protocol error, instead of trying to allocate memory
+/// for them. Servers limit their packets to much less than this.
+const MAX_PACKET_LEN: usize = 1 << 24;
+
+/// A location on another machine, parsed from an URL like 'ssh://user@host:2222/path'. The
+/// 'sftp://' scheme can be used too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SshUrl {
+    pub user: Option<String>,
+    pub host: String,
+    pub port: Option<u16>,
+    /// The path on the remote machine. Paths that don't start with a slash, like '~/src' in
+    /// 'ssh://host/~/src', are relative to the home folder, and an empty path is the home folder.
+    pub path: String,
+}
+
+impl SshUrl {
+    /// Parse `url`, or return `None` if it's not an SSH URL.
+    pub fn parse(url: &str) -> Option<Self> {
+        let rest = url
+            .strip_prefix("ssh://")
+            .or_else(|| url.strip_prefix("sftp://"))?;
+        let (authority, path) = match rest.find('/') {
+            Some(idx) => rest.split_at(idx),
+            None => (rest, ""),
+        };
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user.to_string()), host_port),
+            None => (None, authority),
+        };
+        // IPv6 addresses are in brackets, like [::1]:22
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(bracketed) => {
+                let (host, port) = bracketed.split_once(']')?;
+                (host, port.strip_prefix(':'))
+            }
+            None => match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            },
+        };
+        let port = match port {
+            Some(port) => Some(port.parse().ok()?),
+            None => None,
+        };
+        if host.is_empty() || host.starts_with('-') {
+            return None;
+        }
+        // the path after the host always starts with a slash, except for '/~/', which refers to
+        // the home folder like in scp
+        let path = match path.strip_prefix("/~") {
+            Some("") => String::new(),
+            Some(rest) if rest.starts_with('/') => rest[1..].to_string(),
+            _ => path.to_string(),
+        };
+        Some(Self {
+            user,
+            host: host.to_string(),
+            port,
+            path,
+        })
+    }
+
+    /// The user and host in the form used by scp and ssh, like 'user@host'
+    pub fn destination(&self) -> String {
+        match &self.user {
+            Some(user) => format!("{}@{}", user, self.host),
+            None => self.host.clone(),
+        }
+    }
+}
+
+/// An SFTP protocol error, or an unexpected response from the server
+fn protocol_error(msg: &str) -> IOError {
+    IOError::new(ErrorKind::InvalidData, format!("SFTP error: {}", msg))
+}
+
+/// An SFTP packet that is being built, without its length
+struct Packet(Vec<u8>);
+
+impl Packet {
+    fn new(packet_type: u8, id: u32) -> Self {
+        let ret = Self(vec![packet_type]);
+        ret.u32(id)
+    }
+
+    fn u32(mut self, value: u32) -> Self {
+        self.0.extend_from_slice(&value.to_be_bytes());
+        self
+    }
+
+    fn string(self, value: &[u8]) -> Self {
+        let mut ret = self.u32(value.len() as u32);
+        ret.0.extend_from_slice(value);
+        ret
+    }
+}
+
+/// Reads the fields of a received packet
+struct PacketReader<'a>(&'a [u8]);
+
+impl<'a> PacketReader<'a> {
+    fn bytes(&mut self, len: usize) -> IOResult<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(protocol_error("truncated packet"));
+        }
+        let (ret, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(ret)
+    }
+
+    fn u8(&mut self) -> IOResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> IOResult<u32> {
+        // ok to unwrap, bytes returns exactly 4 bytes
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> IOResult<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> IOResult<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+
+    fn attrs(&mut self) -> IOResult<Attrs> {
+        let flags = self.u32()?;
+        let mut ret = Attrs::default();
+        if flags & SSH_FILEXFER_ATTR_SIZE != 0 {
+            self.u64()?;
+        }
+        if flags & SSH_FILEXFER_ATTR_UIDGID != 0 {
+            self.u32()?;
+            self.u32()?;
+        }
+        if flags & SSH_FILEXFER_ATTR_PERMISSIONS != 0 {
+            ret.permissions = Some(self.u32()?);
+        }
+        if flags & SSH_FILEXFER_ATTR_ACMODTIME != 0 {
+            self.u32()?;
+            ret.mtime = Some(self.u32()?);
+        }
+        if flags & SSH_FILEXFER_ATTR_EXTENDED != 0 {
+            for _ in 0..self.u32()? {
+                self.string()?;
+                self.string()?;
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// The attributes of a file that are needed for browsing
+#[derive(Default)]
+struct Attrs {
+    permissions: Option<u32>,
+    mtime: Option<u32>,
+}
+
+impl Attrs {
+    fn file_type(&self) -> Option<u32> {
+        self.permissions.map(|p| p & S_IFMT)
+    }
+}
+
+/// The names and attributes of files, as received from the server
+type Names = Vec<(Vec<u8>, Attrs)>;
+
+/// A response from the server, without the request id
+enum Response {
+    Status(u32, String),
+    Handle(Vec<u8>),
+    Name(Names),
+    Attrs(Attrs),
+}
+
+impl Response {
+    /// Convert a status response to an error, or an unexpected response to a protocol error
+    fn into_error(self, path: &[u8]) -> IOError {
+        let path = String::from_utf8_lossy(path);
+        match self {
+            Response::Status(SSH_FX_NO_SUCH_FILE, _) => {
+                IOError::new(ErrorKind::NotFound, format!("No such folder: '{}'", path))
+            }
+            Response::Status(SSH_FX_PERMISSION_DENIED, _) => IOError::new(
+                ErrorKind::PermissionDenied,
+                format!("Permission denied: '{}'", path),
+            ),
+            Response::Status(_, msg) => IOError::other(format!("'{}': {}", path, msg)),
+            _ => protocol_error("unexpected response"),
+        }
+    }
+}
+
+/// A connection to the SFTP server, over which one request is made at a time
+struct Session {
+    writer: Box<dyn Write + Send>,
+    reader: Box<dyn Read + Send>,
+    next_id: u32,
+    // The ssh process, which is stopped when the session is dropped
+    child: Option<Child>,
+}
+
+impl Session {
+    /// Start a session over `reader` and `writer`, which are connected to an SFTP server.
+    fn start(reader: Box<dyn Read + Send>, writer: Box<dyn Write + Send>) -> IOResult<Self> {
+        let mut ret = Self {
+            writer,
+            reader,
+            next_id: 0,
+            child: None,
+        };
+        // the init packet has no request id, only the version
+        ret.send(&Packet(vec![SSH_FXP_INIT]).u32(3))?;
+        let packet = ret.receive()?;
+        let mut reader = PacketReader(&packet);
+        if reader.u8()? != SSH_FXP_VERSION {
+            return Err(protocol_error("the server did not send its version"));
+        }
+        Ok(ret)
+    }
+
+    fn send(&mut self, packet: &Packet) -> IOResult<()> {
+        self.writer.write_all(&(packet.0.len() as u32).to_be_bytes())?;
+        self.writer.write_all(&packet.0)?;
+        self.writer.flush()
+    }
+
+    fn receive(&mut self) -> IOResult<Vec<u8>> {
+        let mut len = [0; 4];
+        self.reader.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len) as usize;
+        if len == 0 || len > MAX_PACKET_LEN {
+            return Err(protocol_error("invalid packet length"));
+        }
+        let mut packet = vec![0; len];
+        self.reader.read_exact(&mut packet)?;
+        Ok(packet)
+    }
+
+    /// Send a request of type `packet_type`, whose fields after the request id are added by
+    /// `build`, and wait for the response.
+    fn request(&mut self, packet_type: u8, build: impl Fn(Packet) -> Packet) -> IOResult<Response> {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.send(&build(Packet::new(packet_type, id)))?;
+
+        let packet = self.receive()?;
+        let mut reader = PacketReader(&packet);
+        let response_type = reader.u8()?;
+        if reader.u32()? != id {
+            return Err(protocol_error("response to the wrong request"));
+        }
+        Ok(match response_type {
+            SSH_FXP_STATUS => {
+                let code = reader.u32()?;
+                let msg = String::from_utf8_lossy(reader.string()?).into_owned();
+                Response::Status(code, msg)
+            }
+            SSH_FXP_HANDLE => Response::Handle(reader.string()?.to_vec()),
+            SSH_FXP_NAME => {
+                let count = reader.u32()?;
+                let mut names = vec![];
+                for _ in 0..count {
+                    let name = reader.string()?.to_vec();
+                    // the long name is like a line of 'ls -l', which is only meant for showing
+                    reader.string()?;
+                    names.push((name, reader.attrs()?));
+                }
+                Response::Name(names)
+            }
+            SSH_FXP_ATTRS => Response::Attrs(reader.attrs()?),
+            _ => return Err(protocol_error("unknown response type")),
+        })
+    }
+
+    fn realpath(&mut self, path: &[u8]) -> IOResult<Vec<u8>> {
+        match self.request(SSH_FXP_REALPATH, |p| p.string(path))? {
+            Response::Name(mut names) if names.len() == 1 => Ok(names.remove(0).0),
+            response => Err(response.into_error(path)),
+        }
+    }
+
+    /// The attributes of `path`, following symlinks
+    fn stat(&mut self, path: &[u8]) -> IOResult<Attrs> {
+        match self.request(SSH_FXP_STAT, |p| p.string(path))? {
+            Response::Attrs(attrs) => Ok(attrs),
+            response => Err(response.into_error(path)),
+        }
+    }
+
+    fn open_dir(&mut self, path: &[u8]) -> IOResult<Vec<u8>> {
+        match self.request(SSH_FXP_OPENDIR, |p| p.string(path))? {
+            Response::Handle(handle) => Ok(handle),
+            response => Err(response.into_error(path)),
+        }
+    }
+
+    /// Read the next entries of the open folder `handle`. Returns `None` at the end.
+    fn read_dir(&mut self, handle: &[u8]) -> IOResult<Option<Names>> {
+        match self.request(SSH_FXP_READDIR, |p| p.string(handle))? {
+            Response::Name(names) => Ok(Some(names)),
+            Response::Status(SSH_FX_EOF, _) => Ok(None),
+            response => Err(response.into_error(b"")),
+        }
+    }
+
+    fn close(&mut self, handle: &[u8]) -> IOResult<()> {
+        match self.request(SSH_FXP_CLOSE, |p| p.string(handle))? {
+            Response::Status(SSH_FX_OK, _) => Ok(()),
+            response => Err(response.into_error(b"")),
+        }
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        if let Some(child) = &mut self.child {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// The bytes of a remote path. The paths are always separated with slashes on the server.
+fn path_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    return std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    #[cfg(not(unix))]
+    return path.to_string_lossy().replace('\\', "/").into_bytes();
+}
+
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    #[cfg(unix)]
+    return PathBuf::from(OsString::from(
+        std::os::unix::ffi::OsStrExt::from_bytes(bytes) as &std::ffi::OsStr,
+    ));
+    #[cfg(not(unix))]
+    return PathBuf::from(OsString::from(String::from_utf8_lossy(bytes).into_owned()));
+}
+
+/// The folders of another machine, read over SFTP. Only one request is made at a time, so
+/// reading a folder in the background blocks reading other folders until it's done.
+pub struct SftpDirSource {
+    session: Arc<Mutex<Session>>,
+    destination: String,
+    home: PathBuf,
+}
+
+impl SftpDirSource {
+    /// Connect to the machine of `url` by running `ssh`. Returns the source and the absolute path
+    /// of the folder of the URL on the remote machine.
+    pub fn connect(url: &SshUrl) -> IOResult<(Self, PathBuf)> {
+        let mut cmd = Command::new("ssh");
+        if let Some(port) = url.port {
+            cmd.arg("-p").arg(port.to_string());
+        }
+        if let Some(user) = &url.user {
+            cmd.arg("-l").arg(user);
+        }
+        cmd.args(["-s", "--", &url.host, "sftp"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped());
+        let mut child = cmd.spawn().map_err(|e| {
+            IOError::new(e.kind(), format!("Could not run ssh: {}", e))
+        })?;
+        // ok to unwrap, both were piped above
+        let writer = child.stdin.take().unwrap();
+        let reader = BufReader::new(child.stdout.take().unwrap());
+        match Session::start(Box::new(reader), Box::new(writer)) {
+            Ok(mut session) => {
+                session.child = Some(child);
+                Self::from_session(session, url)
+            }
+            Err(e) => {
+                // ssh has printed the reason to stderr if it couldn't connect
+                let _ = child.kill();
+                let _ = child.wait();
+                Err(match e.kind() {
+                    ErrorKind::UnexpectedEof => {
+                        IOError::other(format!("Could not connect to '{}'", url.destination()))
+                    }
+                    _ => e,
+                })
+            }
+        }
+    }
+
+    fn from_session(mut session: Session, url: &SshUrl) -> IOResult<(Self, PathBuf)> {
+        let home = path_from_bytes(&session.realpath(b".")?);
+        let start = if url.path.is_empty() {
+            home.clone()
+        } else {
+            path_from_bytes(&session.realpath(url.path.as_bytes())?)
+        };
+        let ret = Self {
+            session: Arc::new(Mutex::new(session)),
+            destination: url.destination(),
+            home,
+        };
+        ret.enter_dir(&start)?;
+        Ok((ret, start))
+    }
+}
+
+/// The entries of an open remote folder, which is closed when this is dropped
+struct RemoteEntries {
+    session: Arc<Mutex<Session>>,
+    dir: PathBuf,
+    // `None` after the end of the folder
+    handle: Option<Vec<u8>>,
+}
+
+impl RemoteEntries {
+    fn close(&mut self) {
+        if let Some(handle) = self.handle.take() {
+            let _ = self.session.lock().unwrap().close(&handle);
+        }
+    }
+}
+
+impl Iterator for RemoteEntries {
+    type Item = Vec<CustomDirEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let handle = self.handle.as_ref()?;
+        let mut session = self.session.lock().unwrap();
+        let names = match session.read_dir(handle) {
+            Ok(Some(names)) => names,
+            // errors in the middle of a folder can't be reported, so just end it
+            Ok(None) | Err(_) => {
+                drop(session);
+                self.close();
+                return None;
+            }
+        };
+        let entries = names
+            .into_iter()
+            .filter(|(name, _)| name != b"." && name != b"..")
+            .map(|(name, attrs)| {
+                let path = self.dir.join(path_from_bytes(&name));
+                let is_dir = match attrs.file_type() {
+                    // the listing has the attributes of the symlink itself, so follow it
+                    Some(S_IFLNK) => session
+                        .stat(&path_bytes(&path))
+                        .is_ok_and(|attrs| attrs.file_type() == Some(S_IFDIR)),
+                    file_type => file_type == Some(S_IFDIR),
+                };
+                CustomDirEntry::new_virtual(path, is_dir)
+            })
+            .collect();
+        Some(entries)
+    }
+}
+
+impl Drop for RemoteEntries {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl DirSource for SftpDirSource {
+    fn read_dir(&self, path: &Path) -> IOResult<DirBatches> {
+        let handle = self.session.lock().unwrap().open_dir(&path_bytes(path))?;
+        Ok(Box::new(RemoteEntries {
+            session: self.session.clone(),
+            dir: path.to_path_buf(),
+            handle: Some(handle),
+        }))
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        let attrs = self.session.lock().unwrap().stat(&path_bytes(path)).ok()?;
+        attrs
+            .mtime
+            .map(|mtime| SystemTime::UNIX_EPOCH + Duration::from_secs(mtime.into()))
+    }
+
+    fn enter_dir(&self, path: &Path) -> IOResult<()> {
+        let attrs = self.session.lock().unwrap().stat(&path_bytes(path))?;
+        if attrs.file_type() == Some(S_IFDIR) {
+            Ok(())
+        } else {
+            Err(IOError::other(format!("Not a folder: '{}'", path.display())))
+        }
+    }
+
+    fn host(&self) -> Option<&str> {
+        Some(&self.destination)
+    }
+
+    fn home_dir(&self) -> Option<PathBuf> {
+        Some(self.home.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{HashMap, VecDeque};
+
+    #[test]
+    fn test_parse_ssh_url() {
+        assert_eq!(
+            SshUrl::parse("ssh://me@example.com:2222/srv/www"),
+            Some(SshUrl {
+                user: Some("me".into()),
+                host: "example.com".into(),
+                port: Some(2222),
+                path: "/srv/www".into(),
+            })
+        );
+        let url = SshUrl::parse("sftp://example.com").unwrap();
+        assert_eq!((url.user, url.port, url.path.as_str()), (None, None, ""));
+        assert_eq!(SshUrl::parse("ssh://host/~").unwrap().path, "");
+        assert_eq!(SshUrl::parse("ssh://host/~/src").unwrap().path, "src");
+        assert_eq!(SshUrl::parse("ssh://host/").unwrap().path, "/");
+        let url = SshUrl::parse("ssh://me@[::1]:22/tmp").unwrap();
+        assert_eq!((url.host.as_str(), url.port), ("::1", Some(22)));
+        assert_eq!(url.destination(), "me@::1");
+
+        assert_eq!(SshUrl::parse("/home/me"), None);
+        assert_eq!(SshUrl::parse("ssh:///path"), None);
+        assert_eq!(SshUrl::parse("ssh://host:port/"), None);
+        assert_eq!(SshUrl::parse("ssh://-oProxyCommand=x/"), None);
+    }
+
+    /// A minimal SFTP server for a fixed folder tree, which answers each request as soon as it
+    /// has been written
+    struct FakeServer {
+        // The entries of each folder, and whether they are folders
+        dirs: HashMap<&'static str, Vec<(&'static str, bool)>>,
+        // The folders that are open, by handle
+        open: HashMap<Vec<u8>, bool>,
+        input: Vec<u8>,
+        output: Arc<Mutex<VecDeque<u8>>>,
+    }
+
+    impl FakeServer {
+        fn respond(&mut self, packet: &[u8]) -> Vec<u8> {
+            let mut reader = PacketReader(packet);
+            let packet_type = reader.u8().unwrap();
+            if packet_type == SSH_FXP_INIT {
+                return Packet(vec![SSH_FXP_VERSION]).u32(3).0;
+            }
+            let id = reader.u32().unwrap();
+            let arg = std::str::from_utf8(reader.string().unwrap()).unwrap().to_string();
+            let status = |code| Packet::new(SSH_FXP_STATUS, id).u32(code).string(b"").string(b"");
+            let dir_attrs = |p: Packet, is_dir: bool| {
+                let permissions = if is_dir { S_IFDIR | 0o755 } else { 0o100644 };
+                p.u32(SSH_FILEXFER_ATTR_PERMISSIONS | SSH_FILEXFER_ATTR_ACMODTIME)
+                    .u32(permissions)
+                    .u32(0)
+                    .u32(1000)
+            };
+            let ret = match packet_type {
+                SSH_FXP_REALPATH => {
+                    let path = if arg == "." { "/home/me".into() } else { arg };
+                    let p = Packet::new(SSH_FXP_NAME, id).u32(1).string(path.as_bytes());
+                    p.string(b"").u32(0)
+                }
+                SSH_FXP_STAT => match self.dirs.contains_key(arg.as_str()) {
+                    true => dir_attrs(Packet::new(SSH_FXP_ATTRS, id), true),
+                    false if arg.ends_with(".txt") => dir_attrs(Packet::new(SSH_FXP_ATTRS, id), false),
+                    false => status(SSH_FX_NO_SUCH_FILE),
+                },
+                SSH_FXP_OPENDIR => match self.dirs.contains_key(arg.as_str()) {
+                    true => {
+                        self.open.insert(arg.as_bytes().to_vec(), false);
+                        Packet::new(SSH_FXP_HANDLE, id).string(arg.as_bytes())
+                    }
+                    false => status(SSH_FX_NO_SUCH_FILE),
+                },
+                SSH_FXP_READDIR => match self.open.insert(arg.as_bytes().to_vec(), true) {
+                    Some(false) => {
+                        let entries = &self.dirs[arg.as_str()];
+                        let mut p = Packet::new(SSH_FXP_NAME, id).u32(entries.len() as u32 + 1);
+                        p = dir_attrs(p.string(b".").string(b""), true);
+                        for (name, is_dir) in entries {
+                            p = dir_attrs(p.string(name.as_bytes()).string(b""), *is_dir);
+                        }
+                        p
+                    }
+                    _ => status(SSH_FX_EOF),
+                },
+                SSH_FXP_CLOSE => {
+                    self.open.remove(arg.as_bytes());
+                    status(SSH_FX_OK)
+                }
+                _ => status(8),
+            };
+            ret.0
+        }
+    }
+
+    impl Write for FakeServer {
+        fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+            self.input.extend_from_slice(buf);
+            while self.input.len() >= 4 {
+                let len = u32::from_be_bytes(self.input[..4].try_into().unwrap()) as usize;
+                if self.input.len() < 4 + len {
+                    break;
+                }
+                let packet: Vec<u8> = self.input.drain(..4 + len).skip(4).collect();
+                let response = self.respond(&packet);
+                let mut output = self.output.lock().unwrap();
+                output.extend((response.len() as u32).to_be_bytes());
+                output.extend(response);
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    struct FakeServerOutput(Arc<Mutex<VecDeque<u8>>>);
+
+    impl Read for FakeServerOutput {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            self.0.lock().unwrap().read(buf)
+        }
+    }
+
+    fn fake_source(url: &str) -> IOResult<(SftpDirSource, PathBuf)> {
+        let output = Arc::new(Mutex::new(VecDeque::new()));
+        let server = FakeServer {
+            dirs: HashMap::from([
+                ("/", vec![("home", true)]),
+                ("/home", vec![("me", true)]),
+                ("/home/me", vec![("src", true), ("notes.txt", false)]),
+                ("/home/me/src", vec![]),
+            ]),
+            open: HashMap::new(),
+            input: vec![],
+            output: output.clone(),
+        };
+        let session = Session::start(Box::new(FakeServerOutput(output)), Box::new(server))?;
+        SftpDirSource::from_session(session, &SshUrl::parse(url).unwrap())
+    }
+
+    #[test]
+    fn test_sftp_dir_source() {
+        let (source, start) = fake_source("ssh://me@host").unwrap();
+        assert_eq!(start, PathBuf::from("/home/me"));
+        assert_eq!(source.host(), Some("me@host"));
+        assert_eq!(source.home_dir(), Some(PathBuf::from("/home/me")));
+
+        let mut entries: Vec<_> = source
+            .read_dir(&start)
+            .unwrap()
+            .flatten()
+            .map(|e| (e.file_name_checked(), e.is_dir()))
+            .collect();
+        entries.sort();
+        assert_eq!(entries, vec![("notes.txt".into(), false), ("src".into(), true)]);
+
+        assert!(source.enter_dir(Path::new("/home/me/src")).is_ok());
+        assert!(source.enter_dir(Path::new("/home/me/notes.txt")).is_err());
+        let err = source.read_dir(Path::new("/nonexistent")).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::NotFound);
+        assert_eq!(
+            source.modified(Path::new("/home")),
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(1000))
+        );
+
+        let (_, start) = fake_source("ssh://host/home").unwrap();
+        assert_eq!(start, PathBuf::from("/home"));
+        assert!(fake_source("ssh://host/nonexistent").is_err());
+    }
+}
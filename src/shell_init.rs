@@ -0,0 +1,82 @@
+//! Shell integration scripts, printed by `tere init <shell>`. The scripts define a function called
+//! `tere`, which runs the binary and `cd`s to the folder that it prints, if it exits successfully.
+
+pub const SUPPORTED_SHELLS: [&str; 7] =
+    ["bash", "zsh", "fish", "nushell", "powershell", "xonsh", "tmux"];
+
+const BASH_ZSH_SCRIPT: &str = r#"tere() {
+    local result
+    result=$(command tere "$@")
+    [ $? -eq 0 ] && [ -n "$result" ] && cd -- "$result"
+}
+"#;
+
+const FISH_SCRIPT: &str = r#"function tere
+    set --local result (command tere $argv)
+    and test -n "$result"
+    and cd -- "$result"
+end
+"#;
+
+// Nushell can't capture stdout without also capturing stderr using 'complete', which would break
+// the UI, so use 'try' to catch the error raised when tere exits with a nonzero exit code.
+const NUSHELL_SCRIPT: &str = r#"def --env --wrapped tere [...args] {
+    let result = try { ^tere ...$args | str trim } catch { "" }
+    if ($result | is-not-empty) {
+        cd $result
+    }
+}
+"#;
+
+// The function is called through an alias, so we have to explicitly look up the executable to
+// not call the alias recursively.
+const POWERSHELL_SCRIPT: &str = r#"function Invoke-Tere() {
+    $tere_exe = Get-Command -CommandType Application tere | Select-Object -First 1
+    $result = & $tere_exe @args
+    if ($LASTEXITCODE -eq 0 -and $result) {
+        Set-Location -LiteralPath $result
+    }
+}
+Set-Alias tere Invoke-Tere
+"#;
+
+// Same as for powershell, use shutil.which to find the executable instead of the alias.
+const XONSH_SCRIPT: &str = r#"def _tere(args):
+    import shutil
+    result = $(@(shutil.which("tere")) @(args)).strip()
+    if $LAST_RETURN_CODE == 0 and result:
+        cd @(result)
+
+aliases["tere"] = _tere
+"#;
+
+// Not a shell, but a key binding for .tmux.conf that opens tere in a popup, and then changes the
+// folder of the pane where it was opened.
+const TMUX_SCRIPT: &str = r##"bind-key T display-popup -E -w 80% -h 80% -d "#{pane_current_path}" "tere --tmux-send-keys '#{pane_id}'"
+"##;
+
+/// Get the init script for the given shell, or `None` if the shell is not supported.
+pub fn init_script(shell: &str) -> Option<&'static str> {
+    match shell {
+        "bash" | "zsh" => Some(BASH_ZSH_SCRIPT),
+        "fish" => Some(FISH_SCRIPT),
+        "nushell" => Some(NUSHELL_SCRIPT),
+        "powershell" => Some(POWERSHELL_SCRIPT),
+        "xonsh" => Some(XONSH_SCRIPT),
+        "tmux" => Some(TMUX_SCRIPT),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_all_shells_have_script() {
+        for shell in SUPPORTED_SHELLS {
+            assert!(init_script(shell).is_some(), "no script for {}", shell);
+        }
+        assert!(init_script("foo").is_none());
+    }
+}
@@ -0,0 +1,67 @@
+//! Helpers shared by the unit tests.
+
+use std::io::Result as IOResult;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use crate::app_state::{DirBatches, DirSource, FsDirSource};
+
+/// A new empty folder for a test, which is removed when this is dropped. The name of the folder
+/// includes the process ID and a counter, so that tests running at the same time, in this or
+/// another process, never get the same folder.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+        let path = std::env::temp_dir().join(format!(
+            "tere-test-{}-{}-{}",
+            name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed),
+        ));
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The file system, like `FsDirSource`, except that entering a folder doesn't change the working
+/// directory, which is shared by all tests running in parallel.
+pub struct TestFsDirSource;
+
+impl DirSource for TestFsDirSource {
+    fn read_dir(&self, path: &Path) -> IOResult<DirBatches> {
+        FsDirSource.read_dir(path)
+    }
+
+    fn modified(&self, path: &Path) -> Option<SystemTime> {
+        FsDirSource.modified(path)
+    }
+
+    fn enter_dir(&self, path: &Path) -> IOResult<()> {
+        std::fs::read_dir(path).map(|_| ())
+    }
+}
@@ -0,0 +1,599 @@
+/// Actions that can be bound to keys, and the key map that maps key combinations to actions.
+use std::fmt;
+use std::str::FromStr;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    CursorUp,
+    CursorDown,
+    ChangeDir,
+    ChangeDirParent,
+    ChangeDirAndExit,
+    Exit,
+    ExitWithoutCd,
+    ChangeDirHome,
+    ChangeDirRoot,
+    RefreshListing,
+    GoToPath,
+    NewTab,
+    CloseTab,
+    NextTab,
+    PreviousTab,
+    CursorUpScreen,
+    CursorDownScreen,
+    CursorUpHalfScreen,
+    CursorDownHalfScreen,
+    CursorTop,
+    CursorBottom,
+    EraseSearchChar,
+    ClearSearch,
+    ChangeCaseSensitiveMode,
+    ChangeGapSearchMode,
+    ChangeSortMode,
+    ReverseSort,
+    ToggleHidden,
+    ToggleFoldersOnly,
+    CycleFilterPreset,
+    ViewMenu,
+    ToggleBrowseMode,
+    JumpToLabel,
+    ToggleMouseCapture,
+    ToggleMark,
+    ClearMarks,
+    NextMark,
+    PreviousMark,
+    CopyPath,
+    CopyItemPath,
+    SpawnShell,
+    OpenInEditor,
+    OpenWithDefaultApp,
+    OpenFileManager,
+    CreateDir,
+    CreateDirAndEnter,
+    CreateFile,
+    Rename,
+    Delete,
+    CopyMarkedHere,
+    MoveMarkedHere,
+    LinkMarkedHere,
+    Help,
+    CheatSheet,
+    ShowLastError,
+    CommandPalette,
+    /// Run the user-defined command with this index, see the `--run` option. This is not listed
+    /// in `all()`, since it can't be bound with `--map`.
+    RunCommand(usize),
+    /// Call the script function with this index, see the `--script` option. Like `RunCommand`,
+    /// this is not listed in `all()`.
+    RunScript(usize),
+    /// Run the named command with this index, see the `--command` option. It can be bound with
+    /// `--map` by its name, but it's not listed in `all()` either.
+    NamedCommand(usize),
+}
+
+impl Action {
+    /// All actions, in the order in which they are listed in the help screen.
+    pub fn all() -> &'static [Action] {
+        use Action::*;
+        &[
+            CursorUp,
+            CursorDown,
+            ChangeDir,
+            ChangeDirParent,
+            ChangeDirAndExit,
+            Exit,
+            ExitWithoutCd,
+            ChangeDirHome,
+            ChangeDirRoot,
+            RefreshListing,
+            GoToPath,
+            NewTab,
+            CloseTab,
+            NextTab,
+            PreviousTab,
+            CursorUpScreen,
+            CursorDownScreen,
+            CursorUpHalfScreen,
+            CursorDownHalfScreen,
+            CursorTop,
+            CursorBottom,
+            EraseSearchChar,
+            ClearSearch,
+            ChangeCaseSensitiveMode,
+            ChangeGapSearchMode,
+            ChangeSortMode,
+            ReverseSort,
+            ToggleHidden,
+            ToggleFoldersOnly,
+            CycleFilterPreset,
+            ViewMenu,
+            ToggleBrowseMode,
+            JumpToLabel,
+            ToggleMouseCapture,
+            ToggleMark,
+            ClearMarks,
+            NextMark,
+            PreviousMark,
+            CopyPath,
+            CopyItemPath,
+            SpawnShell,
+            OpenInEditor,
+            OpenWithDefaultApp,
+            OpenFileManager,
+            CreateDir,
+            CreateDirAndEnter,
+            CreateFile,
+            Rename,
+            Delete,
+            CopyMarkedHere,
+            MoveMarkedHere,
+            LinkMarkedHere,
+            Help,
+            CheatSheet,
+            ShowLastError,
+            CommandPalette,
+        ]
+    }
+
+    /// The name of the action, as used in the `--map` option.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::CursorUp                => "cursor-up",
+            Action::CursorDown              => "cursor-down",
+            Action::ChangeDir               => "change-dir",
+            Action::ChangeDirParent         => "change-dir-parent",
+            Action::ChangeDirAndExit        => "change-dir-and-exit",
+            Action::Exit                    => "exit",
+            Action::ExitWithoutCd           => "exit-without-cd",
+            Action::ChangeDirHome           => "change-dir-home",
+            Action::ChangeDirRoot           => "change-dir-root",
+            Action::RefreshListing          => "refresh-listing",
+            Action::GoToPath                => "go-to-path",
+            Action::NewTab                  => "new-tab",
+            Action::CloseTab                => "close-tab",
+            Action::NextTab                 => "next-tab",
+            Action::PreviousTab             => "previous-tab",
+            Action::CursorUpScreen          => "cursor-up-screen",
+            Action::CursorDownScreen        => "cursor-down-screen",
+            Action::CursorUpHalfScreen      => "cursor-up-half-screen",
+            Action::CursorDownHalfScreen    => "cursor-down-half-screen",
+            Action::CursorTop               => "cursor-top",
+            Action::CursorBottom            => "cursor-bottom",
+            Action::EraseSearchChar         => "erase-search-char",
+            Action::ClearSearch             => "clear-search",
+            Action::ChangeCaseSensitiveMode => "change-case-sensitive-mode",
+            Action::ChangeGapSearchMode     => "change-gap-search-mode",
+            Action::ChangeSortMode          => "change-sort-mode",
+            Action::ReverseSort             => "reverse-sort",
+            Action::ToggleHidden            => "toggle-hidden",
+            Action::ToggleFoldersOnly       => "toggle-folders-only",
+            Action::CycleFilterPreset       => "cycle-filter-preset",
+            Action::ViewMenu                => "view-menu",
+            Action::ToggleBrowseMode        => "toggle-browse-mode",
+            Action::JumpToLabel             => "jump-to-label",
+            Action::ToggleMouseCapture      => "toggle-mouse-capture",
+            Action::ToggleMark              => "toggle-mark",
+            Action::ClearMarks              => "clear-marks",
+            Action::NextMark                => "next-mark",
+            Action::PreviousMark            => "previous-mark",
+            Action::CopyPath                => "copy-path",
+            Action::CopyItemPath            => "copy-item-path",
+            Action::SpawnShell              => "spawn-shell",
+            Action::OpenInEditor            => "open-in-editor",
+            Action::OpenWithDefaultApp      => "open-with-default-app",
+            Action::OpenFileManager         => "open-file-manager",
+            Action::CreateDir               => "create-dir",
+            Action::CreateDirAndEnter       => "create-dir-and-enter",
+            Action::CreateFile              => "create-file",
+            Action::Rename                  => "rename",
+            Action::Delete                  => "delete",
+            Action::CopyMarkedHere          => "copy-marked-here",
+            Action::MoveMarkedHere          => "move-marked-here",
+            Action::LinkMarkedHere          => "link-marked-here",
+            Action::Help                    => "help",
+            Action::CheatSheet              => "cheat-sheet",
+            Action::ShowLastError           => "show-last-error",
+            Action::CommandPalette          => "command-palette",
+            Action::RunCommand(_)           => "run-command",
+            Action::RunScript(_)            => "run-script",
+            Action::NamedCommand(_)         => "named-command",
+        }
+    }
+
+    /// A human-readable description of the action, shown in the help screen.
+    pub fn description(&self) -> &'static str {
+        match self {
+            Action::CursorUp                => "Move cursor up",
+            Action::CursorDown              => "Move cursor down",
+            Action::ChangeDir               => "Enter directory",
+            Action::ChangeDirParent         => "Go to parent directory",
+            Action::ChangeDirAndExit        => "Enter directory and exit `tere`",
+            Action::Exit                    => "Exit `tere`",
+            Action::ExitWithoutCd           => "Exit `tere` without changing directory",
+            Action::ChangeDirHome           => "Go to home directory",
+            Action::ChangeDirRoot           => "Go to root directory",
+            Action::RefreshListing          => "Refresh current directory",
+            Action::GoToPath                => "Type a path to go to",
+            Action::NewTab                  => "Open a new tab",
+            Action::CloseTab                => "Close current tab",
+            Action::NextTab                 => "Go to next tab",
+            Action::PreviousTab             => "Go to previous tab",
+            Action::CursorUpScreen          => "Move cursor up by one screen",
+            Action::CursorDownScreen        => "Move cursor down by one screen",
+            Action::CursorUpHalfScreen      => "Move cursor up by half a screen",
+            Action::CursorDownHalfScreen    => "Move cursor down by half a screen",
+            Action::CursorTop               => "Move cursor to the top",
+            Action::CursorBottom            => "Move cursor to the bottom",
+            Action::EraseSearchChar         => "Erase a search character",
+            Action::ClearSearch             => "Clear the search",
+            Action::ChangeCaseSensitiveMode => "Change case sensitivity mode",
+            Action::ChangeGapSearchMode     => "Change gap search mode",
+            Action::ChangeSortMode          => "Change sort order of current folder",
+            Action::ReverseSort             => "Reverse sort order of current folder",
+            Action::ToggleHidden            => "Show or hide hidden items in current folder",
+            Action::ToggleFoldersOnly       => "Show only folders or all items in current folder",
+            Action::CycleFilterPreset       => "Switch to the next filter preset",
+            Action::ViewMenu                => "Choose sort order and view options from a menu",
+            Action::ToggleBrowseMode        => "Toggle browse mode, where letters navigate instead of searching",
+            Action::JumpToLabel             => "Show labels on the items, and jump to the one whose label is typed",
+            Action::ToggleMouseCapture      => "Toggle mouse capture",
+            Action::ToggleMark              => "Mark or unmark item for output",
+            Action::ClearMarks              => "Unmark all items",
+            Action::NextMark                => "Move to the next marked item",
+            Action::PreviousMark            => "Move to the previous marked item",
+            Action::CopyPath                => "Copy current folder path to clipboard",
+            Action::CopyItemPath            => "Copy path of item under cursor to clipboard",
+            Action::SpawnShell              => "Open a shell in the current folder",
+            Action::OpenInEditor            => "Open item under cursor in editor",
+            Action::OpenWithDefaultApp      => "Open item under cursor with default app",
+            Action::OpenFileManager         => "Open current folder in file manager",
+            Action::CreateDir               => "Create a new folder",
+            Action::CreateDirAndEnter       => "Create a new folder and enter it",
+            Action::CreateFile              => "Create an empty file",
+            Action::Rename                  => "Rename item under cursor",
+            Action::Delete                  => "Move marked items or item under cursor to trash",
+            Action::CopyMarkedHere          => "Copy marked items to current folder",
+            Action::MoveMarkedHere          => "Move marked items to current folder",
+            Action::LinkMarkedHere          => "Create symlinks to marked items in current folder",
+            Action::Help                    => "Show help screen",
+            Action::CheatSheet              => "Show a short cheat sheet",
+            Action::ShowLastError           => "Show the last error message in full",
+            Action::CommandPalette          => "Find and run any action",
+            Action::RunCommand(_)           => "Run a custom command",
+            Action::RunScript(_)            => "Run a script function",
+            Action::NamedCommand(_)         => "Run a named command",
+        }
+    }
+
+    /// Whether the action creates, renames or deletes files, which can be disabled with the
+    /// `--no-file-ops` option.
+    pub fn modifies_files(&self) -> bool {
+        matches!(
+            self,
+            Action::CreateDir
+                | Action::CreateDirAndEnter
+                | Action::CreateFile
+                | Action::Rename
+                | Action::Delete
+                | Action::CopyMarkedHere
+                | Action::MoveMarkedHere
+                | Action::LinkMarkedHere
+        )
+    }
+
+    /// Whether the action works with the files on this machine, so that it can't be done in
+    /// folders on other machines or inside archives. This includes the actions that modify files.
+    pub fn uses_local_files(&self) -> bool {
+        self.modifies_files()
+            || matches!(
+                self,
+                Action::SpawnShell
+                    | Action::OpenInEditor
+                    | Action::OpenWithDefaultApp
+                    | Action::OpenFileManager
+                    | Action::RunCommand(_)
+            )
+    }
+
+    /// Whether repeating the action many times in a row can be done all at once, drawing the
+    /// result only once. This is the case for moving the cursor and erasing the search.
+    pub fn can_coalesce(&self) -> bool {
+        matches!(
+            self,
+            Action::CursorUp
+                | Action::CursorDown
+                | Action::CursorUpScreen
+                | Action::CursorDownScreen
+                | Action::CursorUpHalfScreen
+                | Action::CursorDownHalfScreen
+                | Action::EraseSearchChar
+        )
+    }
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::all()
+            .iter()
+            .find(|a| a.name() == s)
+            .copied()
+            .ok_or_else(|| format!("unknown action '{}'", s))
+    }
+}
+
+/// The situation in which a key binding applies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ActionContext {
+    /// The binding applies always, unless overridden by a more specific context
+    Any,
+    /// The binding applies only while searching
+    Searching,
+    /// The binding applies only when not searching
+    NotSearching,
+}
+
+impl FromStr for ActionContext {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "any" => Ok(Self::Any),
+            "searching" => Ok(Self::Searching),
+            "not-searching" => Ok(Self::NotSearching),
+            s => Err(format!("unknown context '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ActionContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            ActionContext::Any          => "",
+            ActionContext::Searching    => "if searching",
+            ActionContext::NotSearching => "if not searching",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+pub struct KeyBinding {
+    pub key: KeyEvent,
+    pub context: ActionContext,
+    pub action: Action,
+}
+
+/// Mapping from key combinations to actions. The bindings are kept in the order in which they
+/// were added, so that the help screen can list the most common shortcut of each action first.
+#[derive(Default)]
+pub struct KeyMap {
+    bindings: Vec<KeyBinding>,
+}
+
+impl KeyMap {
+    /// Get the action bound to `key` in the given context. Bindings for the specific context take
+    /// precedence over bindings for `ActionContext::Any`.
+    pub fn get(&self, key: &KeyEvent, context: ActionContext) -> Option<Action> {
+        let key = normalize_key(*key);
+        let find = |ctx: ActionContext| {
+            self.bindings
+                .iter()
+                .find(|b| b.key == key && b.context == ctx)
+                .map(|b| b.action)
+        };
+        find(context).or_else(|| find(ActionContext::Any))
+    }
+
+    /// Bind `key` to `action` in the given context, replacing any previous binding. If the
+    /// context is `ActionContext::Any`, the bindings of the key in all contexts are replaced.
+    pub fn bind(&mut self, key: KeyEvent, context: ActionContext, action: Action) {
+        self.unbind(key, context);
+        self.bindings.push(KeyBinding {
+            key: normalize_key(key),
+            context,
+            action,
+        });
+    }
+
+    /// Remove the binding of `key` in the given context. If the context is `ActionContext::Any`,
+    /// the bindings in all contexts are removed.
+    pub fn unbind(&mut self, key: KeyEvent, context: ActionContext) {
+        let key = normalize_key(key);
+        self.bindings.retain(|b| {
+            b.key != key || (context != ActionContext::Any && b.context != context)
+        });
+    }
+
+    /// All bindings of a given action.
+    pub fn bindings_for(&self, action: Action) -> impl Iterator<Item = &KeyBinding> {
+        self.bindings.iter().filter(move |b| b.action == action)
+    }
+}
+
+/// Terminals don't agree on whether the shift modifier is reported for characters like '?' or
+/// '~', or for uppercase letters, so shifted letters are stored as uppercase letters and the shift
+/// modifier is ignored for all characters.
+fn normalize_key(mut key: KeyEvent) -> KeyEvent {
+    if let KeyCode::Char(c) = key.code {
+        if key.modifiers.contains(KeyModifiers::SHIFT) {
+            key.code = KeyCode::Char(c.to_uppercase().next().unwrap_or(c));
+        }
+        key.modifiers.remove(KeyModifiers::SHIFT);
+    }
+    key
+}
+
+/// Parse a key combination like "ctrl-alt-h", "alt-G" (or equivalently "alt-shift-g"), "enter"
+/// or "-".
+pub fn parse_key_combination(s: &str) -> Result<KeyEvent, String> {
+    // handle the '-' key separately, since it's also used as the separator
+    let (modifiers_str, key_str) = if s == "-" {
+        ("", "-")
+    } else if let Some(mods) = s.strip_suffix("--") {
+        (mods, "-")
+    } else {
+        s.rsplit_once('-').unwrap_or(("", s))
+    };
+
+    let mut modifiers = KeyModifiers::empty();
+    for m in modifiers_str.split('-').filter(|m| !m.is_empty()) {
+        modifiers.insert(match m.to_lowercase().as_str() {
+            "ctrl" | "control" => KeyModifiers::CONTROL,
+            "alt" => KeyModifiers::ALT,
+            "shift" => KeyModifiers::SHIFT,
+            _ => return Err(format!("unknown modifier '{}' in '{}'", m, s)),
+        });
+    }
+
+    let mut chars = key_str.chars();
+    let code = match (chars.next(), chars.next()) {
+        (Some(c), None) => KeyCode::Char(c),
+        _ => match key_str.to_lowercase().as_str() {
+            "enter" | "return" => KeyCode::Enter,
+            "esc" | "escape" => KeyCode::Esc,
+            "backspace" => KeyCode::Backspace,
+            "tab" => KeyCode::Tab,
+            "backtab" => KeyCode::BackTab,
+            "space" => KeyCode::Char(' '),
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "delete" | "del" => KeyCode::Delete,
+            "insert" => KeyCode::Insert,
+            k => match k.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+                Some(n) if (1..=12).contains(&n) => KeyCode::F(n),
+                _ => return Err(format!("unknown key '{}' in '{}'", key_str, s)),
+            },
+        },
+    };
+
+    // use uppercase letters instead of a lowercase letter + shift
+    Ok(normalize_key(KeyEvent::new(code, modifiers)))
+}
+
+/// Format a key combination for displaying it to the user, for example "Ctrl+Home" or "Alt+k".
+pub fn key_to_string(key: &KeyEvent) -> String {
+    let mut modifiers = key.modifiers;
+    let code = match key.code {
+        KeyCode::Char(c) if c.is_uppercase() => {
+            modifiers.insert(KeyModifiers::SHIFT);
+            c.to_lowercase().to_string()
+        }
+        KeyCode::Char(' ') => "Space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::Up => "↑".to_string(),
+        KeyCode::Down => "↓".to_string(),
+        KeyCode::Left => "←".to_string(),
+        KeyCode::Right => "→".to_string(),
+        KeyCode::PageUp => "Page Up".to_string(),
+        KeyCode::PageDown => "Page Down".to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        c => format!("{:?}", c),
+    };
+
+    let mut ret = String::new();
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        ret.push_str("Ctrl+");
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        ret.push_str("Alt+");
+    }
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        ret.push_str("Shift+");
+    }
+    ret.push_str(&code);
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_combination() {
+        let ctrl_alt = KeyModifiers::CONTROL | KeyModifiers::ALT;
+        assert_eq!(
+            parse_key_combination("ctrl-alt-h"),
+            Ok(KeyEvent::new(KeyCode::Char('h'), ctrl_alt))
+        );
+        assert_eq!(
+            parse_key_combination("alt-shift-g"),
+            Ok(KeyEvent::new(KeyCode::Char('G'), KeyModifiers::ALT))
+        );
+        assert_eq!(
+            parse_key_combination("alt-G"),
+            parse_key_combination("alt-shift-g")
+        );
+        assert_eq!(parse_key_combination("-"), Ok(KeyCode::Char('-').into()));
+        assert_eq!(
+            parse_key_combination("alt--"),
+            Ok(KeyEvent::new(KeyCode::Char('-'), KeyModifiers::ALT))
+        );
+        assert_eq!(parse_key_combination("PageUp"), Ok(KeyCode::PageUp.into()));
+        assert_eq!(parse_key_combination("f3"), Ok(KeyCode::F(3).into()));
+        assert!(parse_key_combination("hyper-x").is_err());
+        assert!(parse_key_combination("foo").is_err());
+    }
+
+    #[test]
+    fn test_key_to_string() {
+        let key = |s| key_to_string(&parse_key_combination(s).unwrap());
+        assert_eq!(key("ctrl-home"), "Ctrl+Home");
+        assert_eq!(key("alt-shift-g"), "Alt+Shift+g");
+        assert_eq!(key("space"), "Space");
+        assert_eq!(key("up"), "↑");
+        assert_eq!(key("?"), "?");
+    }
+
+    #[test]
+    fn test_keymap_contexts() {
+        let mut keymap = KeyMap::default();
+        let esc: KeyEvent = KeyCode::Esc.into();
+        keymap.bind(esc, ActionContext::Searching, Action::ClearSearch);
+        keymap.bind(esc, ActionContext::Any, Action::Exit);
+        // binding in 'Any' context replaces all other contexts
+        assert_eq!(keymap.get(&esc, ActionContext::Searching), Some(Action::Exit));
+
+        keymap.bind(esc, ActionContext::Searching, Action::ClearSearch);
+        assert_eq!(keymap.get(&esc, ActionContext::Searching), Some(Action::ClearSearch));
+        assert_eq!(keymap.get(&esc, ActionContext::NotSearching), Some(Action::Exit));
+
+        keymap.unbind(esc, ActionContext::Searching);
+        assert_eq!(keymap.get(&esc, ActionContext::Searching), Some(Action::Exit));
+        keymap.unbind(esc, ActionContext::Any);
+        assert_eq!(keymap.get(&esc, ActionContext::NotSearching), None);
+    }
+
+    #[test]
+    fn test_keymap_ignores_shift_for_symbols() {
+        let mut keymap = KeyMap::default();
+        keymap.bind(KeyCode::Char('?').into(), ActionContext::Any, Action::Help);
+        let shifted = KeyEvent::new(KeyCode::Char('?'), KeyModifiers::SHIFT);
+        assert_eq!(keymap.get(&shifted, ActionContext::Any), Some(Action::Help));
+    }
+
+    #[test]
+    fn test_keymap_shifted_letters() {
+        let alt_shift = KeyModifiers::ALT | KeyModifiers::SHIFT;
+        let mut keymap = KeyMap::default();
+        keymap.bind(KeyEvent::new(KeyCode::Char('G'), KeyModifiers::ALT), ActionContext::Any, Action::CursorBottom);
+        keymap.bind(parse_key_combination("alt-shift-h").unwrap(), ActionContext::Any, Action::ChangeDirHome);
+
+        // this is what crossterm reports for Alt+Shift+g on Unix
+        let key = KeyEvent::new(KeyCode::Char('G'), alt_shift);
+        assert_eq!(keymap.get(&key, ActionContext::Any), Some(Action::CursorBottom));
+        let key = KeyEvent::new(KeyCode::Char('g'), alt_shift);
+        assert_eq!(keymap.get(&key, ActionContext::Any), Some(Action::CursorBottom));
+        let key = KeyEvent::new(KeyCode::Char('H'), alt_shift);
+        assert_eq!(keymap.get(&key, ActionContext::Any), Some(Action::ChangeDirHome));
+        assert_eq!(keymap.get(&KeyEvent::new(KeyCode::Char('g'), KeyModifiers::ALT), ActionContext::Any), None);
+    }
+}
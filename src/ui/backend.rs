@@ -0,0 +1,353 @@
+/// The terminal that the UI is drawn on. Normally this is stderr, but the UI can also be drawn on
+/// a `TestBackend`, which keeps the screen contents in memory so that tests can check what was
+/// drawn, without a real terminal.
+use std::io::{Result as IOResult, Stderr, Write};
+
+use crossterm::{style::Color, terminal};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// Something that crossterm commands can be written to, and that knows its size
+pub trait Backend: Write {
+    /// The size of the terminal as (columns, rows)
+    fn size(&self) -> IOResult<(u16, u16)>;
+}
+
+impl Backend for Stderr {
+    fn size(&self) -> IOResult<(u16, u16)> {
+        terminal::size()
+    }
+}
+
+/// The style of a cell of a `TestBackend`. `None` colors are the default colors of the terminal.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CellStyle {
+    pub bold: bool,
+    pub dim: bool,
+    pub underlined: bool,
+    pub reversed: bool,
+    pub fg: Option<Color>,
+    pub bg: Option<Color>,
+}
+
+/// A cell of a `TestBackend`, which holds one grapheme cluster.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Cell {
+    pub symbol: String,
+    pub style: CellStyle,
+}
+
+impl Default for Cell {
+    fn default() -> Self {
+        Self {
+            symbol: " ".into(),
+            style: CellStyle::default(),
+        }
+    }
+}
+
+/// A terminal that only exists in memory. It understands the escape sequences that the UI uses,
+/// that is moving the cursor, clearing, and setting attributes and colors, and ignores the rest.
+/// Each grapheme cluster takes one cell, which is also how the UI measures text.
+pub struct TestBackend {
+    width: u16,
+    height: u16,
+    cells: Vec<Cell>,
+    cursor: (u16, u16),
+    style: CellStyle,
+    // The start of an escape sequence or a character that hasn't been completely written yet
+    pending: Vec<u8>,
+}
+
+/// The color of an SGR color code, for example 5;8 for dark grey
+fn parse_color(params: &[u16]) -> Option<Color> {
+    const NAMED: [Color; 16] = [
+        Color::Black,
+        Color::DarkRed,
+        Color::DarkGreen,
+        Color::DarkYellow,
+        Color::DarkBlue,
+        Color::DarkMagenta,
+        Color::DarkCyan,
+        Color::Grey,
+        Color::DarkGrey,
+        Color::Red,
+        Color::Green,
+        Color::Yellow,
+        Color::Blue,
+        Color::Magenta,
+        Color::Cyan,
+        Color::White,
+    ];
+    match params {
+        [5, n, ..] => Some(
+            NAMED
+                .get(usize::from(*n))
+                .copied()
+                .unwrap_or(Color::AnsiValue(*n as u8)),
+        ),
+        [2, r, g, b, ..] => Some(Color::Rgb {
+            r: *r as u8,
+            g: *g as u8,
+            b: *b as u8,
+        }),
+        _ => None,
+    }
+}
+
+impl TestBackend {
+    pub fn new(width: u16, height: u16) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![Cell::default(); usize::from(width) * usize::from(height)],
+            cursor: (0, 0),
+            style: CellStyle::default(),
+            pending: vec![],
+        }
+    }
+
+    /// Change the size of the terminal. The contents are cleared.
+    pub fn resize(&mut self, width: u16, height: u16) {
+        *self = Self::new(width, height);
+    }
+
+    /// The cell at column `x` and row `y`
+    pub fn cell(&self, x: u16, y: u16) -> &Cell {
+        &self.cells[usize::from(y) * usize::from(self.width) + usize::from(x)]
+    }
+
+    /// The text on row `y`, without trailing whitespace
+    pub fn row_text(&self, y: u16) -> String {
+        let text: String = (0..self.width).map(|x| self.cell(x, y).symbol.as_str()).collect();
+        text.trim_end().to_string()
+    }
+
+    /// The text on each row, without trailing whitespace
+    pub fn screen_text(&self) -> Vec<String> {
+        (0..self.height).map(|y| self.row_text(y)).collect()
+    }
+
+    fn clear_cells(&mut self, from: (u16, u16), to: (u16, u16)) {
+        let idx = |(x, y): (u16, u16)| usize::from(y) * usize::from(self.width) + usize::from(x);
+        let (start, end) = (idx(from), idx(to).min(self.cells.len()));
+        for cell in &mut self.cells[start..end] {
+            *cell = Cell::default();
+        }
+    }
+
+    fn print(&mut self, text: &str) {
+        for grapheme in UnicodeSegmentation::graphemes(text, true) {
+            match grapheme {
+                "\r" => self.cursor.0 = 0,
+                "\n" | "\r\n" => {
+                    if grapheme == "\r\n" {
+                        self.cursor.0 = 0;
+                    }
+                    self.cursor.1 = (self.cursor.1 + 1).min(self.height.saturating_sub(1));
+                }
+                _ => {
+                    if self.cursor.0 >= self.width {
+                        // wrap to the next line, like terminals do by default
+                        if self.cursor.1 + 1 >= self.height {
+                            continue;
+                        }
+                        self.cursor = (0, self.cursor.1 + 1);
+                    }
+                    let (x, y) = self.cursor;
+                    let idx = usize::from(y) * usize::from(self.width) + usize::from(x);
+                    if let Some(cell) = self.cells.get_mut(idx) {
+                        *cell = Cell {
+                            symbol: grapheme.to_string(),
+                            style: self.style,
+                        };
+                    }
+                    self.cursor.0 += 1;
+                }
+            }
+        }
+    }
+
+    fn set_graphics(&mut self, params: &[u16]) {
+        let mut rest = params;
+        while !rest.is_empty() {
+            let mut consumed = 1;
+            match rest[0] {
+                0 => self.style = CellStyle::default(),
+                1 => self.style.bold = true,
+                2 => self.style.dim = true,
+                4 => self.style.underlined = true,
+                7 => self.style.reversed = true,
+                22 => {
+                    self.style.bold = false;
+                    self.style.dim = false;
+                }
+                24 => self.style.underlined = false,
+                27 => self.style.reversed = false,
+                c @ 30..=37 => self.style.fg = parse_color(&[5, c - 30]),
+                c @ 40..=47 => self.style.bg = parse_color(&[5, c - 40]),
+                39 => self.style.fg = None,
+                49 => self.style.bg = None,
+                c @ (38 | 48 | 58) => {
+                    let color = parse_color(&rest[1..]);
+                    consumed += if rest.get(1) == Some(&2) { 4 } else { 2 };
+                    match c {
+                        38 => self.style.fg = color,
+                        48 => self.style.bg = color,
+                        _ => {}
+                    }
+                }
+                _ => {}
+            }
+            rest = rest.get(consumed..).unwrap_or_default();
+        }
+    }
+
+    /// Handle the control sequence `ESC [ params final`
+    fn control_sequence(&mut self, params: &str, final_byte: u8) {
+        if params.starts_with('?') {
+            // private modes, like hiding the cursor or the alternate screen
+            return;
+        }
+        // the sub-parameters of for example 4:3 (curly underline) are ignored
+        let nums: Vec<u16> = params
+            .split(';')
+            .map(|p| p.split(':').next().unwrap_or("").parse().unwrap_or(0))
+            .collect();
+        let arg = |i: usize, default: u16| match nums.get(i) {
+            Some(0) | None => default,
+            Some(n) => *n,
+        };
+        let (x, y) = self.cursor;
+        let max_x = self.width.saturating_sub(1);
+        let max_y = self.height.saturating_sub(1);
+        match final_byte {
+            b'H' => self.cursor = ((arg(1, 1) - 1).min(max_x), (arg(0, 1) - 1).min(max_y)),
+            b'G' => self.cursor.0 = (arg(0, 1) - 1).min(max_x),
+            b'd' => self.cursor.1 = (arg(0, 1) - 1).min(max_y),
+            b'A' => self.cursor.1 = y.saturating_sub(arg(0, 1)),
+            b'B' => self.cursor.1 = (y + arg(0, 1)).min(max_y),
+            b'C' => self.cursor.0 = (x + arg(0, 1)).min(max_x),
+            b'D' => self.cursor.0 = x.saturating_sub(arg(0, 1)),
+            b'E' => self.cursor = (0, (y + arg(0, 1)).min(max_y)),
+            b'F' => self.cursor = (0, y.saturating_sub(arg(0, 1))),
+            b'J' => match arg(0, 0) {
+                0 => self.clear_cells((x, y), (0, self.height)),
+                1 => self.clear_cells((0, 0), (x + 1, y)),
+                _ => self.clear_cells((0, 0), (0, self.height)),
+            },
+            b'K' => match arg(0, 0) {
+                0 => self.clear_cells((x, y), (0, y + 1)),
+                1 => self.clear_cells((0, y), (x + 1, y)),
+                _ => self.clear_cells((0, y), (0, y + 1)),
+            },
+            b'm' => self.set_graphics(&nums),
+            _ => {}
+        }
+    }
+
+    /// Handle as much of the pending output as possible, leaving incomplete sequences pending
+    fn process_pending(&mut self) {
+        let pending = std::mem::take(&mut self.pending);
+        let mut i = 0;
+        while i < pending.len() {
+            if pending[i] == 0x1b {
+                match pending.get(i + 1) {
+                    None => break,
+                    Some(b'[') => {
+                        let end = pending[i + 2..].iter().position(|b| (0x40..=0x7e).contains(b));
+                        let end = match end {
+                            Some(end) => i + 2 + end,
+                            None => break,
+                        };
+                        let params = String::from_utf8_lossy(&pending[i + 2..end]).into_owned();
+                        self.control_sequence(&params, pending[end]);
+                        i = end + 1;
+                    }
+                    // other escape sequences are two bytes long
+                    Some(_) => i += 2,
+                }
+            } else {
+                let end = pending[i..]
+                    .iter()
+                    .position(|b| *b == 0x1b)
+                    .map_or(pending.len(), |end| i + end);
+                let segment = &pending[i..end];
+                match std::str::from_utf8(segment) {
+                    Ok(text) => self.print(text),
+                    // a character may have been split between writes, so wait for the rest
+                    Err(e) if e.error_len().is_none() && end == pending.len() => {
+                        // ok to unwrap, this part was just validated
+                        self.print(std::str::from_utf8(&segment[..e.valid_up_to()]).unwrap());
+                        i += e.valid_up_to();
+                        break;
+                    }
+                    Err(_) => self.print(&String::from_utf8_lossy(segment)),
+                }
+                i = end;
+            }
+        }
+        self.pending = pending[i.min(pending.len())..].to_vec();
+    }
+}
+
+impl Write for TestBackend {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        self.pending.extend_from_slice(buf);
+        self.process_pending();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> IOResult<()> {
+        Ok(())
+    }
+}
+
+impl Backend for TestBackend {
+    fn size(&self) -> IOResult<(u16, u16)> {
+        Ok((self.width, self.height))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::{cursor, queue, style, style::Stylize};
+
+    #[test]
+    fn test_test_backend() {
+        let mut backend = TestBackend::new(10, 3);
+        queue!(
+            backend,
+            cursor::MoveTo(2, 1),
+            style::Print("foo".bold()),
+            style::SetBackgroundColor(Color::DarkGrey),
+            style::SetAttribute(style::Attribute::Underlined),
+            style::Print("äb"),
+            style::ResetColor,
+            cursor::MoveTo(8, 0),
+            style::Print("wrap"),
+        )
+        .unwrap();
+        assert_eq!(backend.screen_text(), vec!["        wr", "apfooäb", ""]);
+        assert!(backend.cell(2, 1).style.bold);
+        assert_eq!(backend.cell(5, 1).style.bg, Some(Color::DarkGrey));
+        assert!(backend.cell(6, 1).style.underlined);
+        assert!(!backend.cell(6, 1).style.bold);
+        assert_eq!(backend.cell(7, 1).style, CellStyle::default());
+
+        // a character split between writes
+        queue!(backend, cursor::MoveTo(7, 1)).unwrap();
+        let bytes = "ö".as_bytes();
+        backend.write_all(&bytes[..1]).unwrap();
+        backend.write_all(&bytes[1..]).unwrap();
+        assert_eq!(backend.row_text(1), "apfooäbö");
+
+        queue!(
+            backend,
+            cursor::MoveTo(5, 1),
+            crossterm::terminal::Clear(crossterm::terminal::ClearType::UntilNewLine),
+        )
+        .unwrap();
+        assert_eq!(backend.row_text(1), "apfoo");
+    }
+}
@@ -0,0 +1,53 @@
+/// Copying text to the system clipboard using the OSC 52 terminal escape sequence. This works
+/// also over SSH, as long as the terminal emulator supports it.
+use std::io::Write;
+
+const BASE64_CHARS: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn base64_encode(data: &[u8]) -> String {
+    let mut ret = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                ret.push(BASE64_CHARS[(n >> (18 - 6 * i)) as usize & 0x3f] as char);
+            } else {
+                ret.push('=');
+            }
+        }
+    }
+    ret
+}
+
+/// Ask the terminal to copy `text` to the clipboard. There is no way of knowing whether the
+/// terminal actually supports this.
+pub fn copy_osc52<W: Write>(out: &mut W, text: &str) -> std::io::Result<()> {
+    write!(out, "\x1b]52;c;{}\x07", base64_encode(text.as_bytes()))?;
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"/home/user"), "L2hvbWUvdXNlcg==");
+    }
+
+    #[test]
+    fn test_copy_osc52() {
+        let mut out = vec![];
+        copy_osc52(&mut out, "foo").unwrap();
+        assert_eq!(out, b"\x1b]52;c;Zm9v\x07");
+    }
+}
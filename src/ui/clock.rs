@@ -0,0 +1,70 @@
+/// The time that the UI uses for the auto-cd timeout and for waiting for folders to be read.
+/// Normally this is the system time, but with the `test-harness` feature the UI can also use a
+/// `ManualClock`, which only advances when told to, so that what is drawn doesn't depend on how
+/// fast the machine running the tests is.
+use std::time::Instant;
+
+#[cfg(any(test, feature = "test-harness"))]
+pub use manual::ManualClock;
+
+pub trait Clock {
+    fn now(&self) -> Instant;
+}
+
+/// The real time
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+#[cfg(any(test, feature = "test-harness"))]
+mod manual {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::Clock;
+
+    /// A clock that stands still until it's advanced. The clones of a clock share its time, so
+    /// for example a `ScriptedEvents` can advance the clock that the UI reads.
+    ///
+    /// Because time doesn't pass while a folder is being read, folders are always shown only
+    /// after they have been read completely, never partially.
+    #[derive(Clone)]
+    pub struct ManualClock {
+        start: Instant,
+        elapsed: Arc<Mutex<Duration>>,
+    }
+
+    impl Default for ManualClock {
+        fn default() -> Self {
+            Self {
+                start: Instant::now(),
+                elapsed: Arc::default(),
+            }
+        }
+    }
+
+    impl ManualClock {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn advance(&self, duration: Duration) {
+            *self.elapsed.lock().unwrap() += duration;
+        }
+
+        /// The time that has passed since the clock was created
+        pub fn elapsed(&self) -> Duration {
+            *self.elapsed.lock().unwrap()
+        }
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            self.start + self.elapsed()
+        }
+    }
+}
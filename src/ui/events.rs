@@ -0,0 +1,158 @@
+/// Where the UI reads its input from. Normally this is the terminal, but with the `test-harness`
+/// feature the UI can also be driven by a `ScriptedEvents`, which replays a fixed sequence of
+/// events, so that the whole app can be tested without a real terminal. Together with a
+/// `ManualClock`, the pauses of the script take no real time, and the tests are reproducible.
+use std::time::Duration;
+
+use crossterm::{event::Event, Result as CTResult};
+
+#[cfg(any(test, feature = "test-harness"))]
+pub use scripted::{ScriptStep, ScriptedEvents};
+
+/// A source of key presses, mouse events and resizes
+pub trait EventSource {
+    /// Wait at most `timeout` for an event. Returns true if one can be read without blocking.
+    fn poll(&mut self, timeout: Duration) -> CTResult<bool>;
+
+    /// Read the next event, waiting until there is one
+    fn read(&mut self) -> CTResult<Event>;
+}
+
+/// The events of the terminal, read with crossterm
+pub struct TerminalEvents;
+
+impl EventSource for TerminalEvents {
+    fn poll(&mut self, timeout: Duration) -> CTResult<bool> {
+        crossterm::event::poll(timeout)
+    }
+
+    fn read(&mut self) -> CTResult<Event> {
+        crossterm::event::read()
+    }
+}
+
+#[cfg(any(test, feature = "test-harness"))]
+mod scripted {
+    use std::collections::VecDeque;
+    use std::io::{Error as IOError, ErrorKind};
+    use std::time::Duration;
+
+    use crossterm::{
+        event::{Event, KeyCode, KeyEvent, KeyModifiers},
+        Result as CTResult,
+    };
+
+    use super::super::clock::ManualClock;
+    use super::EventSource;
+
+    /// A step of a `ScriptedEvents`
+    #[derive(Clone, Debug, PartialEq, Eq)]
+    pub enum ScriptStep {
+        Event(Event),
+        /// Don't send any events for this long, for example to let an auto-cd happen
+        Wait(Duration),
+    }
+
+    /// A fixed sequence of events, with pauses between them. After the last event, reading fails
+    /// with `ErrorKind::UnexpectedEof`, which ends the event loop, so scripts that are expected
+    /// to exit normally should end with a key that exits, like Esc.
+    #[derive(Clone, Default)]
+    pub struct ScriptedEvents {
+        steps: VecDeque<ScriptStep>,
+        // If set, waiting advances this clock instead of sleeping
+        clock: Option<ManualClock>,
+    }
+
+    impl ScriptedEvents {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        pub fn event(mut self, event: Event) -> Self {
+            self.steps.push_back(ScriptStep::Event(event));
+            self
+        }
+
+        pub fn key(self, code: KeyCode) -> Self {
+            self.key_with(code, KeyModifiers::NONE)
+        }
+
+        pub fn key_with(self, code: KeyCode, modifiers: KeyModifiers) -> Self {
+            self.event(Event::Key(KeyEvent::new(code, modifiers)))
+        }
+
+        /// Type `text` one character at a time. Uppercase letters are sent with shift held
+        /// down, like terminals do.
+        pub fn text(self, text: &str) -> Self {
+            text.chars().fold(self, |script, c| {
+                let modifiers = if c.is_uppercase() {
+                    KeyModifiers::SHIFT
+                } else {
+                    KeyModifiers::NONE
+                };
+                script.key_with(KeyCode::Char(c), modifiers)
+            })
+        }
+
+        pub fn wait(mut self, duration: Duration) -> Self {
+            self.steps.push_back(ScriptStep::Wait(duration));
+            self
+        }
+
+        /// Advance `clock` on pauses, instead of actually waiting. This should be the clock of
+        /// the UI, see `TereTui::with_clock`.
+        pub fn with_clock(mut self, clock: ManualClock) -> Self {
+            self.clock = Some(clock);
+            self
+        }
+
+        fn pass(&self, duration: Duration) {
+            match &self.clock {
+                Some(clock) => clock.advance(duration),
+                None => std::thread::sleep(duration),
+            }
+        }
+
+        /// True if all events have been read
+        pub fn is_finished(&self) -> bool {
+            self.steps.iter().all(|s| matches!(s, ScriptStep::Wait(_)))
+        }
+    }
+
+    impl EventSource for ScriptedEvents {
+        fn poll(&mut self, timeout: Duration) -> CTResult<bool> {
+            let mut remaining = timeout;
+            loop {
+                match self.steps.front_mut() {
+                    Some(ScriptStep::Wait(d)) if *d > remaining => {
+                        *d -= remaining;
+                        self.pass(remaining);
+                        return Ok(false);
+                    }
+                    Some(ScriptStep::Wait(d)) => {
+                        let d = *d;
+                        remaining -= d;
+                        self.steps.pop_front();
+                        self.pass(d);
+                    }
+                    // When the script has ended, reading fails immediately instead of waiting
+                    // forever
+                    Some(ScriptStep::Event(_)) | None => return Ok(true),
+                }
+            }
+        }
+
+        fn read(&mut self) -> CTResult<Event> {
+            while let Some(step) = self.steps.pop_front() {
+                match step {
+                    ScriptStep::Wait(d) => self.pass(d),
+                    ScriptStep::Event(event) => return Ok(event),
+                }
+            }
+            Err(IOError::new(
+                ErrorKind::UnexpectedEof,
+                "The scripted events ran out",
+            ))
+        }
+    }
+}
@@ -0,0 +1,113 @@
+/// Building commands for running external programs, like the user's editor.
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Split a command from an environment variable like `EDITOR` into the program and its
+/// arguments, e.g. `code --wait`. Quoting is not supported.
+fn split_command(cmd: &str) -> Option<(&str, Vec<&str>)> {
+    let mut parts = cmd.split_whitespace();
+    parts.next().map(|program| (program, parts.collect()))
+}
+
+/// The command for opening `path` in the user's preferred editor, based on the `VISUAL` and
+/// `EDITOR` environment variables.
+pub fn editor_command(path: &Path) -> Command {
+    let editor = std::env::var("VISUAL")
+        .ok()
+        .filter(|e| !e.trim().is_empty())
+        .or_else(|| std::env::var("EDITOR").ok())
+        .filter(|e| !e.trim().is_empty())
+        .unwrap_or_else(|| if cfg!(windows) { "notepad" } else { "vi" }.to_string());
+
+    // ok to unwrap, we made sure above that the string is not empty
+    let (program, args) = split_command(&editor).unwrap();
+    let mut cmd = Command::new(program);
+    cmd.args(args).arg(path);
+    cmd
+}
+
+/// The command for opening `path` with the default application of the desktop environment. The
+/// command does not use the terminal, so its input and output are discarded.
+pub fn opener_command<P: AsRef<OsStr>>(path: P) -> Command {
+    let mut cmd = if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        // the first argument of 'start' is the window title
+        cmd.args(["/C", "start", ""]);
+        cmd
+    } else {
+        Command::new("xdg-open")
+    };
+    cmd.arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    cmd
+}
+
+/// Quote a string so that the shell used by `shell_command` treats it as a single argument.
+pub fn shell_quote(s: &str) -> String {
+    if cfg!(windows) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        format!("'{}'", s.replace('\'', "'\\''"))
+    }
+}
+
+/// Replace each `{}` in the command template with the quoted paths, separated by spaces. If the
+/// template doesn't contain `{}`, the paths are appended to the end.
+pub fn expand_command_template(template: &str, paths: &[PathBuf]) -> String {
+    let paths = paths
+        .iter()
+        .map(|p| shell_quote(&p.to_string_lossy()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    if template.contains("{}") {
+        template.replace("{}", &paths)
+    } else {
+        format!("{} {}", template, paths)
+    }
+}
+
+/// The command for running `command` using the system shell.
+pub fn shell_command(command: &str) -> Command {
+    let mut cmd = if cfg!(windows) {
+        let mut cmd = Command::new("cmd");
+        cmd.arg("/C");
+        cmd
+    } else {
+        let mut cmd = Command::new("sh");
+        cmd.arg("-c");
+        cmd
+    };
+    cmd.arg(command);
+    cmd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_command() {
+        assert_eq!(split_command("vim"), Some(("vim", vec![])));
+        assert_eq!(split_command(" code  --wait "), Some(("code", vec!["--wait"])));
+        assert_eq!(split_command(""), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_expand_command_template() {
+        let paths = vec![PathBuf::from("/foo"), PathBuf::from("/it's")];
+        assert_eq!(
+            expand_command_template("du -sh {}", &paths),
+            r"du -sh '/foo' '/it'\''s'",
+        );
+        assert_eq!(
+            expand_command_template("ls", &paths[..1]),
+            "ls '/foo'",
+        );
+    }
+}
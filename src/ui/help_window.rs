@@ -2,22 +2,37 @@
 use crossterm::style::{StyledContent, Stylize};
 use textwrap::{self, word_splitters::NoHyphenation, Options};
 
+use super::action::{key_to_string, Action, ActionContext, KeyMap};
+use super::i18n::{self, tr, tr_fmt};
+use crate::app_state::TereSettings;
+
 const README_STR: &str = include_str!("../../README.md");
 
 /// Word-wrap the help string to be displayed in the help window, and apply correct formatting
-/// (such as bolding) using crossterm::style.
+/// (such as bolding) using crossterm::style. The table of keyboard shortcuts in the README is
+/// replaced by the currently active key bindings.
 ///
 /// Returns a vector of vectors, where the outer vector represents lines, and the inner vector
 /// contains either a single string for the whole line, or multiple strings, if the style varies
 /// within the line.
-pub fn get_formatted_help_text(width: usize) -> Vec<Vec<StyledContent<String>>> {
+pub fn get_formatted_help_text(
+    width: usize,
+    settings: &TereSettings,
+) -> Vec<Vec<StyledContent<String>>> {
     let help_str = &README_STR[
         README_STR.find("## User guide").expect("Could not find user guide in README")
         ..
         README_STR.find("## Similar projects").expect("Could not find end of user guide in README")
     ];
-
-    // Skip the table of keyboard shortcuts, we'll format it separately
+    // A translated user guide is only used if it has the table of keyboard shortcuts
+    let help_str = i18n::help_text()
+        .filter(|text| {
+            text.split_once("\n\n|")
+                .is_some_and(|(_, rest)| rest.contains("\n\n"))
+        })
+        .unwrap_or(help_str);
+
+    // Skip the table of keyboard shortcuts, we'll generate it from the key map
     let (help_str, rest) = help_str
         .split_once("\n\n|")
         .expect("Could not find keyboard shortcuts table in readme");
@@ -30,7 +45,7 @@ pub fn get_formatted_help_text(width: usize) -> Vec<Vec<StyledContent<String>>>
     // Add justified keyboard shortcuts table to help string
     let mut help_str = help_str.to_string();
     help_str.push_str("\n\n"); // add back newlines eaten by split_once
-    help_str.push_str(&get_justified_keyboard_shortcuts_table());
+    help_str.push_str(&get_justified_keyboard_shortcuts_table(settings));
     help_str.push_str(rest);
 
     // We need to get rid of the `<kbd>` tags before wrapping so it works correctly. We're going to
@@ -50,51 +65,45 @@ pub fn get_formatted_help_text(width: usize) -> Vec<Vec<StyledContent<String>>>
     stylize_wrapped_lines(help_str, bold_toggle_locs)
 }
 
-/// Apply justification to the table of keyboard shortcuts in the README and render it to a String
-/// without the markup
-pub fn get_justified_keyboard_shortcuts_table() -> String {
-    let keyboard_shortcuts = README_STR
-        .split_once("keyboard shortcuts:\n\n")
-        .expect("Couldn't find table of keyboard shortcuts in README")
-        .1;
-    let keyboard_shortcuts = keyboard_shortcuts
-        .split_once("\n\n")
-        .expect("Couldn't find end of keyboard shortcuts table in README")
-        .0;
+/// Render the keyboard shortcuts of the active key map as a justified table, listing the actions
+/// in the same order as `Action::all()`, followed by the actions defined by the user. Actions
+/// without any shortcuts are omitted.
+pub fn get_justified_keyboard_shortcuts_table(settings: &TereSettings) -> String {
+    let keymap = &settings.keymap;
+    let actions = Action::all().iter().copied().chain(settings.user_actions());
+
+    let mut rows = vec![(
+        format!("`{}`", tr("Action")),
+        format!("`{}`", tr("Shortcut(s)")),
+    )];
+    for action in actions {
+        let shortcuts: Vec<String> = keymap
+            .bindings_for(action)
+            .map(|binding| match binding.context {
+                ActionContext::Any => format!("`{}`", key_to_string(&binding.key)),
+                ctx => format!("`{}` ({})", key_to_string(&binding.key), tr(&ctx.to_string())),
+            })
+            .collect();
+        if !shortcuts.is_empty() {
+            rows.push((action_description(action, settings), shortcuts.join(tr(" or "))));
+        }
+    }
 
-    let first_column_width = keyboard_shortcuts
-        .lines()
-        .map(|line| line.split('|').nth(1).unwrap_or("").len())
+    let first_column_width = rows
+        .iter()
+        .map(|(action, _)| action.chars().filter(|c| *c != '`').count())
         .max()
         .unwrap_or(10);
 
     let mut justified = String::new();
 
-    for (i, line) in keyboard_shortcuts.lines().enumerate() {
-        let cols: Vec<&str> = line.split('|').collect();
-        // cols[0] is empty, because the lines start with '|'.
-        let mut action = cols[1].trim().to_string();
-        let mut shortcut = cols[2].trim().to_string();
-
-        // skip markdown table formatting row
-        if action.starts_with(":--") {
-            continue;
-        }
-
-        if i == 0 {
-            // add backticks so that first line is bolded
-            action = format!("`{}`", &action);
-            shortcut = format!("`{}`", &shortcut);
-        }
-
+    for (action, shortcut) in rows {
         justified.push_str(&action);
 
         // backticks will be removed, so add extra space for them
         let extra_len = action.chars().filter(|c| *c == '`').count();
-        let padding = first_column_width + extra_len + 2 - action.len();
+        let padding = first_column_width + extra_len + 2 - action.chars().count();
         justified.push_str(&" ".repeat(padding));
-        // It's ok to add "\n" at the end of every line, because the split_once() above has
-        // eaten too many newlines from the end anyway.
         justified.push_str(&shortcut);
         justified.push('\n');
     }
@@ -105,6 +114,88 @@ pub fn get_justified_keyboard_shortcuts_table() -> String {
     justified
 }
 
+/// The translated description of `action`. The actions defined by the user are described by
+/// their commands, function names or names.
+pub fn action_description(action: Action, settings: &TereSettings) -> String {
+    match action {
+        Action::RunCommand(idx) => tr_fmt("Run `{}`", &[&settings.user_commands[idx]]),
+        Action::RunScript(idx) => {
+            tr_fmt("Run script function `{}`", &[&settings.script_functions[idx]])
+        }
+        Action::NamedCommand(idx) => tr_fmt("Run command `{}`", &[&settings.named_commands[idx].name]),
+        _ => tr(action.description()).to_string(),
+    }
+}
+
+/// A section of the cheat sheet
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum CheatSheetSection {
+    Navigate,
+    Search,
+    Exit,
+}
+
+impl CheatSheetSection {
+    /// The translated title of the section
+    fn title(self) -> &'static str {
+        match self {
+            CheatSheetSection::Navigate => tr("Navigate"),
+            CheatSheetSection::Search => tr("Search"),
+            CheatSheetSection::Exit => tr("Exit"),
+        }
+    }
+}
+
+/// The sections of the cheat sheet, and the actions listed in each of them.
+const CHEAT_SHEET_SECTIONS: &[(CheatSheetSection, &[Action])] = &[
+    (CheatSheetSection::Navigate, &[
+        Action::CursorUp,
+        Action::CursorDown,
+        Action::ChangeDir,
+        Action::ChangeDirParent,
+        Action::ChangeDirHome,
+        Action::ChangeDirRoot,
+    ]),
+    (CheatSheetSection::Search, &[
+        Action::ClearSearch,
+        Action::ChangeCaseSensitiveMode,
+        Action::ChangeGapSearchMode,
+    ]),
+    (CheatSheetSection::Exit, &[
+        Action::Exit,
+        Action::ExitWithoutCd,
+        Action::Help,
+        Action::CommandPalette,
+    ]),
+];
+
+/// The contents of the cheat sheet: a list of sections, each of which has a title and a list of
+/// (shortcut, description) pairs. At most two shortcuts are listed for each action, and actions
+/// without any shortcuts are skipped.
+pub fn get_cheat_sheet(keymap: &KeyMap) -> Vec<(&'static str, Vec<(String, String)>)> {
+    CHEAT_SHEET_SECTIONS
+        .iter()
+        .map(|(section, actions)| {
+            let mut entries = vec![];
+            // typing isn't bound to an action, so it's listed separately
+            if *section == CheatSheetSection::Search {
+                entries.push(("a-z".to_string(), tr("Type to search").to_string()));
+            }
+            for action in actions.iter() {
+                let shortcuts: Vec<String> = keymap
+                    .bindings_for(*action)
+                    .take(2)
+                    .map(|binding| key_to_string(&binding.key))
+                    .collect();
+                if !shortcuts.is_empty() {
+                    entries.push((shortcuts.join(" / "), tr(action.description()).replace('`', "")));
+                }
+            }
+            (section.title(), entries)
+        })
+        .collect()
+}
+
 /// Return a version of `text`, where all markup has been strippeed, and also return a vector of
 /// indices into the returned string where bold should toggle.
 fn strip_markup_and_extract_bold_positions(text: &str) -> (String, Vec<usize>) {
@@ -197,11 +288,65 @@ where
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 
     #[test]
     fn test_user_guide_found() {
         // this should panic if the README is incorrectly formatted
-        get_formatted_help_text(100);
+        get_formatted_help_text(100, &TereSettings::default());
+    }
+
+    #[test]
+    fn test_keyboard_shortcuts_from_keymap() {
+        let mut settings = TereSettings::default();
+        let keymap = &mut settings.keymap;
+        keymap.bind(KeyCode::Up.into(), ActionContext::Any, Action::CursorUp);
+        keymap.bind(
+            KeyEvent::new(KeyCode::Char('k'), KeyModifiers::ALT),
+            ActionContext::Any,
+            Action::CursorUp,
+        );
+        keymap.bind(KeyCode::Char(' ').into(), ActionContext::NotSearching, Action::ChangeDir);
+
+        keymap.bind(KeyCode::F(3).into(), ActionContext::Any, Action::RunCommand(0));
+
+        settings.user_commands.push("du {}".to_string());
+        let table = get_justified_keyboard_shortcuts_table(&settings);
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines[0], format!("`Action`{}`Shortcut(s)`", " ".repeat(11)));
+        assert_eq!(lines[1], "Move cursor up   `↑` or `Alt+k`");
+        assert_eq!(lines[2], "Enter directory  `Space` (if not searching)");
+        assert_eq!(lines[3], "Run `du {}`        `F3`");
+        assert_eq!(lines.len(), 5); // including the empty line at the end
+
+        settings.keymap.bind(KeyCode::F(4).into(), ActionContext::Any, Action::RunScript(0));
+        settings.user_commands.clear();
+        settings.script_functions.push("top".to_string());
+        let table = get_justified_keyboard_shortcuts_table(&settings);
+        assert_eq!(table.lines().nth(3), Some("Run script function `top`  `F4`"));
+    }
+
+    #[test]
+    fn test_cheat_sheet_from_keymap() {
+        let mut keymap = KeyMap::default();
+        keymap.bind(KeyCode::Up.into(), ActionContext::Any, Action::CursorUp);
+        keymap.bind(KeyCode::Char('k').into(), ActionContext::Any, Action::CursorUp);
+        keymap.bind(KeyCode::Char('K').into(), ActionContext::Any, Action::CursorUp);
+        keymap.bind(KeyCode::Esc.into(), ActionContext::Any, Action::Exit);
+
+        let cheat_sheet = get_cheat_sheet(&keymap);
+        let titles: Vec<_> = cheat_sheet.iter().map(|(title, _)| *title).collect();
+        assert_eq!(titles, vec!["Navigate", "Search", "Exit"]);
+
+        assert_eq!(
+            cheat_sheet[0].1,
+            vec![("↑ / k".to_string(), "Move cursor up".to_string())]
+        );
+        assert_eq!(cheat_sheet[1].1.len(), 1); // just the 'type to search' line
+        assert_eq!(
+            cheat_sheet[2].1,
+            vec![("Esc".to_string(), "Exit tere".to_string())]
+        );
     }
 
     #[test]
@@ -0,0 +1,215 @@
+/// Translations of the user interface. The messages are written in English in the source, and
+/// translated by looking them up in a catalog for the locale of the user, which is chosen from the
+/// LC_ALL, LC_MESSAGES and LANG environment variables, like with gettext. The catalogs are JSON
+/// files in '$CONFIG_DIR/tere/locale', named after the locale, like 'fi_FI.json' or 'fi.json',
+/// which map each English message to its translation. Messages that are missing from the catalog
+/// are shown in English, so catalogs can be partial. All the messages are listed in the template
+/// catalog 'locale/template.json' in the repository, which a test keeps up to date. The user
+/// guide in the help screen can be translated with a Markdown file with the same name, like
+/// 'fi.md', which has the same layout as the user guide in the README.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use serde_json::Value;
+
+static CATALOG: OnceLock<Catalog> = OnceLock::new();
+
+/// The translations for one locale
+#[derive(Default)]
+pub struct Catalog {
+    messages: HashMap<String, String>,
+    help: Option<String>,
+}
+
+impl Catalog {
+    /// Parse a catalog from a JSON object, whose keys are the English messages and values their
+    /// translations. Values that aren't strings are ignored, and so are empty strings, which are
+    /// the untranslated messages of a catalog made from the template.
+    pub fn parse(json: &str) -> serde_json::Result<Self> {
+        let messages = match serde_json::from_str(json)? {
+            Value::Object(obj) => obj
+                .into_iter()
+                .filter_map(|(k, v)| Some((k, v.as_str()?.to_string())))
+                .filter(|(_, v)| !v.is_empty())
+                .collect(),
+            _ => HashMap::new(),
+        };
+        Ok(Self { messages, help: None })
+    }
+
+    /// Load the most specific catalog for `locale` from `dir`, if there is one.
+    pub fn load(dir: &Path, locale: &str) -> Option<Self> {
+        locale_names(locale).into_iter().find_map(|name| {
+            let json = std::fs::read_to_string(dir.join(format!("{}.json", name))).ok()?;
+            let mut catalog = Self::parse(&json).ok()?;
+            catalog.help = std::fs::read_to_string(dir.join(format!("{}.md", name))).ok();
+            Some(catalog)
+        })
+    }
+
+    pub fn translate<'a>(&'a self, msg: &'a str) -> &'a str {
+        self.messages.get(msg).map_or(msg, String::as_str)
+    }
+}
+
+/// The names of the catalogs for `locale`, from the most to the least specific. For example,
+/// 'fi_FI.UTF-8' can use the catalogs 'fi_FI' and 'fi'. The 'C' and 'POSIX' locales are not
+/// translated.
+fn locale_names(locale: &str) -> Vec<String> {
+    // drop the encoding and the modifier, like '.UTF-8' and '@euro'
+    let locale = locale.split(['.', '@']).next().unwrap_or_default();
+    if locale.is_empty() || locale == "C" || locale == "POSIX" {
+        return vec![];
+    }
+    let mut names = vec![locale.to_string()];
+    if let Some((language, _)) = locale.split_once('_') {
+        names.push(language.to_string());
+    }
+    names
+}
+
+/// The locale for messages, from the environment variables that set it, in order of precedence.
+pub fn locale_from_env() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|value| !value.is_empty())
+}
+
+/// Load the catalog for the locale of the user, if there is one. Until this is called, and in
+/// tests, messages are not translated.
+pub fn init() {
+    let catalog = locale_from_env().zip(dirs::config_dir()).and_then(|(locale, dir)| {
+        Catalog::load(&dir.join(env!("CARGO_PKG_NAME")).join("locale"), &locale)
+    });
+    if let Some(catalog) = catalog {
+        let _ = CATALOG.set(catalog);
+    }
+}
+
+/// Translate `msg`, or return it as is if it has no translation.
+pub fn tr(msg: &str) -> &str {
+    CATALOG.get().map_or(msg, |catalog| catalog.translate(msg))
+}
+
+/// Translate `msg`, and replace each `{}` in the translation with the next item of `args`.
+pub fn tr_fmt(msg: &str, args: &[&dyn fmt::Display]) -> String {
+    fill_placeholders(tr(msg), args)
+}
+
+fn fill_placeholders(msg: &str, args: &[&dyn fmt::Display]) -> String {
+    let mut parts = msg.split("{}");
+    let mut ret = parts.next().unwrap_or_default().to_string();
+    let mut args = args.iter();
+    for part in parts {
+        if let Some(arg) = args.next() {
+            ret.push_str(&arg.to_string());
+        }
+        ret.push_str(part);
+    }
+    ret
+}
+
+/// The translated user guide for the help screen, if there is one.
+pub fn help_text() -> Option<&'static str> {
+    CATALOG.get()?.help.as_deref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use regex::Regex;
+    use std::collections::{BTreeMap, BTreeSet};
+
+    #[test]
+    fn test_locale_names() {
+        assert_eq!(locale_names("fi_FI.UTF-8"), vec!["fi_FI", "fi"]);
+        assert_eq!(locale_names("de_DE@euro"), vec!["de_DE", "de"]);
+        assert_eq!(locale_names("fi"), vec!["fi"]);
+        assert!(locale_names("C.UTF-8").is_empty());
+        assert!(locale_names("POSIX").is_empty());
+    }
+
+    #[test]
+    fn test_catalog() {
+        let catalog = Catalog::parse(r#"{"Exit": "Poistu", "{} marked - ": "{} merkitty - ", "n": 1, "Skip": ""}"#).unwrap();
+        assert_eq!(catalog.translate("Exit"), "Poistu");
+        assert_eq!(catalog.translate("Help"), "Help");
+        assert_eq!(catalog.translate("n"), "n");
+        assert_eq!(catalog.translate("Skip"), "Skip");
+        assert_eq!(
+            fill_placeholders(catalog.translate("{} marked - "), &[&3]),
+            "3 merkitty - "
+        );
+        assert_eq!(fill_placeholders("{} / {}", &[&1]), "1 / ");
+    }
+
+    #[test]
+    fn test_load_catalog() {
+        let tmp = TempDir::new("load-catalog");
+        std::fs::write(tmp.join("fi.json"), r#"{"Exit": "Poistu"}"#).unwrap();
+        std::fs::write(tmp.join("fi.md"), "## Käyttöohje").unwrap();
+
+        let catalog = Catalog::load(&tmp, "fi_FI.UTF-8").unwrap();
+        assert_eq!(catalog.translate("Exit"), "Poistu");
+        assert_eq!(catalog.help.as_deref(), Some("## Käyttöohje"));
+        assert!(Catalog::load(&tmp, "sv_SE.UTF-8").is_none());
+    }
+
+    /// The messages that are translated: the literals passed to `tr` and `tr_fmt` in the source
+    /// files under `dir`, and the descriptions of the actions and modes, which are translated
+    /// with `tr` after looking them up.
+    fn translatable_messages(dir: &Path, messages: &mut BTreeSet<String>) {
+        let literal = Regex::new(r#"\btr(?:_fmt)?\(\s*("(?:[^"\\]|\\.)*")"#).unwrap();
+        for entry in std::fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+            if path.is_dir() {
+                translatable_messages(&path, messages);
+            } else if path.extension() == Some("rs".as_ref()) {
+                let source = std::fs::read_to_string(&path).unwrap();
+                for m in literal.captures_iter(&source) {
+                    // the escapes in the messages are the same in Rust and JSON
+                    messages.insert(serde_json::from_str(&m[1]).unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_template_catalog() {
+        use crate::app_state::{CaseSensitiveMode, GapSearchMode, SortMode};
+        use crate::ui::action::{Action, ActionContext};
+
+        let mut expected = BTreeSet::new();
+        translatable_messages(&Path::new(env!("CARGO_MANIFEST_DIR")).join("src"), &mut expected);
+        let custom_actions = [Action::RunCommand(0), Action::RunScript(0), Action::NamedCommand(0)];
+        let actions = Action::all().iter().chain(&custom_actions);
+        expected.extend(actions.map(|action| action.description().to_string()));
+        let modes: [&dyn fmt::Display; 12] = [
+            &ActionContext::Searching,
+            &ActionContext::NotSearching,
+            &SortMode::Name,
+            &SortMode::Modified,
+            &SortMode::Size,
+            &CaseSensitiveMode::IgnoreCase,
+            &CaseSensitiveMode::CaseSensitive,
+            &CaseSensitiveMode::SmartCase,
+            &CaseSensitiveMode::Auto,
+            &GapSearchMode::GapSearchFromStart,
+            &GapSearchMode::NoGapSearch,
+            &GapSearchMode::GapSearchAnywere,
+        ];
+        expected.extend(modes.iter().map(|mode| mode.to_string()));
+
+        let template_json = include_str!("../../locale/template.json");
+        let template: BTreeMap<String, String> = serde_json::from_str(template_json).unwrap();
+        let missing: Vec<_> = expected.iter().filter(|msg| !template.contains_key(*msg)).collect();
+        let unused: Vec<_> = template.keys().filter(|msg| !expected.contains(*msg)).collect();
+        assert!(missing.is_empty(), "missing from locale/template.json: {:?}", missing);
+        assert!(unused.is_empty(), "not used anymore in locale/template.json: {:?}", unused);
+        assert!(template.values().all(String::is_empty));
+    }
+}
@@ -0,0 +1,102 @@
+/// Jump labels, which are shown on the items on the screen so that the cursor can be moved to
+/// any of them by typing its label, like easymotion in vim. Like `ViewMenu`, this only keeps
+/// track of the state, and the drawing and moving the cursor is done by the UI.
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// The letters of the labels, starting from the home row, since those are the fastest to type
+const LABEL_LETTERS: &str = "asdfghjklqwertyuiopzxcvbnm";
+
+/// The result of handling a key press
+#[derive(Debug, PartialEq, Eq)]
+pub enum JumpStatus {
+    /// Keep reading input
+    Pending,
+    /// The label of the item on this row of the screen was typed. If `enter` is true, it was
+    /// typed in uppercase, and the item should be entered too.
+    Chosen { row: usize, enter: bool },
+    /// A key that is not part of any label was pressed
+    Cancelled,
+}
+
+pub struct JumpLabels {
+    labels: Vec<String>,
+    typed: String,
+    enter: bool,
+}
+
+impl JumpLabels {
+    /// Create labels for `n` rows. They are single letters if there are enough of them, and
+    /// otherwise all labels have two letters, so that no label is the beginning of another one.
+    pub fn new(n: usize) -> Self {
+        let letters: Vec<char> = LABEL_LETTERS.chars().collect();
+        let labels = if n <= letters.len() {
+            letters.iter().take(n).map(|c| c.to_string()).collect()
+        } else {
+            letters
+                .iter()
+                .flat_map(|a| letters.iter().map(move |b| format!("{}{}", a, b)))
+                .take(n)
+                .collect()
+        };
+        Self { labels, typed: String::new(), enter: false }
+    }
+
+    /// The rest of the label of each row that hasn't been typed yet, or `None` for the rows
+    /// whose label doesn't start with what has been typed.
+    pub fn remaining(&self) -> impl Iterator<Item = Option<&str>> {
+        let typed = self.typed.as_str();
+        self.labels.iter().map(move |label| label.strip_prefix(typed))
+    }
+
+    pub fn handle_key(&mut self, k: &KeyEvent) -> JumpStatus {
+        let c = match k.code {
+            KeyCode::Char(c) if (k.modifiers - KeyModifiers::SHIFT).is_empty() => c,
+            KeyCode::Backspace => {
+                self.typed.pop();
+                return JumpStatus::Pending;
+            }
+            _ => return JumpStatus::Cancelled,
+        };
+        self.enter |= c.is_uppercase();
+        self.typed.extend(c.to_lowercase());
+        match self.labels.iter().position(|label| *label == self.typed) {
+            Some(row) => JumpStatus::Chosen { row, enter: self.enter },
+            None if self.labels.iter().any(|label| label.starts_with(&self.typed)) => {
+                JumpStatus::Pending
+            }
+            None => JumpStatus::Cancelled,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(c: char) -> KeyEvent {
+        let modifiers = if c.is_uppercase() { KeyModifiers::SHIFT } else { KeyModifiers::NONE };
+        KeyEvent::new(KeyCode::Char(c), modifiers)
+    }
+
+    #[test]
+    fn test_jump_labels() {
+        let mut jump = JumpLabels::new(3);
+        assert_eq!(jump.remaining().collect::<Vec<_>>(), vec![Some("a"), Some("s"), Some("d")]);
+        assert_eq!(jump.handle_key(&key('s')), JumpStatus::Chosen { row: 1, enter: false });
+
+        let mut jump = JumpLabels::new(3);
+        assert_eq!(jump.handle_key(&key('D')), JumpStatus::Chosen { row: 2, enter: true });
+        let mut jump = JumpLabels::new(3);
+        assert_eq!(jump.handle_key(&key('x')), JumpStatus::Cancelled);
+
+        // with more rows than letters, all labels have two letters
+        let mut jump = JumpLabels::new(30);
+        assert_eq!(jump.handle_key(&key('a')), JumpStatus::Pending);
+        assert_eq!(jump.remaining().filter(Option::is_some).count(), 26);
+        assert_eq!(jump.remaining().nth(26), Some(None));
+        jump.handle_key(&KeyEvent::new(KeyCode::Backspace, KeyModifiers::NONE));
+        assert_eq!(jump.handle_key(&key('s')), JumpStatus::Pending);
+        assert_eq!(jump.handle_key(&key('d')), JumpStatus::Chosen { row: 28, enter: false });
+        assert_eq!(JumpLabels::new(30).handle_key(&KeyEvent::new(KeyCode::Esc, KeyModifiers::NONE)), JumpStatus::Cancelled);
+    }
+}
@@ -1,17 +1,57 @@
+pub mod action;
+pub mod backend;
+pub mod clock;
+mod clipboard;
+pub mod events;
+pub mod external;
 pub mod help_window;
+pub mod i18n;
+mod jump_labels;
+mod modal;
+mod palette;
+mod view_menu;
+pub mod named_command;
+mod prompt;
+#[cfg(feature = "rhai")]
+pub mod script;
+pub mod signals;
+pub mod theme;
 
 use std::convert::TryFrom;
-use std::io::{Stderr, Write};
-use std::path::PathBuf;
+use std::io::{Result as IOResult, Stderr};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
 use crate::error::TereError;
+use crate::profile;
+use crate::file_ops::{self, FileJob, JobItem, JobKind, PasteMode};
+use crate::output::ExitState;
 use crate::app_state::{
     TereAppState,
+    StartDir,
+    ItemChange,
+    AutoCdMode,
     CaseSensitiveMode,
-    GapSearchMode,
+    split_by_match_spans,
+    check_item_name,
+    SortMode,
+    UnboundKeyBehavior,
     NO_MATCHES_MSG,
 };
-use help_window::get_formatted_help_text;
+use action::{key_to_string, Action, ActionContext};
+use backend::Backend;
+use clock::{Clock, SystemClock};
+use events::{EventSource, TerminalEvents};
+use help_window::{action_description, get_cheat_sheet, get_formatted_help_text};
+use i18n::{tr, tr_fmt};
+use jump_labels::{JumpLabels, JumpStatus};
+use modal::{Modal, ModalStatus};
+use named_command::CommandStep;
+use palette::{Palette, PaletteEntry, PaletteStatus};
+use view_menu::{MenuItem, MenuStatus, ViewMenu};
+use prompt::{InputPrompt, PromptHistory, PromptStatus};
+use signals::TerminationSignals;
 
 use crossterm::{
     execute,
@@ -20,12 +60,12 @@ use crossterm::{
     cursor,
     style::{self, Stylize, Attribute},
     event::{
-        read as read_event,
         Event,
         MouseEvent,
         MouseEventKind,
         MouseButton,
         KeyCode,
+        KeyEvent,
         KeyModifiers,
         EnableMouseCapture,
         DisableMouseCapture,
@@ -34,44 +74,134 @@ use crossterm::{
 };
 
 use clap::ArgMatches;
-use dirs::home_dir;
 use unicode_segmentation::UnicodeSegmentation;
 
 const HEADER_SIZE: usize = 1;
 const INFO_WIN_SIZE: usize = 1;
 const FOOTER_SIZE: usize = 1;
 
-/// This struct is responsible for drawing an app state object to a stderr stream (confusingly
-/// called 'window' for historical reasons) that the UI is written to. Currently it somewhat
-/// conflates application logic with the UI.
-pub struct TereTui<'a> {
-    window: &'a Stderr,
+/// The button in the top right corner of the help view that closes it
+const HELP_CLOSE_BUTTON: &str = "[x]";
+
+/// How often to update the auto-cd countdown in the footer
+const AUTOCD_INDICATOR_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How often to update the progress of file operations running in the background
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How long to wait for a folder to be read before showing it partially. Most folders are read
+/// much faster, and this avoids flashing an incomplete listing for them.
+const LOADING_GRACE_PERIOD: Duration = Duration::from_millis(50);
+
+/// How often to check if a folder that is being read in the background is ready
+const LOADING_POLL_INTERVAL: Duration = Duration::from_millis(20);
+
+/// How often to check for termination signals while waiting for input
+const SIGNAL_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How often to check if the current folder has been changed by other programs
+const WATCH_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// This struct is responsible for drawing an app state object to a terminal backend, normally
+/// stderr (confusingly called 'window' for historical reasons) that the UI is written to.
+/// Currently it somewhat conflates application logic with the UI.
+pub struct TereTui<'a, B: Backend = Stderr> {
+    window: &'a mut B,
     app_state: TereAppState,
+    // If this is set, change to the folder under the cursor at this time, unless some key is
+    // pressed before that.
+    autocd_deadline: Option<Instant>,
+    // If set, give up opening the folder that is being read at this time, see `--read-timeout`
+    read_deadline: Option<Instant>,
+    // Previous inputs of the prompts, for example paths that were typed in
+    prompt_history: PromptHistory,
+    // A file operation (like copying marked items) running in the background, if any
+    job: Option<FileJob>,
+    // An event that was read while looking for repeated key presses, to be handled next
+    pending_event: Option<Event>,
+    // Where key presses and other input are read from, normally the terminal
+    events: Box<dyn EventSource>,
+    // The time used for the auto-cd timeout and for waiting for folders to be read
+    clock: Box<dyn Clock>,
+    // If set, waiting for input is interrupted when one of these signals is received
+    signals: Option<TerminationSignals>,
+    // The last error message in full, since only its beginning fits in the info window
+    last_error: Option<String>,
+    // The columns of the footer that can be clicked, and what clicking them does
+    footer_buttons: Vec<(Range<usize>, FooterButton)>,
+    // In browse mode, if '/' was just pressed, so that the next letter starts a search
+    browse_search_pending: bool,
+}
+
+/// A part of the footer that changes a search mode when it's clicked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum FooterButton {
+    FilterSearch,
+    GapSearchMode,
+    CaseSensitiveMode,
 }
 
 /// Return the current terminal size as a pair of `(usize, usize)` instead of `(u16, 16)` as
 /// is done by crossterm.
-fn terminal_size_usize() -> CTResult<(usize, usize)> {
-    let (w, h): (u16, u16) = terminal::size()?;
+fn terminal_size_usize<B: Backend + ?Sized>(window: &B) -> CTResult<(usize, usize)> {
+    let (w, h): (u16, u16) = window.size()?;
     Ok((w as usize, h as usize))
 }
 
+/// The letters of the drives that exist, in alphabetical order.
+#[cfg(windows)]
+fn available_drives() -> Vec<char> {
+    let mask = unsafe { windows_sys::Win32::Storage::FileSystem::GetLogicalDrives() };
+    ('A'..='Z')
+        .enumerate()
+        .filter(|(i, _)| mask & (1 << i) != 0)
+        .map(|(_, drive)| drive)
+        .collect()
+}
+
 // Dimensions (width, height) of main window
-fn main_window_size() -> CTResult<(usize, usize)> {
-    let (w, h) = terminal_size_usize()?;
+fn main_window_size<B: Backend + ?Sized>(window: &B) -> CTResult<(usize, usize)> {
+    let (w, h) = terminal_size_usize(window)?;
     Ok((
-        w as usize,
-        (h as usize).saturating_sub(HEADER_SIZE + INFO_WIN_SIZE + FOOTER_SIZE),
+        w,
+        h.saturating_sub(HEADER_SIZE + INFO_WIN_SIZE + FOOTER_SIZE),
     ))
 }
 
-impl<'a> TereTui<'a> {
-    pub fn init(args: &ArgMatches, window: &'a mut Stderr) -> Result<Self, TereError> {
-        let (w, h) = main_window_size()?;
-        let state = TereAppState::init(args, w, h)?;
+impl<'a, B: Backend> TereTui<'a, B> {
+    /// Start the app with the command line arguments `args`, in `start` if a folder was given,
+    /// see `StartDir::from_cli_args`.
+    pub fn init(
+        args: &ArgMatches,
+        start: Option<StartDir>,
+        window: &'a mut B,
+    ) -> Result<Self, TereError> {
+        let (w, h) = main_window_size(window)?;
+        let state = TereAppState::init(args, start, w, h)?;
+        Self::new(state, window)
+    }
+
+    /// Start drawing `state` on `window`. The size of the main window of the state is updated to
+    /// fit the terminal.
+    pub fn new(mut state: TereAppState, window: &'a mut B) -> Result<Self, TereError> {
+        let (w, h) = main_window_size(window)?;
+        state.update_main_window_dimensions(w, h);
+        state.watch_current_dir();
+        profile::mark("watch folder");
         let mut ret = Self {
             window,
             app_state: state,
+            autocd_deadline: None,
+            read_deadline: None,
+            prompt_history: PromptHistory::default(),
+            job: None,
+            pending_event: None,
+            events: Box::new(TerminalEvents),
+            clock: Box::new(SystemClock),
+            signals: None,
+            last_error: None,
+            footer_buttons: vec![],
+            browse_search_pending: false,
         };
 
         if ret.app_state.settings.mouse_enabled {
@@ -80,22 +210,55 @@ impl<'a> TereTui<'a> {
 
         ret.update_header()?;
         ret.redraw_all_windows()?;
-        ret.info_message(
-            format!(
+        if !ret.app_state.settings.no_banner {
+            ret.info_message(&tr_fmt(
                 "{} {} - Type something to search, press '?' to view help or Esc to exit.",
-                env!("CARGO_PKG_NAME"),
-                env!("CARGO_PKG_VERSION")
-            )
-            .as_str(),
-        )?;
+                &[&env!("CARGO_PKG_NAME"), &env!("CARGO_PKG_VERSION")],
+            ))?;
+        }
+        profile::mark("draw first frame");
         Ok(ret)
     }
 
-    /// Get the current (logical) path to be printed on exit.
+    /// Read input from `events` instead of the terminal, for example to drive the UI from a
+    /// script in tests.
+    pub fn with_event_source<E: EventSource + 'static>(mut self, events: E) -> Self {
+        self.events = Box::new(events);
+        self
+    }
+
+    /// Use `clock` instead of the system time, for example to make tests reproducible.
+    pub fn with_clock<C: Clock + 'static>(mut self, clock: C) -> Self {
+        self.clock = Box::new(clock);
+        self
+    }
+
+    /// Exit when one of `signals` is received while waiting for input, see `main_event_loop`.
+    pub fn with_termination_signals(mut self, signals: TerminationSignals) -> Self {
+        self.signals = Some(signals);
+        self
+    }
+
+    /// Get the current (logical) path.
     pub fn current_path(&self) -> PathBuf {
         self.app_state.current_path.clone()
     }
 
+    /// Get the state of the app for printing the result on exit.
+    pub fn exit_state(&self) -> ExitState {
+        let selected = if !self.app_state.marked_items().is_empty() {
+            self.app_state.marked_items().to_vec()
+        } else {
+            self.app_state.chosen_file.iter().cloned().collect()
+        };
+        ExitState {
+            final_path: self.current_path(),
+            selected,
+            search_query: self.app_state.search_string().to_string(),
+            host: self.app_state.remote_host().map(String::from),
+        }
+    }
+
     /// Queue up a command to clear a given row (starting from 0). Must be executed/flushed
     /// separately.
     fn queue_clear_row(&mut self, row: usize) -> CTResult<()> {
@@ -111,20 +274,19 @@ impl<'a> TereTui<'a> {
         // add "..." to beginning? or collapse folder names? make configurable?
         // at least, truncate towards the left instead of to the right
 
-        let (max_x, _) = main_window_size()?;
+        let (max_x, _) = main_window_size(self.window)?;
 
         let header_graphemes: Vec<String> =
             UnicodeSegmentation::graphemes(self.app_state.header_msg.as_str(), true)
                 .map(String::from)
                 .collect();
-        let n_skip = header_graphemes.len().saturating_sub(max_x as usize);
+        let n_skip = header_graphemes.len().saturating_sub(max_x);
         let header_msg = header_graphemes[n_skip..].join("");
 
         // must use variable here b/c can't borrow 'self' twice in execute!() below
-        let mut win = self.window;
         self.queue_clear_row(0)?;
         execute!(
-            win,
+            self.window,
             cursor::MoveTo(0, 0),
             style::SetAttribute(Attribute::Reset),
             style::Print(&header_msg.bold().underlined()),
@@ -138,16 +300,18 @@ impl<'a> TereTui<'a> {
     }
 
     pub fn redraw_info_window(&mut self) -> CTResult<()> {
-        let (_, h) = terminal_size_usize()?;
+        let (w, h) = terminal_size_usize(self.window)?;
         let info_win_row = h - FOOTER_SIZE - INFO_WIN_SIZE;
+        // the message must not wrap over the footer
+        let first_line = self.app_state.info_msg.lines().next().unwrap_or_default();
+        let msg: String = first_line.chars().take(w).collect();
 
         self.queue_clear_row(info_win_row)?;
-        let mut win = self.window;
         execute!(
-            win,
+            self.window,
             cursor::MoveTo(0, u16::try_from(info_win_row).unwrap_or(u16::MAX)),
             style::SetAttribute(Attribute::Reset),
-            style::Print(&self.app_state.info_msg.clone().bold()),
+            style::Print(&msg.bold()),
         )
     }
 
@@ -158,22 +322,100 @@ impl<'a> TereTui<'a> {
         self.redraw_info_window()
     }
 
+    /// Show an error message in the info window. If it doesn't fit there, only its beginning is
+    /// shown, and the whole message can be shown with `Action::ShowLastError`.
     pub fn error_message(&mut self, msg: &str) -> CTResult<()> {
         //TODO: red color (also: make it configurable)
-        let error_msg = format!("error: {}", &msg);
-        self.info_message(&error_msg)
+        let error_msg = format!("{}{}", tr("error: "), &msg);
+        tracing::warn!(message = msg, "error shown");
+        self.last_error = Some(error_msg.clone());
+        let (w, _) = terminal_size_usize(self.window)?;
+        if !error_msg.contains('\n') && error_msg.chars().count() <= w {
+            return self.info_message(&error_msg);
+        }
+
+        let hint = match self.app_state.settings.keymap.bindings_for(Action::ShowLastError).next() {
+            Some(binding) => tr_fmt("... ({} for details)", &[&key_to_string(&binding.key)]),
+            None => "...".to_string(),
+        };
+        let first_line = error_msg.lines().next().unwrap_or_default();
+        let summary: String = first_line
+            .chars()
+            .take(w.saturating_sub(hint.chars().count()))
+            .collect();
+        self.info_message(&format!("{}{}", summary, hint))
+    }
+
+    /// Show the last error message in full in a modal, wrapped to fit in the window.
+    fn show_last_error(&mut self) -> CTResult<()> {
+        match self.last_error.clone() {
+            Some(error) => {
+                self.show_modal(&Modal::new(tr("Error"), &error).choice('o', tr("OK")))?;
+                Ok(())
+            }
+            None => self.info_message(tr("No errors")),
+        }
     }
 
     pub fn redraw_footer(&mut self) -> CTResult<()> {
-        let (w, h) = terminal_size_usize()?;
+        let (w, h) = terminal_size_usize(self.window)?;
         let footer_win_row = h - FOOTER_SIZE;
         self.queue_clear_row(footer_win_row)?;
 
-        let mut win = self.window;
         let mut extra_msg = String::new();
 
-        extra_msg.push_str(&format!("{} - ", self.app_state.settings.gap_search_mode));
-        extra_msg.push_str(&format!("{} - ", self.app_state.settings.case_sensitive));
+        if let Some(deadline) = self.autocd_deadline {
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            extra_msg.push_str(&tr_fmt("auto-cd in {} ms - ", &[&remaining.as_millis()]));
+        }
+        if let Some(n) = self.app_state.num_loaded_items() {
+            extra_msg.push_str(&tr_fmt("loading... {} items, Esc to cancel - ", &[&n]));
+        }
+        if let Some(space) = self.app_state.disk_space() {
+            extra_msg.push_str(&tr_fmt(
+                "{} free of {} - ",
+                &[&file_ops::format_size(space.free), &file_ops::format_size(space.total)],
+            ));
+        }
+        if let Some(preset) = self.app_state.filter_preset() {
+            extra_msg.push_str(&tr_fmt("filter: {} - ", &[&preset.name]));
+        }
+        let n_marked = self.app_state.marked_items().len();
+        if n_marked > 0 {
+            extra_msg.push_str(&tr_fmt("{} marked - ", &[&n_marked]));
+        }
+        // the parts of the extra message that can be clicked, as ranges of characters in it
+        let mut extra_buttons = vec![];
+        let gap_search_mode = tr(&self.app_state.settings.gap_search_mode.to_string()).to_string();
+        let start = extra_msg.chars().count();
+        extra_buttons.push((start..start + gap_search_mode.chars().count(), FooterButton::GapSearchMode));
+        extra_msg.push_str(&format!("{} - ", gap_search_mode));
+        let case_mode = if matches!(self.app_state.settings.case_sensitive, CaseSensitiveMode::Auto) {
+            let mode = self.app_state.case_sensitive_mode().to_string();
+            tr_fmt("auto: {}", &[&tr(&mode)])
+        } else {
+            tr(&self.app_state.settings.case_sensitive.to_string()).to_string()
+        };
+        let start = extra_msg.chars().count();
+        extra_buttons.push((start..start + case_mode.chars().count(), FooterButton::CaseSensitiveMode));
+        extra_msg.push_str(&format!("{} - ", case_mode));
+
+        let (n_dirs, n_files) = self.app_state.num_dirs_and_files();
+        let dirs_text = match n_dirs {
+            1 => tr("1 folder").to_string(),
+            n => tr_fmt("{} folders", &[&n]),
+        };
+        let files_text = match n_files {
+            1 => tr("1 file").to_string(),
+            n => tr_fmt("{} files", &[&n]),
+        };
+        let n_hidden = self.app_state.num_hidden_items();
+        let hidden_text = if n_hidden > 0 {
+            tr_fmt(" (+{} hidden)", &[&n_hidden])
+        } else {
+            String::new()
+        };
+        extra_msg.push_str(&format!("{}, {}{} - ", dirs_text, files_text, hidden_text));
 
         let cursor_idx = self
             .app_state
@@ -194,7 +436,6 @@ impl<'a> TereTui<'a> {
                 self.app_state.num_total_items()
             ));
         } else {
-            //TODO: show no. of files/folders separately? like 'n folders, n files'
             extra_msg.push_str(&format!(
                 "{} / {}",
                 cursor_idx + 1,
@@ -203,58 +444,65 @@ impl<'a> TereTui<'a> {
         }
 
         // draw extra message first, so that it gets overwritten by the more important search query
-        // if there is not enough space
+        // if there is not enough space. The end of it is kept if it's wider than the window, since
+        // the cursor position is the most useful part.
+        let extra_msg_len = extra_msg.chars().count();
         queue!(
-            win,
+            self.window,
             cursor::MoveTo(
-                u16::try_from(w.saturating_sub(extra_msg.len())).unwrap_or(u16::MAX),
+                u16::try_from(w.saturating_sub(extra_msg_len)).unwrap_or(u16::MAX),
                 u16::try_from(footer_win_row).unwrap_or(u16::MAX),
             ),
             style::SetAttribute(Attribute::Reset),
             style::Print(
                 extra_msg
                     .chars()
-                    .take(w as usize)
+                    .skip(extra_msg_len.saturating_sub(w))
                     .collect::<String>()
                     .bold()
             ),
         )?;
 
+        let label = if self.is_browsing() {
+            tr("browse")
+        } else if self.app_state.settings.filter_search {
+            tr("filter")
+        } else {
+            tr("search")
+        };
+        let search_text = format!("{}: {}", label, self.app_state.search_string());
+
+        // The search is drawn over the end of the extra message, so the parts of it that are
+        // hidden can't be clicked
+        let search_len = search_text.chars().count();
+        let extra_start = w as isize - extra_msg_len as isize;
+        self.footer_buttons = vec![(0..label.chars().count(), FooterButton::FilterSearch)];
+        self.footer_buttons.extend(extra_buttons.into_iter().filter_map(|(range, button)| {
+            let start = (extra_start + range.start as isize).max(search_len as isize) as usize;
+            let end = (extra_start + range.end as isize).max(0) as usize;
+            (start < end).then_some((start..end, button))
+        }));
+
         execute!(
-            win,
+            self.window,
             cursor::MoveTo(0, u16::try_from(footer_win_row).unwrap_or(u16::MAX)),
             style::SetAttribute(Attribute::Reset),
             //TODO: prevent line wrap here
-            style::Print(
-                &format!(
-                    "{}: {}",
-                    if self.app_state.settings.filter_search {
-                        "filter"
-                    } else {
-                        "search"
-                    },
-                    self.app_state.search_string()
-                )
-                .bold()
-            ),
+            style::Print(&search_text.bold()),
         )
     }
 
     fn draw_main_window_row(&mut self, row: usize, highlight: bool) -> CTResult<()> {
         let row_abs = row + HEADER_SIZE;
 
-        //TODO: make customizable...
-        let highlight_fg = style::Color::Black;
-        let highlight_bg = style::Color::Grey;
-        let matching_letter_bg = style::Color::DarkGrey;
-        let symlink_color = style::Color::Cyan;
+        let theme = self.app_state.settings.theme;
 
         let item = self.app_state.get_item_at_cursor_pos(row);
 
         let text_attr = if item.map(|itm| itm.is_dir()).unwrap_or(false) {
             Attribute::Bold
         } else {
-            Attribute::Dim
+            theme.file_attr
         };
 
         queue!(
@@ -264,56 +512,60 @@ impl<'a> TereTui<'a> {
             style::ResetColor,
             style::SetAttribute(text_attr),
         )?;
+        if highlight {
+            queue!(self.window, style::SetAttribute(theme.highlight_attr))?;
+        }
 
-        let idx = self.app_state.cursor_pos_to_visible_item_index(row);
-
-        // All *byte offsets* that should be underlined
-        let underline_locs = if self.app_state.is_searching()
-            && self.app_state.visible_match_indices().contains(&idx)
-        {
-            self.app_state
-                .get_match_locations_at_cursor_pos(row)
-                .unwrap_or(&vec![])
-                .iter()
-                .flat_map(|(start, end)| (*start..*end).collect::<Vec<usize>>())
-                .collect()
+        // The graphemes of the name that should be underlined
+        let match_spans = if self.app_state.is_searching() {
+            self.app_state.get_match_spans_at_cursor_pos(row)
         } else {
-            vec![]
+            None
         };
 
         let item_size = if let Some(item) = item {
             // we're actually drawing an item
 
-            let symlink_target = &item.symlink_target;
-            let is_symlink = symlink_target.is_some();
-            let fname = item.file_name_checked();
+            let shows_metadata = self.app_state.shows_metadata();
+            let symlink_target = if shows_metadata { item.symlink_target() } else { None };
+            let is_symlink = item.is_symlink();
+            let alias_target = if self.app_state.settings.resolve_aliases && shows_metadata {
+                item.alias_target()
+            } else {
+                None
+            };
+            let is_marked = self.app_state.is_marked(item);
+            if is_marked {
+                queue!(self.window, style::SetAttribute(theme.marked_attr))?;
+            }
+            let fname = item.file_name();
+            let offsets = item.grapheme_offsets();
 
-            // Find out the grapheme clusters corresponding to the
-            // above byte offsets, and determine whether they should be underlined.
-            let letters_underlining: Vec<(&str, bool)> =
-                UnicodeSegmentation::grapheme_indices(fname.as_str(), true)
-                    // this contains() could probably be optimized, but shouldn't be too bad.
-                    .map(|(i, c)| (c, underline_locs.contains(&i)))
-                    .collect();
+            // Split the name into runs of underlined and non-underlined grapheme clusters.
+            let letters_underlining =
+                split_by_match_spans(fname, offsets, match_spans.map_or(&[], |v| v.as_slice()));
 
             // queue draw actions for each (non-)underlined segment
-            for (c, underline) in &letters_underlining {
-
+            for (segment, underline) in &letters_underlining {
                 let (underline, fg, bg) = match (underline, highlight) {
                     (true, _) => (
                         Attribute::Underlined,
-                        style::Color::Reset,
-                        matching_letter_bg,
+                        theme.match_fg,
+                        theme.match_bg,
                     ),
                     (false,  true) => (
                         Attribute::NoUnderline,
-                        highlight_fg,
-                        highlight_bg,
+                        theme.highlight_fg,
+                        theme.highlight_bg,
                     ),
                     (false, false) => (
                         Attribute::NoUnderline,
-                        if is_symlink {
-                            symlink_color
+                        if is_marked {
+                            theme.marked_fg
+                        } else if is_symlink {
+                            theme.symlink_fg
+                        } else if alias_target.is_some() {
+                            theme.alias_fg
                         } else {
                             style::Color::Reset
                         },
@@ -326,33 +578,52 @@ impl<'a> TereTui<'a> {
                     style::SetAttribute(underline),
                     style::SetBackgroundColor(bg),
                     style::SetForegroundColor(fg),
-                    style::Print(c.to_string()),
+                    style::Print(segment),
                 )?;
 
             }
 
-            if let Some(target) = symlink_target {
+            let item_size = if let Some(target) = symlink_target.or(alias_target) {
                 // target is OsStr, so use display() here. This is fine because we're not going to
                 // use it for anything else.
                 //TODO: different color for target?
-                let target_text = format!(" -> {}", target.display());
+                let target_text = if item.is_symlink_loop() {
+                    format!(" -> {}{}", target.display(), tr(" (loop)"))
+                } else {
+                    format!(" -> {}", target.display())
+                };
                 queue!(self.window, style::Print(&target_text))?;
 
-                letters_underlining.len() + UnicodeSegmentation::graphemes(target_text.as_str(), true).count()
+                offsets.len() + UnicodeSegmentation::graphemes(target_text.as_str(), true).count()
+            } else {
+                offsets.len()
+            };
+
+            if let Some(change) = self.app_state.item_change(item) {
+                let indicator = match change {
+                    ItemChange::New      => " +",
+                    ItemChange::Modified => " *",
+                };
+                if !highlight {
+                    queue!(self.window, style::SetForegroundColor(theme.changed_fg))?;
+                }
+                queue!(self.window, style::Print(indicator))?;
+                item_size + indicator.len()
             } else {
-                letters_underlining.len()
+                item_size
             }
         } else {
             0
         };
 
         // color the rest of the line if applicable
-        let width: usize = main_window_size()?.0;
+        let width: usize = main_window_size(self.window)?.0;
         if highlight && width > item_size {
             queue!(
                 self.window,
                 style::SetAttribute(Attribute::Reset), // so that the rest of the line isn't underlined
-                style::SetBackgroundColor(highlight_bg),
+                style::SetAttribute(theme.highlight_attr),
+                style::SetBackgroundColor(theme.highlight_bg),
                 style::Print(" ".repeat(width.saturating_sub(item_size))),
             )?;
         }
@@ -377,7 +648,7 @@ impl<'a> TereTui<'a> {
     }
 
     fn queue_clear_main_window(&mut self) -> CTResult<()> {
-        let (_, h) = main_window_size()?;
+        let (_, h) = main_window_size(self.window)?;
         for row in HEADER_SIZE..(h + HEADER_SIZE) {
             self.queue_clear_row(row)?;
         }
@@ -392,13 +663,13 @@ impl<'a> TereTui<'a> {
     }
 
     pub fn redraw_main_window(&mut self) -> CTResult<()> {
-        let (_, max_y) = main_window_size()?;
-        let mut win = self.window;
+        let (_, max_y) = main_window_size(self.window)?;
 
         // are there any matches?
         let any_matches = self.app_state.num_matching_items() > 0;
         let any_visible_items = self.app_state.num_visible_items() > 0;
         let is_search = self.app_state.is_searching();
+        self.app_state.load_visible_metadata();
 
         // Draw entries. No need to clear the whole main window, because draw_main_window_row takes
         // care of clearing each row when applicable.
@@ -409,7 +680,7 @@ impl<'a> TereTui<'a> {
             self.draw_main_window_row(row, highlight)?;
         }
 
-        win.flush()
+        self.window.flush()
     }
 
     fn redraw_all_windows(&mut self) -> CTResult<()> {
@@ -439,9 +710,76 @@ impl<'a> TereTui<'a> {
         Ok(())
     }
 
+    /// Change the folder. Its contents are read in the background, and the listing is updated
+    /// once they are ready, see `next_event`.
     pub fn change_dir(&mut self, path: &str) -> CTResult<()> {
         //TODO: if there are no visible items, don't do anything?
-        match self.app_state.change_dir(path) {
+        let res = self.app_state.begin_change_dir(path);
+        self.wait_for_dir(res)
+    }
+
+    /// Read the current folder again, keeping the cursor on the same item and the search as it
+    /// is. Like with `change_dir`, the contents are read in the background.
+    pub fn refresh_listing(&mut self) -> CTResult<()> {
+        let res = self.app_state.begin_refresh();
+        self.wait_for_dir(res)
+    }
+
+    /// Wait for a short while for the folder that started being read with result `res` to be
+    /// read, so that fast folders are shown without flickering, and show the result.
+    fn wait_for_dir(&mut self, mut res: IOResult<()>) -> CTResult<()> {
+        self.read_deadline = self
+            .app_state
+            .settings
+            .read_timeout
+            .map(|timeout| self.clock.now() + Duration::from_millis(timeout));
+        let deadline = self.clock.now() + LOADING_GRACE_PERIOD;
+        while res.is_ok() && self.app_state.is_loading() {
+            let now = self.clock.now();
+            if now >= deadline {
+                break;
+            }
+            if let Some(poll_res) = self.app_state.poll_dir_loading(deadline - now) {
+                res = poll_res;
+            }
+        }
+        self.show_change_dir_result(res)
+    }
+
+    /// Open, close or switch tabs with `change`, which is one of the tab methods of the app
+    /// state, and show the tab that is current after it.
+    fn change_tab(&mut self, change: fn(&mut TereAppState) -> IOResult<()>) -> CTResult<()> {
+        // these were for the previous tab
+        self.autocd_deadline = None;
+        self.read_deadline = None;
+        let res = change(&mut self.app_state);
+        self.show_change_dir_result(res)
+    }
+
+    /// Stop reading the folder that is being opened or read in the background, and show the
+    /// previous folder again, with the error message `msg`.
+    fn cancel_dir_loading(&mut self, msg: &str) -> CTResult<()> {
+        self.read_deadline = None;
+        match self.app_state.cancel_dir_loading() {
+            Ok(_) => {
+                self.show_change_dir_result(Ok(()))?;
+                self.error_message(msg)
+            }
+            Err(e) => self.show_change_dir_result(Err(e)),
+        }
+    }
+
+    /// Update the view after changing the folder, or after its contents have been read, or show
+    /// the error if that failed.
+    fn show_change_dir_result(&mut self, res: IOResult<()>) -> CTResult<()> {
+        let path = self.app_state.current_path.display();
+        match &res {
+            Ok(()) => tracing::debug!(%path, "changed folder"),
+            Err(e) => tracing::debug!(%path, error = ?e, "changing folder failed"),
+        }
+        // the previous folder is shown again if reading the new one failed
+        self.update_header()?;
+        match res {
             Err(e) => {
                 if cfg!(debug_assertions) {
                     self.error_message(&format!("{:?}", e))?;
@@ -449,10 +787,13 @@ impl<'a> TereTui<'a> {
                     self.error_message(&format!("{}", e))?;
                 }
             }
-            Ok(()) => {
-                self.update_header()?;
-                self.info_message("")?;
-            }
+            Ok(()) => match self.app_state.take_symlink_loop() {
+                Some(dir) => self.info_message(&tr_fmt(
+                    "Symlink loop: the link leads back to '{}', which was opened instead",
+                    &[&dir.display()],
+                ))?,
+                None => self.info_message("")?,
+            },
         }
         self.redraw_main_window()?;
         self.redraw_footer()?;
@@ -460,21 +801,32 @@ impl<'a> TereTui<'a> {
     }
 
     pub fn on_search_char(&mut self, c: char) -> CTResult<()> {
-        self.app_state.advance_search(&c.to_string());
+        if !self.app_state.advance_search(&c.to_string()) {
+            // the character was discarded, so the matches are the same as before
+            self.info_message(&tr_fmt("No matches with '{}'", &[&c]))?;
+            return self.redraw_footer();
+        }
         let n_matches = self.app_state.num_matching_items();
         if n_matches == 1 {
             // There's only one match, highlight it and then change dir if applicable
-            if let Some(timeout) = self.app_state.settings.autocd_timeout {
+            if self.app_state.should_autocd() {
+                // ok to unwrap, should_autocd checks that the timeout is set
+                let timeout = self.app_state.settings.autocd_timeout.unwrap();
                 self.highlight_row_exclusive(self.app_state.cursor_pos)?;
 
-                std::thread::sleep(std::time::Duration::from_millis(timeout));
-
-                // ignore keys that were pressed during sleep
-                while crossterm::event::poll(std::time::Duration::from_secs(0)).unwrap_or(false) {
-                    read_event()?;
-                }
-
-                self.change_dir("")?;
+                // Don't block here, the main event loop takes care of changing the folder once
+                // the timeout has passed, unless some other key is pressed before that.
+                self.autocd_deadline = Some(self.clock.now() + Duration::from_millis(timeout));
+                self.info_message("")?;
+                return self.redraw_footer();
+            } else if self.app_state.settings.autocd_mode == AutoCdMode::RequireEnter {
+                // Highlight only the match, but don't block. The main window is redrawn as usual
+                // after the next key press.
+                self.highlight_row_exclusive(self.app_state.cursor_pos)?;
+                self.info_message(tr("Only one match, press Enter to change to it"))?;
+                return self.redraw_footer();
+            } else {
+                self.info_message("")?;
             }
         } else if n_matches == 0 {
             self.info_message(NO_MATCHES_MSG)?;
@@ -486,8 +838,11 @@ impl<'a> TereTui<'a> {
         Ok(())
     }
 
-    pub fn erase_search_char(&mut self) -> CTResult<()> {
-        self.app_state.erase_search_char();
+    /// Erase the last `count` characters of the search.
+    pub fn erase_search_char(&mut self, count: usize) -> CTResult<()> {
+        for _ in 0..count {
+            self.app_state.erase_search_char();
+        }
 
         if self.app_state.num_matching_items() == 0 {
             self.info_message(NO_MATCHES_MSG)?;
@@ -501,36 +856,45 @@ impl<'a> TereTui<'a> {
     }
 
     pub fn update_main_window_dimensions(&mut self) -> CTResult<()> {
-        let (w, h) = main_window_size()?;
+        let (w, h) = main_window_size(self.window)?;
         self.app_state.update_main_window_dimensions(w, h);
         Ok(())
     }
 
-    pub fn on_arrow_key(&mut self, up: bool) -> CTResult<()> {
+    /// Move the cursor up or down by `count` items, or by `count` matches when searching.
+    pub fn on_arrow_key(&mut self, up: bool, count: usize) -> CTResult<()> {
         let dir = if up { -1 } else { 1 };
         if self.app_state.is_searching() {
             //TODO: handle case where 'is_searching' but there are no matches - move cursor?
-            self.app_state.move_cursor_to_adjacent_match(dir);
+            for _ in 0..count {
+                self.app_state.move_cursor_to_adjacent_match(dir);
+            }
             self.redraw_main_window()?;
         } else {
-            self.move_cursor(dir, true)?;
+            // wrapping around count times is the same as wrapping around the total amount once
+            self.move_cursor(dir * count as isize, true)?;
         }
         self.redraw_footer()
     }
 
-    // When the 'page up' or 'page down' keys are pressed
-    pub fn on_page_up_down(&mut self, up: bool) -> CTResult<()> {
-        if !self.app_state.is_searching() {
-            let (_, h) = main_window_size()?;
-            let delta = ((h - 1) as isize) * if up { -1 } else { 1 };
+    /// Move the cursor up or down by `count` screens, or by `count` half screens if `half` is
+    /// true. While searching, jump to the matches beyond the view instead.
+    pub fn on_page_up_down(&mut self, up: bool, half: bool, count: usize) -> CTResult<()> {
+        let dir = if up { -1 } else { 1 };
+        if self.app_state.is_searching() {
+            for _ in 0..count {
+                self.app_state.move_cursor_to_match_beyond_view(dir, half);
+            }
+            self.redraw_main_window()?;
+        } else {
+            let delta = (self.app_state.page_scroll_amount(half) * count) as isize * dir;
             self.move_cursor(delta, false)?;
-            self.redraw_footer()?;
-        } //TODO: how to handle page up / page down while searching? jump to the next match below view?
-        Ok(())
+        }
+        self.redraw_footer()
     }
 
     fn on_go_to_home(&mut self) -> CTResult<()> {
-        if let Some(path) = home_dir() {
+        if let Some(path) = self.app_state.home_dir() {
             if let Some(path) = path.to_str() {
                 self.change_dir(path)?;
             }
@@ -539,6 +903,23 @@ impl<'a> TereTui<'a> {
     }
 
     fn on_go_to_root(&mut self) -> CTResult<()> {
+        // There's no single root on Windows, so choose the drive. The root of the current drive
+        // is chosen with the letter of the current drive.
+        #[cfg(windows)]
+        {
+            let drives = available_drives();
+            if drives.len() > 1 && self.app_state.remote_host().is_none() {
+                let modal = drives.iter().fold(
+                    Modal::new(tr("Go to root"), tr("Choose the drive to go to:")),
+                    |modal, drive| modal.choice(drive.to_ascii_lowercase(), &format!("{}:", drive)),
+                );
+                return match self.show_modal(&modal)? {
+                    Some(drive) => self.change_dir(&format!("{}:\\", drive.to_ascii_uppercase())),
+                    None => Ok(()),
+                };
+            }
+        }
+        // on Windows, this is the root of the current drive
         self.change_dir("/")
     }
 
@@ -556,10 +937,18 @@ impl<'a> TereTui<'a> {
         Ok(())
     }
 
-    fn handle_mouse_event(&mut self, event: MouseEvent) -> CTResult<()> {
+    /// Handle a left mouse button event. Returns true if a file was chosen and the app should exit.
+    fn handle_mouse_event(&mut self, event: MouseEvent) -> CTResult<bool> {
         if event.row == 0 {
             //TODO: change to folder by clicking on path component in header
-            return Ok(());
+            return Ok(false);
+        }
+        let (_, h) = terminal_size_usize(self.window)?;
+        if event.row as usize == h - FOOTER_SIZE {
+            if event.kind == MouseEventKind::Up(MouseButton::Left) {
+                self.on_footer_click(event.column as usize)?;
+            }
+            return Ok(false);
         }
 
         if let Some(entry) = self
@@ -568,229 +957,1217 @@ impl<'a> TereTui<'a> {
         {
             let fname = entry.file_name_checked();
             if event.kind == MouseEventKind::Up(MouseButton::Left) {
+                if self.app_state.choose_file(&fname) {
+                    return Ok(true);
+                }
                 self.change_dir(&fname)?;
             } else {
                 self.app_state.move_cursor_to_filename(&fname);
                 self.redraw_main_window()?;
             }
         }
-        Ok(())
+        Ok(false)
+    }
+
+    /// Change the search mode that is shown at `column` of the footer, if any
+    fn on_footer_click(&mut self, column: usize) -> CTResult<()> {
+        let button = self
+            .footer_buttons
+            .iter()
+            .find(|(range, _)| range.contains(&column))
+            .map(|(_, button)| *button);
+        match button {
+            Some(FooterButton::FilterSearch) => {
+                self.app_state.toggle_filter_search();
+                self.redraw_main_window()?;
+                self.redraw_footer()
+            }
+            Some(FooterButton::GapSearchMode) => self.cycle_gap_search_mode(),
+            Some(FooterButton::CaseSensitiveMode) => self.cycle_case_sensitive_mode(),
+            None => Ok(()),
+        }
     }
 
     fn cycle_case_sensitive_mode(&mut self) -> CTResult<()> {
-        self.app_state.settings.case_sensitive = match self.app_state.settings.case_sensitive {
-            CaseSensitiveMode::IgnoreCase => CaseSensitiveMode::CaseSensitive,
-            CaseSensitiveMode::CaseSensitive => CaseSensitiveMode::SmartCase,
-            CaseSensitiveMode::SmartCase => CaseSensitiveMode::IgnoreCase,
-        };
-        self.app_state.advance_search("");
+        self.app_state.cycle_case_sensitive_mode();
         self.redraw_main_window()?;
         self.redraw_footer()?;
         Ok(())
     }
 
     fn cycle_gap_search_mode(&mut self) -> CTResult<()> {
-        self.app_state.settings.gap_search_mode = match self.app_state.settings.gap_search_mode {
-            GapSearchMode::GapSearchFromStart => GapSearchMode::NoGapSearch,
-            GapSearchMode::NoGapSearch => GapSearchMode::GapSearchAnywere,
-            GapSearchMode::GapSearchAnywere => GapSearchMode::GapSearchFromStart,
-        };
         //TODO: do the other stuff that self.on_search_char_does, notably, change dir if only one match. or should it?
-        self.app_state.advance_search("");
+        self.app_state.cycle_gap_search_mode();
         self.redraw_main_window()?;
         self.redraw_footer()?;
         Ok(())
     }
 
-    pub fn main_event_loop(&mut self) -> Result<(), TereError> {
-        #[allow(non_snake_case)]
-        let ALT = KeyModifiers::ALT;
-        #[allow(non_snake_case)]
-        let CONTROL = KeyModifiers::CONTROL;
+    /// Change how the current folder is shown with `change`, which is one of the methods of the
+    /// app state that change the view preferences, and tell the user what changed.
+    fn change_view(&mut self, change: fn(&mut TereAppState) -> IOResult<()>) -> CTResult<()> {
+        if let Err(e) = change(&mut self.app_state) {
+            return self.error_message(&e.to_string());
+        }
+        let prefs = self.app_state.view_prefs();
+        let msg = format!(
+            "{}{}, {}",
+            tr(&prefs.sort_mode.to_string()),
+            if prefs.reverse_sort { tr(" in reverse") } else { "" },
+            match (prefs.folders_only, prefs.show_hidden) {
+                (true, true) => tr("showing only folders"),
+                (true, false) => tr("showing only folders, not hidden ones"),
+                (false, true) => tr("showing all items"),
+                (false, false) => tr("showing all items except hidden ones"),
+            },
+        );
+        self.redraw_main_window()?;
+        self.redraw_footer()?;
+        self.info_message(&msg)
+    }
 
-        loop {
-            match read_event()? {
-                Event::Key(k) => match k.code {
-                    KeyCode::Right => self.change_dir("")?,
-                    KeyCode::Enter => {
-                        if self.app_state.settings.enter_is_cd_and_exit {
-                            self.change_dir("")?;
-                            break
-                        } else if self.app_state.settings.esc_is_cancel {
-                            break
-                        } else {
-                            self.change_dir("")?;
-                        }
-                    }
-                    KeyCode::Char(' ') if !self.app_state.is_searching() => {
-                        // If the first key is space, treat it like enter. It's probably pretty
-                        // rare to have a folder name starting with space.
-                        self.change_dir("")?;
-                    }
-                    KeyCode::Left => self.change_dir("..")?,
-                    KeyCode::Up if k.modifiers == ALT => {
-                        self.change_dir("..")?;
-                    }
-                    KeyCode::Up => self.on_arrow_key(true)?,
-                    KeyCode::Down if k.modifiers == ALT => {
-                        self.change_dir("")?;
-                    }
-                    KeyCode::Down => self.on_arrow_key(false)?,
+    fn cycle_filter_preset(&mut self) -> CTResult<()> {
+        if let Err(e) = self.app_state.cycle_filter_preset() {
+            return self.error_message(&e.to_string());
+        }
+        let msg = match self.app_state.filter_preset() {
+            Some(preset) => tr_fmt("Filter: {}", &[&preset.name]),
+            None => tr("No filter").to_string(),
+        };
+        self.redraw_main_window()?;
+        self.redraw_footer()?;
+        self.info_message(&msg)
+    }
 
-                    KeyCode::PageUp => self.on_page_up_down(true)?,
-                    KeyCode::PageDown => self.on_page_up_down(false)?,
+    fn toggle_browse_mode(&mut self) -> CTResult<()> {
+        self.app_state.settings.browse_mode = !self.app_state.settings.browse_mode;
+        self.browse_search_pending = false;
+        self.redraw_footer()?;
+        if self.app_state.settings.browse_mode {
+            self.info_message(tr("Browse mode: use h/j/k/l to navigate and '/' to search"))
+        } else {
+            self.info_message(tr("Type to search"))
+        }
+    }
 
-                    KeyCode::Home if k.modifiers == CONTROL => {
-                        self.on_go_to_home()?;
-                    }
-                    KeyCode::Char('h') if k.modifiers == CONTROL | ALT => {
-                        self.on_go_to_home()?;
-                    }
-                    KeyCode::Char('~') => {
-                        self.on_go_to_home()?;
-                    }
-                    KeyCode::Char('/') => {
-                        self.on_go_to_root()?;
-                    }
-                    KeyCode::Char('r') if k.modifiers == ALT => {
-                        self.on_go_to_root()?;
-                    }
+    /// Whether letters are navigation keys at the moment, see `TereSettings::browse_mode`
+    fn is_browsing(&self) -> bool {
+        self.app_state.settings.browse_mode
+            && !self.app_state.is_searching()
+            && !self.browse_search_pending
+    }
 
-                    KeyCode::Home => self.on_home_end(true)?,
-                    KeyCode::End => self.on_home_end(false)?,
+    /// Handle the key `k` in browse mode. Returns `None` if it's not a browse mode key, and
+    /// otherwise whether the app should exit.
+    fn on_browse_key(&mut self, k: &KeyEvent) -> Result<Option<bool>, TereError> {
+        let c = match k.code {
+            KeyCode::Char(c) if (k.modifiers - KeyModifiers::SHIFT).is_empty() => c,
+            _ => return Ok(None),
+        };
+        let action = match c {
+            'h' => Action::ChangeDirParent,
+            'j' => Action::CursorDown,
+            'k' => Action::CursorUp,
+            'l' => Action::ChangeDir,
+            'g' => Action::CursorTop,
+            'G' => Action::CursorBottom,
+            '~' => Action::ChangeDirHome,
+            'q' => Action::Exit,
+            '/' => {
+                self.browse_search_pending = true;
+                self.redraw_footer()?;
+                return Ok(Some(false));
+            }
+            _ => return Ok(None),
+        };
+        self.on_action(action).map(Some)
+    }
 
-                    KeyCode::Esc => {
-                        if self.app_state.is_searching() {
-                            self.app_state.clear_search();
-                            self.info_message("")?; // clear possible 'no matches' message
-                            self.redraw_main_window()?;
-                            self.redraw_footer()?;
-                        } else {
-                            if self.app_state.settings.esc_is_cancel {
-                                // exit with error on Esc, to avoid cd'ing
-                                let msg = format!("{}: Exited without changing folder",
-                                                  env!("CARGO_PKG_NAME"));
-                                return Err(TereError::ExitWithoutCd(msg));
-                            } else {
-                                break;
-                            }
-                        }
-                    }
+    fn toggle_mouse_capture(&mut self) -> CTResult<()> {
+        let enabled = !self.app_state.settings.mouse_enabled;
+        self.app_state.settings.mouse_enabled = enabled;
+        if enabled {
+            execute!(self.window, EnableMouseCapture)?;
+            self.info_message(tr("Mouse capture enabled"))
+        } else {
+            execute!(self.window, DisableMouseCapture)?;
+            self.info_message(tr("Mouse capture disabled, you can now select text with the mouse"))
+        }
+    }
 
-                    KeyCode::Char('?') => {
-                        self.help_view_loop()?;
-                    }
+    /// Temporarily leave the UI and run the command `cmd` in the terminal, and wait for it to exit.
+    /// The outer result is an error if restoring the UI fails, and the inner one if running the
+    /// command fails.
+    /// If `wait_for_key` is true, wait for a key press before returning to the UI, so that the
+    /// output of the command can be read.
+    fn run_in_terminal(
+        &mut self,
+        cmd: &mut std::process::Command,
+        wait_for_key: bool,
+    ) -> CTResult<std::io::Result<std::process::ExitStatus>> {
+        if self.app_state.settings.mouse_enabled {
+            execute!(self.window, DisableMouseCapture)?;
+        }
+        terminal::disable_raw_mode()?;
+        execute!(self.window, terminal::LeaveAlternateScreen, cursor::Show)?;
 
-                    // alt + hjkl
-                    KeyCode::Char('h') if k.modifiers == ALT => {
-                        self.change_dir("..")?;
-                    }
-                    KeyCode::Char('j') if k.modifiers == ALT => {
-                        self.on_arrow_key(false)?;
-                    }
-                    KeyCode::Char('k') if k.modifiers == ALT => {
-                        self.on_arrow_key(true)?;
-                    }
-                    KeyCode::Char('l') if k.modifiers == ALT => {
-                        self.change_dir("")?;
-                    }
+        // The stdout of tere is usually captured by the shell function, so the command has to
+        // write to stderr instead.
+        let status = cmd.stdout(std::io::stderr()).status();
 
-                    KeyCode::Char('r') if k.modifiers == CONTROL => {
-                        // refresh the current folder
-                        self.change_dir(".")?;
-                        self.info_message("Refreshed directory listing")?;
-                    }
+        terminal::enable_raw_mode()?;
+        if wait_for_key {
+            execute!(self.window, style::Print(format!("\r\n{}", tr("Press any key to return to tere"))))?;
+            while !matches!(self.read_event()?, Event::Key(_)) {}
+        }
+        execute!(self.window, terminal::EnterAlternateScreen, cursor::Hide)?;
+        if self.app_state.settings.mouse_enabled {
+            execute!(self.window, EnableMouseCapture)?;
+        }
+        Ok(status)
+    }
 
-                    // other chars with modifiers
-                    KeyCode::Char('q') if k.modifiers == ALT => {
-                        break;
-                    }
-                    KeyCode::Char('c') if k.modifiers == CONTROL => {
-                        // exit with error on ctl+c, to avoid cd'ing
-                        let msg = format!("{}: Exited without changing folder",
-                                          env!("CARGO_PKG_NAME"));
-                        return Err(TereError::ExitWithoutCd(msg));
-                    }
-                    KeyCode::Char('u') if (k.modifiers == ALT || k.modifiers == CONTROL) => {
-                        self.on_page_up_down(true)?;
-                    }
-                    KeyCode::Char('d') if (k.modifiers == ALT || k.modifiers == CONTROL) => {
-                        self.on_page_up_down(false)?;
-                    }
-                    KeyCode::Char('g') if k.modifiers == ALT => {
-                        // like vim 'gg'
-                        self.on_home_end(true)?;
-                    }
-                    KeyCode::Char('G') if k.modifiers.contains(ALT) => {
-                        self.on_home_end(false)?;
-                    }
+    /// Temporarily leave the UI and run the user's shell in the current folder. The listing is
+    /// refreshed once the shell exits, since the user might have changed something.
+    fn spawn_shell(&mut self) -> CTResult<()> {
+        if self.app_state.is_list_mode() {
+            return self.error_message(tr("Can't open a shell when choosing from a list read from stdin"));
+        }
 
-                    KeyCode::Char('c') if k.modifiers == ALT => {
-                        self.cycle_case_sensitive_mode()?;
-                    }
+        let shell = std::env::var("SHELL").unwrap_or_else(|_| {
+            if cfg!(windows) { "cmd" } else { "sh" }.to_string()
+        });
 
-                    KeyCode::Char('f') if k.modifiers == CONTROL => {
-                        self.cycle_gap_search_mode()?;
-                    }
+        let mut cmd = std::process::Command::new(&shell);
+        cmd.current_dir(&self.app_state.current_path);
+        let status = self.run_in_terminal(&mut cmd, false)?;
 
-                    KeyCode::Char('-') if !self.app_state.is_searching() => {
-                        // go up with '-', like vim does
-                        self.change_dir("..")?;
-                    }
+        self.change_dir(".")?;
+        self.redraw_all_windows()?;
+        match status {
+            Ok(_) => self.info_message(&tr_fmt("Returned from {}", &[&shell])),
+            Err(e) => self.error_message(&tr_fmt("Could not run '{}': {}", &[&shell, &e])),
+        }
+    }
 
-                    KeyCode::Char(c) => self.on_search_char(c)?,
+    fn open_in_editor(&mut self) -> CTResult<()> {
+        let path = match self.app_state.item_under_cursor_path() {
+            Some(path) if !path.ends_with("..") => path,
+            _ => return Ok(()),
+        };
 
-                    KeyCode::Backspace => {
-                        if self.app_state.is_searching() {
-                            self.erase_search_char()?;
-                        } else {
-                            self.change_dir("..")?;
-                        }
-                    }
+        let mut cmd = external::editor_command(&path);
+        let status = self.run_in_terminal(&mut cmd, false)?;
 
-                    _ => self.info_message(&format!("{:?}", k))?,
-                },
+        // the file might have been created or deleted
+        if !self.app_state.is_list_mode() {
+            self.change_dir(".")?;
+            self.app_state.move_cursor_to_filename(path.file_name().unwrap_or_default());
+        }
+        self.redraw_all_windows()?;
+        match status {
+            Ok(s) if s.success() => Ok(()),
+            Ok(s) => self.error_message(&tr_fmt("Editor exited with {}", &[&s])),
+            Err(e) => self.error_message(&tr_fmt("Could not run editor: {}", &[&e])),
+        }
+    }
 
-                Event::Resize(_, _) => {
-                    self.update_main_window_dimensions()?;
-                    self.redraw_all_windows()?;
-                }
+    /// Run the user-defined command template `template` on the marked items, or on the item
+    /// under the cursor if there are no marked items.
+    fn run_user_command(&mut self, template: &str) -> CTResult<()> {
+        let paths = if self.app_state.marked_items().is_empty() {
+            match self.app_state.item_under_cursor_path() {
+                Some(path) => vec![path],
+                None => return Ok(()),
+            }
+        } else {
+            self.app_state.marked_items().to_vec()
+        };
 
-                Event::Mouse(event) => match event.kind {
-                    MouseEventKind::Down(MouseButton::Left)
-                        | MouseEventKind::Drag(MouseButton::Left)
-                        | MouseEventKind::Up(MouseButton::Left)
-                        => self.handle_mouse_event(event)?,
-                    MouseEventKind::Up(MouseButton::Right) => self.change_dir("..")?,
+        let command = external::expand_command_template(template, &paths);
+        let mut cmd = external::shell_command(&command);
+        cmd.current_dir(&self.app_state.current_path);
+        let status = self.run_in_terminal(&mut cmd, true)?;
+
+        // the command might have changed the contents of the folder
+        if !self.app_state.is_list_mode() {
+            let cursor_item = self.app_state.item_under_cursor_path();
+            self.change_dir(".")?;
+            if let Some(fname) = cursor_item.as_ref().and_then(|p| p.file_name()) {
+                self.app_state.move_cursor_to_filename(fname);
+            }
+        }
+        self.redraw_all_windows()?;
+        match status {
+            Ok(s) if s.success() => self.info_message(&tr_fmt("Ran '{}'", &[&command])),
+            Ok(s) => self.error_message(&tr_fmt("'{}' exited with {}", &[&command, &s])),
+            Err(e) => self.error_message(&tr_fmt("Could not run '{}': {}", &[&command, &e])),
+        }
+    }
 
-                    //TODO: add configuration to jump multiple items on scroll
-                    MouseEventKind::ScrollUp   => self.on_arrow_key(true)?,
-                    MouseEventKind::ScrollDown => self.on_arrow_key(false)?,
+    /// Call the script function with the index `idx`, and make the changes that it requested.
+    /// Returns true if the app should exit.
+    #[cfg(feature = "rhai")]
+    fn run_script_function(&mut self, idx: usize) -> CTResult<bool> {
+        let context = script::ScriptContext {
+            current_dir: self.current_path(),
+            item_under_cursor: self.app_state.item_under_cursor_path(),
+            marked: self.app_state.marked_items().to_vec(),
+            search: self.app_state.search_string().clone(),
+            home_dir: self.app_state.home_dir(),
+        };
+        // ok to index and unwrap, RunScript is only bound to functions of a loaded script
+        let name = self.app_state.settings.script_functions[idx].clone();
+        let res = self.app_state.settings.script.as_ref().unwrap().call(&name, context);
+        let effects = match res {
+            Ok(effects) => effects,
+            Err(e) => {
+                self.error_message(&tr_fmt("Error in '{}': {}", &[&name, &e]))?;
+                return Ok(false);
+            }
+        };
 
-                    //e => self.info_message(&format!("{:?}", e))?, // for debugging
-                    _ => (),
-                },
+        for effect in effects {
+            match effect {
+                script::ScriptEffect::ChangeDir(path) => self.change_dir(&path.to_string_lossy())?,
+                script::ScriptEffect::Select(name) => {
+                    if self.app_state.move_cursor_to_filename(name) {
+                        self.redraw_main_window()?;
+                    }
+                }
+                script::ScriptEffect::Message(msg) => self.info_message(&msg)?,
+                script::ScriptEffect::Error(msg) => self.error_message(&msg)?,
+                script::ScriptEffect::Exit => return Ok(true),
             }
         }
+        Ok(false)
+    }
 
-        if self.app_state.settings.mouse_enabled {
-            execute!(self.window, DisableMouseCapture)?;
+    /// Open a file with its default app, or a folder in the file manager of the system.
+    fn open_with_default_app(&mut self, path: &Path) -> CTResult<()> {
+        // Don't wait for the app, it's usually a GUI app that runs in the background. Wait for it
+        // in another thread instead, so that it doesn't stay around as a zombie process.
+        match external::opener_command(path).spawn() {
+            Ok(mut child) => {
+                std::thread::spawn(move || child.wait());
+                self.info_message(&tr_fmt("Opened '{}'", &[&path.display()]))
+            }
+            Err(e) => self.error_message(&tr_fmt("Could not open '{}': {}", &[&path.display(), &e])),
         }
-        self.app_state.on_exit().map_err(TereError::from)
     }
 
-    fn help_view_loop(&mut self) -> CTResult<()> {
-        self.info_message("Use ↓/↑ or j/k to scroll. Press Esc, 'q', '?' or Ctrl+c to exit help.")?;
-
-        // We don't need the help view scroll state anywhere else, so not worth it to put in
-        // app_state, just keep it here.
-        let mut help_view_scroll: usize = 0;
+    /// Draw an input prompt in the info window, with the terminal cursor at the input position.
+    fn draw_prompt(&mut self, msg: &str, input: &InputPrompt) -> CTResult<()> {
+        let (w, h) = terminal_size_usize(self.window)?;
+        let info_win_row = h - FOOTER_SIZE - INFO_WIN_SIZE;
+        let msg_len = UnicodeSegmentation::graphemes(msg, true).count();
+        // if the input doesn't fit, scroll it so that the cursor stays in view
+        let n_skip = (msg_len + input.cursor_column() + 1).saturating_sub(w);
+        let text: String = UnicodeSegmentation::graphemes(input.text(), true).skip(n_skip).collect();
+        let cursor_col = msg_len + input.cursor_column() - n_skip;
 
-        // drawing the help takes care of clearing the window
-        self.draw_help_view(help_view_scroll)?;
+        self.queue_clear_row(info_win_row)?;
+        execute!(
+            self.window,
+            cursor::MoveTo(0, u16::try_from(info_win_row).unwrap_or(u16::MAX)),
+            style::SetAttribute(Attribute::Reset),
+            style::Print(msg.bold()),
+            style::Print(text),
+            cursor::MoveTo(
+                u16::try_from(cursor_col).unwrap_or(u16::MAX),
+                u16::try_from(info_win_row).unwrap_or(u16::MAX),
+            ),
+            cursor::Show,
+        )
+    }
 
-        loop {
-            match read_event()? {
+    /// Ask the user for a line of text in the info window, starting from the text `initial`.
+    /// Previous inputs with the same `kind` can be recalled with the arrow keys. Returns `None` if
+    /// the input was cancelled.
+    fn read_input(&mut self, kind: &'static str, msg: &str, initial: &str) -> CTResult<Option<String>> {
+        let mut input = InputPrompt::new(initial).with_history(self.prompt_history.get(kind));
+        self.draw_prompt(msg, &input)?;
+
+        let status = loop {
+            match self.read_event()? {
+                Event::Key(k) => match input.handle_key(&k) {
+                    PromptStatus::Editing => self.draw_prompt(msg, &input)?,
+                    status => break status,
+                },
+                Event::Resize(_, _) => {
+                    self.update_main_window_dimensions()?;
+                    self.redraw_all_windows()?;
+                    self.draw_prompt(msg, &input)?;
+                }
+                _ => {}
+            }
+        };
+
+        execute!(self.window, cursor::Hide)?;
+        self.info_message("")?;
+        Ok(match status {
+            PromptStatus::Submitted => {
+                if !input.text().is_empty() {
+                    self.prompt_history.add(kind, input.text());
+                }
+                Some(input.text().to_string())
+            }
+            _ => None,
+        })
+    }
+
+    /// Ask for a path and change to it. The path can be absolute or relative to the current
+    /// folder, and a leading '~' is expanded to the home folder.
+    fn go_to_path(&mut self) -> CTResult<()> {
+        if self.app_state.is_list_mode() {
+            return self.error_message(tr("Can't change folder when choosing from a list read from stdin"));
+        }
+        let path = match self.read_input("path", tr("Go to: "), "")? {
+            Some(path) if !path.is_empty() => path,
+            _ => return Ok(()),
+        };
+
+        self.change_dir(&self.expand_home(path))
+    }
+
+    /// Replace a '~' at the start of `path` with the home folder.
+    fn expand_home(&self, path: String) -> String {
+        match (path.strip_prefix('~'), self.app_state.home_dir()) {
+            (Some(rest), Some(home)) if rest.is_empty() || rest.starts_with(std::path::is_separator) => {
+                format!("{}{}", home.display(), rest)
+            }
+            _ => path,
+        }
+    }
+
+    /// Run the steps of the named command with the index `idx`. Returns true if the app should
+    /// exit.
+    fn run_named_command(&mut self, idx: usize) -> Result<bool, TereError> {
+        // ok to index, the index comes from the settings
+        let command = self.app_state.settings.named_commands[idx].clone();
+        for step in command.steps {
+            let uses_local_files = matches!(
+                step,
+                CommandStep::Run(_) | CommandStep::ChangeDirToOutput(_)
+            );
+            if uses_local_files && self.app_state.is_virtual_dir() {
+                self.error_message(tr("This can't be done in folders on other machines or in archives"))?;
+                return Ok(false);
+            }
+            match step {
+                CommandStep::Action(action) => {
+                    if self.on_action(action)? {
+                        return Ok(true);
+                    }
+                }
+                CommandStep::ChangeDir(path) => self.change_dir(&self.expand_home(path))?,
+                CommandStep::Run(template) => self.run_user_command(&template)?,
+                CommandStep::ChangeDirToOutput(command) => {
+                    let output = external::shell_command(&command)
+                        .current_dir(&self.app_state.current_path)
+                        .stdin(std::process::Stdio::null())
+                        .output();
+                    match output {
+                        Ok(out) if out.status.success() => {
+                            let stdout = String::from_utf8_lossy(&out.stdout);
+                            let path = stdout.lines().next().unwrap_or_default().to_string();
+                            self.change_dir(&self.expand_home(path))?;
+                        }
+                        Ok(out) => {
+                            let stderr = String::from_utf8_lossy(&out.stderr);
+                            let msg = stderr.lines().next().map_or_else(|| out.status.to_string(), String::from);
+                            self.error_message(&tr_fmt("'{}' failed: {}", &[&command, &msg]))?;
+                            return Ok(false);
+                        }
+                        Err(e) => {
+                            self.error_message(&tr_fmt("Could not run '{}': {}", &[&command, &e]))?;
+                            return Ok(false);
+                        }
+                    }
+                }
+            }
+        }
+        Ok(false)
+    }
+
+    /// Ask for a name and create a new folder in the current folder. If `enter` is true, change
+    /// to the new folder, otherwise move the cursor to it.
+    fn create_dir(&mut self, enter: bool) -> CTResult<()> {
+        if self.app_state.is_list_mode() {
+            return self.error_message(tr("Can't create folders when choosing from a list read from stdin"));
+        }
+
+        let name = match self.read_input("name", tr("New folder: "), "")? {
+            Some(name) if !name.is_empty() => name,
+            _ => return Ok(()),
+        };
+
+        if let Err(e) = self.app_state.create_dir(&name) {
+            return self.error_message(&tr_fmt("Could not create '{}': {}", &[&name, &e]));
+        }
+        if enter {
+            self.change_dir(&name)
+        } else {
+            self.redraw_main_window()?;
+            self.redraw_footer()?;
+            self.info_message(&tr_fmt("Created folder '{}'", &[&name]))
+        }
+    }
+
+    /// Ask for a name and create an empty file in the current folder.
+    fn create_file(&mut self) -> CTResult<()> {
+        if self.app_state.is_list_mode() {
+            return self.error_message(tr("Can't create files when choosing from a list read from stdin"));
+        }
+
+        let name = match self.read_input("name", tr("New file: "), "")? {
+            Some(name) if !name.is_empty() => name,
+            _ => return Ok(()),
+        };
+
+        if let Err(e) = self.app_state.create_file(&name) {
+            return self.error_message(&tr_fmt("Could not create '{}': {}", &[&name, &e]));
+        }
+        self.redraw_main_window()?;
+        self.redraw_footer()?;
+        self.info_message(&tr_fmt("Created file '{}'", &[&name]))
+    }
+
+    /// Ask for a new name for the item under the cursor, and rename it.
+    fn rename_item(&mut self) -> CTResult<()> {
+        if self.app_state.is_list_mode() {
+            return self.error_message(tr("Can't rename items when choosing from a list read from stdin"));
+        }
+        let old_name = match self.app_state.item_under_cursor_path() {
+            Some(path) => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            None => return Ok(()),
+        };
+        if old_name.is_empty() || old_name == ".." {
+            return Ok(());
+        }
+
+        let new_name = match self.read_input("name", tr("Rename to: "), &old_name)? {
+            Some(name) if name != old_name => name,
+            _ => return Ok(()),
+        };
+
+        if let Err(e) = self.app_state.rename_item(&old_name, &new_name) {
+            return self.error_message(&tr_fmt("Could not rename '{}': {}", &[&old_name, &e]));
+        }
+        self.redraw_main_window()?;
+        self.redraw_footer()?;
+        self.info_message(&tr_fmt("Renamed '{}' to '{}'", &[&old_name, &new_name]))
+    }
+
+    /// Ask the user to confirm something with a yes/no modal. Returns true if 'yes' was chosen.
+    fn confirm(&mut self, title: &str, msg: &str) -> CTResult<bool> {
+        Ok(self.show_modal(&Modal::yes_no(title, msg))? == Some('y'))
+    }
+
+    /// Move the marked items, or the item under the cursor if nothing is marked, to the trash
+    /// after asking for confirmation.
+    fn delete_items(&mut self) -> CTResult<()> {
+        if self.app_state.is_list_mode() {
+            return self.error_message(tr("Can't delete items when choosing from a list read from stdin"));
+        }
+        let paths = if self.app_state.marked_items().is_empty() {
+            match self.app_state.item_under_cursor_path() {
+                Some(path) if !path.ends_with("..") => vec![path],
+                _ => return Ok(()),
+            }
+        } else {
+            self.app_state.marked_items().to_vec()
+        };
+
+        let msg = match paths.as_slice() {
+            [path] => {
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                tr_fmt("Move '{}' to trash?", &[&name])
+            }
+            _ => tr_fmt("Move {} marked items to trash?", &[&paths.len()]),
+        };
+        if !self.confirm(tr("Delete"), &msg)? {
+            return Ok(());
+        }
+
+        let items = paths
+            .into_iter()
+            .map(|src| JobItem { src, dst: PathBuf::new(), overwrite: false })
+            .collect();
+        self.start_job(JobKind::Trash, items)
+    }
+
+    /// Copy, move or link the marked items to the current folder. If an item with the same name
+    /// already exists, ask what to do.
+    fn paste_marked(&mut self, mode: PasteMode) -> CTResult<()> {
+        if self.app_state.is_list_mode() {
+            return self.error_message(tr("Can't paste items when choosing from a list read from stdin"));
+        }
+        let sources = self.app_state.marked_items().to_vec();
+        if sources.is_empty() {
+            return self.error_message(tr("No marked items, mark items with Tab first"));
+        }
+
+        let dir = self.app_state.current_path.clone();
+        let mut items: Vec<JobItem> = vec![];
+
+        for src in sources.iter() {
+            let name = match src.file_name() {
+                Some(name) => name.to_os_string(),
+                None => continue,
+            };
+            let mut dst = dir.join(&name);
+            let mut overwrite = false;
+
+            // two marked items from different folders can have the same name
+            let clashes_in_batch = items.iter().any(|item| item.dst == dst);
+            if dst.symlink_metadata().is_ok() || clashes_in_batch {
+                if mode == PasteMode::Move && dst == *src {
+                    // already here, nothing to do
+                    self.app_state.unmark(src);
+                    continue;
+                }
+                let message = if clashes_in_batch {
+                    tr_fmt("Another marked item is also called '{}'.", &[&name.to_string_lossy()])
+                } else {
+                    tr_fmt("'{}' already exists in this folder.", &[&name.to_string_lossy()])
+                };
+                let mut modal = Modal::new(tr("File exists"), &message);
+                // overwriting would remove the item that is pasted there earlier in this batch
+                if !clashes_in_batch {
+                    modal = modal.choice('o', tr("Overwrite"));
+                }
+                let modal = modal
+                    .choice('s', tr("Skip"))
+                    .choice('r', tr("Rename"))
+                    .choice('a', tr("Abort"));
+                match self.show_modal(&modal)? {
+                    // overwriting an item with itself would delete it
+                    Some('o') if dst != *src => overwrite = true,
+                    Some('o') | Some('s') => continue,
+                    Some('r') => {
+                        let taken: Vec<PathBuf> = items.iter().map(|item| item.dst.clone()).collect();
+                        let suggestion = file_ops::unique_name(&dir, &name, &taken);
+                        match self.read_input("name", tr("New name: "), &suggestion.to_string_lossy())? {
+                            Some(new_name) if !new_name.is_empty() => {
+                                if let Err(e) = check_item_name(&new_name) {
+                                    return self.error_message(&e.to_string());
+                                }
+                                dst = dir.join(new_name);
+                            }
+                            _ => continue,
+                        }
+                        if dst.symlink_metadata().is_ok() || items.iter().any(|item| item.dst == dst) {
+                            return self.error_message(&tr_fmt("'{}' already exists", &[&dst.display()]));
+                        }
+                    }
+                    _ => return self.info_message(""),
+                }
+            }
+            items.push(JobItem { src: src.clone(), dst, overwrite });
+        }
+
+        if items.is_empty() {
+            self.redraw_footer()?;
+            return self.info_message(tr("Nothing to paste"));
+        }
+        self.start_job(JobKind::Paste(mode), items)
+    }
+
+    /// Start a file operation in the background. Its progress is shown in the info window while
+    /// it runs.
+    fn start_job(&mut self, kind: JobKind, items: Vec<JobItem>) -> CTResult<()> {
+        let job = FileJob::start(kind, items);
+        self.info_message(&job.status())?;
+        self.job = Some(job);
+        Ok(())
+    }
+
+    /// Check the progress of the running file operation, if any, and update the info window.
+    fn update_job(&mut self) -> CTResult<()> {
+        let job = match self.job.as_mut() {
+            Some(job) => job,
+            None => return Ok(()),
+        };
+        if job.update() {
+            // ok to unwrap, we just checked that there is a job
+            let job = self.job.take().unwrap();
+            return self.finish_job(job);
+        }
+        let status = if job.is_cancelled() {
+            tr("Cancelling...").to_string()
+        } else {
+            tr_fmt("{} - press Esc to cancel", &[&job.status()])
+        };
+        self.info_message(&status)
+    }
+
+    /// Update the listing and the marks after a file operation has finished, and show how it went.
+    fn finish_job(&mut self, job: FileJob) -> CTResult<()> {
+        for item in job.done_items() {
+            self.app_state.unmark(&item.src);
+        }
+
+        let dir = self.app_state.current_path.clone();
+        if job.affects_folder(&dir) {
+            if let Err(e) = self.app_state.refresh_after_file_op() {
+                return self.error_message(&e.to_string());
+            }
+            let first_pasted = job
+                .done_items()
+                .map(|item| &item.dst)
+                .find(|dst| dst.parent() == Some(&dir));
+            if let (JobKind::Paste(_), Some(dst)) = (job.kind(), first_pasted) {
+                self.app_state.move_cursor_to_filename(dst.file_name().unwrap_or_default());
+            }
+            self.redraw_main_window()?;
+        }
+        self.redraw_footer()?;
+
+        let n_done = job.done_items().count();
+        let done = job.kind().past_participle();
+        match job.error() {
+            Some(_) if job.is_cancelled() => {
+                let args: &[&dyn std::fmt::Display] = &[&done, &n_done, &job.num_items()];
+                self.info_message(&match job.num_items() {
+                    1 => tr_fmt("Cancelled. {} {} of {} item", args),
+                    _ => tr_fmt("Cancelled. {} {} of {} items", args),
+                })
+            }
+            Some((item, e)) => {
+                let msg = match job.kind() {
+                    JobKind::Paste(_) => tr_fmt("Could not paste '{}': {}", &[&item.src.display(), &e]),
+                    JobKind::Trash => {
+                        tr_fmt("Could not move '{}' to trash: {}", &[&item.src.display(), &e])
+                    }
+                };
+                self.error_message(&msg)
+            }
+            None => self.info_message(&match n_done {
+                1 => tr_fmt("{} {} item", &[&done, &n_done]),
+                _ => tr_fmt("{} {} items", &[&done, &n_done]),
+            }),
+        }
+    }
+
+    /// Wait at most `timeout` for an event. Fails with a `signals::Terminated` error if a
+    /// termination signal is received while waiting.
+    fn poll_event(&mut self, timeout: Duration) -> CTResult<bool> {
+        let signals = match &self.signals {
+            Some(signals) => signals.clone(),
+            None => return self.events.poll(timeout),
+        };
+        let mut remaining = timeout;
+        loop {
+            signals.check()?;
+            let step = remaining.min(SIGNAL_POLL_INTERVAL);
+            if self.events.poll(step)? {
+                return Ok(true);
+            }
+            remaining -= step;
+            if remaining.is_zero() {
+                return Ok(false);
+            }
+        }
+    }
+
+    /// Read the next event, like `poll_event`, but without a timeout.
+    fn read_event(&mut self) -> CTResult<Event> {
+        if self.signals.is_some() {
+            while !self.poll_event(SIGNAL_POLL_INTERVAL)? {}
+        }
+        let event = self.events.read()?;
+        tracing::debug!(?event, "input event");
+        Ok(event)
+    }
+
+    /// Wait for the folder that is being read to be opened before exiting, because the folder
+    /// that is printed on exit must be one that could actually be read. Reading can still be
+    /// cancelled with Esc, in which case the previous folder is printed.
+    fn wait_for_loading(&mut self) -> Result<(), TereError> {
+        while self.app_state.is_opening_dir() {
+            self.app_state.poll_dir_loading(LOADING_POLL_INTERVAL);
+            if self.events.poll(Duration::ZERO)? {
+                if let Event::Key(k) = self.events.read()? {
+                    if k.code == KeyCode::Esc {
+                        self.app_state.cancel_dir_loading()?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Wait for the running file operation to finish before exiting, so that it's not left half
+    /// done. It can still be cancelled with Esc.
+    fn wait_for_job(&mut self) -> CTResult<()> {
+        while self.job.is_some() {
+            self.update_job()?;
+            if self.events.poll(PROGRESS_INTERVAL)? {
+                if let Event::Key(k) = self.events.read()? {
+                    if k.code == KeyCode::Esc {
+                        if let Some(job) = &self.job {
+                            job.cancel();
+                        }
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_to_clipboard(&mut self, path: PathBuf) -> CTResult<()> {
+        // copy paths on other machines in the form that scp uses
+        let text = match self.app_state.remote_host() {
+            Some(host) => format!("{}:{}", host, path.display()),
+            None => path.display().to_string(),
+        };
+        clipboard::copy_osc52(&mut self.window, &text)?;
+        self.info_message(&tr_fmt("Copied '{}' to clipboard", &[&text]))
+    }
+
+    /// The message shown when a key that isn't bound to any action is pressed, which tells how to
+    /// open the help, if it's bound to some key.
+    fn unbound_key_message(&self, key: &KeyEvent) -> String {
+        let key = key_to_string(key);
+        match self.app_state.settings.keymap.bindings_for(Action::Help).next() {
+            Some(binding) => tr_fmt(
+                "{} is not bound to any action. Press '{}' to view help.",
+                &[&key, &key_to_string(&binding.key)],
+            ),
+            None => tr_fmt("{} is not bound to any action.", &[&key]),
+        }
+    }
+
+    /// Check whether `action` can be done right now, and show an error message if not.
+    fn check_action(&mut self, action: Action) -> CTResult<bool> {
+        tracing::debug!(action = action.name(), "action");
+        if action.modifies_files() && self.app_state.settings.no_file_ops {
+            self.error_message(tr("Modifying files is disabled with --no-file-ops"))?;
+            return Ok(false);
+        }
+        if action.uses_local_files() && self.app_state.is_virtual_dir() {
+            self.error_message(tr("This can't be done in folders on other machines or in archives"))?;
+            return Ok(false);
+        }
+        if action.modifies_files() && self.job.is_some() {
+            self.error_message(tr("Another file operation is running, wait for it or press Esc to cancel it"))?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Perform the given action. Returns true if the app should exit.
+    fn on_action(&mut self, action: Action) -> Result<bool, TereError> {
+        if !self.check_action(action)? {
+            return Ok(false);
+        }
+
+        match action {
+            Action::CursorUp => self.on_arrow_key(true, 1)?,
+            Action::CursorDown => self.on_arrow_key(false, 1)?,
+            Action::ChangeDir => {
+                if self.app_state.choose_file("") {
+                    return Ok(true);
+                }
+                self.change_dir("")?;
+            }
+            Action::ChangeDirParent => self.change_dir("..")?,
+            Action::ChangeDirAndExit => {
+                if !self.app_state.choose_file("") {
+                    self.change_dir("")?;
+                }
+                return Ok(true);
+            }
+            Action::Exit => {
+                if self.app_state.is_list_mode() && self.app_state.marked_items().is_empty() {
+                    // there is no current folder to print in stdin mode
+                    let msg = tr_fmt("{}: Exited without choosing an item", &[&env!("CARGO_PKG_NAME")]);
+                    return Err(TereError::ExitWithoutCd(msg));
+                }
+                return Ok(true);
+            }
+            Action::ExitWithoutCd => {
+                let msg = tr_fmt("{}: Exited without changing folder", &[&env!("CARGO_PKG_NAME")]);
+                return Err(TereError::ExitWithoutCd(msg));
+            }
+            Action::ChangeDirHome => self.on_go_to_home()?,
+            Action::ChangeDirRoot => self.on_go_to_root()?,
+            Action::RefreshListing => {
+                self.refresh_listing()?;
+                self.info_message(tr("Refreshed directory listing"))?;
+            }
+            Action::CursorUpScreen => self.on_page_up_down(true, false, 1)?,
+            Action::CursorDownScreen => self.on_page_up_down(false, false, 1)?,
+            Action::CursorUpHalfScreen => self.on_page_up_down(true, true, 1)?,
+            Action::CursorDownHalfScreen => self.on_page_up_down(false, true, 1)?,
+            Action::CursorTop => self.on_home_end(true)?,
+            Action::CursorBottom => self.on_home_end(false)?,
+            Action::EraseSearchChar => self.erase_search_char(1)?,
+            Action::ClearSearch => {
+                self.app_state.clear_search();
+                self.info_message("")?; // clear possible 'no matches' message
+                self.redraw_main_window()?;
+                self.redraw_footer()?;
+            }
+            Action::ChangeCaseSensitiveMode => self.cycle_case_sensitive_mode()?,
+            Action::ChangeGapSearchMode => self.cycle_gap_search_mode()?,
+            Action::ChangeSortMode => self.change_view(TereAppState::cycle_sort_mode)?,
+            Action::ReverseSort => self.change_view(TereAppState::toggle_reverse_sort)?,
+            Action::ToggleHidden => self.change_view(TereAppState::toggle_show_hidden)?,
+            Action::ToggleFoldersOnly => self.change_view(TereAppState::toggle_folders_only)?,
+            Action::CycleFilterPreset => self.cycle_filter_preset()?,
+            Action::ViewMenu => self.view_menu()?,
+            Action::ToggleBrowseMode => self.toggle_browse_mode()?,
+            Action::JumpToLabel => return self.jump_to_label(),
+            Action::ToggleMouseCapture => self.toggle_mouse_capture()?,
+            Action::ToggleMark => {
+                if self.app_state.toggle_mark_under_cursor() {
+                    // move to the next item, so that multiple items can be marked quickly. This
+                    // also redraws the marked row.
+                    self.move_cursor(1, false)?;
+                    self.redraw_footer()?;
+                }
+            }
+            Action::SpawnShell => self.spawn_shell()?,
+            Action::OpenInEditor => self.open_in_editor()?,
+            Action::OpenWithDefaultApp => match self.app_state.item_under_cursor_path() {
+                Some(path) if !path.ends_with("..") => self.open_with_default_app(&path)?,
+                _ => {}
+            },
+            Action::OpenFileManager => self.open_with_default_app(&self.current_path())?,
+            Action::GoToPath => self.go_to_path()?,
+            Action::NewTab => self.change_tab(TereAppState::new_tab)?,
+            Action::CloseTab => self.change_tab(TereAppState::close_tab)?,
+            Action::NextTab => self.change_tab(TereAppState::next_tab)?,
+            Action::PreviousTab => self.change_tab(TereAppState::previous_tab)?,
+            Action::CreateDir => self.create_dir(false)?,
+            Action::CreateDirAndEnter => self.create_dir(true)?,
+            Action::CreateFile => self.create_file()?,
+            Action::Rename => self.rename_item()?,
+            Action::Delete => self.delete_items()?,
+            Action::CopyMarkedHere => self.paste_marked(PasteMode::Copy)?,
+            Action::MoveMarkedHere => self.paste_marked(PasteMode::Move)?,
+            Action::LinkMarkedHere => self.paste_marked(PasteMode::Link)?,
+            Action::CopyPath => self.copy_to_clipboard(self.current_path())?,
+            Action::CopyItemPath => {
+                if let Some(path) = self.app_state.item_under_cursor_path() {
+                    self.copy_to_clipboard(path)?;
+                }
+            }
+            Action::ClearMarks => {
+                self.app_state.clear_marks();
+                self.info_message(tr("Cleared all marks"))?;
+                self.redraw_main_window()?;
+                self.redraw_footer()?;
+            }
+            Action::NextMark => self.move_to_adjacent_mark(1)?,
+            Action::PreviousMark => self.move_to_adjacent_mark(-1)?,
+            Action::Help => self.help_view_loop()?,
+            Action::CheatSheet => self.cheat_sheet_view()?,
+            Action::ShowLastError => self.show_last_error()?,
+            Action::CommandPalette => return self.command_palette(),
+            Action::RunCommand(idx) => {
+                // ok to index, the index comes from the settings
+                let template = self.app_state.settings.user_commands[idx].clone();
+                self.run_user_command(&template)?
+            }
+            Action::NamedCommand(idx) => return self.run_named_command(idx),
+            #[cfg(feature = "rhai")]
+            Action::RunScript(idx) => return Ok(self.run_script_function(idx)?),
+            #[cfg(not(feature = "rhai"))]
+            Action::RunScript(_) => {}
+        }
+        Ok(false)
+    }
+
+    /// Handle an action that can be coalesced (see `Action::can_coalesce`), together with any
+    /// repeats of the same key press that are already waiting to be handled. When a key is held
+    /// down, the repeats can arrive faster than a frame can be drawn, especially over slow
+    /// connections, so the UI would fall behind if each of them was drawn separately. Erasing
+    /// search characters is only coalesced until the search is empty, since the same key may do
+    /// something else when not searching.
+    fn on_coalesced_action(&mut self, action: Action, key: &KeyEvent) -> CTResult<()> {
+        if !self.check_action(action)? {
+            return Ok(());
+        }
+        let max_count = match action {
+            Action::EraseSearchChar => self.app_state.search_string().chars().count().max(1),
+            _ => usize::MAX,
+        };
+        let mut count = 1;
+        while count < max_count && self.pending_event.is_none() && self.poll_event(Duration::ZERO)? {
+            match self.read_event()? {
+                Event::Key(k) if k == *key => count += 1,
+                event => self.pending_event = Some(event),
+            }
+        }
+
+        match action {
+            Action::CursorUp => self.on_arrow_key(true, count),
+            Action::CursorDown => self.on_arrow_key(false, count),
+            Action::CursorUpScreen => self.on_page_up_down(true, false, count),
+            Action::CursorDownScreen => self.on_page_up_down(false, false, count),
+            Action::CursorUpHalfScreen => self.on_page_up_down(true, true, count),
+            Action::CursorDownHalfScreen => self.on_page_up_down(false, true, count),
+            Action::EraseSearchChar => self.erase_search_char(count),
+            _ => unreachable!("action {:?} can't be coalesced", action),
+        }
+    }
+
+    /// Cancel a pending auto-cd, and show all items again.
+    fn cancel_autocd(&mut self) -> CTResult<()> {
+        if self.autocd_deadline.take().is_some() {
+            self.redraw_main_window()?;
+            self.redraw_footer()?;
+        }
+        Ok(())
+    }
+
+    /// Key presses and mouse clicks cancel a pending auto-cd.
+    fn cancel_autocd_on_input(&mut self, event: &Event) -> CTResult<()> {
+        match event {
+            Event::Key(_) => self.cancel_autocd(),
+            Event::Mouse(MouseEvent { kind, .. }) if *kind != MouseEventKind::Moved => {
+                self.cancel_autocd()
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Wait for the next event. If an auto-cd is pending, change the folder once its timeout has
+    /// passed, and update the countdown in the footer while waiting. Key presses and mouse clicks
+    /// cancel the auto-cd, and are returned to be processed normally. The progress of a running
+    /// file operation, the contents of a folder that is being read, and the listing of the
+    /// current folder if other programs have changed it, are updated while waiting, too.
+    fn next_event(&mut self) -> CTResult<Event> {
+        if let Some(event) = self.pending_event.take() {
+            self.cancel_autocd_on_input(&event)?;
+            return Ok(event);
+        }
+        loop {
+            let mut timeout = None;
+            if let Some(deadline) = self.autocd_deadline {
+                let now = self.clock.now();
+                if now >= deadline {
+                    self.autocd_deadline = None;
+                    self.change_dir("")?;
+                    continue;
+                }
+                timeout = Some(std::cmp::min(deadline - now, AUTOCD_INDICATOR_INTERVAL));
+            }
+            if let Some(deadline) = self.read_deadline {
+                let now = self.clock.now();
+                if !self.app_state.is_opening_dir() {
+                    self.read_deadline = None;
+                } else if now >= deadline {
+                    let path = self.app_state.current_path.display();
+                    let msg = tr_fmt("Timed out opening '{}'", &[&path]);
+                    self.cancel_dir_loading(&msg)?;
+                    continue;
+                } else {
+                    timeout = Some(timeout.map_or(deadline - now, |t| t.min(deadline - now)));
+                }
+            }
+            if self.job.is_some() {
+                timeout = Some(timeout.map_or(PROGRESS_INTERVAL, |t| t.min(PROGRESS_INTERVAL)));
+            }
+            if self.app_state.is_loading() {
+                timeout = Some(timeout.map_or(LOADING_POLL_INTERVAL, |t| t.min(LOADING_POLL_INTERVAL)));
+            }
+            if self.app_state.is_watching() {
+                timeout = Some(timeout.map_or(WATCH_POLL_INTERVAL, |t| t.min(WATCH_POLL_INTERVAL)));
+            }
+            let timeout = match timeout {
+                Some(timeout) => timeout,
+                None => return self.read_event(),
+            };
+
+            if self.poll_event(timeout)? {
+                let event = self.read_event()?;
+                self.cancel_autocd_on_input(&event)?;
+                return Ok(event);
+            }
+            if self.autocd_deadline.is_some() {
+                self.redraw_footer()?;
+            }
+            match self.app_state.poll_dir_loading(Duration::ZERO) {
+                Some(res) => self.show_change_dir_result(res)?,
+                // update the number of items read so far
+                None if self.app_state.is_loading() => self.redraw_footer()?,
+                None => {}
+            }
+            self.update_job()?;
+            // don't move the items around while an auto-cd is pending
+            if self.autocd_deadline.is_none() {
+                match self.app_state.refresh_if_changed() {
+                    Ok(true) => {
+                        self.redraw_main_window()?;
+                        self.redraw_footer()?;
+                    }
+                    Ok(false) => {}
+                    Err(e) => self.error_message(&tr_fmt("Could not refresh the listing: {}", &[&e]))?,
+                }
+            }
+        }
+    }
+
+    pub fn main_event_loop(&mut self) -> Result<(), TereError> {
+        let res = self.run_event_loop().or_else(|e| self.on_termination_signal(e));
+        // don't exit in the middle of a file operation
+        self.wait_for_job()?;
+        res?;
+
+        self.wait_for_loading()?;
+        if self.app_state.settings.mouse_enabled {
+            execute!(self.window, DisableMouseCapture)?;
+        }
+        self.app_state.extract_archived_items()?;
+        self.app_state.on_exit().map_err(TereError::from)
+    }
+
+    /// If `err` was caused by a termination signal, exit without changing the folder, or like
+    /// with the Exit action if `--cd-on-signal` was given. A running file operation is cancelled.
+    /// Other errors are returned as they are.
+    fn on_termination_signal(&mut self, err: TereError) -> Result<(), TereError> {
+        let signal = match &err {
+            TereError::Io(e) => signals::terminated_by(e),
+            _ => None,
+        };
+        let signal = match signal {
+            Some(signal) => signal,
+            None => return Err(err),
+        };
+        if let Some(job) = &self.job {
+            job.cancel();
+        }
+        self.app_state.cancel_dir_loading()?;
+        if self.app_state.settings.cd_on_signal && !self.app_state.is_list_mode() {
+            Ok(())
+        } else {
+            Err(TereError::ExitWithoutCd(tr_fmt(
+                "{}: Exited on signal {}",
+                &[&env!("CARGO_PKG_NAME"), &signal],
+            )))
+        }
+    }
+
+    fn run_event_loop(&mut self) -> Result<(), TereError> {
+        loop {
+            match self.next_event()? {
+                Event::Key(k) if k.code == KeyCode::Esc && self.job.is_some() => {
+                    // Esc cancels the running file operation instead of exiting
+                    if let Some(job) = &self.job {
+                        job.cancel();
+                    }
+                    self.update_job()?;
+                }
+                Event::Key(k) if k.code == KeyCode::Esc && self.app_state.is_loading() => {
+                    // likewise, Esc cancels reading a folder that is slow to open
+                    let path = self.app_state.current_path.display();
+                    let msg = tr_fmt("Cancelled reading '{}'", &[&path]);
+                    self.cancel_dir_loading(&msg)?;
+                }
+                Event::Key(k) => {
+                    let browsing = self.is_browsing();
+                    if browsing {
+                        match self.on_browse_key(&k)? {
+                            Some(true) => break,
+                            Some(false) => continue,
+                            None => {}
+                        }
+                    }
+                    // after '/' in browse mode, the next key either starts the search or
+                    // cancels it
+                    if std::mem::take(&mut self.browse_search_pending) {
+                        match k.code {
+                            KeyCode::Char(c) if (k.modifiers - KeyModifiers::SHIFT).is_empty() => {
+                                self.on_search_char(c)?;
+                                continue;
+                            }
+                            KeyCode::Esc => {
+                                self.redraw_footer()?;
+                                continue;
+                            }
+                            _ => self.redraw_footer()?,
+                        }
+                    }
+                    let context = if self.app_state.is_searching() {
+                        ActionContext::Searching
+                    } else {
+                        ActionContext::NotSearching
+                    };
+
+                    match self.app_state.settings.keymap.get(&k, context) {
+                        Some(action) if action.can_coalesce() => {
+                            self.on_coalesced_action(action, &k)?
+                        }
+                        Some(action) => {
+                            if self.on_action(action)? {
+                                break;
+                            }
+                        }
+                        None => match k.code {
+                            KeyCode::Char(c) if !browsing => self.on_search_char(c)?,
+                            _ => match self.app_state.settings.unbound_key {
+                                UnboundKeyBehavior::Ignore => {}
+                                UnboundKeyBehavior::Message => {
+                                    self.info_message(&self.unbound_key_message(&k))?
+                                }
+                                UnboundKeyBehavior::Action(action) => {
+                                    if self.on_action(action)? {
+                                        break;
+                                    }
+                                }
+                            },
+                        },
+                    }
+                }
+
+                Event::Resize(_, _) => {
+                    self.update_main_window_dimensions()?;
+                    self.redraw_all_windows()?;
+                    if self.autocd_deadline.is_some() {
+                        self.highlight_row_exclusive(self.app_state.cursor_pos)?;
+                    }
+                }
+
+                Event::Mouse(event) => match event.kind {
+                    MouseEventKind::Down(MouseButton::Left)
+                        | MouseEventKind::Drag(MouseButton::Left)
+                        | MouseEventKind::Up(MouseButton::Left)
+                        => {
+                            let file_chosen = self.handle_mouse_event(event)?;
+                            if file_chosen {
+                                break;
+                            }
+                        }
+                    MouseEventKind::Up(MouseButton::Right) => self.change_dir("..")?,
+
+                    //TODO: add configuration to jump multiple items on scroll
+                    MouseEventKind::ScrollUp   => self.on_arrow_key(true, 1)?,
+                    MouseEventKind::ScrollDown => self.on_arrow_key(false, 1)?,
+
+                    //e => self.info_message(&format!("{:?}", e))?, // for debugging
+                    _ => (),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    fn help_view_loop(&mut self) -> CTResult<()> {
+        self.info_message(tr("Use ↓/↑, j/k or the mouse wheel to scroll. Press Esc, 'q', '?' or Ctrl+c, or click [x] to exit help."))?;
+
+        // We don't need the help view scroll state anywhere else, so not worth it to put in
+        // app_state, just keep it here.
+        let mut help_view_scroll: usize = 0;
+
+        // drawing the help takes care of clearing the window
+        self.draw_help_view(help_view_scroll)?;
+
+        loop {
+            match self.read_event()? {
                 Event::Key(k) => match k.code {
                     KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('?') => {
                         self.info_message("")?;
@@ -815,6 +2192,22 @@ impl<'a> TereTui<'a> {
                     _ => {}
                 },
 
+                Event::Mouse(event) => match event.kind {
+                    MouseEventKind::ScrollDown => {
+                        help_view_scroll += 1;
+                        self.draw_help_view(help_view_scroll)?;
+                    }
+                    MouseEventKind::ScrollUp => {
+                        help_view_scroll = help_view_scroll.saturating_sub(1);
+                        self.draw_help_view(help_view_scroll)?;
+                    }
+                    MouseEventKind::Up(MouseButton::Left) if self.is_help_close_button(&event)? => {
+                        self.info_message("")?;
+                        return self.redraw_all_windows();
+                    }
+                    _ => {}
+                },
+
                 Event::Resize(_, _) => {
                     self.update_main_window_dimensions()?;
                     // Redraw all windows except for main window
@@ -823,10 +2216,368 @@ impl<'a> TereTui<'a> {
                     self.redraw_footer()?;
                     self.draw_help_view(help_view_scroll)?;
                 }
+            }
+        }
+    }
+
+    /// Show the cheat sheet on top of the main window until any key is pressed.
+    fn cheat_sheet_view(&mut self) -> CTResult<()> {
+        self.draw_cheat_sheet()?;
+        loop {
+            match self.read_event()? {
+                Event::Key(_) | Event::Mouse(MouseEvent { kind: MouseEventKind::Down(_), .. }) => {
+                    break;
+                }
+                Event::Resize(_, _) => {
+                    self.update_main_window_dimensions()?;
+                    self.redraw_all_windows()?;
+                    self.draw_cheat_sheet()?;
+                }
+                _ => {}
+            }
+        }
+        self.redraw_all_windows()
+    }
+
+    /// Draw the cheat sheet as a box in the middle of the main window.
+    fn draw_cheat_sheet(&mut self) -> CTResult<()> {
+        let sections = get_cheat_sheet(&self.app_state.settings.keymap);
+
+        let key_width = sections
+            .iter()
+            .flat_map(|(_, entries)| entries.iter().map(|(key, _)| key.chars().count()))
+            .max()
+            .unwrap_or(0);
+
+        // the rows inside the box, with a flag telling whether they should be bolded
+        let mut rows: Vec<(String, bool)> = vec![];
+        for (i, (section_title, entries)) in sections.iter().enumerate() {
+            if i > 0 {
+                rows.push((String::new(), false));
+            }
+            rows.push((section_title.to_string(), true));
+            for (key, desc) in entries {
+                let padding = key_width - key.chars().count();
+                rows.push((format!("{}{}  {}", key, " ".repeat(padding), desc), false));
+            }
+        }
+
+        self.draw_box(
+            &format!(" {} ", tr("Cheat sheet")),
+            rows,
+            &format!(" {} ", tr("Press any key to close")),
+        )
+    }
+
+    /// Show the command palette, and perform the action that is chosen from it. Returns true if
+    /// the app should exit.
+    fn command_palette(&mut self) -> Result<bool, TereError> {
+        let settings = &self.app_state.settings;
+        let entries = Action::all()
+            .iter()
+            .copied()
+            .chain(settings.user_actions())
+            .filter(|action| *action != Action::CommandPalette)
+            .map(|action| PaletteEntry {
+                action,
+                description: action_description(action, settings).replace('`', ""),
+                shortcut: settings
+                    .keymap
+                    .bindings_for(action)
+                    .next()
+                    .map_or_else(String::new, |binding| key_to_string(&binding.key)),
+            })
+            .collect();
+        let mut palette = Palette::new(entries, &settings.gap_search_mode);
+
+        self.draw_palette(&palette)?;
+        let action = loop {
+            match self.read_event()? {
+                Event::Key(k) => match palette.handle_key(&k) {
+                    PaletteStatus::Pending => self.draw_palette(&palette)?,
+                    PaletteStatus::Chosen(action) => break Some(action),
+                    PaletteStatus::Cancelled => break None,
+                },
+                Event::Resize(_, _) => {
+                    self.update_main_window_dimensions()?;
+                    self.redraw_all_windows()?;
+                    self.draw_palette(&palette)?;
+                }
+                _ => {}
+            }
+        };
+        self.redraw_all_windows()?;
+        match action {
+            Some(action) => self.on_action(action),
+            None => Ok(false),
+        }
+    }
+
+    /// Draw the command palette as a box in the middle of the main window, with the query on the
+    /// first row and the matching actions below it.
+    fn draw_palette(&mut self, palette: &Palette) -> CTResult<()> {
+        let (_, h) = main_window_size(self.window)?;
+        // leave room for the borders, the query and the empty row below it
+        let n_rows = h.saturating_sub(6).clamp(1, 20);
+
+        // the box keeps the same size while typing, so that it doesn't have to be cleared
+        let width = |text: &str| text.chars().count();
+        let desc_width = palette.entries().iter().map(|e| width(&e.description)).max().unwrap_or(0);
+        let key_width = palette.entries().iter().map(|e| width(&e.shortcut)).max().unwrap_or(0);
+        let row_width = desc_width + key_width + 4;
+
+        let query = format!("> {}", palette.query());
+        let padding = row_width.saturating_sub(width(&query));
+        let mut rows = vec![(format!("{}{}", query, " ".repeat(padding)), false), (String::new(), false)];
+        for (entry, selected) in palette.visible_entries(n_rows) {
+            let padding = desc_width - width(&entry.description) + key_width - width(&entry.shortcut);
+            let marker = if selected { '>' } else { ' ' };
+            let row = format!("{} {}  {}{}", marker, entry.description, " ".repeat(padding), entry.shortcut);
+            rows.push((row, selected));
+        }
+        rows.resize(n_rows + 2, (String::new(), false));
+
+        self.draw_box(
+            &format!(" {} ", tr("Command palette")),
+            rows,
+            &format!(" {} ", tr("Enter to run, Esc to cancel")),
+        )
+    }
+
+    /// Show the view menu until it's closed. The chosen options are applied right away, so that
+    /// their effect on the listing can be seen behind the menu.
+    fn view_menu(&mut self) -> CTResult<()> {
+        if self.app_state.is_list_mode() {
+            return self.error_message(tr("Can't change the view when choosing from a list read from stdin"));
+        }
+        let mut menu = ViewMenu::new(&self.app_state.view_prefs(), self.app_state.settings.filter_presets.len());
+        self.draw_view_menu(&menu)?;
+        loop {
+            match self.read_event()? {
+                Event::Key(k) => match menu.handle_key(&k) {
+                    MenuStatus::Pending => {}
+                    MenuStatus::Chosen { item, close } => {
+                        let prefs = self.app_state.view_prefs();
+                        let res = match (item, item.apply(prefs)) {
+                            (_, Some(prefs)) => self.app_state.set_view_prefs(prefs),
+                            (MenuItem::Filter(preset), None) => self.app_state.set_filter_preset(preset),
+                            _ => Ok(()),
+                        };
+                        if let Err(e) = res {
+                            self.redraw_all_windows()?;
+                            return self.error_message(&e.to_string());
+                        }
+                        if close {
+                            break;
+                        }
+                        self.redraw_main_window()?;
+                        self.redraw_footer()?;
+                    }
+                    MenuStatus::Closed => break,
+                },
+                Event::Resize(_, _) => {
+                    self.update_main_window_dimensions()?;
+                    self.redraw_all_windows()?;
+                }
+                _ => {}
+            }
+            self.draw_view_menu(&menu)?;
+        }
+        self.redraw_all_windows()
+    }
 
+    /// Draw the view menu as a box in the middle of the main window, with a mark showing which
+    /// options are on.
+    fn draw_view_menu(&mut self, menu: &ViewMenu) -> CTResult<()> {
+        let prefs = self.app_state.view_prefs();
+        let active_preset = self.app_state.filter_preset().map(|p| p.name.clone());
+        let presets = &self.app_state.settings.filter_presets;
+        let radio = |on: bool| if on { "(*)" } else { "( )" };
+        let check = |on: bool| if on { "[x]" } else { "[ ]" };
+        let rows = menu
+            .items()
+            .iter()
+            .enumerate()
+            .map(|(i, item)| {
+                let text = match item {
+                    MenuItem::Sort(mode) => format!("{} {}", radio(prefs.sort_mode == *mode), match mode {
+                        SortMode::Name => tr("Sort by name"),
+                        SortMode::Modified => tr("Sort by modification time"),
+                        SortMode::Size => tr("Sort by size"),
+                    }),
+                    MenuItem::Reverse => format!("{} {}", check(prefs.reverse_sort), tr("Reverse order")),
+                    MenuItem::FoldersOnly => format!("{} {}", check(prefs.folders_only), tr("Show only folders")),
+                    MenuItem::ShowHidden => format!("{} {}", check(prefs.show_hidden), tr("Show hidden items")),
+                    MenuItem::Filter(None) => format!("{} {}", radio(active_preset.is_none()), tr("No filter")),
+                    MenuItem::Filter(Some(idx)) => {
+                        let name = &presets[*idx].name;
+                        let on = active_preset.as_ref() == Some(name);
+                        format!("{} {}", radio(on), tr_fmt("Filter: {}", &[name]))
+                    }
+                };
+                let selected = i == menu.selected();
+                (format!("{} {}", if selected { '>' } else { ' ' }, text), selected)
+            })
+            .collect();
+        self.draw_box(
+            &format!(" {} ", tr("View")),
+            rows,
+            &format!(" {} ", tr("Enter to choose, Space to apply, Esc to close")),
+        )
+    }
+
+    /// Show a label on each item on the screen, and move the cursor to the item whose label is
+    /// typed, or enter it if the label is typed in uppercase. Returns true if the app should
+    /// exit, which can happen when a file is entered in file selection mode.
+    fn jump_to_label(&mut self) -> Result<bool, TereError> {
+        let (_, h) = main_window_size(self.window)?;
+        let n_rows = self
+            .app_state
+            .num_visible_items()
+            .saturating_sub(self.app_state.scroll_pos)
+            .min(h);
+        if n_rows == 0 {
+            return Ok(false);
+        }
+        let mut jump = JumpLabels::new(n_rows);
+        self.info_message(tr("Type a label to jump to the item, or type it in uppercase to enter the item"))?;
+        self.draw_jump_labels(&jump)?;
+        loop {
+            match self.read_event()? {
+                Event::Key(k) => match jump.handle_key(&k) {
+                    JumpStatus::Pending => self.draw_jump_labels(&jump)?,
+                    JumpStatus::Chosen { row, enter } => {
+                        self.app_state.move_cursor_to(self.app_state.scroll_pos + row);
+                        self.info_message("")?;
+                        self.redraw_main_window()?;
+                        self.redraw_footer()?;
+                        return if enter { self.on_action(Action::ChangeDir) } else { Ok(false) };
+                    }
+                    JumpStatus::Cancelled => break,
+                },
+                // the labels would change with the number of rows
+                Event::Resize(_, _) => {
+                    self.update_main_window_dimensions()?;
+                    break;
+                }
                 _ => {}
             }
         }
+        self.info_message("")?;
+        self.redraw_all_windows()?;
+        Ok(false)
+    }
+
+    /// Draw the listing with the rest of the jump label of each row over the beginning of the
+    /// item on it
+    fn draw_jump_labels(&mut self, jump: &JumpLabels) -> CTResult<()> {
+        self.redraw_main_window()?;
+        for (row, label) in jump.remaining().enumerate() {
+            if let Some(label) = label {
+                queue!(
+                    self.window,
+                    cursor::MoveTo(0, u16::try_from(row + HEADER_SIZE).unwrap_or(u16::MAX)),
+                    style::SetAttribute(Attribute::Reset),
+                    style::PrintStyledContent(label.bold().reverse()),
+                )?;
+            }
+        }
+        execute!(self.window)
+    }
+
+    /// Move the cursor to the next (`dir` > 0) or previous marked item, wrapping around.
+    fn move_to_adjacent_mark(&mut self, dir: isize) -> CTResult<()> {
+        if self.app_state.move_cursor_to_adjacent_mark(dir) {
+            self.redraw_main_window()?;
+            self.redraw_footer()
+        } else {
+            self.info_message(tr("No marked items in this folder"))
+        }
+    }
+
+    /// Draw a box with a border in the middle of the main window. The title and the footer are
+    /// drawn on the top and bottom borders, and each row has a flag telling whether it should be
+    /// bolded.
+    fn draw_box(&mut self, title: &str, rows: Vec<(String, bool)>, footer: &str) -> CTResult<()> {
+        let inner_width = rows
+            .iter()
+            .map(|(text, _)| text.chars().count())
+            .chain([title.chars().count(), footer.chars().count()])
+            .max()
+            .unwrap_or(0);
+
+        let (w, h) = main_window_size(self.window)?;
+        let box_width = inner_width + 4;
+        let box_height = rows.len() + 2;
+        let x = u16::try_from(w.saturating_sub(box_width) / 2).unwrap_or(u16::MAX);
+        let y = HEADER_SIZE + h.saturating_sub(box_height) / 2;
+        let max_row = HEADER_SIZE + h;
+
+        let border = |left: &str, text: &str, right: &str| {
+            let fill = "─".repeat(inner_width + 2 - text.chars().count());
+            format!("{}{}{}{}", left, text, fill, right)
+        };
+
+        let mut lines = vec![(border("┌", title, "┐"), false)];
+        lines.extend(rows.into_iter().map(|(text, bold)| {
+            let padding = inner_width - text.chars().count();
+            (format!("│ {}{} │", text, " ".repeat(padding)), bold)
+        }));
+        lines.push((border("└", footer, "┘"), false));
+
+        queue!(
+            self.window,
+            style::SetAttribute(Attribute::Reset),
+            style::ResetColor,
+        )?;
+        for (i, (line, bold)) in lines.iter().enumerate() {
+            let row = y + i;
+            if row >= max_row {
+                break;
+            }
+            let line: String = line.chars().take(w).collect();
+            queue!(
+                self.window,
+                cursor::MoveTo(x, u16::try_from(row).unwrap_or(u16::MAX)),
+                style::Print(if *bold { line.bold() } else { line.stylize() }),
+            )?;
+        }
+        self.window.flush()
+    }
+
+    fn draw_modal(&mut self, modal: &Modal) -> CTResult<()> {
+        let (w, _) = main_window_size(self.window)?;
+        // leave some room around the box, but don't make it too wide to read
+        let width = std::cmp::min(w.saturating_sub(8), 60);
+        let rows = modal.rows(width).into_iter().map(|row| (row, false)).collect();
+        self.draw_box(
+            &format!(" {} ", modal.title()),
+            rows,
+            &format!(" {} ", tr("Esc to cancel")),
+        )
+    }
+
+    /// Show a modal over the main window until one of its choices is chosen. Returns the key of
+    /// the choice, or `None` if the modal was cancelled.
+    fn show_modal(&mut self, modal: &Modal) -> CTResult<Option<char>> {
+        self.draw_modal(modal)?;
+        let choice = loop {
+            match self.read_event()? {
+                Event::Key(k) => match modal.handle_key(&k) {
+                    ModalStatus::Pending => {}
+                    ModalStatus::Chosen(c) => break Some(c),
+                    ModalStatus::Cancelled => break None,
+                },
+                Event::Resize(_, _) => {
+                    self.update_main_window_dimensions()?;
+                    self.redraw_all_windows()?;
+                    self.draw_modal(modal)?;
+                }
+                _ => {}
+            }
+        };
+        self.redraw_all_windows()?;
+        Ok(choice)
     }
 
     fn draw_help_view(&mut self, scroll: usize) -> CTResult<()> {
@@ -837,13 +2588,13 @@ impl<'a> TereTui<'a> {
             style::ResetColor,
         )?;
 
-        let (w, h) = main_window_size()?;
-        let help_text = get_formatted_help_text(w);
+        let (w, h) = main_window_size(self.window)?;
+        let help_text = get_formatted_help_text(w, &self.app_state.settings);
         for (i, line) in help_text
             .iter()
             .skip(scroll)
-            .chain(vec![vec![]].iter().cycle()) // add empty lines at the end
-            .take(h as usize)
+            .chain([vec![]].iter().cycle()) // add empty lines at the end
+            .take(h)
             .enumerate()
         {
             // Set up cursor position
@@ -870,8 +2621,547 @@ impl<'a> TereTui<'a> {
             )?;
         }
 
+        // the button for closing the help with the mouse
+        let (term_w, _) = terminal_size_usize(self.window)?;
+        queue!(
+            self.window,
+            cursor::MoveTo(
+                u16::try_from(term_w.saturating_sub(HELP_CLOSE_BUTTON.len())).unwrap_or(u16::MAX),
+                u16::try_from(HEADER_SIZE).unwrap_or(u16::MAX),
+            ),
+            style::PrintStyledContent(HELP_CLOSE_BUTTON.bold()),
+        )?;
+
         execute!(self.window)?;
 
         Ok(())
     }
+
+    /// Whether the mouse `event` is on the button that closes the help view
+    fn is_help_close_button(&self, event: &MouseEvent) -> CTResult<bool> {
+        let (w, _) = terminal_size_usize(self.window)?;
+        Ok(event.row as usize == HEADER_SIZE
+            && event.column as usize >= w.saturating_sub(HELP_CLOSE_BUTTON.len()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app_state::{DirSource, GapSearchMode, MemoryDirSource};
+    use backend::TestBackend;
+    use std::sync::Arc;
+
+    /// A folder tree with the items `paths`, where the ones ending with '/' are folders
+    fn memory_source(paths: &[&str]) -> MemoryDirSource {
+        let source = MemoryDirSource::new();
+        for path in paths {
+            if path.ends_with('/') {
+                source.add_dir(path);
+            } else {
+                source.add_file(path);
+            }
+        }
+        source
+    }
+
+    /// Draw the folder `cwd` of a folder tree with the items `paths` on `backend`
+    fn create_test_ui<'a>(
+        backend: &'a mut TestBackend,
+        cwd: &str,
+        paths: &[&str],
+    ) -> TereTui<'a, TestBackend> {
+        create_test_ui_with_source(backend, cwd, Arc::new(memory_source(paths)))
+    }
+
+    fn create_test_ui_with_source<'a>(
+        backend: &'a mut TestBackend,
+        cwd: &str,
+        source: Arc<dyn DirSource>,
+    ) -> TereTui<'a, TestBackend> {
+        let mut settings = crate::app_state::TereSettings {
+            no_banner: true,
+            ..Default::default()
+        };
+        settings.keymap = settings.default_keymap();
+        let state = TereAppState::with_dir_source(settings, cwd.into(), source, 1, 1).unwrap();
+        TereTui::new(state, backend).unwrap()
+    }
+
+    #[test]
+    fn test_draw_screen() {
+        let mut backend = TestBackend::new(80, 7);
+        drop(create_test_ui(&mut backend, "/home/user", &["/home/user/docs/", "/home/user/a.txt"]));
+        assert_eq!(
+            backend.screen_text(),
+            vec![
+                "/home/user",
+                "..",
+                "docs",
+                "a.txt",
+                "",
+                "",
+                "search:            gap search from start - smart case - 1 folder, 1 file - 2 / 3",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_header_truncation() {
+        let mut backend = TestBackend::new(12, 5);
+        drop(create_test_ui(&mut backend, "/home/user/projects", &["/home/user/projects/a"]));
+        // the end of the path is more interesting than the start
+        assert_eq!(backend.row_text(0), "ser/projects");
+        let style = backend.cell(0, 0).style;
+        assert!(style.bold && style.underlined);
+    }
+
+    #[test]
+    fn test_highlight_fill() {
+        let mut backend = TestBackend::new(20, 6);
+        let paths = ["/a/docs/", "/a/link", "/a/b.txt"];
+        drop(create_test_ui(&mut backend, "/a", &paths));
+        // the cursor starts on the first item after '..', and the highlight fills the whole row
+        assert_eq!(backend.row_text(2), "docs");
+        for x in 0..20 {
+            assert_eq!(backend.cell(x, 2).style.bg, Some(style::Color::Grey));
+        }
+        assert_eq!(backend.cell(0, 2).style.fg, Some(style::Color::Black));
+        assert!(backend.cell(0, 2).style.bold);
+        // the other rows are not highlighted, and files are dimmed
+        assert_eq!(backend.cell(0, 3).style.bg, None);
+        assert_eq!(backend.cell(10, 3).style.bg, None);
+        assert_eq!(backend.row_text(3), "b.txt");
+        assert!(backend.cell(0, 3).style.dim);
+    }
+
+    #[test]
+    fn test_themes() {
+        let mut backend = TestBackend::new(20, 6);
+        let paths = ["/a/docs/", "/a/b.txt"];
+
+        // the monochrome theme highlights with reversed text instead of colors
+        let mut ui = create_test_ui(&mut backend, "/a", &paths);
+        ui.app_state.settings.theme = theme::Theme::from_name("monochrome").unwrap();
+        ui.redraw_main_window().unwrap();
+        drop(ui);
+        for x in 0..20 {
+            let style = backend.cell(x, 2).style;
+            assert!(style.reversed);
+            assert_eq!((style.fg, style.bg), (None, None));
+        }
+        assert!(!backend.cell(0, 3).style.reversed);
+
+        // the high contrast theme doesn't dim files
+        let mut ui = create_test_ui(&mut backend, "/a", &paths);
+        ui.app_state.settings.theme = theme::Theme::from_name("high-contrast").unwrap();
+        ui.redraw_main_window().unwrap();
+        drop(ui);
+        assert_eq!(backend.cell(0, 2).style.bg, Some(style::Color::White));
+        assert!(!backend.cell(0, 2).style.reversed);
+        assert!(!backend.cell(0, 3).style.dim);
+    }
+
+    #[test]
+    fn test_footer_layout() {
+        let mut backend = TestBackend::new(90, 7);
+        let paths = ["/a/docs/", "/a/downloads/", "/a/b.txt"];
+        let mut ui = create_test_ui(&mut backend, "/a", &paths);
+        ui.on_search_char('d').unwrap();
+        ui.on_search_char('o').unwrap();
+        drop(ui);
+        // the search is on the left, and the other information is aligned to the right
+        let footer = backend.row_text(6);
+        assert!(footer.starts_with("search: do "));
+        // while searching, only the matching folders and files are counted
+        assert!(footer.ends_with(" gap search from start - smart case - 2 folders, 0 files - 1 / 2 / 4"));
+        assert_eq!(footer.len(), 90);
+        // the matching letters are underlined
+        assert!(backend.cell(0, 2).style.underlined);
+        assert!(backend.cell(1, 2).style.underlined);
+        assert!(!backend.cell(2, 2).style.underlined);
+
+        // if there's not enough room, the search is drawn over the other information
+        backend.resize(60, 7);
+        let mut ui = create_test_ui(&mut backend, "/a", &paths);
+        ui.on_search_char('d').unwrap();
+        ui.on_search_char('o').unwrap();
+        drop(ui);
+        assert_eq!(backend.row_text(6), "search: dotart - smart case - 2 folders, 0 files - 1 / 2 / 4");
+    }
+
+    #[test]
+    fn test_footer_click() {
+        let mut backend = TestBackend::new(90, 7);
+        let paths = ["/a/docs/", "/a/downloads/", "/a/b.txt"];
+        let mut ui = create_test_ui(&mut backend, "/a", &paths);
+        ui.on_search_char('d').unwrap();
+        let click = |column: usize| MouseEvent {
+            kind: MouseEventKind::Up(MouseButton::Left),
+            column: column as u16,
+            row: 6,
+            modifiers: KeyModifiers::NONE,
+        };
+
+        ui.handle_mouse_event(click(2)).unwrap();
+        assert!(ui.app_state.settings.filter_search);
+        assert_eq!(ui.app_state.num_visible_items(), 2);
+
+        let footer = ui.window.row_text(6);
+        assert!(footer.starts_with("filter: d "));
+        let gap_column = footer.find("gap search from start").unwrap();
+        ui.handle_mouse_event(click(gap_column + 3)).unwrap();
+        assert!(matches!(ui.app_state.settings.gap_search_mode, GapSearchMode::NoGapSearch));
+
+        let footer = ui.window.row_text(6);
+        let case_column = footer.find("smart case").unwrap();
+        ui.handle_mouse_event(click(case_column)).unwrap();
+        assert!(matches!(ui.app_state.settings.case_sensitive, CaseSensitiveMode::Auto));
+
+        // the separators and counts don't do anything
+        let footer = ui.window.row_text(6);
+        ui.handle_mouse_event(click(footer.find(" / ").unwrap())).unwrap();
+        assert!(matches!(ui.app_state.settings.case_sensitive, CaseSensitiveMode::Auto));
+    }
+
+    #[test]
+    fn test_help_view_mouse() {
+        let mut backend = TestBackend::new(80, 7);
+        let mouse = |kind, column| {
+            Event::Mouse(MouseEvent { kind, column, row: 1, modifiers: KeyModifiers::NONE })
+        };
+        let help_line = |i: usize, ui: &TereTui<TestBackend>| -> String {
+            get_formatted_help_text(80, &ui.app_state.settings)[i]
+                .iter()
+                .map(|fragment| fragment.content().as_str())
+                .collect()
+        };
+
+        // the wheel scrolls, and clicking elsewhere than on the close button does nothing
+        let events = events::ScriptedEvents::new()
+            .event(mouse(MouseEventKind::ScrollDown, 0))
+            .event(mouse(MouseEventKind::ScrollDown, 0))
+            .event(mouse(MouseEventKind::ScrollUp, 0))
+            .event(mouse(MouseEventKind::Up(MouseButton::Left), 10));
+        let mut ui = create_test_ui(&mut backend, "/a", &["/a/b/"]).with_event_source(events);
+        assert!(ui.help_view_loop().is_err());
+        let expected = help_line(2, &ui);
+        drop(ui);
+        assert_eq!(backend.row_text(2), expected.trim_end());
+        assert!(backend.row_text(1).ends_with("[x]"));
+
+        let events = events::ScriptedEvents::new()
+            .event(mouse(MouseEventKind::Up(MouseButton::Left), 78));
+        let mut ui = create_test_ui(&mut backend, "/a", &["/a/b/"]).with_event_source(events);
+        ui.help_view_loop().unwrap();
+        drop(ui);
+        assert_eq!(backend.row_text(1), "..");
+    }
+
+    #[test]
+    fn test_browse_mode() {
+        let mut backend = TestBackend::new(40, 7);
+        let paths = ["/a/docs/", "/a/bin/", "/a/b.txt"];
+
+        // letters navigate, and '/' starts a search
+        let events = events::ScriptedEvents::new().text("jl").key(KeyCode::Char('h')).text("/b");
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        ui.app_state.settings.browse_mode = true;
+        ui.app_state.settings.autocd_timeout = None;
+        assert!(ui.main_event_loop().is_err());
+        assert_eq!(ui.current_path(), PathBuf::from("/a"));
+        assert_eq!(ui.app_state.search_string(), "b");
+        assert_eq!(ui.app_state.num_matching_items(), 2);
+
+        // other letters don't search, and Esc after '/' goes back to browsing
+        let events = events::ScriptedEvents::new().text("x/").key(KeyCode::Esc).text("jq");
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        ui.app_state.settings.browse_mode = true;
+        ui.main_event_loop().unwrap();
+        assert!(!ui.app_state.is_searching());
+        assert_eq!(ui.app_state.item_under_cursor_path(), Some(PathBuf::from("/a/docs")));
+        drop(ui);
+        assert!(backend.row_text(6).starts_with("browse: "));
+
+        // Alt+b switches back to typing to search
+        let events = events::ScriptedEvents::new().key_with(KeyCode::Char('b'), KeyModifiers::ALT).text("d");
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        ui.app_state.settings.browse_mode = true;
+        ui.app_state.settings.autocd_timeout = None;
+        assert!(ui.main_event_loop().is_err());
+        assert_eq!(ui.app_state.search_string(), "d");
+    }
+
+    #[test]
+    fn test_coalesced_erase_search_char() {
+        let mut backend = TestBackend::new(40, 7);
+        let paths = ["/a/b/xyz/", "/a/b/xy.txt"];
+
+        // the Backspaces that are left after the search is empty go to the parent folder, like
+        // they would if they arrived one at a time
+        let events = events::ScriptedEvents::new()
+            .text("xy")
+            .key(KeyCode::Backspace)
+            .key(KeyCode::Backspace)
+            .key(KeyCode::Backspace);
+        let mut ui = create_test_ui(&mut backend, "/a/b", &paths).with_event_source(events);
+        ui.app_state.settings.autocd_timeout = None;
+        assert!(ui.main_event_loop().is_err());
+        assert!(!ui.app_state.is_searching());
+        assert_eq!(ui.current_path(), PathBuf::from("/a"));
+    }
+
+    #[test]
+    fn test_jump_to_label() {
+        let mut backend = TestBackend::new(40, 7);
+        let paths = ["/a/docs/", "/a/bin/", "/a/b.txt"];
+
+        let events = events::ScriptedEvents::new();
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        // the labels are drawn over the beginning of the items
+        assert!(ui.on_action(Action::JumpToLabel).is_err());
+        drop(ui);
+        assert_eq!(backend.row_text(1), "a.");
+        assert_eq!(backend.row_text(2), "sin");
+        assert!(backend.cell(0, 2).style.reversed);
+
+        // a label moves the cursor, and an uppercase label enters the item too
+        let events = events::ScriptedEvents::new().text("d");
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        assert!(!ui.on_action(Action::JumpToLabel).unwrap());
+        assert_eq!(ui.app_state.item_under_cursor_path(), Some(PathBuf::from("/a/docs")));
+        let events = events::ScriptedEvents::new().text("S");
+        let mut ui = ui.with_event_source(events);
+        assert!(!ui.on_action(Action::JumpToLabel).unwrap());
+        assert_eq!(ui.current_path(), PathBuf::from("/a/bin"));
+
+        // other keys cancel
+        let events = events::ScriptedEvents::new().key(KeyCode::Esc);
+        let mut ui = ui.with_event_source(events);
+        assert!(!ui.on_action(Action::JumpToLabel).unwrap());
+        assert_eq!(ui.current_path(), PathBuf::from("/a/bin"));
+    }
+
+    #[test]
+    fn test_scripted_autocd() {
+        let mut backend = TestBackend::new(40, 7);
+        let paths = ["/a/docs/", "/a/bin/", "/a/b.txt"];
+        let events = events::ScriptedEvents::new()
+            .text("do")
+            .wait(Duration::from_millis(200))
+            .key(KeyCode::Esc);
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        ui.app_state.settings.autocd_timeout = Some(50);
+        ui.main_event_loop().unwrap();
+        assert_eq!(ui.current_path(), PathBuf::from("/a/docs"));
+        drop(ui);
+        assert_eq!(backend.row_text(0), "/a/docs");
+
+        // with a manual clock, the countdown doesn't depend on how fast the test runs
+        backend.resize(100, 7);
+        let clock = clock::ManualClock::new();
+        let events = events::ScriptedEvents::new()
+            .text("do")
+            .wait(Duration::from_millis(60))
+            .with_clock(clock.clone());
+        let mut ui = create_test_ui(&mut backend, "/a", &paths)
+            .with_event_source(events)
+            .with_clock(clock.clone());
+        ui.app_state.settings.autocd_timeout = Some(100);
+        assert!(ui.main_event_loop().is_err());
+        assert_eq!(ui.current_path(), PathBuf::from("/a"));
+        drop(ui);
+        assert_eq!(clock.elapsed(), Duration::from_millis(60));
+        assert_eq!(
+            backend.row_text(6),
+            "search: do     auto-cd in 50 ms - gap search from start - smart case - 1 folder, 0 files - 1 / 1 / 4"
+        );
+
+        // without the Esc at the end, the loop ends with an error when the script runs out
+        let events = events::ScriptedEvents::new().text("b");
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        assert!(ui.main_event_loop().is_err());
+        assert_eq!(ui.app_state.search_string(), "b");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_termination_signal() {
+        let mut backend = TestBackend::new(40, 7);
+        let paths = ["/a/docs/"];
+        let signals = TerminationSignals::received_for_test(signal_hook::consts::SIGTERM);
+
+        // the signal is noticed before the key press is handled
+        let events = events::ScriptedEvents::new().key(KeyCode::Enter);
+        let mut ui = create_test_ui(&mut backend, "/a", &paths)
+            .with_event_source(events)
+            .with_termination_signals(signals.clone());
+        let res = ui.main_event_loop();
+        assert!(matches!(res, Err(TereError::ExitWithoutCd(_))));
+        assert_eq!(ui.current_path(), PathBuf::from("/a"));
+
+        let events = events::ScriptedEvents::new().key(KeyCode::Enter);
+        let mut ui = create_test_ui(&mut backend, "/a", &paths)
+            .with_event_source(events)
+            .with_termination_signals(signals);
+        ui.app_state.settings.cd_on_signal = true;
+        assert!(ui.main_event_loop().is_ok());
+    }
+
+    #[test]
+    fn test_long_error_message() {
+        let mut backend = TestBackend::new(40, 12);
+        let paths = ["/a/docs/"];
+        let mut ui = create_test_ui(&mut backend, "/a", &paths);
+        ui.error_message("short").unwrap();
+        drop(ui);
+        assert_eq!(backend.row_text(10), "error: short");
+
+        // only the beginning of a long message fits, and the rest is shown in a modal
+        let events = events::ScriptedEvents::new().key_with(KeyCode::Char('E'), KeyModifiers::ALT);
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        ui.error_message("could not read the folder because of a long reason\nsecond line")
+            .unwrap();
+        assert_eq!(ui.app_state.info_msg, "error: coul... (Alt+Shift+e for details)");
+        // the script runs out while the modal is shown
+        assert!(ui.main_event_loop().is_err());
+        drop(ui);
+        let screen = backend.screen_text().join("\n");
+        assert!(screen.contains("│ error: could not read the folder │"));
+        assert!(screen.contains("│ because of a long reason         │"));
+        assert!(screen.contains("│ second line                      │"));
+    }
+
+    #[test]
+    fn test_open_parent_entry() {
+        let mut backend = TestBackend::new(40, 7);
+        let mut ui = create_test_ui(&mut backend, "/a", &["/a/docs/"]);
+        ui.on_action(Action::CursorUp).unwrap();
+        assert_eq!(ui.app_state.item_under_cursor_path(), Some(PathBuf::from("/a/..")));
+        // '..' is not opened
+        ui.on_action(Action::OpenWithDefaultApp).unwrap();
+        assert_eq!(ui.app_state.info_msg, "");
+        ui.on_action(Action::OpenInEditor).unwrap();
+        assert_eq!(ui.app_state.info_msg, "");
+    }
+
+    #[test]
+    fn test_paste_items_with_same_name() {
+        let tmp = crate::test_util::TempDir::new("paste-same-name");
+        for dir in ["x", "y", "z"] {
+            std::fs::create_dir(tmp.join(dir)).unwrap();
+        }
+        std::fs::write(tmp.join("x/a"), "x").unwrap();
+        std::fs::write(tmp.join("y/a"), "y").unwrap();
+
+        let mut backend = TestBackend::new(60, 7);
+        let source = Arc::new(crate::test_util::TestFsDirSource);
+        // overwriting is not offered for the second 'a', so 'o' is ignored, and it's renamed
+        // with the suggested name instead
+        let events = events::ScriptedEvents::new().text("or").key(KeyCode::Enter);
+        let cwd = tmp.join("x").to_string_lossy().to_string();
+        let mut ui = create_test_ui_with_source(&mut backend, &cwd, source).with_event_source(events);
+        ui.on_action(Action::ToggleMark).unwrap();
+        ui.change_dir(&tmp.join("y").to_string_lossy()).unwrap();
+        ui.on_action(Action::ToggleMark).unwrap();
+        ui.change_dir(&tmp.join("z").to_string_lossy()).unwrap();
+        ui.on_action(Action::MoveMarkedHere).unwrap();
+        ui.job.as_mut().unwrap().wait();
+
+        assert_eq!(std::fs::read_to_string(tmp.join("z/a")).unwrap(), "x");
+        assert_eq!(std::fs::read_to_string(tmp.join("z/a (1)")).unwrap(), "y");
+        assert!(!tmp.join("x/a").exists());
+        assert!(!tmp.join("y/a").exists());
+    }
+
+    #[test]
+    fn test_unbound_key_message() {
+        let mut backend = TestBackend::new(80, 7);
+        let mut ui = create_test_ui(&mut backend, "/a", &["/a/docs/"]);
+        let f9 = KeyEvent::from(KeyCode::F(9));
+        assert_eq!(ui.unbound_key_message(&f9), "F9 is not bound to any action. Press '?' to view help.");
+
+        // the message shows the key that the help is bound to
+        let keymap = &mut ui.app_state.settings.keymap;
+        keymap.unbind(KeyCode::Char('?').into(), ActionContext::Any);
+        keymap.bind(KeyCode::F(2).into(), ActionContext::Any, Action::Help);
+        assert_eq!(ui.unbound_key_message(&f9), "F9 is not bound to any action. Press 'F2' to view help.");
+
+        ui.app_state.settings.keymap.unbind(KeyCode::F(2).into(), ActionContext::Any);
+        assert_eq!(ui.unbound_key_message(&f9), "F9 is not bound to any action.");
+    }
+
+    #[test]
+    fn test_view_menu() {
+        let mut backend = TestBackend::new(60, 16);
+        let paths = ["/a/docs/", "/a/b.txt", "/a/c.txt"];
+        let events = events::ScriptedEvents::new()
+            .key_with(KeyCode::Char('v'), KeyModifiers::ALT)
+            .key(KeyCode::Down)
+            .key(KeyCode::Down)
+            .key(KeyCode::Down)
+            .key(KeyCode::Char(' '));
+        let mut ui = create_test_ui(&mut backend, "/a", &paths).with_event_source(events);
+        // the script runs out while the menu is shown
+        assert!(ui.main_event_loop().is_err());
+        assert!(ui.app_state.view_prefs().reverse_sort);
+        drop(ui);
+        let screen = backend.screen_text().join("\n");
+        assert!(screen.contains("│   (*) Sort by name "), "{}", screen);
+        assert!(screen.contains("│ > [x] Reverse order "), "{}", screen);
+        assert!(screen.contains("│   [x] Show hidden items "), "{}", screen);
+    }
+
+    /// A folder tree in which reading folders called 'dead' hangs for a while, like on an
+    /// unresponsive network file system
+    struct HangingDirSource(MemoryDirSource);
+
+    impl DirSource for HangingDirSource {
+        fn read_dir(&self, path: &Path) -> IOResult<crate::app_state::DirBatches> {
+            if path.ends_with("dead") {
+                std::thread::sleep(Duration::from_secs(2));
+            }
+            self.0.read_dir(path)
+        }
+
+        fn modified(&self, path: &Path) -> Option<std::time::SystemTime> {
+            self.0.modified(path)
+        }
+
+        fn enter_dir(&self, path: &Path) -> IOResult<()> {
+            self.0.enter_dir(path)
+        }
+    }
+
+    #[test]
+    fn test_cancel_hanging_read() {
+        let mut backend = TestBackend::new(60, 7);
+        let source = Arc::new(HangingDirSource(memory_source(&["/a/dead/x", "/a/b.txt"])));
+        let events = events::ScriptedEvents::new()
+            .text("de")
+            .key(KeyCode::Enter)
+            .wait(Duration::from_millis(100))
+            .key(KeyCode::Esc)
+            .key(KeyCode::Esc);
+        let mut ui = create_test_ui_with_source(&mut backend, "/a", source.clone())
+            .with_event_source(events);
+        ui.main_event_loop().unwrap();
+        assert_eq!(ui.current_path(), PathBuf::from("/a"));
+        drop(ui);
+        assert_eq!(backend.row_text(0), "/a");
+        assert_eq!(backend.row_text(5), "error: Cancelled reading '/a/dead'");
+
+        let events = events::ScriptedEvents::new()
+            .text("de")
+            .key(KeyCode::Enter)
+            .wait(Duration::from_millis(300))
+            .key(KeyCode::Esc);
+        let mut ui = create_test_ui_with_source(&mut backend, "/a", source)
+            .with_event_source(events);
+        ui.app_state.settings.read_timeout = Some(100);
+        ui.main_event_loop().unwrap();
+        assert_eq!(ui.current_path(), PathBuf::from("/a"));
+        drop(ui);
+        assert_eq!(backend.row_text(5), "error: Timed out opening '/a/dead'");
+    }
 }
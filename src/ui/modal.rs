@@ -0,0 +1,105 @@
+/// A dialog box that is drawn over the main window, with a message and a set of choices, each of
+/// which is chosen by pressing a key. Used for confirming file operations, for example.
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::i18n::tr;
+
+/// The result of handling a key press in the modal.
+#[derive(Debug, PartialEq, Eq)]
+pub enum ModalStatus {
+    /// The key didn't correspond to any choice, keep waiting
+    Pending,
+    /// The choice with this key was chosen
+    Chosen(char),
+    /// The modal was closed with Esc or Ctrl+C
+    Cancelled,
+}
+
+pub struct Modal {
+    title: String,
+    message: String,
+    /// The key and the label of each choice
+    choices: Vec<(char, String)>,
+}
+
+impl Modal {
+    /// Create a modal without any choices, add them with `choice`.
+    pub fn new(title: &str, message: &str) -> Self {
+        Self {
+            title: title.to_string(),
+            message: message.to_string(),
+            choices: vec![],
+        }
+    }
+
+    /// A modal with the choices 'y' (yes) and 'n' (no).
+    pub fn yes_no(title: &str, message: &str) -> Self {
+        Self::new(title, message).choice('y', tr("Yes")).choice('n', tr("No"))
+    }
+
+    /// Add a choice that is chosen by pressing `key`, which should be a lowercase character.
+    pub fn choice(mut self, key: char, label: &str) -> Self {
+        self.choices.push((key, label.to_string()));
+        self
+    }
+
+    pub fn title(&self) -> &str {
+        &self.title
+    }
+
+    pub fn handle_key(&self, k: &KeyEvent) -> ModalStatus {
+        match k.code {
+            KeyCode::Esc => ModalStatus::Cancelled,
+            KeyCode::Char('c') if k.modifiers == KeyModifiers::CONTROL => ModalStatus::Cancelled,
+            KeyCode::Char(c) => self
+                .choices
+                .iter()
+                .find(|(key, _)| *key == c.to_ascii_lowercase())
+                .map_or(ModalStatus::Pending, |(key, _)| ModalStatus::Chosen(*key)),
+            _ => ModalStatus::Pending,
+        }
+    }
+
+    /// The rows of text inside the box: the message wrapped to `width` columns, an empty row, and
+    /// the choices.
+    pub fn rows(&self, width: usize) -> Vec<String> {
+        let mut rows: Vec<String> = textwrap::wrap(&self.message, width.max(1))
+            .into_iter()
+            .map(|line| line.to_string())
+            .collect();
+        rows.push(String::new());
+        rows.push(
+            self.choices
+                .iter()
+                .map(|(key, label)| format!("[{}] {}", key, label))
+                .collect::<Vec<_>>()
+                .join("   "),
+        );
+        rows
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_modal_keys() {
+        let modal = Modal::yes_no("Delete", "Delete foo?");
+        let key = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::NONE);
+        assert_eq!(modal.handle_key(&key('y')), ModalStatus::Chosen('y'));
+        assert_eq!(modal.handle_key(&key('Y')), ModalStatus::Chosen('y'));
+        assert_eq!(modal.handle_key(&key('n')), ModalStatus::Chosen('n'));
+        assert_eq!(modal.handle_key(&key('x')), ModalStatus::Pending);
+        assert_eq!(modal.handle_key(&KeyCode::Esc.into()), ModalStatus::Cancelled);
+    }
+
+    #[test]
+    fn test_modal_rows() {
+        let modal = Modal::new("Exists", "foo bar baz").choice('o', "Overwrite").choice('s', "Skip");
+        assert_eq!(
+            modal.rows(7),
+            vec!["foo bar", "baz", "", "[o] Overwrite   [s] Skip"],
+        );
+    }
+}
@@ -0,0 +1,116 @@
+/// Commands defined by the user with the `--command` option, which combine built-in actions,
+/// changing folders and external commands under a name. They can be bound to keys like the
+/// built-in actions.
+use std::str::FromStr;
+
+use super::action::Action;
+
+/// One step of a named command
+#[derive(Debug, Clone, PartialEq)]
+pub enum CommandStep {
+    /// Perform a built-in action
+    Action(Action),
+    /// Change to this folder, which can be relative to the current folder or start with '~'
+    ChangeDir(String),
+    /// Run a shell command like with `--run`, with `{}` replaced by the paths of the marked items
+    /// or the item under the cursor
+    Run(String),
+    /// Run a shell command, and change to the folder that it prints
+    ChangeDirToOutput(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NamedCommand {
+    pub name: String,
+    pub steps: Vec<CommandStep>,
+}
+
+impl NamedCommand {
+    /// Parse a command in the format 'NAME:STEP; STEP; ...'. Each step is the name of an action,
+    /// 'cd PATH', 'run COMMAND' or 'cd-output COMMAND'. The shell commands can contain ';', so
+    /// 'run' and 'cd-output' have to be the last step.
+    pub fn parse(spec: &str) -> Result<Self, String> {
+        let (name, mut rest) = spec
+            .split_once(':')
+            .ok_or_else(|| "expected NAME:STEPS".to_string())?;
+        let name = name.trim();
+        if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '_') {
+            return Err(format!("invalid name '{}'", name));
+        }
+        if Action::from_str(name).is_ok() || name == "none" {
+            return Err(format!("'{}' is already the name of an action", name));
+        }
+
+        let mut steps = vec![];
+        loop {
+            rest = rest.trim_start();
+            let (word, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+            let step = match word {
+                "run" | "cd-output" => {
+                    let command = args.trim();
+                    if command.is_empty() {
+                        return Err(format!("the command of '{}' is empty", word));
+                    }
+                    rest = "";
+                    match word {
+                        "run" => CommandStep::Run(command.to_string()),
+                        _ => CommandStep::ChangeDirToOutput(command.to_string()),
+                    }
+                }
+                _ => {
+                    let (step, remaining) = rest.split_once(';').unwrap_or((rest, ""));
+                    rest = remaining;
+                    match step.trim().split_once(char::is_whitespace) {
+                        Some(("cd", path)) => CommandStep::ChangeDir(path.trim().to_string()),
+                        _ => CommandStep::Action(Action::from_str(step.trim())?),
+                    }
+                }
+            };
+            steps.push(step);
+            if rest.trim().is_empty() {
+                break;
+            }
+        }
+        Ok(Self {
+            name: name.to_string(),
+            steps,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_named_command() {
+        assert_eq!(
+            NamedCommand::parse("projects:cd ~/src; cursor-top").unwrap(),
+            NamedCommand {
+                name: "projects".into(),
+                steps: vec![
+                    CommandStep::ChangeDir("~/src".into()),
+                    CommandStep::Action(Action::CursorTop),
+                ],
+            }
+        );
+        assert_eq!(
+            NamedCommand::parse("git-root: cd-output git rev-parse --show-toplevel").unwrap().steps,
+            vec![CommandStep::ChangeDirToOutput("git rev-parse --show-toplevel".into())],
+        );
+        assert_eq!(
+            NamedCommand::parse("sizes:clear-marks; run du -sh {}; echo done").unwrap().steps,
+            vec![
+                CommandStep::Action(Action::ClearMarks),
+                CommandStep::Run("du -sh {}; echo done".into()),
+            ],
+        );
+
+        assert!(NamedCommand::parse("no-steps").is_err());
+        assert!(NamedCommand::parse("bad name:cursor-top").is_err());
+        assert!(NamedCommand::parse("exit:cursor-top").is_err());
+        assert!(NamedCommand::parse("x:cursor-top; no-such-action").is_err());
+        assert!(NamedCommand::parse("x:run ").is_err());
+        assert!(NamedCommand::parse("x:").is_err());
+    }
+}
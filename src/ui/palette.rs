@@ -0,0 +1,218 @@
+/// The command palette, which lists all actions, including the ones defined by the user, and
+/// lets the user find and run one of them by typing a part of its description. Like `Modal`,
+/// this only keeps track of the state, the drawing is done by the UI.
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use super::action::Action;
+use crate::app_state::{search_pattern, GapSearchMode};
+
+/// The result of handling a key press in the palette.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PaletteStatus {
+    /// Keep reading input
+    Pending,
+    /// This action was chosen with Enter
+    Chosen(Action),
+    /// The palette was closed with Esc or Ctrl+C
+    Cancelled,
+}
+
+/// An action in the palette
+pub struct PaletteEntry {
+    pub action: Action,
+    pub description: String,
+    /// The shortcut of the action, or an empty string if it has none
+    pub shortcut: String,
+}
+
+pub struct Palette {
+    entries: Vec<PaletteEntry>,
+    query: String,
+    gap_search_mode: GapSearchMode,
+    /// The indices of the entries that match the query
+    matches: Vec<usize>,
+    /// The index into `matches` of the selected entry
+    selected: usize,
+}
+
+impl Palette {
+    /// Create a palette with all of `entries` shown. The query is matched like the search in the
+    /// listing with `gap_search_mode`, but anywhere in the description and with smart case.
+    pub fn new(entries: Vec<PaletteEntry>, gap_search_mode: &GapSearchMode) -> Self {
+        let gap_search_mode = match gap_search_mode {
+            GapSearchMode::NoGapSearch => GapSearchMode::NoGapSearch,
+            // the descriptions are sentences, so matching only from the start is not useful
+            _ => GapSearchMode::GapSearchAnywere,
+        };
+        let matches = (0..entries.len()).collect();
+        Self {
+            entries,
+            query: String::new(),
+            gap_search_mode,
+            matches,
+            selected: 0,
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    fn update_matches(&mut self) {
+        let ignore_case = !self.query.chars().any(|c| c.is_uppercase());
+        let pattern = if self.gap_search_mode == GapSearchMode::NoGapSearch {
+            // the query can be anywhere in the description
+            regex::Regex::new(&regex::escape(&self.query)).unwrap()
+        } else {
+            search_pattern(&self.query, &self.gap_search_mode)
+        };
+        self.matches = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| {
+                let description = entry.description.replace('`', "");
+                match ignore_case {
+                    true => pattern.is_match(&description.to_lowercase()),
+                    false => pattern.is_match(&description),
+                }
+            })
+            .map(|(i, _)| i)
+            .collect();
+        self.selected = 0;
+    }
+
+    fn move_selection(&mut self, up: bool) {
+        let n = self.matches.len();
+        if n > 0 {
+            self.selected = if up { (self.selected + n - 1) % n } else { (self.selected + 1) % n };
+        }
+    }
+
+    pub fn handle_key(&mut self, k: &KeyEvent) -> PaletteStatus {
+        let ctrl = k.modifiers == KeyModifiers::CONTROL;
+        match k.code {
+            KeyCode::Esc => return PaletteStatus::Cancelled,
+            KeyCode::Char('c') if ctrl => return PaletteStatus::Cancelled,
+            KeyCode::Enter => {
+                return self
+                    .matches
+                    .get(self.selected)
+                    .map_or(PaletteStatus::Pending, |i| PaletteStatus::Chosen(self.entries[*i].action))
+            }
+            KeyCode::Up => self.move_selection(true),
+            KeyCode::Char('p') | KeyCode::Char('k') if ctrl => self.move_selection(true),
+            KeyCode::Down => self.move_selection(false),
+            KeyCode::Char('n') | KeyCode::Char('j') if ctrl => self.move_selection(false),
+            KeyCode::Backspace => {
+                self.query.pop();
+                self.update_matches();
+            }
+            KeyCode::Char(c) if !ctrl && !k.modifiers.contains(KeyModifiers::ALT) => {
+                self.query.push(c);
+                self.update_matches();
+            }
+            _ => {}
+        }
+        PaletteStatus::Pending
+    }
+
+    /// The matching entries that fit in `height` rows, scrolled so that the selected entry is
+    /// visible, with a flag telling whether each of them is selected.
+    pub fn visible_entries(&self, height: usize) -> Vec<(&PaletteEntry, bool)> {
+        let skip = (self.selected + 1).saturating_sub(height);
+        self.matches
+            .iter()
+            .enumerate()
+            .skip(skip)
+            .take(height)
+            .map(|(i, idx)| (&self.entries[*idx], i == self.selected))
+            .collect()
+    }
+
+    /// All entries, whether they match or not
+    pub fn entries(&self) -> &[PaletteEntry] {
+        &self.entries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_palette() -> Palette {
+        let entry = |action, description: &str| PaletteEntry {
+            action,
+            description: description.to_string(),
+            shortcut: String::new(),
+        };
+        Palette::new(
+            vec![
+                entry(Action::ChangeDirHome, "Go to home folder"),
+                entry(Action::CreateDir, "Create a new folder"),
+                entry(Action::RunCommand(0), "Run `du -sh {}`"),
+            ],
+            &GapSearchMode::GapSearchFromStart,
+        )
+    }
+
+    fn type_str(palette: &mut Palette, s: &str) {
+        for c in s.chars() {
+            palette.handle_key(&KeyCode::Char(c).into());
+        }
+    }
+
+    fn visible(palette: &Palette) -> Vec<Action> {
+        palette.visible_entries(10).iter().map(|(e, _)| e.action).collect()
+    }
+
+    #[test]
+    fn test_palette_search() {
+        let mut palette = test_palette();
+        assert_eq!(visible(&palette).len(), 3);
+
+        type_str(&mut palette, "fold");
+        assert_eq!(visible(&palette), vec![Action::ChangeDirHome, Action::CreateDir]);
+
+        // gaps are allowed, anywhere in the description
+        for _ in 0..3 {
+            palette.handle_key(&KeyCode::Backspace.into());
+        }
+        type_str(&mut palette, "nwf");
+        assert_eq!(palette.query(), "fnwf");
+        assert!(visible(&palette).is_empty());
+        for _ in 0..4 {
+            palette.handle_key(&KeyCode::Backspace.into());
+        }
+        type_str(&mut palette, "nwf");
+        assert_eq!(visible(&palette), vec![Action::CreateDir]);
+
+        // smart case
+        let mut palette = test_palette();
+        type_str(&mut palette, "Du");
+        assert!(visible(&palette).is_empty());
+        let mut palette = test_palette();
+        type_str(&mut palette, "du");
+        assert_eq!(visible(&palette), vec![Action::RunCommand(0)]);
+    }
+
+    #[test]
+    fn test_palette_keys() {
+        let mut palette = test_palette();
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        assert_eq!(palette.handle_key(&KeyCode::Enter.into()), PaletteStatus::Chosen(Action::ChangeDirHome));
+        palette.handle_key(&KeyCode::Down.into());
+        assert_eq!(palette.handle_key(&KeyCode::Enter.into()), PaletteStatus::Chosen(Action::CreateDir));
+        // the selection wraps around
+        palette.handle_key(&ctrl('p'));
+        palette.handle_key(&ctrl('p'));
+        assert_eq!(palette.visible_entries(2).len(), 2);
+        assert!(palette.visible_entries(2)[1].1);
+        assert_eq!(palette.handle_key(&KeyCode::Enter.into()), PaletteStatus::Chosen(Action::RunCommand(0)));
+
+        type_str(&mut palette, "xyz");
+        assert_eq!(palette.handle_key(&KeyCode::Enter.into()), PaletteStatus::Pending);
+        assert_eq!(palette.handle_key(&KeyCode::Esc.into()), PaletteStatus::Cancelled);
+        assert_eq!(palette.handle_key(&ctrl('c')), PaletteStatus::Cancelled);
+    }
+}
@@ -0,0 +1,309 @@
+/// A single-line text input, used for all prompts, like entering a path or the name of a new
+/// folder. This only keeps track of the text, the cursor and the history of previous inputs, the
+/// drawing is done by the UI.
+use std::collections::HashMap;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use unicode_segmentation::UnicodeSegmentation;
+
+/// The result of handling a key press in the prompt.
+#[derive(Debug, PartialEq, Eq)]
+pub enum PromptStatus {
+    /// Keep reading input
+    Editing,
+    /// The input was accepted with Enter
+    Submitted,
+    /// The input was cancelled with Esc or Ctrl+C
+    Cancelled,
+}
+
+/// The maximum number of previous inputs that are remembered for each kind of prompt
+const MAX_HISTORY_LEN: usize = 100;
+
+/// Previous inputs of each kind of prompt, so that the same path doesn't have to be typed again,
+/// for example. The history is not saved when exiting.
+#[derive(Default)]
+pub struct PromptHistory {
+    entries: HashMap<&'static str, Vec<String>>,
+}
+
+impl PromptHistory {
+    /// The previous inputs of the given kind, oldest first
+    pub fn get(&self, kind: &'static str) -> &[String] {
+        self.entries.get(kind).map_or(&[], |v| v.as_slice())
+    }
+
+    pub fn add(&mut self, kind: &'static str, text: &str) {
+        let entries = self.entries.entry(kind).or_default();
+        // move repeated entries to the end instead of duplicating them
+        entries.retain(|e| e != text);
+        entries.push(text.to_string());
+        if entries.len() > MAX_HISTORY_LEN {
+            entries.remove(0);
+        }
+    }
+}
+
+pub struct InputPrompt {
+    text: String,
+    /// The cursor position as a byte offset into `text`. Always on a grapheme boundary.
+    cursor: usize,
+    /// Previous inputs, which can be browsed with the up and down arrows
+    history: Vec<String>,
+    /// The index of the history entry that is shown, or `None` when editing a new input
+    history_pos: Option<usize>,
+    /// The text that was being edited before browsing the history
+    draft: String,
+}
+
+impl InputPrompt {
+    /// Create a prompt with some initial text, with the cursor at the end.
+    pub fn new(initial: &str) -> Self {
+        Self {
+            text: initial.to_string(),
+            cursor: initial.len(),
+            history: vec![],
+            history_pos: None,
+            draft: String::new(),
+        }
+    }
+
+    /// Set the previous inputs that can be browsed, oldest first.
+    pub fn with_history(mut self, history: &[String]) -> Self {
+        self.history = history.to_vec();
+        self
+    }
+
+    fn set_text(&mut self, text: String) {
+        self.cursor = text.len();
+        self.text = text;
+    }
+
+    /// Show the previous (if `back` is true) or next history entry
+    fn browse_history(&mut self, back: bool) {
+        let new_pos = match (self.history_pos, back) {
+            (None, true) if !self.history.is_empty() => {
+                self.draft = self.text.clone();
+                Some(self.history.len() - 1)
+            }
+            (Some(pos), true) => Some(pos.saturating_sub(1)),
+            (Some(pos), false) if pos + 1 < self.history.len() => Some(pos + 1),
+            (Some(_), false) => {
+                // back to the text that was being edited
+                self.history_pos = None;
+                let draft = std::mem::take(&mut self.draft);
+                self.set_text(draft);
+                return;
+            }
+            _ => return,
+        };
+        self.history_pos = new_pos;
+        if let Some(pos) = new_pos {
+            self.set_text(self.history[pos].clone());
+        }
+    }
+
+    /// The byte offset of the start of the word before the cursor. Whitespace and path
+    /// separators right before the cursor are part of the word, so that "/foo/" becomes "/".
+    fn prev_word_boundary(&self) -> usize {
+        let is_boundary = |c: char| c.is_whitespace() || std::path::is_separator(c);
+        let before = &self.text[..self.cursor];
+        before
+            .trim_end_matches(is_boundary)
+            .rfind(is_boundary)
+            .map_or(0, |i| i + 1)
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// The cursor position, counted in graphemes from the start of the text.
+    pub fn cursor_column(&self) -> usize {
+        self.text[..self.cursor].graphemes(true).count()
+    }
+
+    /// The byte offset of the grapheme before the cursor, if there is one
+    fn prev_boundary(&self) -> Option<usize> {
+        self.text[..self.cursor]
+            .grapheme_indices(true)
+            .next_back()
+            .map(|(i, _)| i)
+    }
+
+    /// The byte offset of the grapheme after the cursor, if there is one
+    fn next_boundary(&self) -> Option<usize> {
+        self.text[self.cursor..]
+            .graphemes(true)
+            .next()
+            .map(|g| self.cursor + g.len())
+    }
+
+    pub fn handle_key(&mut self, k: &KeyEvent) -> PromptStatus {
+        match k.code {
+            KeyCode::Enter => return PromptStatus::Submitted,
+            KeyCode::Esc => return PromptStatus::Cancelled,
+            KeyCode::Char('c') if k.modifiers == KeyModifiers::CONTROL => {
+                return PromptStatus::Cancelled
+            }
+            KeyCode::Char('u') if k.modifiers == KeyModifiers::CONTROL => {
+                self.text.replace_range(..self.cursor, "");
+                self.cursor = 0;
+            }
+            KeyCode::Char('a') if k.modifiers == KeyModifiers::CONTROL => self.cursor = 0,
+            KeyCode::Char('e') if k.modifiers == KeyModifiers::CONTROL => {
+                self.cursor = self.text.len()
+            }
+            KeyCode::Char('k') if k.modifiers == KeyModifiers::CONTROL => {
+                self.text.truncate(self.cursor);
+            }
+            KeyCode::Char('w') if k.modifiers == KeyModifiers::CONTROL => {
+                let start = self.prev_word_boundary();
+                self.text.replace_range(start..self.cursor, "");
+                self.cursor = start;
+            }
+            KeyCode::Char(c) if !k.modifiers.intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) => {
+                self.text.insert(self.cursor, c);
+                self.cursor += c.len_utf8();
+            }
+            KeyCode::Backspace => {
+                if let Some(prev) = self.prev_boundary() {
+                    self.text.replace_range(prev..self.cursor, "");
+                    self.cursor = prev;
+                }
+            }
+            KeyCode::Delete => {
+                if let Some(next) = self.next_boundary() {
+                    self.text.replace_range(self.cursor..next, "");
+                }
+            }
+            KeyCode::Left => {
+                if let Some(prev) = self.prev_boundary() {
+                    self.cursor = prev;
+                }
+            }
+            KeyCode::Right => {
+                if let Some(next) = self.next_boundary() {
+                    self.cursor = next;
+                }
+            }
+            KeyCode::Home => self.cursor = 0,
+            KeyCode::End => self.cursor = self.text.len(),
+            KeyCode::Up => self.browse_history(true),
+            KeyCode::Down => self.browse_history(false),
+            _ => {}
+        }
+        PromptStatus::Editing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn type_str(p: &mut InputPrompt, s: &str) {
+        for c in s.chars() {
+            assert_eq!(p.handle_key(&key(KeyCode::Char(c))), PromptStatus::Editing);
+        }
+    }
+
+    #[test]
+    fn test_prompt_editing() {
+        let mut p = InputPrompt::new("foo");
+        assert_eq!(p.cursor_column(), 3);
+        type_str(&mut p, "bär");
+        assert_eq!(p.text(), "foobär");
+
+        p.handle_key(&key(KeyCode::Left));
+        p.handle_key(&key(KeyCode::Left));
+        p.handle_key(&key(KeyCode::Backspace));
+        assert_eq!(p.text(), "fooär");
+        assert_eq!(p.cursor_column(), 3);
+
+        p.handle_key(&key(KeyCode::Delete));
+        assert_eq!(p.text(), "foor");
+
+        p.handle_key(&key(KeyCode::Home));
+        type_str(&mut p, "x");
+        assert_eq!(p.text(), "xfoor");
+
+        p.handle_key(&KeyEvent::new(KeyCode::Char('e'), KeyModifiers::CONTROL));
+        p.handle_key(&key(KeyCode::Right));
+        type_str(&mut p, "!");
+        assert_eq!(p.text(), "xfoor!");
+
+        p.handle_key(&key(KeyCode::Left));
+        p.handle_key(&KeyEvent::new(KeyCode::Char('u'), KeyModifiers::CONTROL));
+        assert_eq!(p.text(), "!");
+        assert_eq!(p.cursor_column(), 0);
+
+        let mut p = InputPrompt::new("/foo/bar baz");
+        p.handle_key(&KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(p.text(), "/foo/bar ");
+        p.handle_key(&KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL));
+        assert_eq!(p.text(), "/foo/");
+        p.handle_key(&key(KeyCode::Home));
+        p.handle_key(&key(KeyCode::Right));
+        p.handle_key(&KeyEvent::new(KeyCode::Char('k'), KeyModifiers::CONTROL));
+        assert_eq!(p.text(), "/");
+    }
+
+    #[test]
+    fn test_prompt_erase_word_after_separator() {
+        let ctrl_w = KeyEvent::new(KeyCode::Char('w'), KeyModifiers::CONTROL);
+        let mut p = InputPrompt::new("/foo/bar/");
+        p.handle_key(&ctrl_w);
+        assert_eq!(p.text(), "/foo/");
+        p.handle_key(&ctrl_w);
+        assert_eq!(p.text(), "/");
+        p.handle_key(&ctrl_w);
+        assert_eq!(p.text(), "");
+    }
+
+    #[test]
+    fn test_prompt_history() {
+        let mut history = PromptHistory::default();
+        history.add("path", "a");
+        history.add("path", "b");
+        history.add("path", "a");
+        history.add("name", "c");
+        assert_eq!(history.get("path"), &["b", "a"]);
+        assert!(history.get("foo").is_empty());
+
+        let mut p = InputPrompt::new("dr").with_history(history.get("path"));
+        p.handle_key(&key(KeyCode::Up));
+        assert_eq!(p.text(), "a");
+        p.handle_key(&key(KeyCode::Up));
+        assert_eq!(p.text(), "b");
+        p.handle_key(&key(KeyCode::Up));
+        assert_eq!(p.text(), "b");
+        p.handle_key(&key(KeyCode::Down));
+        assert_eq!(p.text(), "a");
+        p.handle_key(&key(KeyCode::Down));
+        assert_eq!(p.text(), "dr");
+        assert_eq!(p.cursor_column(), 2);
+        p.handle_key(&key(KeyCode::Down));
+        assert_eq!(p.text(), "dr");
+    }
+
+    #[test]
+    fn test_prompt_submit_cancel() {
+        let mut p = InputPrompt::new("");
+        assert_eq!(p.handle_key(&key(KeyCode::Enter)), PromptStatus::Submitted);
+        assert_eq!(p.handle_key(&key(KeyCode::Esc)), PromptStatus::Cancelled);
+        assert_eq!(
+            p.handle_key(&KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL)),
+            PromptStatus::Cancelled,
+        );
+        // modified characters are not inserted
+        p.handle_key(&KeyEvent::new(KeyCode::Char('x'), KeyModifiers::ALT));
+        assert_eq!(p.text(), "");
+        // but shifted ones are
+        p.handle_key(&KeyEvent::new(KeyCode::Char('X'), KeyModifiers::SHIFT));
+        assert_eq!(p.text(), "X");
+    }
+}
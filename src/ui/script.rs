@@ -0,0 +1,235 @@
+/// Custom actions written in the Rhai scripting language, see the `--script` option. A script
+/// defines functions, and binds them to keys at the top level with `bind(key, function)`. The
+/// functions can't change the state of the app directly. Instead, they read a snapshot of it,
+/// like `current_dir()` and `item_under_cursor()`, and request changes, like `cd(path)` and
+/// `message(text)`, which are done in order after the function returns.
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Scope, AST};
+
+/// The maximum number of operations that a script function can take, so that an infinite loop
+/// in a script doesn't freeze the app
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+/// The state of the app that a script function can read
+#[derive(Default, Clone)]
+pub struct ScriptContext {
+    pub current_dir: PathBuf,
+    pub item_under_cursor: Option<PathBuf>,
+    pub marked: Vec<PathBuf>,
+    pub search: String,
+    pub home_dir: Option<PathBuf>,
+}
+
+/// A change to the state of the app that a script function has requested
+#[derive(Debug, PartialEq)]
+pub enum ScriptEffect {
+    ChangeDir(PathBuf),
+    /// Move the cursor to the item with this name
+    Select(String),
+    Message(String),
+    Error(String),
+    /// Exit the app in the current folder
+    Exit,
+}
+
+#[derive(Default)]
+struct ScriptState {
+    context: ScriptContext,
+    effects: Vec<ScriptEffect>,
+    // The keys and functions given to `bind` when the script was loaded
+    bindings: Vec<(String, String)>,
+}
+
+/// A loaded script, whose functions can be called with `call`.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Arc<Mutex<ScriptState>>,
+}
+
+fn path_string(path: &Path) -> String {
+    path.to_string_lossy().into_owned()
+}
+
+impl Script {
+    /// Compile the script `source` and run its top level. Returns the script and the key
+    /// bindings that it made, as pairs of the key and the function name.
+    pub fn new(source: &str) -> Result<(Self, Vec<(String, String)>), String> {
+        let state = Arc::new(Mutex::new(ScriptState::default()));
+        let engine = Self::engine(&state);
+        let ast = engine.compile(source).map_err(|e| e.to_string())?;
+        engine.run_ast(&ast).map_err(|e| e.to_string())?;
+
+        let bindings = std::mem::take(&mut state.lock().unwrap().bindings);
+        for (_, function) in &bindings {
+            if !ast.iter_functions().any(|f| f.name == function && f.params.is_empty()) {
+                return Err(format!("no function '{}()' for bind", function));
+            }
+        }
+        Ok((Self { engine, ast, state }, bindings))
+    }
+
+    /// Load the script from the file `path`, like `new`.
+    pub fn load(path: &Path) -> Result<(Self, Vec<(String, String)>), String> {
+        let source = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Self::new(&source)
+    }
+
+    /// Call the function `name` of the script with the state of the app `context`. Returns the
+    /// changes that it requested.
+    pub fn call(&self, name: &str, context: ScriptContext) -> Result<Vec<ScriptEffect>, String> {
+        self.state.lock().unwrap().context = context;
+        // don't run the top level again, it was already run when the script was loaded
+        let options = CallFnOptions::new().eval_ast(false);
+        let res = self
+            .engine
+            .call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, name, ());
+        let effects = std::mem::take(&mut self.state.lock().unwrap().effects);
+        res.map(|_| effects).map_err(|e| e.to_string())
+    }
+
+    /// Create the engine with the functions that scripts can use. They all work on `state`.
+    fn engine(state: &Arc<Mutex<ScriptState>>) -> Engine {
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        // requesting changes
+        let effect = |state: &Arc<Mutex<ScriptState>>, f: fn(String) -> ScriptEffect| {
+            let state = state.clone();
+            move |arg: &str| state.lock().unwrap().effects.push(f(arg.to_string()))
+        };
+        engine.register_fn("cd", effect(state, |path| ScriptEffect::ChangeDir(path.into())));
+        engine.register_fn("select", effect(state, ScriptEffect::Select));
+        engine.register_fn("message", effect(state, ScriptEffect::Message));
+        engine.register_fn("error", effect(state, ScriptEffect::Error));
+        let s = state.clone();
+        engine.register_fn("exit_app", move || s.lock().unwrap().effects.push(ScriptEffect::Exit));
+        let s = state.clone();
+        engine.on_print(move |text| {
+            s.lock().unwrap().effects.push(ScriptEffect::Message(text.to_string()))
+        });
+        let s = state.clone();
+        engine.register_fn("bind", move |key: &str, function: &str| {
+            s.lock().unwrap().bindings.push((key.to_string(), function.to_string()))
+        });
+
+        // reading the state of the app
+        let s = state.clone();
+        engine.register_fn("current_dir", move || path_string(&s.lock().unwrap().context.current_dir));
+        let s = state.clone();
+        engine.register_fn("item_under_cursor", move || {
+            s.lock().unwrap().context.item_under_cursor.as_deref().map_or_else(String::new, path_string)
+        });
+        let s = state.clone();
+        engine.register_fn("marked", move || -> Array {
+            let state = s.lock().unwrap();
+            state.context.marked.iter().map(|p| path_string(p).into()).collect()
+        });
+        let s = state.clone();
+        engine.register_fn("search", move || s.lock().unwrap().context.search.clone());
+        let s = state.clone();
+        engine.register_fn("home_dir", move || {
+            s.lock().unwrap().context.home_dir.as_deref().map_or_else(String::new, path_string)
+        });
+
+        // working with paths. These only read the file system.
+        engine.register_fn("exists", |path: &str| Path::new(path).exists());
+        engine.register_fn("is_dir", |path: &str| Path::new(path).is_dir());
+        engine.register_fn("parent", |path: &str| {
+            Path::new(path).parent().map_or_else(String::new, path_string)
+        });
+        engine.register_fn("file_name", |path: &str| {
+            Path::new(path).file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned())
+        });
+        engine.register_fn("join", |path: &str, name: &str| path_string(&Path::new(path).join(name)));
+        engine.register_fn("env", |name: &str| std::env::var(name).unwrap_or_default());
+
+        engine
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn test_script() {
+        let source = r#"
+            fn git_root() {
+                let dir = current_dir();
+                while dir != "" {
+                    if exists(join(dir, ".git")) {
+                        cd(dir);
+                        return;
+                    }
+                    dir = parent(dir);
+                }
+                message("Not in a git repository");
+                cd(home_dir());
+            }
+            fn mark_count() {
+                print(`${marked().len()} marked, searching for '${search()}'`);
+                select(file_name(item_under_cursor()));
+                exit_app();
+            }
+            bind("ctrl-g", "git_root");
+            bind("f5", "mark_count");
+        "#;
+        let (script, bindings) = Script::new(source).unwrap();
+        assert_eq!(
+            bindings,
+            vec![
+                ("ctrl-g".to_string(), "git_root".to_string()),
+                ("f5".to_string(), "mark_count".to_string()),
+            ]
+        );
+
+        let tmp = TempDir::new("script");
+        std::fs::create_dir_all(tmp.join("repo/.git")).unwrap();
+        std::fs::create_dir_all(tmp.join("repo/src")).unwrap();
+
+        let context = |dir: PathBuf| ScriptContext {
+            current_dir: dir,
+            home_dir: Some(PathBuf::from("/home")),
+            ..Default::default()
+        };
+        assert_eq!(
+            script.call("git_root", context(tmp.join("repo/src"))).unwrap(),
+            vec![ScriptEffect::ChangeDir(tmp.join("repo"))]
+        );
+        assert_eq!(
+            script.call("git_root", context(tmp.to_path_buf())).unwrap(),
+            vec![
+                ScriptEffect::Message("Not in a git repository".into()),
+                ScriptEffect::ChangeDir("/home".into()),
+            ]
+        );
+
+        let context = ScriptContext {
+            item_under_cursor: Some(tmp.join("repo")),
+            marked: vec![tmp.join("a"), tmp.join("b")],
+            search: "re".into(),
+            ..Default::default()
+        };
+        assert_eq!(
+            script.call("mark_count", context).unwrap(),
+            vec![
+                ScriptEffect::Message("2 marked, searching for 're'".into()),
+                ScriptEffect::Select("repo".into()),
+                ScriptEffect::Exit,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_script_errors() {
+        assert!(Script::new("fn f( {").is_err());
+        assert!(Script::new(r#"bind("f5", "missing");"#).is_err());
+        // infinite loops are stopped
+        let (script, _) = Script::new("fn f() { loop {} }").unwrap();
+        assert!(script.call("f", ScriptContext::default()).is_err());
+    }
+}
@@ -0,0 +1,83 @@
+/// Handling of the signals that ask tere to terminate, like SIGTERM. Instead of being killed in
+/// the middle of drawing, with the terminal left in raw mode and the alternate screen, tere checks
+/// for the signals while it waits for input, and exits normally. If it doesn't respond, for example
+/// because it's stuck reading a hung network mount, a second signal kills it right away.
+use std::fmt;
+use std::io::{Error as IOError, ErrorKind, Result as IOResult};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use signal_hook::consts::{SIGINT, SIGTERM};
+#[cfg(unix)]
+use signal_hook::consts::SIGHUP;
+use signal_hook::flag;
+
+#[cfg(unix)]
+const SIGNALS: &[i32] = &[SIGTERM, SIGINT, SIGHUP];
+#[cfg(not(unix))]
+const SIGNALS: &[i32] = &[SIGTERM, SIGINT];
+
+/// The termination signals that have been received
+#[derive(Clone, Default)]
+pub struct TerminationSignals {
+    // The number of the last signal that was received, or 0
+    received: Arc<AtomicUsize>,
+    terminating: Arc<AtomicBool>,
+}
+
+impl TerminationSignals {
+    /// Start catching SIGTERM, SIGINT and, on Unix, SIGHUP.
+    pub fn register() -> IOResult<Self> {
+        let ret = Self::default();
+        for &signal in SIGNALS {
+            // This has to be registered first, so that it only exits on the second signal. The
+            // exit code is the one that shells use for processes killed by a signal.
+            flag::register_conditional_shutdown(signal, 128 + signal, ret.terminating.clone())?;
+            flag::register(signal, ret.terminating.clone())?;
+            flag::register_usize(signal, ret.received.clone(), signal as usize)?;
+        }
+        Ok(ret)
+    }
+
+    /// Signals that have already received `signal`, without catching any actual signals, so that
+    /// tests don't change how the whole test process handles them.
+    #[cfg(test)]
+    pub(crate) fn received_for_test(signal: i32) -> Self {
+        let ret = Self::default();
+        ret.received.store(signal as usize, Ordering::SeqCst);
+        ret
+    }
+
+    /// The signal that was received, if any
+    pub fn received(&self) -> Option<i32> {
+        match self.received.load(Ordering::SeqCst) {
+            0 => None,
+            signal => Some(signal as i32),
+        }
+    }
+
+    /// Fail with a `Terminated` error if a signal has been received
+    pub fn check(&self) -> IOResult<()> {
+        match self.received() {
+            Some(signal) => Err(IOError::new(ErrorKind::Interrupted, Terminated(signal))),
+            None => Ok(()),
+        }
+    }
+}
+
+/// The error with which reading events fails after a termination signal has been received
+#[derive(Debug)]
+pub struct Terminated(pub i32);
+
+impl fmt::Display for Terminated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Received signal {}", self.0)
+    }
+}
+
+impl std::error::Error for Terminated {}
+
+/// The signal that caused the error `err`, if it's a `Terminated` error
+pub fn terminated_by(err: &IOError) -> Option<i32> {
+    err.get_ref()?.downcast_ref::<Terminated>().map(|t| t.0)
+}
@@ -0,0 +1,102 @@
+/// The colors and text attributes of the listing, chosen with `--theme`. The default theme uses a
+/// grey highlight, which can be hard to see on terminals with a light background or for users
+/// with low vision, so a few other presets are built in.
+use crossterm::style::{Attribute, Color};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    /// The colors and attribute of the item under the cursor
+    pub highlight_fg: Color,
+    pub highlight_bg: Color,
+    pub highlight_attr: Attribute,
+    /// The colors of the letters that match the search, which are also underlined
+    pub match_fg: Color,
+    pub match_bg: Color,
+    /// The attribute of file names. Folders are always bold.
+    pub file_attr: Attribute,
+    pub symlink_fg: Color,
+    pub alias_fg: Color,
+    pub marked_fg: Color,
+    /// An extra attribute of marked items, for themes in which the color can't be seen
+    pub marked_attr: Attribute,
+    /// The color of the indicators of new and modified items
+    pub changed_fg: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            highlight_fg: Color::Black,
+            highlight_bg: Color::Grey,
+            highlight_attr: Attribute::NoReverse,
+            match_fg: Color::Reset,
+            match_bg: Color::DarkGrey,
+            file_attr: Attribute::Dim,
+            symlink_fg: Color::Cyan,
+            alias_fg: Color::Magenta,
+            marked_fg: Color::Yellow,
+            marked_attr: Attribute::NoItalic,
+            changed_fg: Color::Green,
+        }
+    }
+}
+
+impl Theme {
+    /// The names of the built-in themes, which `from_name` accepts
+    pub const NAMES: [&'static str; 4] = ["default", "high-contrast", "monochrome", "light"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "default" => Some(Self::default()),
+            // bright colors, and no dimmed file names
+            "high-contrast" => Some(Self {
+                highlight_fg: Color::Black,
+                highlight_bg: Color::White,
+                match_fg: Color::Black,
+                match_bg: Color::Yellow,
+                file_attr: Attribute::NormalIntensity,
+                ..Self::default()
+            }),
+            // only text attributes, for terminals without colors or with NO_COLOR set
+            "monochrome" => Some(Self {
+                highlight_fg: Color::Reset,
+                highlight_bg: Color::Reset,
+                highlight_attr: Attribute::Reverse,
+                match_fg: Color::Reset,
+                match_bg: Color::Reset,
+                symlink_fg: Color::Reset,
+                alias_fg: Color::Reset,
+                marked_fg: Color::Reset,
+                marked_attr: Attribute::Italic,
+                changed_fg: Color::Reset,
+                ..Self::default()
+            }),
+            // dark colors that can be seen on a light background
+            "light" => Some(Self {
+                highlight_fg: Color::White,
+                highlight_bg: Color::DarkBlue,
+                match_bg: Color::Grey,
+                symlink_fg: Color::DarkCyan,
+                alias_fg: Color::DarkMagenta,
+                marked_fg: Color::DarkYellow,
+                changed_fg: Color::DarkGreen,
+                ..Self::default()
+            }),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_theme_names() {
+        for name in Theme::NAMES {
+            assert!(Theme::from_name(name).is_some(), "{}", name);
+        }
+        assert_eq!(Theme::from_name("default"), Some(Theme::default()));
+        assert_eq!(Theme::from_name("dark"), None);
+    }
+}
@@ -0,0 +1,135 @@
+/// The view menu, which lists the sort orders and the other options of how the current folder is
+/// shown, so that they can be chosen with the arrow keys instead of remembering the shortcut of
+/// each of them. Like `Palette`, this only keeps track of the state, the drawing and changing the
+/// view is done by the UI.
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::app_state::{SortMode, ViewPrefs};
+
+/// An option in the menu
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MenuItem {
+    Sort(SortMode),
+    Reverse,
+    FoldersOnly,
+    ShowHidden,
+    /// The filter preset with this index, or no filter
+    Filter(Option<usize>),
+}
+
+impl MenuItem {
+    /// Apply the option to `prefs`. Returns `None` for the filter presets, which are not part of
+    /// the view preferences.
+    pub fn apply(&self, prefs: ViewPrefs) -> Option<ViewPrefs> {
+        match self {
+            Self::Sort(sort_mode) => Some(ViewPrefs { sort_mode: *sort_mode, ..prefs }),
+            Self::Reverse => Some(ViewPrefs { reverse_sort: !prefs.reverse_sort, ..prefs }),
+            Self::FoldersOnly => Some(ViewPrefs { folders_only: !prefs.folders_only, ..prefs }),
+            Self::ShowHidden => Some(ViewPrefs { show_hidden: !prefs.show_hidden, ..prefs }),
+            Self::Filter(_) => None,
+        }
+    }
+}
+
+/// The result of handling a key press in the menu.
+#[derive(Debug, PartialEq, Eq)]
+pub enum MenuStatus {
+    /// Keep reading input
+    Pending,
+    /// This option was chosen, with Enter if `close` is true, and otherwise with Space, which
+    /// keeps the menu open
+    Chosen { item: MenuItem, close: bool },
+    /// The menu was closed with Esc, q or Ctrl+C
+    Closed,
+}
+
+pub struct ViewMenu {
+    items: Vec<MenuItem>,
+    selected: usize,
+}
+
+impl ViewMenu {
+    /// Create a menu with the sort orders and view options, and the filter presets if there are
+    /// `n_presets` of them. The cursor starts on the current sort order.
+    pub fn new(prefs: &ViewPrefs, n_presets: usize) -> Self {
+        let mut items = vec![
+            MenuItem::Sort(SortMode::Name),
+            MenuItem::Sort(SortMode::Modified),
+            MenuItem::Sort(SortMode::Size),
+            MenuItem::Reverse,
+            MenuItem::FoldersOnly,
+            MenuItem::ShowHidden,
+        ];
+        if n_presets > 0 {
+            items.push(MenuItem::Filter(None));
+            items.extend((0..n_presets).map(|i| MenuItem::Filter(Some(i))));
+        }
+        let selected = items.iter().position(|item| *item == MenuItem::Sort(prefs.sort_mode)).unwrap_or(0);
+        Self { items, selected }
+    }
+
+    pub fn items(&self) -> &[MenuItem] {
+        &self.items
+    }
+
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn handle_key(&mut self, k: &KeyEvent) -> MenuStatus {
+        let ctrl = k.modifiers == KeyModifiers::CONTROL;
+        let n = self.items.len();
+        match k.code {
+            KeyCode::Esc | KeyCode::Char('q') => return MenuStatus::Closed,
+            KeyCode::Char('c') if ctrl => return MenuStatus::Closed,
+            KeyCode::Enter => return MenuStatus::Chosen { item: self.items[self.selected], close: true },
+            KeyCode::Char(' ') => return MenuStatus::Chosen { item: self.items[self.selected], close: false },
+            KeyCode::Up | KeyCode::Char('k') => self.selected = (self.selected + n - 1) % n,
+            KeyCode::Char('p') if ctrl => self.selected = (self.selected + n - 1) % n,
+            KeyCode::Down | KeyCode::Char('j') => self.selected = (self.selected + 1) % n,
+            KeyCode::Char('n') if ctrl => self.selected = (self.selected + 1) % n,
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = n - 1,
+            _ => {}
+        }
+        MenuStatus::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    #[test]
+    fn test_view_menu() {
+        let prefs = ViewPrefs { sort_mode: SortMode::Size, ..ViewPrefs::default() };
+        let mut menu = ViewMenu::new(&prefs, 0);
+        assert_eq!(menu.items().len(), 6);
+        assert_eq!(menu.selected(), 2);
+
+        assert_eq!(menu.handle_key(&key(KeyCode::Down)), MenuStatus::Pending);
+        assert_eq!(
+            menu.handle_key(&key(KeyCode::Char(' '))),
+            MenuStatus::Chosen { item: MenuItem::Reverse, close: false },
+        );
+        assert!(MenuItem::Reverse.apply(prefs).unwrap().reverse_sort);
+
+        menu.handle_key(&key(KeyCode::Up));
+        menu.handle_key(&key(KeyCode::Up));
+        assert_eq!(
+            menu.handle_key(&key(KeyCode::Enter)),
+            MenuStatus::Chosen { item: MenuItem::Sort(SortMode::Modified), close: true },
+        );
+        assert_eq!(menu.handle_key(&key(KeyCode::Esc)), MenuStatus::Closed);
+
+        // the selection wraps around, and the presets come last
+        let mut menu = ViewMenu::new(&ViewPrefs::default(), 2);
+        menu.handle_key(&key(KeyCode::Up));
+        assert_eq!(menu.items()[menu.selected()], MenuItem::Filter(Some(1)));
+        assert_eq!(MenuItem::Filter(None).apply(prefs), None);
+    }
+}
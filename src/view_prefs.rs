@@ -0,0 +1,279 @@
+/// How the contents of each folder are shown: the sort order, and whether hidden items and files
+/// are shown. These can be changed separately for each folder, and the changes are saved next to
+/// the history file, so that for example a downloads folder can stay sorted by modification time
+/// while other folders are sorted by name.
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde_json::{json, Map, Value};
+
+use super::CustomDirEntry;
+
+/// The version of the format of the view preferences file. Files with other versions are ignored.
+const PREFS_FILE_VERSION: u64 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortMode {
+    /// By name, ignoring case
+    #[default]
+    Name,
+    /// The most recently modified first
+    Modified,
+    /// The largest first. Folders are sorted by name.
+    Size,
+}
+
+impl SortMode {
+    /// The name of the mode in the view preferences file
+    fn name(&self) -> &'static str {
+        match self {
+            SortMode::Name     => "name",
+            SortMode::Modified => "modified",
+            SortMode::Size     => "size",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        [SortMode::Name, SortMode::Modified, SortMode::Size]
+            .iter()
+            .copied()
+            .find(|mode| mode.name() == name)
+    }
+
+    pub fn next(&self) -> Self {
+        match self {
+            SortMode::Name     => SortMode::Modified,
+            SortMode::Modified => SortMode::Size,
+            SortMode::Size     => SortMode::Name,
+        }
+    }
+}
+
+impl fmt::Display for SortMode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = match self {
+            SortMode::Name     => "sorted by name",
+            SortMode::Modified => "sorted by modification time",
+            SortMode::Size     => "sorted by size",
+        };
+        write!(f, "{}", text)
+    }
+}
+
+/// How the contents of a folder are shown
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ViewPrefs {
+    pub sort_mode: SortMode,
+    /// If true, the items are sorted in the opposite order. Folders are still listed first.
+    pub reverse_sort: bool,
+    pub show_hidden: bool,
+    /// If true, files are not shown, only folders and symlinks to folders
+    pub folders_only: bool,
+}
+
+impl Default for ViewPrefs {
+    fn default() -> Self {
+        Self {
+            sort_mode: SortMode::Name,
+            reverse_sort: false,
+            show_hidden: true,
+            folders_only: false,
+        }
+    }
+}
+
+impl ViewPrefs {
+    /// The entries that are shown with these preferences, in the order in which they are shown.
+    /// `entries` are sorted by name with folders first, like the listings read with
+    /// `read_dir_sorted`. Folders stay first with every sort mode.
+    pub fn apply(&self, entries: &[CustomDirEntry]) -> Vec<CustomDirEntry> {
+        let mut shown: Vec<CustomDirEntry> = entries
+            .iter()
+            .filter(|e| !self.folders_only || e.is_dir())
+            .filter(|e| self.show_hidden || !e.is_hidden())
+            .cloned()
+            .collect();
+        if self.sort_mode != SortMode::Name {
+            // the sort is stable, so items that compare equal stay sorted by name
+            CustomDirEntry::load_metadata(&shown.iter().collect::<Vec<_>>());
+        }
+        match self.sort_mode {
+            SortMode::Modified => shown.sort_by_key(|e| {
+                let mtime = e.metadata().and_then(|m| m.modified().ok());
+                (!e.is_dir(), std::cmp::Reverse(mtime.unwrap_or(SystemTime::UNIX_EPOCH)))
+            }),
+            SortMode::Size => shown.sort_by_key(|e| {
+                let size = e.metadata().filter(|_| !e.is_dir()).map_or(0, |m| m.len());
+                (!e.is_dir(), std::cmp::Reverse(size))
+            }),
+            SortMode::Name => {}
+        }
+        if self.reverse_sort {
+            // the folders are first in every sort mode
+            let num_dirs = shown.iter().take_while(|e| e.is_dir()).count();
+            shown[..num_dirs].reverse();
+            shown[num_dirs..].reverse();
+        }
+        shown
+    }
+}
+
+/// The view preferences of the folders in which they have been changed from the default.
+#[derive(Default)]
+pub struct ViewPrefsStore {
+    prefs: HashMap<PathBuf, ViewPrefs>,
+    /// Whether the preferences have changed since they were loaded
+    changed: bool,
+}
+
+impl ViewPrefsStore {
+    /// The file next to the history file `history_file` in which the preferences are saved
+    pub fn file_for_history_file(history_file: &Path) -> PathBuf {
+        history_file.with_extension("view.json")
+    }
+
+    /// The preferences of the folder `path`, or `default` if they haven't been changed there.
+    pub fn get(&self, path: &Path, default: ViewPrefs) -> ViewPrefs {
+        self.prefs.get(path).copied().unwrap_or(default)
+    }
+
+    /// Change the preferences of the folder `path`. They are forgotten if they are the same as
+    /// `default`, so that changing the default later applies to the folder again.
+    pub fn set(&mut self, path: &Path, prefs: ViewPrefs, default: ViewPrefs) {
+        if prefs == default {
+            self.prefs.remove(path);
+        } else {
+            self.prefs.insert(path.to_path_buf(), prefs);
+        }
+        self.changed = true;
+    }
+
+    /// Write the preferences to `file` if they have changed since they were loaded, see `load`.
+    pub fn save(&self, file: &Path) -> IOResult<()> {
+        if !self.changed {
+            return Ok(());
+        }
+        // paths that aren't valid unicode can't be saved
+        let folders: Map<String, Value> = self
+            .prefs
+            .iter()
+            .filter_map(|(path, prefs)| {
+                let prefs = json!({
+                    "sort": prefs.sort_mode.name(),
+                    "reverse": prefs.reverse_sort,
+                    "show_hidden": prefs.show_hidden,
+                    "folders_only": prefs.folders_only,
+                });
+                Some((path.to_str()?.to_string(), prefs))
+            })
+            .collect();
+        let contents = json!({
+            "version": PREFS_FILE_VERSION,
+            "folders": folders,
+        });
+
+        if let Some(parent) = file.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::write(file, contents.to_string())
+    }
+
+    /// Read the preferences that were saved to `file` with `save`. Folders whose preferences
+    /// can't be parsed are skipped, and the whole file is ignored if it was written by another
+    /// version of tere with a different format.
+    pub fn load(file: &Path) -> IOResult<Self> {
+        let contents: Value = serde_json::from_slice(&std::fs::read(file)?)?;
+        let mut ret = Self::default();
+        if contents["version"] != PREFS_FILE_VERSION {
+            return Ok(ret);
+        }
+        let folders = contents["folders"].as_object().into_iter().flatten();
+        for (path, prefs) in folders {
+            let parsed = (|| {
+                Some(ViewPrefs {
+                    sort_mode: SortMode::from_name(prefs["sort"].as_str()?)?,
+                    // this was added later, so it's missing from older files
+                    reverse_sort: prefs["reverse"].as_bool().unwrap_or(false),
+                    show_hidden: prefs["show_hidden"].as_bool()?,
+                    folders_only: prefs["folders_only"].as_bool()?,
+                })
+            })();
+            if let Some(prefs) = parsed {
+                ret.prefs.insert(PathBuf::from(path), prefs);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::TempDir;
+    use crate::app_state::{read_dir_sorted, FsDirSource};
+    use std::fs::File;
+    use std::time::Duration;
+
+    #[test]
+    fn test_apply_view_prefs() {
+        let tmp = TempDir::new("view-prefs");
+        std::fs::create_dir_all(tmp.join("dir")).unwrap();
+        std::fs::create_dir_all(tmp.join(".hidden-dir")).unwrap();
+        for (name, size, age) in [("a", 10, 1), ("b", 30, 3), (".c", 20, 2)] {
+            std::fs::write(tmp.join(name), vec![0; size]).unwrap();
+            let mtime = SystemTime::now() - Duration::from_secs(3600 * age);
+            File::options().write(true).open(tmp.join(name)).unwrap().set_modified(mtime).unwrap();
+        }
+        let entries = read_dir_sorted(&FsDirSource, &tmp).unwrap();
+        let names = |prefs: ViewPrefs| -> Vec<String> {
+            prefs.apply(&entries).iter().map(|e| e.file_name_checked()).collect()
+        };
+
+        let default = ViewPrefs::default();
+        assert_eq!(names(default), vec![".hidden-dir", "dir", ".c", "a", "b"]);
+        let prefs = ViewPrefs { sort_mode: SortMode::Modified, ..default };
+        assert_eq!(names(prefs), vec![".hidden-dir", "dir", "a", ".c", "b"]);
+        let prefs = ViewPrefs { sort_mode: SortMode::Size, show_hidden: false, ..default };
+        assert_eq!(names(prefs), vec!["dir", "b", "a"]);
+        let prefs = ViewPrefs { folders_only: true, show_hidden: false, ..default };
+        assert_eq!(names(prefs), vec!["dir"]);
+        let prefs = ViewPrefs { reverse_sort: true, ..default };
+        assert_eq!(names(prefs), vec!["dir", ".hidden-dir", "b", "a", ".c"]);
+        let prefs = ViewPrefs { sort_mode: SortMode::Size, reverse_sort: true, ..default };
+        assert_eq!(names(prefs), vec!["dir", ".hidden-dir", "a", ".c", "b"]);
+    }
+
+    #[test]
+    fn test_view_prefs_file() {
+        let tmp = TempDir::new("view-prefs-file");
+        let file = ViewPrefsStore::file_for_history_file(&tmp.join("history.json"));
+        assert_eq!(file, tmp.join("history.view.json"));
+
+        let default = ViewPrefs::default();
+        let by_mtime = ViewPrefs { sort_mode: SortMode::Modified, reverse_sort: true, ..default };
+        let mut store = ViewPrefsStore::default();
+        store.set(Path::new("/downloads"), by_mtime, default);
+        store.set(Path::new("/src"), ViewPrefs { folders_only: true, ..default }, default);
+        // going back to the default forgets the folder
+        store.set(Path::new("/src"), default, default);
+        store.save(&file).unwrap();
+
+        let loaded = ViewPrefsStore::load(&file).unwrap();
+        assert_eq!(loaded.get(Path::new("/downloads"), default), by_mtime);
+        assert_eq!(loaded.get(Path::new("/src"), default), default);
+        assert_eq!(loaded.prefs.len(), 1);
+
+        // files saved before the sort could be reversed
+        let old = r#"{"version": 1, "folders": {"/x": {"sort": "size", "show_hidden": true, "folders_only": false}}}"#;
+        std::fs::write(&file, old).unwrap();
+        let loaded = ViewPrefsStore::load(&file).unwrap();
+        let by_size = ViewPrefs { sort_mode: SortMode::Size, ..default };
+        assert_eq!(loaded.get(Path::new("/x"), default), by_size);
+
+        std::fs::write(&file, r#"{"version": 1, "folders": {"/x": {"sort": "bad"}}}"#).unwrap();
+        assert!(ViewPrefsStore::load(&file).unwrap().prefs.is_empty());
+    }
+}
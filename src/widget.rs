@@ -0,0 +1,331 @@
+/// A folder browser widget for applications built with ratatui, for example to use as a folder
+/// picker. The widget doesn't read any events by itself, instead the application translates its
+/// own key events into `Action`s and search characters, and passes them to the widget state.
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+
+use ratatui::buffer::Buffer;
+use ratatui::layout::Rect;
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, StatefulWidget, Widget};
+
+use crate::app_state::{split_by_match_spans, ItemChange, TereAppState, TereSettings};
+use crate::error::TereError;
+use crate::ui::action::Action;
+
+/// Something that happened in the widget that the application should react to.
+#[derive(Debug, PartialEq, Eq)]
+pub enum WidgetEvent {
+    /// The user chose this folder (or file, if `choose_file` is set), so the picker can be closed
+    Chosen(PathBuf),
+    /// The user wants to close the picker without choosing anything
+    Cancelled,
+}
+
+/// The state of the folder browser, i.e. the current folder, the cursor position and the search.
+pub struct TereWidgetState {
+    app_state: TereAppState,
+}
+
+impl TereWidgetState {
+    /// Start browsing from the folder `path`, which should be an absolute path. Note that the
+    /// default settings don't include any key bindings, since the application is responsible for
+    /// handling the keys. Also note that changing the folder changes the working directory of the
+    /// whole process, like in tere itself.
+    pub fn new(path: PathBuf, settings: TereSettings) -> Result<Self, TereError> {
+        // the window size is updated when the widget is rendered
+        let app_state = TereAppState::new(settings, path, 0, 0)?;
+        Ok(Self { app_state })
+    }
+
+    /// The folder that is currently shown
+    pub fn current_path(&self) -> &Path {
+        &self.app_state.current_path
+    }
+
+    /// The path of the item under the cursor, if there is one
+    pub fn selected_path(&self) -> Option<PathBuf> {
+        self.app_state.item_under_cursor_path()
+    }
+
+    pub fn search_query(&self) -> &str {
+        self.app_state.search_string()
+    }
+
+    /// Absolute paths of the marked items, in the order in which they were marked
+    pub fn marked_items(&self) -> &[PathBuf] {
+        self.app_state.marked_items()
+    }
+
+    /// Access the underlying app state, for example to show the number of matches.
+    pub fn app_state(&self) -> &TereAppState {
+        &self.app_state
+    }
+
+    /// Add a character to the search query.
+    pub fn on_search_char(&mut self, c: char) {
+        self.app_state.advance_search(&c.to_string());
+    }
+
+    /// Perform the given action. Actions that need the terminal (like opening the help screen,
+    /// spawning a shell or prompting for a file name) are ignored, since they don't make sense
+    /// inside another application.
+    /// Errors from changing the folder (for example because of missing permissions) are returned,
+    /// and the state is left unchanged.
+    pub fn handle_action(&mut self, action: Action) -> IOResult<Option<WidgetEvent>> {
+        let state = &mut self.app_state;
+        match action {
+            Action::CursorUp | Action::CursorDown => {
+                let dir = if action == Action::CursorUp { -1 } else { 1 };
+                if state.is_searching() {
+                    state.move_cursor_to_adjacent_match(dir);
+                } else {
+                    state.move_cursor(dir, true);
+                }
+            }
+            Action::ChangeDir => {
+                if state.choose_file("") {
+                    return Ok(state.chosen_file.clone().map(WidgetEvent::Chosen));
+                }
+                state.change_dir("")?;
+            }
+            Action::ChangeDirParent => state.change_dir("..")?,
+            Action::ChangeDirAndExit => {
+                if state.choose_file("") {
+                    return Ok(state.chosen_file.clone().map(WidgetEvent::Chosen));
+                }
+                state.change_dir("")?;
+                return Ok(Some(WidgetEvent::Chosen(state.current_path.clone())));
+            }
+            Action::Exit => return Ok(Some(WidgetEvent::Chosen(state.current_path.clone()))),
+            Action::ExitWithoutCd => return Ok(Some(WidgetEvent::Cancelled)),
+            Action::ChangeDirHome => {
+                if let Some(path) = dirs::home_dir().as_ref().and_then(|p| p.to_str()) {
+                    state.change_dir(path)?;
+                }
+            }
+            Action::ChangeDirRoot => state.change_dir("/")?,
+            Action::RefreshListing => state.refresh()?,
+            Action::NewTab => state.new_tab()?,
+            Action::CloseTab => state.close_tab()?,
+            Action::NextTab => state.next_tab()?,
+            Action::PreviousTab => state.previous_tab()?,
+            Action::CursorUpScreen
+            | Action::CursorDownScreen
+            | Action::CursorUpHalfScreen
+            | Action::CursorDownHalfScreen => {
+                let up = matches!(action, Action::CursorUpScreen | Action::CursorUpHalfScreen);
+                let half = matches!(action, Action::CursorUpHalfScreen | Action::CursorDownHalfScreen);
+                let dir = if up { -1 } else { 1 };
+                if state.is_searching() {
+                    state.move_cursor_to_match_beyond_view(dir, half);
+                } else {
+                    state.move_cursor((state.page_scroll_amount(half) as isize) * dir, false);
+                }
+            }
+            Action::CursorTop => state.move_cursor_to(0),
+            Action::CursorBottom => state.move_cursor_to(state.num_visible_items()),
+            Action::EraseSearchChar => state.erase_search_char(),
+            Action::ClearSearch => state.clear_search(),
+            Action::ChangeCaseSensitiveMode => state.cycle_case_sensitive_mode(),
+            Action::ChangeGapSearchMode => state.cycle_gap_search_mode(),
+            Action::ChangeSortMode => state.cycle_sort_mode()?,
+            Action::ReverseSort => state.toggle_reverse_sort()?,
+            Action::ToggleHidden => state.toggle_show_hidden()?,
+            Action::ToggleFoldersOnly => state.toggle_folders_only()?,
+            Action::CycleFilterPreset => state.cycle_filter_preset()?,
+            Action::ToggleMark => {
+                if state.toggle_mark_under_cursor() {
+                    state.move_cursor(1, false);
+                }
+            }
+            Action::ClearMarks => state.clear_marks(),
+            Action::NextMark => {
+                state.move_cursor_to_adjacent_mark(1);
+            }
+            Action::PreviousMark => {
+                state.move_cursor_to_adjacent_mark(-1);
+            }
+            Action::ToggleMouseCapture
+            | Action::CopyPath
+            | Action::CopyItemPath
+            | Action::SpawnShell
+            | Action::OpenInEditor
+            | Action::OpenWithDefaultApp
+            | Action::OpenFileManager
+            | Action::Help
+            | Action::CheatSheet
+            | Action::ShowLastError
+            | Action::ViewMenu
+            | Action::ToggleBrowseMode
+            | Action::JumpToLabel
+            | Action::CommandPalette
+            | Action::GoToPath
+            | Action::CreateDir
+            | Action::CreateDirAndEnter
+            | Action::CreateFile
+            | Action::Rename
+            | Action::Delete
+            | Action::CopyMarkedHere
+            | Action::MoveMarkedHere
+            | Action::LinkMarkedHere
+            | Action::RunCommand(_)
+            | Action::RunScript(_)
+            | Action::NamedCommand(_) => {}
+        }
+        Ok(None)
+    }
+}
+
+/// The widget that draws a `TereWidgetState`. It shows the contents of the current folder, with
+/// the same colors as tere itself.
+#[derive(Default)]
+pub struct TereWidget<'a> {
+    block: Option<Block<'a>>,
+}
+
+impl<'a> TereWidget<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Draw the listing inside this block, for example to add borders and a title.
+    pub fn block(mut self, block: Block<'a>) -> Self {
+        self.block = Some(block);
+        self
+    }
+}
+
+impl StatefulWidget for TereWidget<'_> {
+    type State = TereWidgetState;
+
+    fn render(self, area: Rect, buf: &mut Buffer, state: &mut Self::State) {
+        let area = match self.block {
+            Some(block) => {
+                let inner = block.inner(area);
+                block.render(area, buf);
+                inner
+            }
+            None => area,
+        };
+
+        let app_state = &mut state.app_state;
+        app_state.update_main_window_dimensions(area.width as usize, area.height as usize);
+        app_state.load_visible_metadata();
+
+        let is_search = app_state.is_searching();
+        let any_items = app_state.num_matching_items() > 0 || app_state.num_visible_items() > 0;
+
+        for row in 0..(area.height as usize) {
+            let item = match app_state.get_item_at_cursor_pos(row) {
+                Some(item) => item,
+                None => break,
+            };
+            let row_area = Rect::new(area.x, area.y + row as u16, area.width, 1);
+            let highlight = app_state.cursor_pos == row && (!is_search || any_items);
+
+            let mut style = if item.is_dir() {
+                Style::default().add_modifier(Modifier::BOLD)
+            } else {
+                Style::default().add_modifier(Modifier::DIM)
+            };
+            if highlight {
+                style = style.fg(Color::Black).bg(Color::Gray);
+            } else if app_state.is_marked(item) {
+                style = style.fg(Color::Yellow);
+            } else if item.is_symlink() {
+                style = style.fg(Color::Cyan);
+            }
+            buf.set_style(row_area, style);
+
+            // The graphemes of the name that should be underlined
+            let match_spans = if is_search {
+                app_state.get_match_spans_at_cursor_pos(row)
+            } else {
+                None
+            };
+            let match_style = Style::default()
+                .add_modifier(Modifier::UNDERLINED)
+                .fg(Color::Reset)
+                .bg(Color::DarkGray);
+
+            let fname = item.file_name();
+            let offsets = item.grapheme_offsets();
+            let mut spans: Vec<Span> =
+                split_by_match_spans(fname, offsets, match_spans.map_or(&[], |v| v.as_slice()))
+                    .into_iter()
+                    .map(|(segment, underline)| {
+                        if underline {
+                            Span::styled(segment, match_style)
+                        } else {
+                            Span::raw(segment)
+                        }
+                    })
+                    .collect();
+            let symlink_target = if app_state.shows_metadata() { item.symlink_target() } else { None };
+            if let Some(target) = symlink_target {
+                let loop_note = if item.is_symlink_loop() { " (loop)" } else { "" };
+                spans.push(Span::raw(format!(" -> {}{}", target.display(), loop_note)));
+            }
+            if let Some(change) = app_state.item_change(item) {
+                let indicator = match change {
+                    ItemChange::New      => " +",
+                    ItemChange::Modified => " *",
+                };
+                if highlight {
+                    spans.push(Span::raw(indicator));
+                } else {
+                    spans.push(Span::styled(indicator, Style::default().fg(Color::Green)));
+                }
+            }
+            buf.set_line(row_area.x, row_area.y, &Line::from(spans), row_area.width);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{TempDir, TestFsDirSource};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_widget_navigation() {
+        let tmp = TempDir::new("widget");
+        std::fs::create_dir_all(tmp.join("foo")).unwrap();
+        std::fs::create_dir_all(tmp.join("bar")).unwrap();
+        // don't change the working directory of the process, like `TereWidgetState::new` would
+        let source = Arc::new(TestFsDirSource);
+        let app_state =
+            TereAppState::with_dir_source(TereSettings::default(), tmp.to_path_buf(), source, 0, 0)
+                .unwrap();
+        let mut state = TereWidgetState { app_state };
+
+        let area = Rect::new(0, 0, 20, 5);
+        let mut buf = Buffer::empty(area);
+        TereWidget::new().render(area, &mut buf, &mut state);
+        let row = |buf: &Buffer, y| (0..3).map(|x| buf[(x, y)].symbol().to_string()).collect::<String>();
+        assert_eq!(row(&buf, 0), ".. ");
+        assert_eq!(row(&buf, 1), "bar");
+        assert_eq!(row(&buf, 2), "foo");
+
+        // the cursor starts on the first item after '..'
+        assert_eq!(state.selected_path(), Some(tmp.join("bar")));
+        state.handle_action(Action::CursorDown).unwrap();
+        assert_eq!(state.handle_action(Action::ChangeDir).unwrap(), None);
+        assert_eq!(state.current_path(), tmp.join("foo"));
+
+        state.handle_action(Action::ChangeDirParent).unwrap();
+        state.on_search_char('b');
+        assert_eq!(state.selected_path(), Some(tmp.join("bar")));
+        assert_eq!(
+            state.handle_action(Action::ChangeDirAndExit).unwrap(),
+            Some(WidgetEvent::Chosen(tmp.join("bar"))),
+        );
+        assert_eq!(
+            state.handle_action(Action::ExitWithoutCd).unwrap(),
+            Some(WidgetEvent::Cancelled),
+        );
+    }
+}